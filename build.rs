@@ -1,7 +1,14 @@
+use std::path::PathBuf;
+
 fn main() {
     println!("cargo:rerun-if-changed=./proto");
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
     tonic_build::compile_protos("./proto/hello.proto").unwrap();
-    tonic_build::compile_protos("./proto/ondo_remote.proto").unwrap();
+    // The descriptor set is served by the gRPC server reflection.
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("ondo_remote_descriptor.bin"))
+        .compile(&["./proto/ondo_remote.proto"], &["./proto"])
+        .unwrap();
     cargo_emit::rerun_if_changed!(
         "./proto/hello.proto",
         "./proto/ondo_remote.proto",
@@ -62,6 +62,8 @@ fn table_server_example(rda: &RocksDbAccessor, domain_reference_msg: &DomainRefe
     };
     let table_msg = TableMessage {
         table_reference: Some(table_reference_msg.clone()),
+        schema: String::new(),
+        validate_values: false,
     };
     let answer = rda.create_table(Request::new(table_msg.clone()));
     println!("Created Table: {:?}", answer);
@@ -108,7 +110,10 @@ fn table_value_server_example(
 
     let answer = rda.create_value(Request::new(create_table_value_msg.clone()));
     println!("Created Value: {:?}", answer);
-    let new_ondo_key = answer.unwrap().get_ref().clone();
+    let created = answer.unwrap().into_inner();
+    let new_ondo_key = OndoKeyMessage {
+        json_keys: created.json_keys,
+    };
     let table_value_reference_msg = TableValueReferenceMessage {
         table_reference: Some(table_reference_msg.clone()),
         key: Some(new_ondo_key), //Update reference with new id
@@ -121,10 +126,16 @@ fn table_value_server_example(
     let table_value_msg2 = TableValueMessage {
         table_value_reference: Some(table_value_reference_msg.clone()),
         json: serde_json::to_string(&person).unwrap(),
+        expected_revision: Some(created.revision),
     };
 
     let answer = rda.update_value(Request::new(table_value_msg2.clone()));
     println!("Updated Value: {:?}", answer);
-    let answer = rda.delete_value(Request::new(table_value_reference_msg.clone()));
+    let delete_table_value_msg = DeleteTableValueMessage {
+        table_reference: table_value_reference_msg.table_reference.clone(),
+        key: table_value_reference_msg.key.clone(),
+        expected_revision: None,
+    };
+    let answer = rda.delete_value(Request::new(delete_table_value_msg));
     println!("Deleted Value: {:?}", answer);
 }
@@ -1,6 +1,16 @@
+use clap::Parser;
+use tokio::net::UnixListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::codec::CompressionEncoding;
 use tonic::transport::Server;
 use tonic::{Request, Response, Status};
 
+use ondo::config::ServerArgs;
+use ondo::grpc_services;
+use ondo::logging;
+use ondo::metrics::{self, GrpcMetricsLayer};
 use ondo::ondo_remote;
 use ondo_remote::ondo_remote_server::{OndoRemote, OndoRemoteServer};
 use ondo_remote::*;
@@ -9,6 +19,7 @@ use ondo::db::server::{
     database_server_trait::DatabaseServerTrait, domain_server_trait::DomainServerTrait,
     index_server_trait::IndexServerTrait, rocks_db_accessor::RocksDbAccessor,
     table_server_trait::TableServerTrait, table_value_server_trait::TableValueServerTrait,
+    transaction_server_trait::TransactionServerTrait, value_stream::JsonMessageStream,
 };
 
 #[derive(Default)]
@@ -135,6 +146,14 @@ impl OndoRemote for MyServer {
         self.rocks_db_accessor.update_table(r)
     }
 
+    /// Reports the values of a table that do not match its schema, or the given one.
+    async fn validate_table_values(
+        &self,
+        r: Request<ValidateTableValuesMessage>,
+    ) -> Result<Response<TableValidationMessage>, Status> {
+        self.rocks_db_accessor.validate_table_values(r)
+    }
+
     /// Lists the indexes associated with the specified table.
     async fn list_indexes(
         &self,
@@ -151,6 +170,24 @@ impl OndoRemote for MyServer {
         self.rocks_db_accessor.list_values(r)
     }
 
+    /// Lists a page of the values in the specified table.
+    async fn list_values_page(
+        &self,
+        r: Request<ListValuesMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status> {
+        self.rocks_db_accessor.list_values_page(r)
+    }
+
+    type StreamValuesStream = JsonMessageStream;
+
+    /// Streams the values in the specified table.
+    async fn stream_values(
+        &self,
+        r: Request<TableReferenceMessage>,
+    ) -> Result<Response<Self::StreamValuesStream>, Status> {
+        self.rocks_db_accessor.stream_values(r)
+    }
+
     /// Lists the values in the specified table with the given key prefix.
     async fn list_values_by_key_prefix(
         &self,
@@ -159,6 +196,24 @@ impl OndoRemote for MyServer {
         self.rocks_db_accessor.list_values_by_key_prefix(r)
     }
 
+    /// Lists a page of the values in the specified table with the given key prefix.
+    async fn list_values_by_key_prefix_page(
+        &self,
+        r: Request<ListValuesByKeyPrefixMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status> {
+        self.rocks_db_accessor.list_values_by_key_prefix_page(r)
+    }
+
+    type StreamValuesByKeyPrefixStream = JsonMessageStream;
+
+    /// Streams the values in the specified table with the given key prefix.
+    async fn stream_values_by_key_prefix(
+        &self,
+        r: Request<TableValueReferenceMessage>,
+    ) -> Result<Response<Self::StreamValuesByKeyPrefixStream>, Status> {
+        self.rocks_db_accessor.stream_values_by_key_prefix(r)
+    }
+
     /// Lists the values in the specified table within the given ID range.
     async fn list_values_by_id_range(
         &self,
@@ -175,6 +230,19 @@ impl OndoRemote for MyServer {
         self.rocks_db_accessor.list_values_by_id_list(r)
     }
 
+    /// Retrieves the values of a table that match a filter.
+    async fn query(&self, r: Request<QueryMessage>) -> Result<Response<JsonMessage>, Status> {
+        self.rocks_db_accessor.query(r)
+    }
+
+    /// Returns the access path of a query or a find, and optionally what running it costs.
+    async fn explain(
+        &self,
+        r: Request<ExplainMessage>,
+    ) -> Result<Response<ExplainResponse>, Status> {
+        self.rocks_db_accessor.explain(r)
+    }
+
     /// Creates a new index with the given configuration.
     async fn create_index(
         &self,
@@ -207,18 +275,42 @@ impl OndoRemote for MyServer {
         self.rocks_db_accessor.update_index(r)
     }
 
+    /// Retrieves the state and the progress of the build of an index.
+    async fn get_index_build(
+        &self,
+        r: Request<IndexReferenceMessage>,
+    ) -> Result<Response<IndexBuildMessage>, Status> {
+        self.rocks_db_accessor.get_index_build(r)
+    }
+
+    /// Cancels the build of an index, which drops the index.
+    async fn cancel_index_build(
+        &self,
+        r: Request<IndexReferenceMessage>,
+    ) -> Result<Response<EmptyMessage>, Status> {
+        self.rocks_db_accessor.cancel_index_build(r)
+    }
+
+    /// Verifies the entries of an index against the values of its table, and repairs them.
+    async fn verify_index(
+        &self,
+        r: Request<VerifyIndexMessage>,
+    ) -> Result<Response<IndexVerificationMessage>, Status> {
+        self.rocks_db_accessor.verify_index(r)
+    }
+
     /// Creates a new value in the specified table with the given configuration.
     async fn create_value(
         &self,
         r: Request<CreateTableValueMessage>,
-    ) -> Result<Response<OndoKeyMessage>, Status> {
+    ) -> Result<Response<CreateValueResponse>, Status> {
         self.rocks_db_accessor.create_value(r)
     }
 
     /// Deletes an existing value identified by the given reference from the specified table.
     async fn delete_value(
         &self,
-        r: Request<TableValueReferenceMessage>,
+        r: Request<DeleteTableValueMessage>,
     ) -> Result<Response<EmptyMessage>, Status> {
         self.rocks_db_accessor.delete_value(r)
     }
@@ -239,6 +331,22 @@ impl OndoRemote for MyServer {
         self.rocks_db_accessor.update_value(r)
     }
 
+    /// Updates a value with a merge patch or a JSON Patch applied to the stored value.
+    async fn patch_value(
+        &self,
+        r: Request<PatchTableValueMessage>,
+    ) -> Result<Response<EmptyMessage>, Status> {
+        self.rocks_db_accessor.patch_value(r)
+    }
+
+    /// Creates a value with the given key, or replaces the value that has it.
+    async fn upsert_value(
+        &self,
+        r: Request<UpsertTableValueMessage>,
+    ) -> Result<Response<UpsertValueResponse>, Status> {
+        self.rocks_db_accessor.upsert_value(r)
+    }
+
     /// Finds values in the specified table based on the given indexed value reference.
     async fn find_values(
         &self,
@@ -247,6 +355,24 @@ impl OndoRemote for MyServer {
         self.rocks_db_accessor.find_values(r)
     }
 
+    /// Finds a page of values in the specified table based on the given indexed value reference.
+    async fn find_values_page(
+        &self,
+        r: Request<FindValuesMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status> {
+        self.rocks_db_accessor.find_values_page(r)
+    }
+
+    type StreamFoundValuesStream = JsonMessageStream;
+
+    /// Streams values in the specified table based on the given indexed value reference.
+    async fn stream_found_values(
+        &self,
+        r: Request<IndexedValueReferenceMessage>,
+    ) -> Result<Response<Self::StreamFoundValuesStream>, Status> {
+        self.rocks_db_accessor.stream_found_values(r)
+    }
+
     /// Finds values in the specified table based on the given indexed value range reference.
     async fn find_values_by_range(
         &self,
@@ -254,21 +380,200 @@ impl OndoRemote for MyServer {
     ) -> Result<Response<JsonMessage>, Status> {
         self.rocks_db_accessor.find_values_by_range(r)
     }
+
+    /// Finds a page of values in the specified table based on the given indexed value range reference.
+    async fn find_values_by_range_page(
+        &self,
+        r: Request<FindValuesByRangeMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status> {
+        self.rocks_db_accessor.find_values_by_range_page(r)
+    }
+
+    type StreamFoundValuesByRangeStream = JsonMessageStream;
+
+    /// Streams values in the specified table based on the given indexed value range reference.
+    async fn stream_found_values_by_range(
+        &self,
+        r: Request<IndexedValueRangeReferenceMessage>,
+    ) -> Result<Response<Self::StreamFoundValuesByRangeStream>, Status> {
+        self.rocks_db_accessor.stream_found_values_by_range(r)
+    }
+
+    /// Searches a text index, returning the matching values ranked by relevance.
+    async fn search_values(
+        &self,
+        r: Request<SearchValuesMessage>,
+    ) -> Result<Response<SearchValuesResponse>, Status> {
+        self.rocks_db_accessor.search_values(r)
+    }
+
+    /// Finds the values with the nearest vectors in a vector index.
+    async fn nearest_values(
+        &self,
+        r: Request<NearestValuesMessage>,
+    ) -> Result<Response<NearestValuesResponse>, Status> {
+        self.rocks_db_accessor.nearest_values(r)
+    }
+
+    /// Applies an ordered list of value operations of one domain all-or-nothing.
+    async fn execute_transaction(
+        &self,
+        r: Request<TransactionMessage>,
+    ) -> Result<Response<TransactionResponse>, Status> {
+        self.rocks_db_accessor.execute_transaction(r)
+    }
+}
+
+/// Resolves on the first SIGINT or SIGTERM. A signal whose handler can not be
+/// installed is logged and only the other one is waited for.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!(%err, "Can not listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                tracing::error!(%err, "Can not listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tokio::spawn(async move {
-        tokio::signal::ctrl_c().await.unwrap();
-        std::process::exit(0);
+    let config = ServerArgs::parse().load_config()?;
+    logging::init(&config.log).map_err(|err| err.to_string())?;
+
+    let (mut health_reporter, health_service) =
+        grpc_services::health_service::<OndoRemoteServer<MyServer>>().await;
+    let reflection_service = grpc_services::reflection_service()?;
+
+    let options = config.db.rocksdb_options()?;
+    let rocks_db_accessor =
+        RocksDbAccessor::open(config.db.path.clone(), options).map_err(|err| err.to_string())?;
+    rocks_db_accessor
+        .migrate_cf_names()
+        .map_err(|err| err.to_string())?;
+    rocks_db_accessor
+        .migrate_key_encoding()
+        .map_err(|err| err.to_string())?;
+    rocks_db_accessor
+        .migrate_index_entries()
+        .map_err(|err| err.to_string())?;
+    let resumed_builds = rocks_db_accessor
+        .resume_index_builds()
+        .map_err(|err| err.to_string())?;
+    if resumed_builds > 0 {
+        tracing::info!("Resumed the builds of {} indexes", resumed_builds);
+    }
+    grpc_services::set_serving::<OndoRemoteServer<MyServer>>(&mut health_reporter, true).await;
+    let remote_server = MyServer {
+        rocks_db_accessor: rocks_db_accessor.clone(),
+    };
+
+    let mut service = OndoRemoteServer::new(remote_server);
+    if let Some(max_message_size) = config.server.max_message_size {
+        service = service
+            .max_decoding_message_size(max_message_size)
+            .max_encoding_message_size(max_message_size);
+    }
+    if config.server.gzip {
+        service = service
+            .accept_compressed(CompressionEncoding::Gzip)
+            .send_compressed(CompressionEncoding::Gzip);
+    }
+
+    let metrics_task = config.metrics.address.map(|metrics_address| {
+        let rocks_db_accessor = rocks_db_accessor.clone();
+        tokio::spawn(async move {
+            tracing::info!(address = %metrics_address, "Serving metrics");
+            if let Err(err) = metrics::serve(metrics_address, rocks_db_accessor).await {
+                tracing::error!("Metrics listener failed: {}", err);
+            }
+        })
     });
-    let addr = "0.0.0.0:50051".parse()?;
 
-    let remote_server = MyServer::default();
-    Server::builder()
-        .add_service(OndoRemoteServer::new(remote_server))
-        .serve(addr)
-        .await?;
+    let mut builder = Server::builder().trace_fn(logging::request_span);
+    if let Some(request_timeout) = config.server.request_timeout() {
+        builder = builder.timeout(request_timeout);
+    }
+    if let Some(tls) = &config.server.tls {
+        builder = builder.tls_config(tls.server_tls_config()?)?;
+    }
+    let router = builder
+        .layer(GrpcMetricsLayer)
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(service);
+
+    let listener = match &config.server.unix_socket {
+        Some(unix_socket) => {
+            // A socket left behind by a previous run would make bind fail.
+            let _ = std::fs::remove_file(unix_socket);
+            Some(UnixListener::bind(unix_socket)?)
+        }
+        None => None,
+    };
+
+    // On a signal the server stops accepting new requests and waits for the running ones,
+    // but no longer than the shutdown timeout.
+    let (shutdown_sender, mut shutdown_receiver) = watch::channel(());
+    let shutdown = async move {
+        shutdown_signal().await;
+        tracing::info!("Shutting down");
+        grpc_services::set_serving::<OndoRemoteServer<MyServer>>(&mut health_reporter, false).await;
+        let _ = shutdown_sender.send(());
+    };
+    let serve = async {
+        match listener {
+            Some(listener) => {
+                router
+                    .serve_with_incoming_shutdown(UnixListenerStream::new(listener), shutdown)
+                    .await
+            }
+            None => {
+                router
+                    .serve_with_shutdown(config.server.address, shutdown)
+                    .await
+            }
+        }
+    };
+    let shutdown_deadline = async {
+        let _ = shutdown_receiver.changed().await;
+        tokio::time::sleep(config.server.shutdown_timeout()).await;
+    };
+    match &config.server.unix_socket {
+        Some(unix_socket) => tracing::info!(unix_socket = %unix_socket.display(), "Listening"),
+        None => tracing::info!(address = %config.server.address, "Listening"),
+    }
+    tokio::select! {
+        result = serve => result?,
+        _ = shutdown_deadline => tracing::warn!("Shutdown timeout passed, dropping the running requests"),
+    }
+
+    if let Some(unix_socket) = &config.server.unix_socket {
+        let _ = std::fs::remove_file(unix_socket);
+    }
+    // The metrics listener holds a clone of the accessor, which close waits for.
+    if let Some(metrics_task) = metrics_task {
+        metrics_task.abort();
+        let _ = metrics_task.await;
+    }
+    rocks_db_accessor
+        .close(config.server.shutdown_timeout())
+        .map_err(|err| err.to_string())?;
+    tracing::info!("Database closed");
 
     Ok(())
 }
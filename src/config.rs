@@ -0,0 +1,477 @@
+//config.rs
+//! Configuration of ondo-server.
+//!
+//! Settings are read from an optional TOML file, then overridden by environment variables
+//! and finally by command line flags:
+//!
+//! ```toml
+//! [server]
+//! address = "0.0.0.0:50051"
+//! # unix_socket = "/var/run/ondo.sock"   # listens on the socket instead of the address
+//! max_message_size = 4194304
+//! gzip = true
+//! request_timeout_secs = 30
+//! shutdown_timeout_secs = 30           # how long running requests may finish on SIGINT/SIGTERM
+//!
+//! [server.tls]
+//! cert = "server.pem"
+//! key = "server.key"
+//! # client_ca = "ca.pem"                 # requires client certificates (mTLS)
+//!
+//! [db]
+//! path = "./db/ondo_rocksdb"
+//! block_cache_size = 536870912
+//! max_open_files = 1000
+//! parallelism = 4
+//!
+//! [metrics]
+//! address = "0.0.0.0:9090"             # serves Prometheus metrics on /metrics
+//!
+//! [log]
+//! level = "info"                       # an EnvFilter directive, e.g. "info,ondo=trace"
+//! format = "json"                      # or "text"
+//! ```
+use clap::{Parser, ValueEnum};
+use rocksdb::{BlockBasedOptions, Cache, Options};
+use serde::Deserialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+pub const DEFAULT_ADDRESS: &str = "0.0.0.0:50051";
+pub const DEFAULT_DB_PATH: &str = "./db/ondo_rocksdb";
+pub const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub db: DbConfig,
+    pub metrics: MetricsConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub address: SocketAddr,
+    pub unix_socket: Option<PathBuf>,
+    pub tls: Option<TlsConfig>,
+    pub max_message_size: Option<usize>,
+    pub gzip: bool,
+    pub request_timeout_secs: Option<u64>,
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: DEFAULT_ADDRESS.parse().unwrap(),
+            unix_socket: None,
+            tls: None,
+            max_message_size: None,
+            gzip: false,
+            request_timeout_secs: None,
+            shutdown_timeout_secs: DEFAULT_SHUTDOWN_TIMEOUT_SECS,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn server_tls_config(&self) -> Result<ServerTlsConfig, ConfigError> {
+        let cert = read_file(&self.cert)?;
+        let key = read_file(&self.key)?;
+        let tls_config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+        match &self.client_ca {
+            Some(client_ca) => {
+                let client_ca = read_file(client_ca)?;
+                Ok(tls_config.client_ca_root(Certificate::from_pem(client_ca)))
+            }
+            None => Ok(tls_config),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DbConfig {
+    pub path: String,
+    pub block_cache_size: Option<usize>,
+    pub max_open_files: Option<i32>,
+    pub parallelism: Option<i32>,
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        DbConfig {
+            path: DEFAULT_DB_PATH.to_owned(),
+            block_cache_size: None,
+            max_open_files: None,
+            parallelism: None,
+        }
+    }
+}
+
+impl DbConfig {
+    pub fn rocksdb_options(&self) -> Result<Options, rocksdb::Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        if let Some(block_cache_size) = self.block_cache_size {
+            let cache = Cache::new_lru_cache(block_cache_size)?;
+            let mut block_based_options = BlockBasedOptions::default();
+            block_based_options.set_block_cache(&cache);
+            options.set_block_based_table_factory(&block_based_options);
+        }
+        if let Some(max_open_files) = self.max_open_files {
+            options.set_max_open_files(max_open_files);
+        }
+        if let Some(parallelism) = self.parallelism {
+            options.increase_parallelism(parallelism);
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub address: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "info".to_owned(),
+            format: LogFormat::Json,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Json,
+    Text,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Toml(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "Can not read {}: {}", path.display(), err),
+            ConfigError::Toml(path, err) => write!(f, "Invalid config {}: {}", path.display(), err),
+            ConfigError::Invalid(msg) => write!(f, "Invalid config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, ConfigError> {
+    std::fs::read(path).map_err(|err| ConfigError::Io(path.to_owned(), err))
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let text = String::from_utf8_lossy(&read_file(path)?).into_owned();
+        toml::from_str(&text).map_err(|err| ConfigError::Toml(path.to_owned(), err))
+    }
+}
+
+/// Command line of ondo-server. Every flag can also be set by its environment variable.
+#[derive(Debug, Default, Parser)]
+#[command(name = "ondo-server", version, about = "Ondo gRPC server")]
+pub struct ServerArgs {
+    /// TOML config file
+    #[arg(short, long, env = "ONDO_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on
+    #[arg(long, env = "ONDO_ADDRESS", conflicts_with = "unix_socket")]
+    pub address: Option<SocketAddr>,
+    /// Unix domain socket to listen on instead of an address
+    #[arg(long, env = "ONDO_UNIX_SOCKET")]
+    pub unix_socket: Option<PathBuf>,
+    /// Path of the RocksDB database
+    #[arg(long, env = "ONDO_DB_PATH")]
+    pub db_path: Option<String>,
+    /// PEM certificate of the server, enables TLS
+    #[arg(long, env = "ONDO_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of the server
+    #[arg(long, env = "ONDO_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    /// PEM certificate authority of the clients, enables mTLS
+    #[arg(long, env = "ONDO_TLS_CLIENT_CA")]
+    pub tls_client_ca: Option<PathBuf>,
+    /// Maximum size of a gRPC message in bytes
+    #[arg(long, env = "ONDO_MAX_MESSAGE_SIZE")]
+    pub max_message_size: Option<usize>,
+    /// Accept and send gzip compressed messages
+    #[arg(long, env = "ONDO_GZIP")]
+    pub gzip: bool,
+    /// Timeout of a request in seconds
+    #[arg(long, env = "ONDO_REQUEST_TIMEOUT_SECS")]
+    pub request_timeout_secs: Option<u64>,
+    /// Seconds running requests may take to finish when the server is stopped
+    #[arg(long, env = "ONDO_SHUTDOWN_TIMEOUT_SECS")]
+    pub shutdown_timeout_secs: Option<u64>,
+    /// Size of the RocksDB block cache in bytes
+    #[arg(long, env = "ONDO_BLOCK_CACHE_SIZE")]
+    pub block_cache_size: Option<usize>,
+    /// Maximum number of files RocksDB keeps open
+    #[arg(long, env = "ONDO_MAX_OPEN_FILES")]
+    pub max_open_files: Option<i32>,
+    /// Number of RocksDB background threads
+    #[arg(long, env = "ONDO_PARALLELISM")]
+    pub parallelism: Option<i32>,
+    /// Address of the Prometheus metrics listener
+    #[arg(long, env = "ONDO_METRICS_ADDRESS")]
+    pub metrics_address: Option<SocketAddr>,
+    /// Log level, e.g. info or info,ondo=trace
+    #[arg(long, env = "ONDO_LOG_LEVEL")]
+    pub log_level: Option<String>,
+    /// Log format
+    #[arg(long, env = "ONDO_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+}
+
+impl ServerArgs {
+    /// Reads the config file, if any, and applies the flags on top of it.
+    pub fn load_config(&self) -> Result<Config, ConfigError> {
+        let config = match &self.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        self.apply(config)
+    }
+
+    fn apply(&self, mut config: Config) -> Result<Config, ConfigError> {
+        let server = &mut config.server;
+        if let Some(address) = self.address {
+            server.address = address;
+            server.unix_socket = None;
+        }
+        if let Some(unix_socket) = &self.unix_socket {
+            server.unix_socket = Some(unix_socket.clone());
+        }
+        if self.tls_cert.is_some() || self.tls_key.is_some() || self.tls_client_ca.is_some() {
+            let tls = server.tls.take();
+            let cert = self
+                .tls_cert
+                .clone()
+                .or(tls.as_ref().map(|tls| tls.cert.clone()));
+            let key = self
+                .tls_key
+                .clone()
+                .or(tls.as_ref().map(|tls| tls.key.clone()));
+            let client_ca = self
+                .tls_client_ca
+                .clone()
+                .or(tls.and_then(|tls| tls.client_ca));
+            match (cert, key) {
+                (Some(cert), Some(key)) => {
+                    server.tls = Some(TlsConfig {
+                        cert,
+                        key,
+                        client_ca,
+                    })
+                }
+                _ => {
+                    return Err(ConfigError::Invalid(
+                        "TLS needs both a certificate and a key".to_owned(),
+                    ))
+                }
+            }
+        }
+        server.max_message_size = self.max_message_size.or(server.max_message_size);
+        server.gzip |= self.gzip;
+        server.request_timeout_secs = self.request_timeout_secs.or(server.request_timeout_secs);
+        if let Some(shutdown_timeout_secs) = self.shutdown_timeout_secs {
+            server.shutdown_timeout_secs = shutdown_timeout_secs;
+        }
+
+        let db = &mut config.db;
+        if let Some(db_path) = &self.db_path {
+            db.path = db_path.clone();
+        }
+        db.block_cache_size = self.block_cache_size.or(db.block_cache_size);
+        db.max_open_files = self.max_open_files.or(db.max_open_files);
+        db.parallelism = self.parallelism.or(db.parallelism);
+
+        config.metrics.address = self.metrics_address.or(config.metrics.address);
+
+        let log = &mut config.log;
+        if let Some(log_level) = &self.log_level {
+            log.level = log_level.clone();
+        }
+        log.format = self.log_format.unwrap_or(log.format);
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [server]
+        address = "127.0.0.1:6000"
+        max_message_size = 1024
+        gzip = true
+
+        [server.tls]
+        cert = "server.pem"
+        key = "server.key"
+
+        [db]
+        path = "/data/ondo"
+        block_cache_size = 1048576
+        parallelism = 2
+
+        [metrics]
+        address = "127.0.0.1:9090"
+
+        [log]
+        format = "text"
+    "#;
+
+    #[test]
+    fn test_default_config() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.server.address.to_string(), DEFAULT_ADDRESS);
+        assert_eq!(config.db.path, DEFAULT_DB_PATH);
+        assert_eq!(
+            config.server.shutdown_timeout(),
+            Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT_SECS)
+        );
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(config.server.address.to_string(), "127.0.0.1:6000");
+        assert_eq!(config.server.max_message_size, Some(1024));
+        assert!(config.server.gzip);
+        assert_eq!(config.server.tls.unwrap().key, PathBuf::from("server.key"));
+        assert_eq!(config.db.path, "/data/ondo");
+        assert_eq!(config.db.parallelism, Some(2));
+        assert_eq!(config.db.max_open_files, None);
+        assert_eq!(
+            config.metrics.address,
+            Some("127.0.0.1:9090".parse().unwrap())
+        );
+        assert_eq!(config.log.level, "info");
+        assert_eq!(config.log.format, LogFormat::Text);
+    }
+
+    #[test]
+    fn test_unknown_field_is_rejected() {
+        assert!(toml::from_str::<Config>("[server]\nport = 1").is_err());
+    }
+
+    #[test]
+    fn test_flags_override_config() {
+        let args = ServerArgs {
+            unix_socket: Some(PathBuf::from("/tmp/ondo.sock")),
+            tls_client_ca: Some(PathBuf::from("ca.pem")),
+            db_path: Some("/other".to_owned()),
+            max_open_files: Some(10),
+            ..Default::default()
+        };
+        let config = args.apply(toml::from_str(CONFIG).unwrap()).unwrap();
+        assert_eq!(
+            config.server.unix_socket,
+            Some(PathBuf::from("/tmp/ondo.sock"))
+        );
+        assert_eq!(
+            config.server.tls,
+            Some(TlsConfig {
+                cert: PathBuf::from("server.pem"),
+                key: PathBuf::from("server.key"),
+                client_ca: Some(PathBuf::from("ca.pem")),
+            })
+        );
+        assert_eq!(config.server.max_message_size, Some(1024));
+        assert_eq!(config.db.path, "/other");
+        assert_eq!(config.db.block_cache_size, Some(1048576));
+        assert_eq!(config.db.max_open_files, Some(10));
+    }
+
+    #[test]
+    fn test_tls_needs_cert_and_key() {
+        let args = ServerArgs {
+            tls_cert: Some(PathBuf::from("server.pem")),
+            ..Default::default()
+        };
+        assert!(args.apply(Config::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        let args = ServerArgs::try_parse_from([
+            "ondo-server",
+            "--address",
+            "127.0.0.1:7000",
+            "--request-timeout-secs",
+            "5",
+            "--log-format",
+            "json",
+        ])
+        .unwrap();
+        let config = args.apply(Config::default()).unwrap();
+        assert_eq!(config.server.address.to_string(), "127.0.0.1:7000");
+        assert_eq!(
+            config.server.request_timeout(),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(config.log.format, LogFormat::Json);
+        assert!(ServerArgs::try_parse_from([
+            "ondo-server",
+            "--address",
+            "127.0.0.1:7000",
+            "--unix-socket",
+            "/tmp/ondo.sock",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_rocksdb_options() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        assert!(config.db.rocksdb_options().is_ok());
+    }
+}
@@ -1,2 +1,6 @@
 pub const BINARY_KEY_DELIMITER: u8 = 0b1;
 pub static BINARY_KEY_DELIMITER_SLICE: &[u8] = &[BINARY_KEY_DELIMITER];
+pub const KEY_ENCODING_VERSION: u64 = 1;
+pub const CF_NAME_SCHEME_VERSION: u64 = 1;
+pub const INDEX_ENTRY_VERSION: u64 = 1;
+pub const MIGRATION_CHUNK_SIZE: usize = 1000;
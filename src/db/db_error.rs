@@ -1,7 +1,25 @@
 //db_error.rs
 use std::fmt;
 
+/// A field of a value that does not match the schema of its table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// The JSON pointer of the field, empty for the whole value.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.path.is_empty() {
+            true => write!(f, "{}", self.message),
+            false => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum DbError {
     Other(String),
     DatabaseNotInitialized,
@@ -15,6 +33,18 @@ pub enum DbError {
     SerializationError(String),
     CfNotFound,
     RocksDbError(rocksdb::Error),
+    RevisionConflict(u64, u64), // expected, actual
+    InvalidName(String),
+    UniqueConstraintViolation(String),
+    InvalidQuery(String),
+    InvalidVector(String),
+    IndexNotReady,
+    InvalidSchema(String),
+    SchemaViolation(Vec<SchemaViolation>),
+    ValuesViolateSchema(u64),
+    InvalidPatch(String),
+    PatchTestFailed(String),
+    ParallelArrays(String),
 }
 
 impl fmt::Display for DbError {
@@ -32,6 +62,32 @@ impl fmt::Display for DbError {
             DbError::SerializationError(msg) => write!(f, "Serialization error: {}", msg),
             DbError::CfNotFound => write!(f, "Column family not found"),
             DbError::RocksDbError(err) => write!(f, "RocksDbError: {}", err),
+            DbError::RevisionConflict(expected, actual) => write!(
+                f,
+                "Revision conflict: expected {}, found {}",
+                expected, actual
+            ),
+            DbError::InvalidName(msg) => write!(f, "Invalid name: {}", msg),
+            DbError::UniqueConstraintViolation(msg) => {
+                write!(f, "Unique constraint violation: {}", msg)
+            }
+            DbError::InvalidQuery(msg) => write!(f, "Invalid query: {}", msg),
+            DbError::InvalidVector(msg) => write!(f, "Invalid vector: {}", msg),
+            DbError::IndexNotReady => write!(f, "The index is being built"),
+            DbError::InvalidSchema(msg) => write!(f, "Invalid schema: {}", msg),
+            DbError::SchemaViolation(violations) => {
+                let violations = violations
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>();
+                write!(f, "Schema violation: {}", violations.join("; "))
+            }
+            DbError::ValuesViolateSchema(count) => {
+                write!(f, "{} values of the table do not match the schema", count)
+            }
+            DbError::InvalidPatch(msg) => write!(f, "Invalid patch: {}", msg),
+            DbError::PatchTestFailed(msg) => write!(f, "Patch test failed: {}", msg),
+            DbError::ParallelArrays(msg) => write!(f, "Parallel arrays: {}", msg),
         }
     }
 }
@@ -53,6 +109,18 @@ impl From<DbError> for u32 {
             DbError::SerializationError(_) => 9,
             DbError::CfNotFound => 10,
             DbError::RocksDbError(_) => 11,
+            DbError::RevisionConflict(_, _) => 12,
+            DbError::InvalidName(_) => 13,
+            DbError::UniqueConstraintViolation(_) => 14,
+            DbError::InvalidQuery(_) => 15,
+            DbError::InvalidVector(_) => 16,
+            DbError::IndexNotReady => 17,
+            DbError::InvalidSchema(_) => 18,
+            DbError::SchemaViolation(_) => 19,
+            DbError::ValuesViolateSchema(_) => 20,
+            DbError::InvalidPatch(_) => 21,
+            DbError::PatchTestFailed(_) => 22,
+            DbError::ParallelArrays(_) => 23,
         }
     }
 }
@@ -12,5 +12,15 @@ pub(crate) struct DatabaseServer {
 pub(crate) struct DatabaseServerStored {
     pub database_server: DatabaseServer,
     pub meta_revision: u64,
+    #[serde(default)]
+    pub key_encoding: u64,
+    #[serde(default)]
+    pub cf_name_scheme: u64,
+    #[serde(default)]
+    pub index_entries: u64,
+    /// The domain and table names of the last table a running migration finished,
+    /// so an interrupted migration resumes after it.
+    #[serde(default)]
+    pub migrated_table: Option<(String, String)>,
     pub domains: HashMap<String, ()>,
 }
@@ -11,9 +11,6 @@ pub(crate) struct KeyValue {
 
 impl KeyValue {
     pub fn new(key: IndexKey, value: IndexValue) -> Self {
-        Self {
-            key: key,
-            value: value,
-        }
+        Self { key, value }
     }
 }
@@ -2,12 +2,18 @@
 use crate::db::entity::table_value::get_key_from_table_value;
 use crate::db::entity::table_value::TableValue;
 use crate::db::entity::OndoKey;
+use crate::db::{DbError, DbResult};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 mod key_value;
+pub(crate) mod text;
+pub(crate) mod vector;
 
 use crate::db::reference::IndexReference;
 pub(crate) use key_value::*;
+use text::TextIndexOptions;
+use vector::{HnswOptions, VectorIndexOptions, VectorMetric};
 
 pub(crate) const DEFAULT_ID_FIELD: &str = "_id";
 
@@ -15,17 +21,37 @@ pub(crate) const DEFAULT_ID_FIELD: &str = "_id";
 pub(crate) struct Index {
     pub reference: IndexReference,
     pub fields: Vec<String>,
+    #[serde(default)]
+    pub unique: bool,
+    #[serde(default)]
+    pub kind: IndexKind,
+    /// The index is being built from the values of the table. The writes keep it up
+    /// to date meanwhile, but the reads do not use it until the build is done.
+    #[serde(default)]
+    pub building: bool,
+}
+
+/// How the documents are indexed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum IndexKind {
+    /// The keys are the values of the fields, ordered for key prefix and range lookups.
+    #[default]
+    Ordered,
+    /// The keys are the terms of the string fields, for full-text search.
+    Text(TextIndexOptions),
+    /// The keys are the ids and the values the vectors of a numeric array field,
+    /// for nearest neighbor search.
+    Vector(VectorIndexOptions),
 }
 
 pub(crate) type IndexStored = Index;
 
 impl Index {
     pub fn get_fields(&self) -> Vec<String> {
-        let my_fields = self.fields.clone();
-        my_fields
+        self.fields.clone()
     }
 
-    /// Get the index key for a given document.
+    /// Get the index keys for a given document.
     ///
     /// This function supports nested properties by splitting field names
     /// containing dots, e.g., "label.property1.property1a", and recursively
@@ -52,47 +78,204 @@ impl Index {
     /// "property2", this function will navigate the nested structure to obtain
     /// the values "value1a" and "value2" respectively. These values are combined
     /// into an `OndoKey` object, which is then returned as the index key.
-    pub fn key_of(&self, doc: &TableValue) -> IndexKey {
-        let fields = self.get_fields();
-
-        let mut values: Vec<serde_json::Value> = fields
-            .iter()
-            .map(|f: &String| {
-                let item = get_nested_property(doc, f);
-                item
+    ///
+    /// An array field makes the index multikey: the document gets one key for
+    /// each distinct element, so `"tags": ["a", "b"]` is found by "a" and by "b".
+    /// Arrays of objects on a dotted path are searched element by element, e.g.
+    /// "items.sku" on `{"items": [{"sku": 1}, {"sku": 2}]}` gives 1 and 2.
+    ///
+    /// Fails with a serialization error if the `_id` of the document is not a key.
+    pub fn keys_of(&self, doc: &TableValue) -> DbResult<Vec<IndexKey>> {
+        let ondo_key_of_doc = get_key_from_table_value(doc)?;
+        let keys = self
+            .fields_keys_of(doc)?
+            .into_iter()
+            .map(|mut key| {
+                key.values.extend(ondo_key_of_doc.values.iter().cloned());
+                key
             })
             .collect();
-        let ondo_key_of_doc = get_key_from_table_value(doc);
-        values.extend(ondo_key_of_doc.values);
+        Ok(keys)
+    }
 
-        OndoKey { values }
+    /// The values of the indexed fields, the prefixes of the index keys of the document.
+    ///
+    /// With an array field there is a key for each of its elements. At most one of the
+    /// fields may be an array, so a document has at most as many keys as the elements
+    /// of its array, otherwise it fails with ParallelArrays.
+    pub fn fields_keys_of(&self, doc: &TableValue) -> DbResult<Vec<IndexKey>> {
+        let mut keys = vec![OndoKey { values: vec![] }];
+        let mut array_field: Option<&String> = None;
+        for field in self.fields.iter() {
+            let (field_values, is_array) = nested_properties(doc, field);
+            if is_array {
+                if let Some(array_field) = array_field {
+                    return Err(DbError::ParallelArrays(format!(
+                        "index {} has the array fields {} and {}",
+                        self.reference.index_name, array_field, field
+                    )));
+                }
+                array_field = Some(field);
+            }
+            keys = keys
+                .into_iter()
+                .flat_map(|key| {
+                    field_values.iter().map(move |value| {
+                        let mut key = key.clone();
+                        key.values.push(value.clone());
+                        key
+                    })
+                })
+                .collect();
+        }
+        Ok(keys)
     }
 
-    pub(crate) fn key_value_of(&self, doc: &TableValue) -> KeyValue {
-        let key = self.key_of(doc);
-        let value = get_key_from_table_value(doc);
-        KeyValue::new(key, value)
+    /// The keys that must not be shared with another document, if the index is unique.
+    ///
+    /// Documents with a null or missing indexed field are not constrained.
+    pub fn unique_keys_of(&self, doc: &TableValue) -> DbResult<Vec<IndexKey>> {
+        if !self.unique || self.kind != IndexKind::Ordered {
+            return Ok(vec![]);
+        }
+        let keys = self
+            .fields_keys_of(doc)?
+            .into_iter()
+            .filter(|key| !key.values.iter().any(|value| value.is_null()))
+            .collect();
+        Ok(keys)
+    }
+
+    /// The entries of the document in the index.
+    pub(crate) fn key_values_of(&self, doc: &TableValue) -> DbResult<Vec<KeyValue>> {
+        let value = get_key_from_table_value(doc)?;
+        match &self.kind {
+            IndexKind::Ordered => {}
+            IndexKind::Text(options) => {
+                return Ok(text::key_values_of(doc, &self.fields, options, &value))
+            }
+            IndexKind::Vector(options) => {
+                return vector::key_values_of(doc, &self.fields, options, &value)
+            }
+        }
+        let key_values = self
+            .keys_of(doc)?
+            .into_iter()
+            .map(|key| KeyValue::new(key, value.clone()))
+            .collect();
+        Ok(key_values)
+    }
+
+    /// The id of the document of an entry of the index, None if the entry is malformed.
+    pub(crate) fn id_of_entry(&self, key: &IndexKey, value: &IndexValue) -> Option<OndoKey> {
+        match &self.kind {
+            IndexKind::Ordered => Some(value.clone()),
+            IndexKind::Text(_) => text::id_of_key(key),
+            IndexKind::Vector(_) => Some(key.clone()),
+        }
     }
+
+    /// Whether the entry is the aggregate entry of a text index, which has no document.
+    pub(crate) fn is_stats_entry(&self, key: &IndexKey) -> bool {
+        matches!(self.kind, IndexKind::Text(_)) && *key == text::stats_key()
+    }
+
+    /// The number of terms of the document, if the index is a text index.
+    pub(crate) fn text_length_of(&self, doc: &TableValue) -> Option<u64> {
+        match &self.kind {
+            IndexKind::Text(options) => Some(text::document_terms(doc, &self.fields, options).1),
+            _ => None,
+        }
+    }
+
+    /// The vector of the document, if the index is a vector index and the document has one.
+    pub(crate) fn vector_of(&self, doc: &TableValue) -> DbResult<Option<Vec<f64>>> {
+        match (&self.kind, self.fields.first()) {
+            (IndexKind::Vector(options), Some(field)) => vector::vector_of(doc, field, options),
+            _ => Ok(None),
+        }
+    }
+
+    /// The options of the HNSW graph, if the index is a vector index with a graph.
+    pub(crate) fn graph_options(&self) -> Option<(VectorMetric, HnswOptions)> {
+        match &self.kind {
+            IndexKind::Vector(options) => options.hnsw.map(|hnsw| (options.metric, hnsw)),
+            _ => None,
+        }
+    }
+}
+
+// The distinct values of a dotted field, one for each element if the field is an array.
+// A missing field is null.
+fn get_nested_properties(doc: &TableValue, field: &str) -> Vec<serde_json::Value> {
+    nested_properties(doc, field).0
 }
 
-pub(self) fn get_nested_property(doc: &TableValue, field: &str) -> serde_json::Value {
-    let mut current_value = doc;
+// The distinct values of a dotted field, and whether an array was on its path.
+fn nested_properties(doc: &TableValue, field: &str) -> (Vec<serde_json::Value>, bool) {
     let field_parts = field.split('.').collect::<Vec<&str>>();
+    let mut values = Vec::new();
+    let is_array = collect_nested_properties(doc, &field_parts, &mut values);
+    if values.is_empty() {
+        values.push(serde_json::Value::Null);
+    }
+    // The JSON text of a value is its canonical form, the keys of objects are sorted.
+    let mut seen = HashSet::new();
+    values.retain(|value| seen.insert(value.to_string()));
+    (values, is_array)
+}
 
-    for field_part in field_parts {
-        match current_value.get(field_part) {
-            Some(value) => {
-                if value.is_object() {
-                    current_value = value;
-                } else {
-                    return value.clone();
+// Returns true if an array was on the path.
+fn collect_nested_properties(
+    current_value: &serde_json::Value,
+    field_parts: &[&str],
+    values: &mut Vec<serde_json::Value>,
+) -> bool {
+    let (field_part, rest) = match field_parts.split_first() {
+        Some(split) => split,
+        None => {
+            return match current_value {
+                serde_json::Value::Array(elements) => {
+                    for element in elements {
+                        values.push(leaf_value(element));
+                    }
+                    true
                 }
+                value => {
+                    values.push(leaf_value(value));
+                    false
+                }
+            };
+        }
+    };
+    match current_value {
+        serde_json::Value::Object(object) => match object.get(*field_part) {
+            Some(value) => collect_nested_properties(value, rest, values),
+            None => {
+                values.push(serde_json::Value::Null);
+                false
+            }
+        },
+        serde_json::Value::Array(elements) => {
+            for element in elements {
+                collect_nested_properties(element, field_parts, values);
             }
-            None => return serde_json::Value::Null,
+            true
+        }
+        _ => {
+            values.push(serde_json::Value::Null);
+            false
         }
     }
+}
 
-    serde_json::Value::Null
+// Objects are not indexed by their content.
+fn leaf_value(value: &serde_json::Value) -> serde_json::Value {
+    if value.is_object() {
+        serde_json::Value::Null
+    } else {
+        value.clone()
+    }
 }
 
 #[cfg(test)]
@@ -115,18 +298,18 @@ mod tests {
     }
 
     fn sample_document() -> SampleDocument {
-        return SampleDocument {
+        SampleDocument {
             _id: OndoKey {
                 values: vec![json!(1),],
             },
             name: "John".to_owned(),
             age: 30,
             city: "New York".to_owned(),
-        };
+        }
     }
 
     fn sample_document_json() -> TableValue {
-        return json!(&sample_document());
+        json!(&sample_document())
     }
 
     fn sample_index() -> Index {
@@ -139,6 +322,9 @@ mod tests {
                 index_name: "sample_index".to_owned(),
             },
             fields: vec!["city".to_owned(), "age".to_owned()],
+            unique: false,
+            kind: IndexKind::Ordered,
+            building: false,
         }
     }
 
@@ -149,12 +335,12 @@ mod tests {
         let new_ondo_key: OndoKey = 99u64.into();
         insert_key_into_table_value(&mut doc, &new_ondo_key);
 
-        let key = index.key_of(&doc);
+        let keys = index.keys_of(&doc).unwrap();
         assert_eq!(
-            key,
-            OndoKey {
+            keys,
+            vec![OndoKey {
                 values: vec![json!("New York"), json!(30), json!(99),],
-            }
+            }]
         );
     }
     #[test]
@@ -164,7 +350,9 @@ mod tests {
         let new_ondo_key: OndoKey = 99u64.into();
         insert_key_into_table_value(&mut doc, &new_ondo_key);
 
-        let key_value = index.key_value_of(&doc);
+        let mut key_values = index.key_values_of(&doc).unwrap();
+        assert_eq!(key_values.len(), 1);
+        let key_value = key_values.remove(0);
         let key = key_value.key;
         let value = key_value.value;
         assert_eq!(
@@ -197,31 +385,31 @@ mod tests {
     fn test_key_of() {
         let index = sample_index();
         let doc = sample_document_json();
-        let existing_key = index.key_of(&doc);
+        let existing_keys = index.keys_of(&doc).unwrap();
         let expected_key = OndoKey {
             values: vec![json!("New York"), json!(30), json!(1)],
         };
 
 
-        assert_eq!(existing_key, expected_key);
+        assert_eq!(existing_keys, vec![expected_key]);
     }
 
     #[test]
-    fn test_get_nested_property_single_level() {
+    fn test_get_nested_properties_single_level() {
         let doc = json!({
             "name": "John Doe",
             "age": 30
         });
 
-        let value = get_nested_property(&doc, "name");
-        assert_eq!(value, json!("John Doe"));
+        let values = get_nested_properties(&doc, "name");
+        assert_eq!(values, vec![json!("John Doe")]);
 
-        let value = get_nested_property(&doc, "age");
-        assert_eq!(value, json!(30));
+        let values = get_nested_properties(&doc, "age");
+        assert_eq!(values, vec![json!(30)]);
     }
 
     #[test]
-    fn test_get_nested_property_multi_level() {
+    fn test_get_nested_properties_multi_level() {
         let doc = json!({
             "name": "John Doe",
             "age": 30,
@@ -231,15 +419,15 @@ mod tests {
             }
         });
 
-        let value = get_nested_property(&doc, "address.city");
-        assert_eq!(value, json!("New York"));
+        let values = get_nested_properties(&doc, "address.city");
+        assert_eq!(values, vec![json!("New York")]);
 
-        let value = get_nested_property(&doc, "address.country");
-        assert_eq!(value, json!("USA"));
+        let values = get_nested_properties(&doc, "address.country");
+        assert_eq!(values, vec![json!("USA")]);
     }
 
     #[test]
-    fn test_get_nested_property_nonexistent() {
+    fn test_get_nested_properties_nonexistent() {
         let doc = json!({
             "name": "John Doe",
             "age": 30,
@@ -249,15 +437,15 @@ mod tests {
             }
         });
 
-        let value = get_nested_property(&doc, "nonexistent");
-        assert_eq!(value, serde_json::Value::Null);
+        let values = get_nested_properties(&doc, "nonexistent");
+        assert_eq!(values, vec![serde_json::Value::Null]);
 
-        let value = get_nested_property(&doc, "address.nonexistent");
-        assert_eq!(value, serde_json::Value::Null);
+        let values = get_nested_properties(&doc, "address.nonexistent");
+        assert_eq!(values, vec![serde_json::Value::Null]);
     }
 
     #[test]
-    fn test_get_nested_property_deeply_nested() {
+    fn test_get_nested_properties_deeply_nested() {
         let doc = json!({
             "person": {
                 "name": "John Doe",
@@ -269,10 +457,85 @@ mod tests {
             }
         });
 
-        let value = get_nested_property(&doc, "person.name");
-        assert_eq!(value, json!("John Doe"));
+        let values = get_nested_properties(&doc, "person.name");
+        assert_eq!(values, vec![json!("John Doe")]);
+
+        let values = get_nested_properties(&doc, "person.address.city");
+        assert_eq!(values, vec![json!("New York")]);
+    }
+
+    #[test]
+    fn test_get_nested_properties_array() {
+        let doc = json!({
+            "tags": ["a", "b", "a"],
+            "empty": [],
+            "items": [{"sku": 1, "tags": ["x"]}, {"sku": 2, "tags": ["x", "y"]}, {"other": 3}]
+        });
+
+        assert_eq!(
+            get_nested_properties(&doc, "tags"),
+            vec![json!("a"), json!("b")]
+        );
+        assert_eq!(get_nested_properties(&doc, "empty"), vec![json!(null)]);
+        assert_eq!(
+            get_nested_properties(&doc, "items.sku"),
+            vec![json!(1), json!(2), json!(null)]
+        );
+        assert_eq!(
+            get_nested_properties(&doc, "items.tags"),
+            vec![json!("x"), json!("y"), json!(null)]
+        );
+    }
+
+    #[test]
+    fn test_keys_of_multikey_index() {
+        let mut index = sample_index();
+        index.fields = vec!["tags".to_owned(), "age".to_owned()];
+        let doc = json!({"_id": {"values": [7]}, "tags": ["a", "b"], "age": 30});
+
+        assert_eq!(
+            index.keys_of(&doc).unwrap(),
+            vec![
+                OndoKey {
+                    values: vec![json!("a"), json!(30), json!(7)],
+                },
+                OndoKey {
+                    values: vec![json!("b"), json!(30), json!(7)],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unique_keys_of() {
+        let mut index = sample_index();
+        index.fields = vec!["tags".to_owned()];
+        let doc = json!({"_id": {"values": [7]}, "tags": ["a", "b"]});
+        assert_eq!(index.unique_keys_of(&doc), Ok(vec![]));
+
+        index.unique = true;
+        assert_eq!(
+            index.unique_keys_of(&doc),
+            Ok(vec![OndoKey::from("a"), OndoKey::from("b")])
+        );
+        assert_eq!(index.unique_keys_of(&json!({"tags": []})), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_keys_of_parallel_arrays() {
+        let mut index = sample_index();
+        index.fields = vec!["tags".to_owned(), "items.sku".to_owned()];
+        let doc = json!({
+            "_id": {"values": [7]},
+            "tags": ["a", "b"],
+            "items": [{"sku": 1}, {"sku": 2}]
+        });
+        assert!(matches!(
+            index.keys_of(&doc),
+            Err(DbError::ParallelArrays(_))
+        ));
 
-        let value = get_nested_property(&doc, "person.address.city");
-        assert_eq!(value, json!("New York"));
+        let doc = json!({"_id": {"values": [7]}, "tags": ["a", "b"], "items": {"sku": 1}});
+        assert_eq!(index.keys_of(&doc).unwrap().len(), 2);
     }
 }
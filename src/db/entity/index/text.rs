@@ -0,0 +1,335 @@
+//text.rs
+//! Entries of the full-text indexes.
+//!
+//! A text index keeps two kinds of entries in its column family:
+//! `["t", term, ...id]` holds the positions of the term in the document `id`, and
+//! `["d", ...id]` holds the number of terms of the document.
+//! The aggregate entry `["s"]` holds the number of documents and their total number of
+//! terms, so a search does not read the entries of every document.
+use super::{get_nested_properties, IndexKey, IndexValue, KeyValue};
+use crate::db::entity::{OndoKey, TableValue};
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct TextIndexOptions {
+    /// Reduces the English words to their stems, so "runs" matches "running".
+    pub stemming: bool,
+    /// Leaves the common English words like "the" out of the index.
+    pub stop_words: bool,
+}
+
+const POSTING_TAG: &str = "t";
+const DOCUMENT_TAG: &str = "d";
+const STATS_TAG: &str = "s";
+
+// The positions of consecutive strings are this far apart, so phrases do not span them.
+const POSITION_GAP: u64 = 100;
+
+const STOP_WORDS: [&str; 33] = [
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+/// Splits texts into terms the same way for the documents and the queries.
+pub(crate) struct Analyzer {
+    stemmer: Option<Stemmer>,
+    stop_words: bool,
+}
+
+impl Analyzer {
+    pub fn new(options: &TextIndexOptions) -> Self {
+        Analyzer {
+            stemmer: options
+                .stemming
+                .then(|| Stemmer::create(Algorithm::English)),
+            stop_words: options.stop_words,
+        }
+    }
+
+    /// The lowercased Unicode words of the text with their positions.
+    /// Stop words are left out but keep their positions.
+    pub fn analyze(&self, text: &str) -> Vec<(String, u64)> {
+        text.unicode_words()
+            .enumerate()
+            .filter_map(|(position, word)| {
+                let word = word.to_lowercase();
+                if self.stop_words && STOP_WORDS.contains(&word.as_str()) {
+                    return None;
+                }
+                let term = match &self.stemmer {
+                    Some(stemmer) => stemmer.stem(&word).into_owned(),
+                    None => word,
+                };
+                Some((term, position as u64))
+            })
+            .collect()
+    }
+}
+
+/// The number of documents of a text index and their total number of terms.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TextIndexStats {
+    pub document_count: u64,
+    pub total_length: u64,
+}
+
+impl TextIndexStats {
+    pub fn add(&mut self, length: u64) {
+        self.document_count += 1;
+        self.total_length += length;
+    }
+
+    pub fn remove(&mut self, length: u64) {
+        self.document_count = self.document_count.saturating_sub(1);
+        self.total_length = self.total_length.saturating_sub(length);
+    }
+
+    /// The average number of terms of the documents, at least 1.
+    pub fn average_length(&self) -> f64 {
+        (self.total_length as f64 / self.document_count as f64).max(1.0)
+    }
+}
+
+/// The positions of the terms in the string values of the fields, and the number of terms.
+pub(crate) fn document_terms(
+    doc: &TableValue,
+    fields: &[String],
+    options: &TextIndexOptions,
+) -> (BTreeMap<String, Vec<u64>>, u64) {
+    let analyzer = Analyzer::new(options);
+    let mut terms: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+    let mut length = 0;
+    let mut offset = 0;
+    for field in fields {
+        for value in get_nested_properties(doc, field) {
+            let text = match value.as_str() {
+                Some(text) => text,
+                None => continue,
+            };
+            let mut end = offset;
+            for (term, position) in analyzer.analyze(text) {
+                terms.entry(term).or_default().push(offset + position);
+                length += 1;
+                end = offset + position + 1;
+            }
+            offset = end + POSITION_GAP;
+        }
+    }
+    (terms, length)
+}
+
+pub(crate) fn key_values_of(
+    doc: &TableValue,
+    fields: &[String],
+    options: &TextIndexOptions,
+    id: &OndoKey,
+) -> Vec<KeyValue> {
+    let (terms, length) = document_terms(doc, fields, options);
+    let mut key_values = terms
+        .into_iter()
+        .map(|(term, positions)| {
+            let positions = positions.into_iter().map(|position| json!(position));
+            KeyValue::new(
+                posting_key(&term, id),
+                OndoKey {
+                    values: positions.collect(),
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+    key_values.push(KeyValue::new(
+        document_key(id),
+        OndoKey {
+            values: vec![json!(length)],
+        },
+    ));
+    key_values
+}
+
+/// The prefix of the entries of the documents containing the term.
+pub(crate) fn posting_prefix(term: &str) -> IndexKey {
+    OndoKey {
+        values: vec![json!(POSTING_TAG), json!(term)],
+    }
+}
+
+fn posting_key(term: &str, id: &OndoKey) -> IndexKey {
+    let mut key = posting_prefix(term);
+    key.values.extend(id.values.iter().cloned());
+    key
+}
+
+/// The prefix of the entries holding the number of terms of every document.
+pub(crate) fn documents_prefix() -> IndexKey {
+    OndoKey {
+        values: vec![json!(DOCUMENT_TAG)],
+    }
+}
+
+/// The id of the document of an entry of the index.
+pub(crate) fn id_of_key(key: &IndexKey) -> Option<OndoKey> {
+    let id_start = match key.values.first()?.as_str()? {
+        POSTING_TAG => 2,
+        DOCUMENT_TAG => 1,
+        _ => return None,
+    };
+    let values = key.values.get(id_start..)?;
+    match values.is_empty() {
+        true => None,
+        false => Some(OndoKey {
+            values: values.to_vec(),
+        }),
+    }
+}
+
+/// The key of the entry holding the number of terms of the document.
+pub(crate) fn document_key(id: &OndoKey) -> IndexKey {
+    let mut key = documents_prefix();
+    key.values.extend(id.values.iter().cloned());
+    key
+}
+
+/// Whether the entry holds the number of terms of a document.
+pub(crate) fn is_document_key(key: &IndexKey) -> bool {
+    key.values.len() > 1 && key.values[0] == json!(DOCUMENT_TAG)
+}
+
+/// The key of the aggregate entry of the index.
+pub(crate) fn stats_key() -> IndexKey {
+    OndoKey {
+        values: vec![json!(STATS_TAG)],
+    }
+}
+
+pub(crate) fn stats_value(stats: &TextIndexStats) -> IndexValue {
+    OndoKey {
+        values: vec![json!(stats.document_count), json!(stats.total_length)],
+    }
+}
+
+pub(crate) fn parse_stats(value: &IndexValue) -> TextIndexStats {
+    let count = |i: usize| value.values.get(i).and_then(|v| v.as_u64()).unwrap_or(0);
+    TextIndexStats {
+        document_count: count(0),
+        total_length: count(1),
+    }
+}
+
+/// The document id and the term positions of a posting entry.
+pub(crate) fn parse_posting(key: &IndexKey, value: &IndexValue) -> (OndoKey, Vec<u64>) {
+    let id = OndoKey {
+        values: key.values.iter().skip(2).cloned().collect(),
+    };
+    let positions = value.values.iter().filter_map(|v| v.as_u64()).collect();
+    (id, positions)
+}
+
+/// The document id and the number of terms of a document entry.
+pub(crate) fn parse_document(key: &IndexKey, value: &IndexValue) -> (OndoKey, u64) {
+    let id = OndoKey {
+        values: key.values.iter().skip(1).cloned().collect(),
+    };
+    let length = value.values.first().and_then(|v| v.as_u64()).unwrap_or(0);
+    (id, length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(stemming: bool, stop_words: bool) -> TextIndexOptions {
+        TextIndexOptions {
+            stemming,
+            stop_words,
+        }
+    }
+
+    #[test]
+    fn test_analyze() {
+        let analyzer = Analyzer::new(&options(false, false));
+        assert_eq!(
+            analyzer.analyze("The Quick, brown fox's café!"),
+            vec![
+                ("the".to_owned(), 0),
+                ("quick".to_owned(), 1),
+                ("brown".to_owned(), 2),
+                ("fox's".to_owned(), 3),
+                ("café".to_owned(), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_with_stemming_and_stop_words() {
+        let analyzer = Analyzer::new(&options(true, true));
+        assert_eq!(
+            analyzer.analyze("The foxes are running"),
+            vec![("fox".to_owned(), 1), ("run".to_owned(), 3)]
+        );
+    }
+
+    #[test]
+    fn test_key_values_of() {
+        let doc = json!({"title": "Red fox", "tags": ["fox", "dog"], "age": 3});
+        let fields = vec!["title".to_owned(), "tags".to_owned(), "age".to_owned()];
+        let id: OndoKey = 7u64.into();
+        let key_values = key_values_of(&doc, &fields, &options(false, false), &id);
+
+        let entries = key_values
+            .iter()
+            .map(|key_value| (key_value.key.values.clone(), key_value.value.values.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (vec![json!("t"), json!("dog"), json!(7)], vec![json!(203)]),
+                (
+                    vec![json!("t"), json!("fox"), json!(7)],
+                    vec![json!(1), json!(102)]
+                ),
+                (vec![json!("t"), json!("red"), json!(7)], vec![json!(0)]),
+                (vec![json!("d"), json!(7)], vec![json!(4)]),
+            ]
+        );
+        assert_eq!(
+            parse_posting(&key_values[1].key, &key_values[1].value),
+            (id.clone(), vec![1, 102])
+        );
+        assert_eq!(
+            parse_document(&key_values[3].key, &key_values[3].value),
+            (id.clone(), 4)
+        );
+        for key_value in key_values.iter() {
+            assert_eq!(id_of_key(&key_value.key), Some(id.clone()));
+        }
+        assert_eq!(id_of_key(&posting_prefix("fox")), None);
+        assert_eq!(id_of_key(&stats_key()), None);
+        assert!(is_document_key(&key_values[3].key));
+        assert!(!is_document_key(&key_values[0].key));
+        assert!(!is_document_key(&documents_prefix()));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut stats = TextIndexStats::default();
+        assert_eq!(stats.average_length(), 1.0);
+        stats.add(4);
+        stats.add(2);
+        stats.remove(4);
+        stats.add(6);
+        assert_eq!(
+            stats,
+            TextIndexStats {
+                document_count: 2,
+                total_length: 8
+            }
+        );
+        assert_eq!(stats.average_length(), 4.0);
+        assert_eq!(parse_stats(&stats_value(&stats)), stats);
+    }
+}
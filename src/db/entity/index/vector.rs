@@ -0,0 +1,200 @@
+//vector.rs
+//! Entries of the vector indexes.
+//!
+//! A vector index keeps the vector of every document in its column family, keyed by
+//! the id of the document. With HNSW options the index also keeps a graph in a
+//! column family of its own, see reference::hnsw.
+use super::KeyValue;
+use crate::db::entity::{OndoKey, TableValue};
+use crate::db::{DbError, DbResult};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct VectorIndexOptions {
+    /// The number of elements of the vectors.
+    pub dimension: usize,
+    pub metric: VectorMetric,
+    /// Keeps an HNSW graph for approximate search, the search is exact without it.
+    pub hnsw: Option<HnswOptions>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum VectorMetric {
+    Cosine,
+    DotProduct,
+    L2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct HnswOptions {
+    /// The number of neighbors of a node on the upper layers, twice as many on layer 0.
+    pub m: usize,
+    /// The number of candidates considered for the neighbors of an inserted node.
+    pub ef_construction: usize,
+}
+
+impl Default for HnswOptions {
+    fn default() -> Self {
+        HnswOptions {
+            m: 16,
+            ef_construction: 200,
+        }
+    }
+}
+
+impl VectorMetric {
+    /// The distance of two vectors of the same dimension, smaller is closer.
+    ///
+    /// Cosine gives 1 - the cosine similarity, 1 if a vector is zero.
+    /// DotProduct gives the negated dot product and L2 the Euclidean distance.
+    pub fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        let dot = || a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>();
+        match self {
+            VectorMetric::Cosine => {
+                let norms = norm(a) * norm(b);
+                if norms == 0.0 {
+                    1.0
+                } else {
+                    1.0 - dot() / norms
+                }
+            }
+            VectorMetric::DotProduct => -dot(),
+            VectorMetric::L2 => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f64>()
+                .sqrt(),
+        }
+    }
+}
+
+fn norm(a: &[f64]) -> f64 {
+    a.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+/// Fails if the vector does not have the dimension of the index or is not finite.
+pub(crate) fn check_vector(vector: &[f64], options: &VectorIndexOptions) -> DbResult<()> {
+    if !vector.iter().all(|x| x.is_finite()) {
+        return Err(DbError::InvalidVector(
+            "the vector has an element that is not finite".to_owned(),
+        ));
+    }
+    if vector.len() != options.dimension {
+        return Err(DbError::InvalidVector(format!(
+            "the vector has {} elements instead of {}",
+            vector.len(),
+            options.dimension
+        )));
+    }
+    Ok(())
+}
+
+/// The vector in the field of the document, None if the field is null or missing.
+///
+/// Fails if the field is not an array of numbers with the dimension of the index.
+pub(crate) fn vector_of(
+    doc: &TableValue,
+    field: &str,
+    options: &VectorIndexOptions,
+) -> DbResult<Option<Vec<f64>>> {
+    let value = field
+        .split('.')
+        .try_fold(doc, |value, part| value.get(part))
+        .unwrap_or(&Value::Null);
+    if value.is_null() {
+        return Ok(None);
+    }
+    let vector = value
+        .as_array()
+        .and_then(|array| array.iter().map(|v| v.as_f64()).collect::<Option<Vec<_>>>())
+        .ok_or_else(|| {
+            DbError::InvalidVector(format!("the field {} is not an array of numbers", field))
+        })?;
+    check_vector(&vector, options)?;
+    Ok(Some(vector))
+}
+
+pub(crate) fn key_values_of(
+    doc: &TableValue,
+    fields: &[String],
+    options: &VectorIndexOptions,
+    id: &OndoKey,
+) -> DbResult<Vec<KeyValue>> {
+    let vector = match fields.first() {
+        Some(field) => vector_of(doc, field, options)?,
+        None => None,
+    };
+    let key_values = vector
+        .map(|vector| KeyValue::new(id.clone(), vector_value(&vector)))
+        .into_iter()
+        .collect();
+    Ok(key_values)
+}
+
+pub(crate) fn vector_value(vector: &[f64]) -> OndoKey {
+    OndoKey {
+        values: vector.iter().map(|x| json!(x)).collect(),
+    }
+}
+
+pub(crate) fn parse_vector(values: &[Value]) -> Vec<f64> {
+    values.iter().filter_map(|v| v.as_f64()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(dimension: usize) -> VectorIndexOptions {
+        VectorIndexOptions {
+            dimension,
+            metric: VectorMetric::L2,
+            hnsw: None,
+        }
+    }
+
+    #[test]
+    fn test_distance() {
+        let a = [1.0, 0.0];
+        let b = [3.0, 4.0];
+        assert_eq!(VectorMetric::L2.distance(&a, &b), (4.0f64 + 16.0).sqrt());
+        assert_eq!(VectorMetric::DotProduct.distance(&a, &b), -3.0);
+        assert!((VectorMetric::Cosine.distance(&a, &b) - 0.4).abs() < 1e-12);
+        assert_eq!(VectorMetric::Cosine.distance(&a, &[0.0, 0.0]), 1.0);
+    }
+
+    #[test]
+    fn test_vector_of() {
+        let doc = json!({"a": {"embedding": [1, 2.5, -3]}, "b": [1, "x", 3], "c": null});
+        assert_eq!(
+            vector_of(&doc, "a.embedding", &options(3)),
+            Ok(Some(vec![1.0, 2.5, -3.0]))
+        );
+        assert_eq!(vector_of(&doc, "c", &options(3)), Ok(None));
+        assert_eq!(vector_of(&doc, "a.missing", &options(3)), Ok(None));
+        assert!(matches!(
+            vector_of(&doc, "a.embedding", &options(2)),
+            Err(DbError::InvalidVector(_))
+        ));
+        assert!(matches!(
+            vector_of(&doc, "b", &options(3)),
+            Err(DbError::InvalidVector(_))
+        ));
+    }
+
+    #[test]
+    fn test_key_values_of() {
+        let doc = json!({"embedding": [1, 2]});
+        let id: OndoKey = 7u64.into();
+        let fields = vec!["embedding".to_owned()];
+        let key_values = key_values_of(&doc, &fields, &options(2), &id).unwrap();
+        assert_eq!(key_values.len(), 1);
+        assert_eq!(key_values[0].key, id);
+        assert_eq!(parse_vector(&key_values[0].value.values), vec![1.0, 2.0]);
+        assert!(key_values_of(&json!({}), &fields, &options(2), &id)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -13,8 +13,12 @@ pub(crate) use table::*;
 pub(crate) mod index;
 pub(crate) use index::*;
 
+pub(crate) mod table_schema;
+
 pub(crate) mod table_value;
 pub(crate) use table_value::*;
 
+pub(crate) mod value_patch;
+
 pub(crate) mod ondo_key;
 pub(crate) use ondo_key::*;
@@ -1,4 +1,5 @@
 //ondo_key.rs
+use crate::db::{DbError, DbResult};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -11,33 +12,34 @@ pub(crate) struct OndoKey {
 
 impl OndoKey {
     pub(crate) fn to_value(&self) -> serde_json::Value {
-        serde_json::to_value(&self).unwrap()
+        serde_json::to_value(self).unwrap()
     }
 
-    pub(crate) fn from_value(value: &serde_json::Value) -> OndoKey {
-        serde_json::from_value(value.clone()).unwrap()
+    pub(crate) fn from_value(value: &serde_json::Value) -> DbResult<OndoKey> {
+        serde_json::from_value(value.clone())
+            .map_err(|err| DbError::SerializationError(format!("Invalid key {}: {}", value, err)))
     }
 }
 
-impl Into<OndoKey> for u64 {
-    fn into(self) -> OndoKey {
-        let value = Value::Number(self.into());
+impl From<u64> for OndoKey {
+    fn from(val: u64) -> Self {
+        let value = Value::Number(val.into());
         let values = vec![value];
         OndoKey { values }
     }
 }
 
-impl Into<OndoKey> for &str {
-    fn into(self) -> OndoKey {
-        let value = Value::String(self.into());
+impl From<&str> for OndoKey {
+    fn from(val: &str) -> Self {
+        let value = Value::String(val.into());
         let values = vec![value];
         OndoKey { values }
     }
 }
 
-impl Into<OndoKey> for String {
-    fn into(self) -> OndoKey {
-        let value = Value::String(self.into());
+impl From<String> for OndoKey {
+    fn from(val: String) -> Self {
+        let value = Value::String(val);
         let values = vec![value];
         OndoKey { values }
     }
@@ -7,6 +7,9 @@ use std::collections::HashMap;
 #[derive(PartialEq, Eq, Clone, Debug, Deserialize, Serialize)]
 pub(crate) struct Table {
     pub reference: TableReference,
+    /// The JSON Schema the values must match, see table_schema.
+    #[serde(default)]
+    pub schema: Option<serde_json::Value>,
 }
 
 #[derive(PartialEq, Eq, Clone, Debug, Deserialize, Serialize)]
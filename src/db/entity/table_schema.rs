@@ -0,0 +1,171 @@
+//table_schema.rs
+//! The JSON Schemas of the tables, of draft 2020-12.
+//!
+//! The values are validated without their _id and _revision fields, which the database
+//! sets, so a schema does not need to list them even if it forbids additional properties.
+//!
+//! The writes validate the values with the compiled schemas of their tables, which each
+//! database keeps until the schema of the table changes.
+use crate::db::entity::index::DEFAULT_ID_FIELD;
+use crate::db::entity::table_value::DEFAULT_REVISION_FIELD;
+use crate::db::entity::TableValue;
+use crate::db::reference::TableReference;
+use crate::db::{DbError, DbResult, SchemaViolation};
+use jsonschema::{Draft, JSONSchema};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Compiles the schema, failing with InvalidSchema if it is not a valid JSON Schema.
+pub(crate) fn compile_schema(schema: &Value) -> DbResult<JSONSchema> {
+    JSONSchema::options()
+        .with_draft(Draft::Draft202012)
+        .compile(schema)
+        .map_err(|err| DbError::InvalidSchema(err.to_string()))
+}
+
+/// The violations of the schema by the value, with the JSON pointers of their fields.
+pub(crate) fn schema_violations(schema: &JSONSchema, value: &TableValue) -> Vec<SchemaViolation> {
+    let mut value = value.clone();
+    if let Some(object) = value.as_object_mut() {
+        object.remove(DEFAULT_ID_FIELD);
+        object.remove(DEFAULT_REVISION_FIELD);
+    }
+    let violations = match schema.validate(&value) {
+        Ok(()) => return vec![],
+        Err(errors) => errors,
+    };
+    violations
+        .map(|err| SchemaViolation {
+            path: err.instance_path.to_string(),
+            message: err.to_string(),
+        })
+        .collect()
+}
+
+type CompiledSchemas = HashMap<TableReference, (Value, Arc<JSONSchema>)>;
+
+/// The compiled schemas of the tables of a database, with the schemas they were compiled from.
+#[derive(Default)]
+pub(crate) struct TableSchemas {
+    compiled: Mutex<CompiledSchemas>,
+}
+
+impl TableSchemas {
+    fn lock(&self) -> MutexGuard<'_, CompiledSchemas> {
+        self.compiled.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// The compiled schema of the table. The schema is compiled again only if it is not
+    /// the one the table had when it was last compiled.
+    pub(crate) fn compiled(
+        &self,
+        table_reference: &TableReference,
+        schema: &Value,
+    ) -> DbResult<Arc<JSONSchema>> {
+        if let Some((compiled_from, compiled)) = self.lock().get(table_reference) {
+            if compiled_from == schema {
+                return Ok(compiled.clone());
+            }
+        }
+        let compiled = Arc::new(compile_schema(schema)?);
+        self.lock()
+            .insert(table_reference.clone(), (schema.clone(), compiled.clone()));
+        Ok(compiled)
+    }
+
+    /// Drops the compiled schema of the table, when its schema changes or it is deleted.
+    pub(crate) fn forget(&self, table_reference: &TableReference) {
+        self.lock().remove(table_reference);
+    }
+}
+
+/// Fails with SchemaViolation if the value does not match the schema.
+pub(crate) fn validate_table_value(schema: &JSONSchema, value: &TableValue) -> DbResult<()> {
+    let violations = schema_violations(schema, value);
+    match violations.is_empty() {
+        true => Ok(()),
+        false => Err(DbError::SchemaViolation(violations)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn person_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "age": {"type": "integer", "minimum": 0},
+                "tags": {"type": "array", "items": {"type": "string"}}
+            },
+            "required": ["name"],
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn test_valid_value() {
+        let value = json!({"_id": {"values": [1]}, "_revision": 2, "name": "John", "age": 30});
+        let schema = compile_schema(&person_schema()).unwrap();
+        assert_eq!(validate_table_value(&schema, &value), Ok(()));
+    }
+
+    #[test]
+    fn test_compiled_table_schema() {
+        let table_schemas = TableSchemas::default();
+        let table_reference = TableReference::build("schema_domain", "compiled_table");
+        let compiled = table_schemas
+            .compiled(&table_reference, &person_schema())
+            .unwrap();
+        let again = table_schemas
+            .compiled(&table_reference, &person_schema())
+            .unwrap();
+        assert!(Arc::ptr_eq(&compiled, &again));
+
+        let changed = table_schemas
+            .compiled(&table_reference, &json!({"type": "object"}))
+            .unwrap();
+        assert!(!Arc::ptr_eq(&compiled, &changed));
+        assert!(changed.is_valid(&json!({"age": -1})));
+
+        table_schemas.forget(&table_reference);
+        let recompiled = table_schemas
+            .compiled(&table_reference, &json!({"type": "object"}))
+            .unwrap();
+        assert!(!Arc::ptr_eq(&changed, &recompiled));
+
+        let other_schemas = TableSchemas::default();
+        let other = other_schemas
+            .compiled(&table_reference, &json!({"type": "object"}))
+            .unwrap();
+        assert!(!Arc::ptr_eq(&recompiled, &other));
+    }
+
+    #[test]
+    fn test_schema_violations() {
+        let schema = compile_schema(&person_schema()).unwrap();
+        let value = json!({"name": "John", "age": -1, "tags": ["a", 2]});
+
+        let paths = schema_violations(&schema, &value)
+            .into_iter()
+            .map(|violation| violation.path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths.len(), 2);
+        assert!(paths.contains(&"/age".to_owned()));
+        assert!(paths.contains(&"/tags/1".to_owned()));
+
+        let violations = schema_violations(&schema, &json!({"age": 3, "city": "Paris"}));
+        assert_eq!(violations.len(), 2);
+        assert!(violations.iter().all(|violation| violation.path.is_empty()));
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        let result = compile_schema(&json!({"type": "no-such-type"}));
+        assert!(matches!(result, Err(DbError::InvalidSchema(_))));
+    }
+}
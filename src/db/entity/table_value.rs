@@ -7,24 +7,30 @@ use crate::db::entity::index::DEFAULT_ID_FIELD;
 
 pub(crate) type TableValue = serde_json::Value;
 
+pub(crate) const DEFAULT_REVISION_FIELD: &str = "_revision";
+
 pub(crate) fn do_index_table_value(value: &TableValue, the_index: &Index) -> DbResult<Effects> {
-    let key_value = the_index.key_value_of(&value);
-    let index_value_reference = IndexValueReference {
-        index_reference: the_index.reference.clone(),
-        key: key_value.key,
-    };
-    let r_index_value_effects = index_value_reference.put_index_value(&key_value.value);
-    r_index_value_effects
+    let mut effects = Vec::new();
+    for key_value in the_index.key_values_of(value)? {
+        let index_value_reference = IndexValueReference {
+            index_reference: the_index.reference.clone(),
+            key: key_value.key,
+        };
+        effects.extend(index_value_reference.put_index_value(&key_value.value)?);
+    }
+    Ok(effects)
 }
 
 pub(crate) fn do_deindex_table_value(value: &TableValue, the_index: &Index) -> DbResult<Effects> {
-    let key_value = the_index.key_value_of(&value);
-    let index_value_reference = IndexValueReference {
-        index_reference: the_index.reference.clone(),
-        key: key_value.key,
-    };
-    let r_index_value_effects = index_value_reference.delete_index_value();
-    r_index_value_effects
+    let mut effects = Vec::new();
+    for key_value in the_index.key_values_of(value)? {
+        let index_value_reference = IndexValueReference {
+            index_reference: the_index.reference.clone(),
+            key: key_value.key,
+        };
+        effects.extend(index_value_reference.delete_index_value()?);
+    }
+    Ok(effects)
 }
 
 pub(crate) fn insert_key_into_table_value(value: &mut TableValue, new_ondo_key: &OndoKey) {
@@ -36,9 +42,19 @@ pub(crate) fn insert_key_into_table_value(value: &mut TableValue, new_ondo_key:
     }
 }
 
-pub(crate) fn get_key_from_table_value(value: &TableValue) -> OndoKey {
-    let id_value = value[DEFAULT_ID_FIELD].clone();
-    serde_json::from_value(id_value).unwrap()
+pub(crate) fn get_key_from_table_value(value: &TableValue) -> DbResult<OndoKey> {
+    OndoKey::from_value(&value[DEFAULT_ID_FIELD])
+}
+
+pub(crate) fn insert_revision_into_table_value(value: &mut TableValue, revision: u64) {
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert(DEFAULT_REVISION_FIELD.to_owned(), revision.into());
+    }
+}
+
+/// Values stored before revisions were introduced are at revision 0.
+pub(crate) fn get_revision_from_table_value(value: &TableValue) -> u64 {
+    value[DEFAULT_REVISION_FIELD].as_u64().unwrap_or(0)
 }
 
 #[cfg(test)]
@@ -56,7 +72,7 @@ mod tests {
         insert_key_into_table_value(&mut table_value, &ondo_key);
 
         // 3. Get OndoKey from TableValue
-        let retrieved_ondo_key = get_key_from_table_value(&table_value);
+        let retrieved_ondo_key = get_key_from_table_value(&table_value).unwrap();
 
         // 4. Assert that the OndoKey is the same as the one that was inserted
         assert_eq!(ondo_key, retrieved_ondo_key, "OndoKeys are not the same");
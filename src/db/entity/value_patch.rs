@@ -0,0 +1,126 @@
+//value_patch.rs
+//! Partial updates of the table values.
+//!
+//! A merge patch (RFC 7396) is a document with the fields to set, null removing a field.
+//! A JSON Patch (RFC 6902) is a list of operations on JSON pointers; its `test` operations
+//! make the update conditional on the stored value.
+use crate::db::entity::index::DEFAULT_ID_FIELD;
+use crate::db::entity::TableValue;
+use crate::db::{DbError, DbResult};
+use json_patch::{Patch, PatchErrorKind};
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ValuePatch {
+    Merge(Value),
+    Json(Patch),
+}
+
+impl ValuePatch {
+    /// Parses the operations of a JSON Patch, failing with InvalidPatch if they are malformed.
+    pub fn json(operations: Value) -> DbResult<Self> {
+        serde_json::from_value(operations)
+            .map(ValuePatch::Json)
+            .map_err(|err| DbError::InvalidPatch(err.to_string()))
+    }
+
+    /// The patched value. A failed `test` operation fails with PatchTestFailed, the other
+    /// failures and a change of the _id field with InvalidPatch.
+    pub fn apply(&self, value: &TableValue) -> DbResult<TableValue> {
+        let mut patched = value.clone();
+        match self {
+            ValuePatch::Merge(patch) => json_patch::merge(&mut patched, patch),
+            ValuePatch::Json(patch) => {
+                json_patch::patch(&mut patched, patch).map_err(|err| match err.kind {
+                    PatchErrorKind::TestFailed => DbError::PatchTestFailed(err.to_string()),
+                    _ => DbError::InvalidPatch(err.to_string()),
+                })?
+            }
+        }
+        if patched.get(DEFAULT_ID_FIELD) != value.get(DEFAULT_ID_FIELD) {
+            return Err(DbError::InvalidPatch(format!(
+                "the patch can not change the {} field",
+                DEFAULT_ID_FIELD
+            )));
+        }
+        Ok(patched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stored_value() -> TableValue {
+        json!({
+            "_id": {"values": [1]},
+            "_revision": 2,
+            "name": "John",
+            "address": {"city": "Paris", "zip": "75001"},
+            "tags": ["a"]
+        })
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let patch =
+            ValuePatch::Merge(json!({"address": {"zip": null, "street": "Rivoli"}, "age": 30}));
+        assert_eq!(
+            patch.apply(&stored_value()),
+            Ok(json!({
+                "_id": {"values": [1]},
+                "_revision": 2,
+                "name": "John",
+                "address": {"city": "Paris", "street": "Rivoli"},
+                "tags": ["a"],
+                "age": 30
+            }))
+        );
+    }
+
+    #[test]
+    fn test_json_patch() {
+        let patch = ValuePatch::json(json!([
+            {"op": "test", "path": "/name", "value": "John"},
+            {"op": "replace", "path": "/address/city", "value": "Rome"},
+            {"op": "add", "path": "/tags/-", "value": "b"},
+            {"op": "remove", "path": "/address/zip"}
+        ]))
+        .unwrap();
+        let patched = patch.apply(&stored_value()).unwrap();
+        assert_eq!(patched["address"], json!({"city": "Rome"}));
+        assert_eq!(patched["tags"], json!(["a", "b"]));
+
+        let patch =
+            ValuePatch::json(json!([{"op": "test", "path": "/name", "value": "Ann"}])).unwrap();
+        assert!(matches!(
+            patch.apply(&stored_value()),
+            Err(DbError::PatchTestFailed(_))
+        ));
+        let patch = ValuePatch::json(json!([{"op": "remove", "path": "/no/such"}])).unwrap();
+        assert!(matches!(
+            patch.apply(&stored_value()),
+            Err(DbError::InvalidPatch(_))
+        ));
+        assert!(matches!(
+            ValuePatch::json(json!({"op": "remove"})),
+            Err(DbError::InvalidPatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_patch_can_not_change_id() {
+        let patches = [
+            ValuePatch::Merge(json!({"_id": null})),
+            ValuePatch::Merge(json!([1])),
+            ValuePatch::json(json!([{"op": "replace", "path": "/_id", "value": 2}])).unwrap(),
+        ];
+        for patch in patches {
+            assert!(matches!(
+                patch.apply(&stored_value()),
+                Err(DbError::InvalidPatch(_))
+            ));
+        }
+    }
+}
@@ -1,9 +1,11 @@
-//TODO: use an XPATH like format for cf names
 use super::{DomainReference, IndexReference, TableReference};
 
 pub(crate) struct CfNameMaker;
 pub(crate) type CfName = String;
 
+/// Column family names are paths like `/domains/{domain}/tables/{table}/indexes/{index}`.
+/// Every name is a single path step: bytes other than ASCII letters, digits, `_`, `-`
+/// and `.` are percent-encoded, so no name can reach into another entity's path.
 impl CfNameMaker {
     pub fn for_server_meta() -> String {
         "/server".to_owned()
@@ -13,6 +15,52 @@ impl CfNameMaker {
         "/domains".to_owned()
     }
 
+    pub fn for_table_counters(r: &DomainReference) -> String {
+        format!("/domains/{}/counters", escape_step(&r.domain_name))
+    }
+
+    pub fn for_table_meta(r: &DomainReference) -> String {
+        format!("/domains/{}/tables", escape_step(&r.domain_name))
+    }
+
+    pub fn for_table_values(r: &TableReference) -> String {
+        format!(
+            "{}/{}",
+            Self::for_table_meta(&r.domain_reference),
+            escape_step(&r.table_name)
+        )
+    }
+
+    pub fn for_index_values(r: &IndexReference) -> String {
+        format!(
+            "{}/indexes/{}",
+            Self::for_table_values(&r.table_reference),
+            escape_step(&r.index_name)
+        )
+    }
+
+    /// The HNSW graph of a vector index, next to its values.
+    pub fn for_index_graph(r: &IndexReference) -> String {
+        format!("{}/graph", Self::for_index_values(r))
+    }
+}
+
+fn escape_step(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'_' | b'-' | b'.') {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// The column family names written before names were escaped.
+pub(crate) struct LegacyCfNameMaker;
+
+impl LegacyCfNameMaker {
     pub fn for_table_counters(r: &DomainReference) -> String {
         format!("/domains/{}/counters", r.domain_name)
     }
@@ -58,7 +106,10 @@ mod tests {
     #[test]
     fn test_for_table_values() {
         let r = TableReference::build("domain1", "table1");
-        assert_eq!(CfNameMaker::for_table_values(&r), "domain1::/table1");
+        assert_eq!(
+            CfNameMaker::for_table_values(&r),
+            "/domains/domain1/tables/table1"
+        );
     }
 
     #[test]
@@ -66,7 +117,46 @@ mod tests {
         let r = IndexReference::build("domain1", "table1", "index1");
         assert_eq!(
             CfNameMaker::for_index_values(&r),
-            "domain1::/table1/indexes/index1"
+            "/domains/domain1/tables/table1/indexes/index1"
+        );
+    }
+
+    #[test]
+    fn test_for_index_graph() {
+        let r = IndexReference::build("domain1", "table1", "index1");
+        assert_eq!(
+            CfNameMaker::for_index_graph(&r),
+            "/domains/domain1/tables/table1/indexes/index1/graph"
+        );
+    }
+
+    #[test]
+    fn test_names_are_escaped() {
+        let r = TableReference::build("a::b", "x/indexes/y");
+        assert_eq!(
+            CfNameMaker::for_table_values(&r),
+            "/domains/a%3A%3Ab/tables/x%2Findexes%2Fy"
+        );
+        let r = IndexReference::build("d", "t%2F", "ü");
+        assert_eq!(
+            CfNameMaker::for_index_values(&r),
+            "/domains/d/tables/t%252F/indexes/%C3%BC"
+        );
+    }
+
+    #[test]
+    fn test_names_do_not_collide() {
+        // With the legacy names the table "x/indexes/y" shared its column family
+        // with the index "y" of the table "x".
+        let table = TableReference::build("d", "x/indexes/y");
+        let index = IndexReference::build("d", "x", "y");
+        assert_eq!(
+            LegacyCfNameMaker::for_table_values(&table),
+            LegacyCfNameMaker::for_index_values(&index)
+        );
+        assert_ne!(
+            CfNameMaker::for_table_values(&table),
+            CfNameMaker::for_index_values(&index)
         );
     }
 }
@@ -78,7 +78,7 @@ impl ColumnValueReferenceTrait for ColumnValueReference {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use mockall::*;
     use serde_json::json;
@@ -1,4 +1,5 @@
 //database_server_reference.rs
+use crate::db::constants::{CF_NAME_SCHEME_VERSION, INDEX_ENTRY_VERSION, KEY_ENCODING_VERSION};
 use crate::db::entity::{DatabaseServer, DatabaseServerStored};
 use crate::db::reference::requests::{
     DatabaseServerStoredRequests, DomainStoredRequests, TableStoredRequests,
@@ -59,7 +60,7 @@ impl DatabaseServerReferenceTrait for DatabaseServerReference {
     ) -> DbResult<Effects> {
         let stored_opt = self.get_database_server_stored(requests)?;
         match stored_opt {
-            None => return Err(DbError::DatabaseNotInitialized),
+            None => Err(DbError::DatabaseNotInitialized),
             Some(stored) => {
                 let mut new_stored = stored.clone();
                 new_stored.database_server = (*database_server).clone();
@@ -82,6 +83,10 @@ impl DatabaseServerReferenceTrait for DatabaseServerReference {
             Ok(None) | Err(_) => {
                 let new_stored = DatabaseServerStored {
                     meta_revision: 0,
+                    key_encoding: KEY_ENCODING_VERSION,
+                    cf_name_scheme: CF_NAME_SCHEME_VERSION,
+                    index_entries: INDEX_ENTRY_VERSION,
+                    migrated_table: None,
                     database_server: (*database_server).clone(),
                     domains: Default::default(),
                 };
@@ -178,6 +183,10 @@ mod tests {
 
             let example_stored = DatabaseServerStored {
                 meta_revision: 0,
+                key_encoding: KEY_ENCODING_VERSION,
+                cf_name_scheme: CF_NAME_SCHEME_VERSION,
+                index_entries: INDEX_ENTRY_VERSION,
+                migrated_table: None,
                 database_server: DatabaseServer::default(),
                 domains: vec![
                     ("example1.com".to_owned(), ()),
@@ -159,7 +159,7 @@ pub(crate) mod tests {
     }
 
     pub(crate) fn create_database_server_ref() -> DatabaseServerReference {
-        DatabaseServerReference::default()
+        DatabaseServerReference
     }
 
     pub(crate) fn create_database_server() -> DatabaseServer {
@@ -169,6 +169,10 @@ pub(crate) mod tests {
     pub(crate) fn create_database_server_stored() -> DatabaseServerStored {
         DatabaseServerStored {
             meta_revision: 0,
+            key_encoding: KEY_ENCODING_VERSION,
+            cf_name_scheme: CF_NAME_SCHEME_VERSION,
+            index_entries: INDEX_ENTRY_VERSION,
+            migrated_table: None,
             database_server: create_database_server(),
             domains: HashMap::new(),
         }
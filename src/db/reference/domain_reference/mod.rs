@@ -1,5 +1,5 @@
 //domain_reference.rs
-use super::{CfNameMaker, DatabaseServerReference, Effect, Effects};
+use super::{validate_name, CfNameMaker, DatabaseServerReference, Effect, Effects};
 use crate::db::entity::{Domain, DomainStored};
 use crate::db::reference::requests::{
     DatabaseServerStoredRequests, DomainStoredRequests, TableStoredRequests,
@@ -30,7 +30,7 @@ pub(crate) trait DomainReferenceTrait {
 }
 
 pub(crate) type DomainName = String;
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct DomainReference {
     pub domain_name: DomainName,
 }
@@ -48,7 +48,7 @@ impl DomainReference {
     }
 
     pub fn to_database_server_reference(&self) -> DatabaseServerReference {
-        DatabaseServerReference::default()
+        DatabaseServerReference
     }
 
     pub fn cf_name_for_table_counters(&self) -> String {
@@ -80,6 +80,7 @@ impl DomainReferenceTrait for DomainReference {
         requests: &dyn DomainStoredRequests,
         parent_requests: &dyn DatabaseServerStoredRequests,
     ) -> DbResult<Effects> {
+        validate_name("domain", &self.domain_name)?;
         let stored_opt = self.get_domain_stored(requests)?;
         match stored_opt {
             Some(_) => {
@@ -174,5 +175,20 @@ mod tests {
                 "post_domain should return DbError::AlreadyExists if the key already exists"
             );
         }
+
+        #[test]
+        fn test_post_domain_invalid_name() {
+            let mock = MockDomainStoredTestRequests::new();
+            let parent_mock = MockDatabaseServerStoredTestRequests::new();
+            let ref_trait = DomainReference::build("a::b");
+            let domain = Domain {
+                reference: ref_trait.clone(),
+            };
+
+            assert!(matches!(
+                ref_trait.post_domain(&domain, &mock, &parent_mock),
+                Err(DbError::InvalidName(_))
+            ));
+        }
     }
 }
@@ -1,5 +1,4 @@
 //domain_reference/stored.rs
-//TODO: validate table name
 //TEST: test cascade_delete
 
 use super::*;
@@ -78,7 +77,7 @@ impl DomainStoredReferenceTrait for DomainReference {
         let mut effects = self
             .required_cf_names()
             .into_iter()
-            .map(|cf_name| Effect::CreateCf(cf_name))
+            .map(Effect::CreateCf)
             .collect::<Vec<_>>();
 
         database_server_stored
@@ -131,11 +130,7 @@ impl DomainStoredReferenceTrait for DomainReference {
             self.container_cf_name(),
             self.domain_name.clone(),
         )));
-        effects.extend(
-            self.required_cf_names()
-                .into_iter()
-                .map(|cf_name| Effect::DeleteCf(cf_name)),
-        );
+        effects.extend(self.required_cf_names().into_iter().map(Effect::DeleteCf));
         Ok(effects)
     }
 
@@ -151,6 +146,7 @@ impl DomainStoredReferenceTrait for DomainReference {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::db::constants::{CF_NAME_SCHEME_VERSION, INDEX_ENTRY_VERSION, KEY_ENCODING_VERSION};
     use crate::db::entity::{DatabaseServer, DatabaseServerStored};
     use crate::db::reference::database_server_reference::stored::tests::{
         create_database_server_stored, MockDatabaseServerStoredTestRequests,
@@ -248,6 +244,10 @@ pub(crate) mod tests {
                     (),
                     DatabaseServerStored {
                         meta_revision: 0,
+                        key_encoding: KEY_ENCODING_VERSION,
+                        cf_name_scheme: CF_NAME_SCHEME_VERSION,
+                        index_entries: INDEX_ENTRY_VERSION,
+                        migrated_table: None,
                         database_server: DatabaseServer::default(),
                         domains: {
                             vec!["sample_domain".to_owned()]
@@ -292,6 +292,10 @@ pub(crate) mod tests {
                     (),
                     DatabaseServerStored {
                         meta_revision: 0,
+                        key_encoding: KEY_ENCODING_VERSION,
+                        cf_name_scheme: CF_NAME_SCHEME_VERSION,
+                        index_entries: INDEX_ENTRY_VERSION,
+                        migrated_table: None,
                         database_server: DatabaseServer::default(),
                         domains: HashMap::new(),
                     },
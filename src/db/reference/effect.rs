@@ -25,4 +25,20 @@ pub(crate) enum Effect {
     ColumnValueEffect(ColumnValueEffect),
 }
 
+impl Effect {
+    /// The name of the variant, used as a metric label.
+    pub(crate) fn variant_name(&self) -> &'static str {
+        match self {
+            Effect::CreateCf(_) => "CreateCf",
+            Effect::DeleteCf(_) => "DeleteCf",
+            Effect::DatabaseServerStoredEffect(_) => "DatabaseServerStoredEffect",
+            Effect::DomainStoredEffect(_) => "DomainStoredEffect",
+            Effect::TableStoredEffect(_) => "TableStoredEffect",
+            Effect::IndexValueEffect(_) => "IndexValueEffect",
+            Effect::TableValueEffect(_) => "TableValueEffect",
+            Effect::ColumnValueEffect(_) => "ColumnValueEffect",
+        }
+    }
+}
+
 pub(crate) type Effects = Vec<Effect>;
@@ -0,0 +1,443 @@
+//filter.rs
+//! Mongo-style filters of the queries.
+//!
+//! A filter is a JSON object whose keys are dotted field paths or the operators $and,
+//! $or and $not, e.g. `{"age": {"$gte": 18}, "$or": [{"city": "Paris"}, {"vip": true}]}`.
+//! A field is compared with a value, or with the operators $eq, $ne, $gt, $gte, $lt,
+//! $lte, $in, $exists and $not, e.g. `{"tags": {"$in": ["a", "b"]}}`.
+//!
+//! Fields are read like the ordered indexes read them: arrays of objects on a path are
+//! searched element by element, and an array field matches if the array or one of its
+//! elements matches. A missing field is null, except for $exists. The comparisons only
+//! match values of the same type, and only take null, booleans, numbers and strings.
+use crate::db::entity::TableValue;
+use crate::db::{DbError, DbResult};
+use serde_json::{Map, Number, Value};
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Filter {
+    /// Matches if all the filters match, an empty And matches every value.
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    Field(String, Condition),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Condition {
+    Eq(Value),
+    Ne(Value),
+    Gt(Value),
+    Gte(Value),
+    Lt(Value),
+    Lte(Value),
+    In(Vec<Value>),
+    Exists(bool),
+}
+
+fn invalid_filter(message: String) -> DbError {
+    DbError::InvalidQuery(message)
+}
+
+/// Parses a filter, null is the filter that matches every value.
+pub(crate) fn parse_filter(filter: &Value) -> DbResult<Filter> {
+    match filter {
+        Value::Null => Ok(Filter::And(vec![])),
+        Value::Object(object) => parse_filter_object(object),
+        _ => Err(invalid_filter(format!(
+            "the filter {} is not an object",
+            filter
+        ))),
+    }
+}
+
+fn parse_filter_object(object: &Map<String, Value>) -> DbResult<Filter> {
+    let mut filters = object
+        .iter()
+        .map(|(key, value)| match key.as_str() {
+            "$and" => parse_filter_array(key, value).map(Filter::And),
+            "$or" => parse_filter_array(key, value).map(Filter::Or),
+            "$not" => match value {
+                Value::Object(object) => Ok(Filter::Not(Box::new(parse_filter_object(object)?))),
+                _ => Err(invalid_filter(format!("{} takes a filter", key))),
+            },
+            operator if operator.starts_with('$') => {
+                Err(invalid_filter(format!("unknown operator {}", operator)))
+            }
+            field => parse_field(field, value),
+        })
+        .collect::<DbResult<Vec<_>>>()?;
+    if filters.len() == 1 {
+        return Ok(filters.remove(0));
+    }
+    Ok(Filter::And(filters))
+}
+
+fn parse_filter_array(key: &str, value: &Value) -> DbResult<Vec<Filter>> {
+    match value {
+        Value::Array(filters) if !filters.is_empty() => filters
+            .iter()
+            .map(|filter| match filter {
+                Value::Object(object) => parse_filter_object(object),
+                _ => Err(invalid_filter(format!("{} takes an array of filters", key))),
+            })
+            .collect(),
+        _ => Err(invalid_filter(format!(
+            "{} takes a non-empty array of filters",
+            key
+        ))),
+    }
+}
+
+fn is_operator_object(value: &Value) -> bool {
+    match value {
+        Value::Object(object) => object.keys().any(|key| key.starts_with('$')),
+        _ => false,
+    }
+}
+
+fn parse_field(field: &str, value: &Value) -> DbResult<Filter> {
+    if field.is_empty() || field.split('.').any(|part| part.is_empty()) {
+        return Err(invalid_filter(format!("invalid field path \"{}\"", field)));
+    }
+    let object = match value {
+        Value::Object(object) if is_operator_object(value) => object,
+        _ => {
+            return Ok(Filter::Field(
+                field.to_owned(),
+                Condition::Eq(value.clone()),
+            ))
+        }
+    };
+    let mut filters = object
+        .iter()
+        .map(|(operator, operand)| parse_operator(field, operator, operand))
+        .collect::<DbResult<Vec<_>>>()?;
+    if filters.len() == 1 {
+        return Ok(filters.remove(0));
+    }
+    Ok(Filter::And(filters))
+}
+
+fn parse_operator(field: &str, operator: &str, operand: &Value) -> DbResult<Filter> {
+    let comparable = || {
+        if is_comparable(operand) {
+            Ok(operand.clone())
+        } else {
+            Err(invalid_filter(format!(
+                "{} takes null, a boolean, a number or a string",
+                operator
+            )))
+        }
+    };
+    let condition = match operator {
+        "$eq" => Condition::Eq(operand.clone()),
+        "$ne" => Condition::Ne(operand.clone()),
+        "$gt" => Condition::Gt(comparable()?),
+        "$gte" => Condition::Gte(comparable()?),
+        "$lt" => Condition::Lt(comparable()?),
+        "$lte" => Condition::Lte(comparable()?),
+        "$in" => match operand {
+            Value::Array(values) => Condition::In(values.clone()),
+            _ => return Err(invalid_filter("$in takes an array".to_owned())),
+        },
+        "$exists" => match operand {
+            Value::Bool(exists) => Condition::Exists(*exists),
+            _ => return Err(invalid_filter("$exists takes a boolean".to_owned())),
+        },
+        "$not" if is_operator_object(operand) => {
+            return Ok(Filter::Not(Box::new(parse_field(field, operand)?)))
+        }
+        "$not" => return Err(invalid_filter("$not takes operators".to_owned())),
+        _ if !operator.starts_with('$') => {
+            return Err(invalid_filter(format!(
+                "{} can not be mixed with operators",
+                operator
+            )))
+        }
+        _ => return Err(invalid_filter(format!("unknown operator {}", operator))),
+    };
+    Ok(Filter::Field(field.to_owned(), condition))
+}
+
+// The values that are compared with $gt, $gte, $lt and $lte.
+fn is_comparable(value: &Value) -> bool {
+    !value.is_array() && !value.is_object()
+}
+
+impl Filter {
+    pub(crate) fn matches(&self, doc: &TableValue) -> bool {
+        match self {
+            Filter::And(filters) => filters.iter().all(|filter| filter.matches(doc)),
+            Filter::Or(filters) => filters.iter().any(|filter| filter.matches(doc)),
+            Filter::Not(filter) => !filter.matches(doc),
+            Filter::Field(field, condition) => condition.matches(&field_values(doc, field)),
+        }
+    }
+}
+
+impl Condition {
+    // values are the values of the field, None where it is missing.
+    fn matches(&self, values: &[Option<&Value>]) -> bool {
+        let any = |predicate: &dyn Fn(&Value) -> bool| {
+            values.iter().any(|value| match value {
+                Some(array @ Value::Array(elements)) => {
+                    elements.iter().any(predicate) || predicate(array)
+                }
+                Some(value) => predicate(value),
+                None => predicate(&Value::Null),
+            })
+        };
+        let compare = |operand: &Value, accept: fn(Ordering) -> bool| {
+            any(&|value| {
+                type_rank(value) == type_rank(operand) && accept(compare_values(value, operand))
+            })
+        };
+        match self {
+            Condition::Eq(operand) => any(&|value| compare_values(value, operand).is_eq()),
+            Condition::Ne(operand) => !any(&|value| compare_values(value, operand).is_eq()),
+            Condition::Gt(operand) => compare(operand, Ordering::is_gt),
+            Condition::Gte(operand) => compare(operand, Ordering::is_ge),
+            Condition::Lt(operand) => compare(operand, Ordering::is_lt),
+            Condition::Lte(operand) => compare(operand, Ordering::is_le),
+            Condition::In(operands) => any(&|value| {
+                operands
+                    .iter()
+                    .any(|operand| compare_values(value, operand).is_eq())
+            }),
+            Condition::Exists(exists) => values.iter().any(|value| value.is_some()) == *exists,
+        }
+    }
+}
+
+/// The values of a dotted field, None where the field is missing.
+///
+/// Arrays on the path are searched element by element, like in the ordered indexes.
+pub(crate) fn field_values<'a>(doc: &'a Value, field: &str) -> Vec<Option<&'a Value>> {
+    let parts = field.split('.').collect::<Vec<_>>();
+    let mut values = Vec::new();
+    collect_field_values(doc, &parts, &mut values);
+    values
+}
+
+fn collect_field_values<'a>(value: &'a Value, parts: &[&str], values: &mut Vec<Option<&'a Value>>) {
+    let (part, rest) = match parts.split_first() {
+        Some(split) => split,
+        None => {
+            values.push(Some(value));
+            return;
+        }
+    };
+    match value {
+        Value::Object(object) => match object.get(*part) {
+            Some(value) => collect_field_values(value, rest, values),
+            None => values.push(None),
+        },
+        Value::Array(elements) => {
+            for element in elements {
+                collect_field_values(element, parts, values);
+            }
+        }
+        _ => values.push(None),
+    }
+}
+
+// The order of the types is the order of the index keys.
+fn type_rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
+
+/// A total order of the JSON values: null < booleans < numbers < strings < arrays < objects.
+///
+/// Numbers are compared by their exact values, so 1 and 1.0 are equal. Arrays are
+/// compared element by element and objects by their sorted fields.
+pub(crate) fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => compare_numbers(a, b),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| compare_values(a, b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Value::Object(a), Value::Object(b)) => {
+            let mut a = a.iter().collect::<Vec<_>>();
+            let mut b = b.iter().collect::<Vec<_>>();
+            a.sort_by(|x, y| x.0.cmp(y.0));
+            b.sort_by(|x, y| x.0.cmp(y.0));
+            a.iter()
+                .zip(&b)
+                .map(|((a_key, a), (b_key, b))| a_key.cmp(b_key).then(compare_values(a, b)))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len()))
+        }
+        _ => type_rank(a).cmp(&type_rank(b)),
+    }
+}
+
+fn compare_numbers(a: &Number, b: &Number) -> Ordering {
+    match (integer_of(a), integer_of(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        // A number that is not an integer is small enough to compare as a float.
+        _ => {
+            let (a, b) = (a.as_f64().unwrap_or(0.0), b.as_f64().unwrap_or(0.0));
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        }
+    }
+}
+
+/// The value of the number if it is an integer, even if it is written as a float.
+pub(crate) fn integer_of(number: &Number) -> Option<i128> {
+    if let Some(n) = number.as_i64() {
+        return Some(n as i128);
+    }
+    if let Some(n) = number.as_u64() {
+        return Some(n as i128);
+    }
+    let f = number.as_f64()?;
+    // Floats from 2^100 are integers too, but far from the integers of JSON.
+    if f.fract() == 0.0 && f.abs() < 2f64.powi(100) {
+        Some(f as i128)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matches(filter: Value, doc: Value) -> bool {
+        parse_filter(&filter).unwrap().matches(&doc)
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(parse_filter(&Value::Null), Ok(Filter::And(vec![])));
+        assert_eq!(
+            parse_filter(&json!({"a.b": 1})),
+            Ok(Filter::Field("a.b".to_owned(), Condition::Eq(json!(1))))
+        );
+        assert_eq!(
+            parse_filter(&json!({"a": {"$gt": 1, "$lte": 5}})),
+            Ok(Filter::And(vec![
+                Filter::Field("a".to_owned(), Condition::Gt(json!(1))),
+                Filter::Field("a".to_owned(), Condition::Lte(json!(5))),
+            ]))
+        );
+        assert_eq!(
+            parse_filter(&json!({"$or": [{"a": 1}, {"b": {"$not": {"$exists": true}}}]})),
+            Ok(Filter::Or(vec![
+                Filter::Field("a".to_owned(), Condition::Eq(json!(1))),
+                Filter::Not(Box::new(Filter::Field(
+                    "b".to_owned(),
+                    Condition::Exists(true)
+                ))),
+            ]))
+        );
+        assert_eq!(
+            parse_filter(&json!({"a": {"b": 1}})),
+            Ok(Filter::Field(
+                "a".to_owned(),
+                Condition::Eq(json!({"b": 1}))
+            ))
+        );
+    }
+
+    #[test]
+    fn test_invalid_filter() {
+        for filter in [
+            json!([1]),
+            json!({"$nor": []}),
+            json!({"$or": []}),
+            json!({"$and": [1]}),
+            json!({"a": {"$gt": [1]}}),
+            json!({"a": {"$in": 1}}),
+            json!({"a": {"$exists": 1}}),
+            json!({"a": {"$not": 1}}),
+            json!({"a": {"$gt": 1, "b": 2}}),
+            json!({"a..b": 1}),
+        ] {
+            assert!(
+                matches!(parse_filter(&filter), Err(DbError::InvalidQuery(_))),
+                "{}",
+                filter
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let doc = json!({
+            "name": "John",
+            "age": 30,
+            "tags": ["a", "b"],
+            "address": {"city": "Paris"},
+            "items": [{"sku": 1}, {"sku": [2, 3]}, {}],
+            "none": null
+        });
+        let cases = [
+            (json!({}), true),
+            (json!({"name": "John", "age": 30.0}), true),
+            (json!({"age": {"$gt": 20, "$lt": 30}}), false),
+            (json!({"age": {"$gte": 20, "$lte": 30}}), true),
+            (json!({"age": {"$gt": "20"}}), false),
+            (json!({"name": {"$ne": "Mary"}}), true),
+            (json!({"tags": "b"}), true),
+            (json!({"tags": ["a", "b"]}), true),
+            (json!({"tags": {"$in": ["c", "a"]}}), true),
+            (json!({"tags": {"$ne": "a"}}), false),
+            (json!({"address.city": "Paris"}), true),
+            (json!({"items.sku": 3}), true),
+            (json!({"items.sku": null}), true),
+            (json!({"items.sku": {"$gt": 2}}), true),
+            (json!({"missing": null}), true),
+            (json!({"missing": {"$exists": false}}), true),
+            (json!({"none": {"$exists": true}}), true),
+            (json!({"none": {"$gte": null}}), true),
+            (json!({"$or": [{"age": 1}, {"name": "John"}]}), true),
+            (json!({"$and": [{"age": 30}, {"name": "Mary"}]}), false),
+            (json!({"$not": {"age": 30}}), false),
+            (json!({"age": {"$not": {"$gt": 40}}}), true),
+        ];
+        for (filter, expected) in cases {
+            assert_eq!(matches(filter.clone(), doc.clone()), expected, "{}", filter);
+        }
+    }
+
+    #[test]
+    fn test_compare_values() {
+        let ordered = [
+            json!(null),
+            json!(false),
+            json!(true),
+            json!(-1.5),
+            json!(-1),
+            json!(0),
+            json!(u64::MAX),
+            json!(""),
+            json!("a"),
+            json!([]),
+            json!([1]),
+            json!({}),
+        ];
+        for (i, a) in ordered.iter().enumerate() {
+            for (j, b) in ordered.iter().enumerate() {
+                assert_eq!(compare_values(a, b), i.cmp(&j), "{} {}", a, b);
+            }
+        }
+        assert!(compare_values(&json!(1), &json!(1.0)).is_eq());
+        assert!(compare_values(&json!({"b": 1, "a": 2}), &json!({"a": 2, "b": 1})).is_eq());
+    }
+}
@@ -0,0 +1,735 @@
+//hnsw.rs
+//! HNSW graphs of the vector indexes, for approximate nearest neighbor search.
+//!
+//! The graph of an index is kept in its own column family:
+//! `["e"]` holds the level and the id of the entry point,
+//! `["n", ...id]` holds the level and the vector of a node, and
+//! `["l", layer, ...id]` holds the ids of the neighbors of a node on a layer.
+//! A node is on the layers from 0 up to its level, and the entry point is a node
+//! of the highest level.
+use crate::db::entity::index::vector::{parse_vector, HnswOptions, VectorMetric};
+use crate::db::entity::{IndexKey, IndexValue, OndoKey};
+use crate::db::reference::effect::IndexValueEffect;
+use crate::db::reference::requests::{IndexIteratorRequests, IndexValueRequests};
+use crate::db::reference::{
+    CfNameMaker, Effect, Effects, IndexReference, IndexValueReference, Page, PageRequest,
+};
+use crate::db::{DbError, DbResult};
+use serde_json::{json, Value};
+use std::cmp::{Ordering, Reverse};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+const ENTRY_TAG: &str = "e";
+const NODE_TAG: &str = "n";
+const LAYER_TAG: &str = "l";
+
+const MAX_LEVEL: u64 = 16;
+
+/// The number of candidates searched when the request does not set it.
+pub(crate) const DEFAULT_EF_SEARCH: usize = 64;
+
+/// A node found by a search, ordered by its distance.
+#[derive(Debug, Clone)]
+pub(crate) struct Candidate {
+    pub distance: f64,
+    pub id: OndoKey,
+    key: String,
+}
+
+impl Candidate {
+    pub fn new(distance: f64, id: OndoKey) -> DbResult<Self> {
+        let key = id_key(&id)?;
+        Ok(Candidate { distance, id, key })
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then_with(|| self.key.cmp(&other.key))
+    }
+}
+
+/// Keeps the k closest of the candidates pushed into it.
+pub(crate) struct Nearest {
+    k: usize,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl Nearest {
+    pub fn new(k: usize) -> Self {
+        Nearest {
+            k,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    pub fn push(&mut self, candidate: Candidate) {
+        if self.heap.len() < self.k {
+            self.heap.push(candidate);
+        } else if self
+            .heap
+            .peek()
+            .map_or(false, |farthest| &candidate < farthest)
+        {
+            self.heap.pop();
+            self.heap.push(candidate);
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.heap.len() >= self.k
+    }
+
+    fn farthest_distance(&self) -> Option<f64> {
+        self.heap.peek().map(|candidate| candidate.distance)
+    }
+
+    /// The candidates, closest first.
+    pub fn into_sorted_vec(self) -> Vec<Candidate> {
+        self.heap.into_sorted_vec()
+    }
+}
+
+fn id_key(id: &OndoKey) -> DbResult<String> {
+    serde_json::to_string(&id.values).map_err(|e| DbError::SerializationError(e.to_string()))
+}
+
+fn tagged_key(tag: &str, prefix: Option<u64>, id: &OndoKey) -> IndexKey {
+    let mut values = vec![json!(tag)];
+    values.extend(prefix.map(|layer| json!(layer)));
+    values.extend(id.values.iter().cloned());
+    OndoKey { values }
+}
+
+fn entry_key() -> IndexKey {
+    OndoKey {
+        values: vec![json!(ENTRY_TAG)],
+    }
+}
+
+fn node_key(id: &OndoKey) -> IndexKey {
+    tagged_key(NODE_TAG, None, id)
+}
+
+fn layer_key(layer: u64, id: &OndoKey) -> IndexKey {
+    tagged_key(LAYER_TAG, Some(layer), id)
+}
+
+fn id_value(id: &OndoKey) -> Value {
+    Value::Array(id.values.clone())
+}
+
+fn parse_id(value: &Value) -> Option<OndoKey> {
+    value.as_array().map(|values| OndoKey {
+        values: values.clone(),
+    })
+}
+
+// The level of a new node, drawn from an exponential distribution by the hash of its id,
+// so the number of nodes shrinks by m on every level.
+fn level_of(id_key: &str, m: usize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    id_key.hash(&mut hasher);
+    let uniform = ((hasher.finish() >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+    let level = -uniform.ln() / (m.max(2) as f64).ln();
+    (level as u64).min(MAX_LEVEL)
+}
+
+// Reads nothing, for graphs that are built in a new column family.
+struct NoIndexValues;
+
+impl IndexValueRequests for NoIndexValues {
+    fn get_index_value_stored(
+        &self,
+        _cf_name: &str,
+        _key: &IndexValueReference,
+    ) -> DbResult<Option<IndexValue>> {
+        Ok(None)
+    }
+
+    fn index_values_with_key_prefix(
+        &self,
+        _cf_name: &str,
+        _key_prefix: &IndexKey,
+        _limit: usize,
+    ) -> DbResult<Vec<(IndexKey, IndexValue)>> {
+        Ok(vec![])
+    }
+}
+
+static NO_INDEX_VALUES: NoIndexValues = NoIndexValues;
+
+/// The graph of a vector index, read from the stored entries and changed in memory.
+/// The changes become effects with into_effects.
+pub(crate) struct HnswGraph<'a> {
+    index_reference: IndexReference,
+    cf_name: String,
+    metric: VectorMetric,
+    options: HnswOptions,
+    requests: &'a dyn IndexValueRequests,
+    // The entries read or written so far by their keys, None if there is no entry.
+    entries: HashMap<String, Option<IndexValue>>,
+    // The keys of the written entries, in the order they were first written.
+    written: Vec<(String, IndexKey)>,
+    written_keys: HashSet<String>,
+}
+
+impl<'a> HnswGraph<'a> {
+    pub fn new(
+        index_reference: &IndexReference,
+        metric: VectorMetric,
+        options: HnswOptions,
+        requests: &'a dyn IndexValueRequests,
+    ) -> Self {
+        HnswGraph {
+            index_reference: index_reference.clone(),
+            cf_name: CfNameMaker::for_index_graph(index_reference),
+            metric,
+            options,
+            requests,
+            entries: HashMap::new(),
+            written: vec![],
+            written_keys: HashSet::new(),
+        }
+    }
+
+    /// A graph that is built from scratch, without reading the stored entries.
+    pub fn new_empty(
+        index_reference: &IndexReference,
+        metric: VectorMetric,
+        options: HnswOptions,
+    ) -> HnswGraph<'static> {
+        HnswGraph::new(index_reference, metric, options, &NO_INDEX_VALUES)
+    }
+
+    /// Puts and deletes the written entries.
+    pub fn into_effects(mut self) -> Effects {
+        self.written
+            .into_iter()
+            .map(|(key_string, key)| {
+                let effect = match self.entries.remove(&key_string).flatten() {
+                    Some(value) => IndexValueEffect::Put(self.cf_name.clone(), key, value),
+                    None => IndexValueEffect::Delete(self.cf_name.clone(), key),
+                };
+                Effect::IndexValueEffect(effect)
+            })
+            .collect()
+    }
+
+    fn get(&mut self, key: &IndexKey) -> DbResult<Option<&IndexValue>> {
+        let key_string = id_key(key)?;
+        if !self.entries.contains_key(&key_string) {
+            let reference = IndexValueReference::new(self.index_reference.clone(), key.clone());
+            let value = self
+                .requests
+                .get_index_value_stored(&self.cf_name, &reference)?;
+            self.entries.insert(key_string.clone(), value);
+        }
+        Ok(self.entries[&key_string].as_ref())
+    }
+
+    fn set(&mut self, key: IndexKey, value: Option<IndexValue>) -> DbResult<()> {
+        let key_string = id_key(&key)?;
+        if self.written_keys.insert(key_string.clone()) {
+            self.written.push((key_string.clone(), key));
+        }
+        self.entries.insert(key_string, value);
+        Ok(())
+    }
+
+    fn entry_point(&mut self) -> DbResult<Option<(u64, OndoKey)>> {
+        let entry = self.get(&entry_key())?;
+        Ok(entry.and_then(|value| {
+            let level = value.values.first()?.as_u64()?;
+            let id = parse_id(value.values.get(1)?)?;
+            Some((level, id))
+        }))
+    }
+
+    fn set_entry_point(&mut self, entry_point: Option<(u64, OndoKey)>) -> DbResult<()> {
+        let value = entry_point.map(|(level, id)| OndoKey {
+            values: vec![json!(level), id_value(&id)],
+        });
+        self.set(entry_key(), value)
+    }
+
+    // The level and the vector of a node.
+    fn node(&mut self, id: &OndoKey) -> DbResult<Option<(u64, Vec<f64>)>> {
+        let node = self.get(&node_key(id))?;
+        Ok(node.and_then(|value| {
+            let (level, vector) = value.values.split_first()?;
+            Some((level.as_u64()?, parse_vector(vector)))
+        }))
+    }
+
+    fn neighbors(&mut self, layer: u64, id: &OndoKey) -> DbResult<Vec<OndoKey>> {
+        let neighbors = self.get(&layer_key(layer, id))?;
+        Ok(neighbors
+            .map(|value| value.values.iter().filter_map(parse_id).collect())
+            .unwrap_or_default())
+    }
+
+    fn set_neighbors(&mut self, layer: u64, id: &OndoKey, neighbors: &[OndoKey]) -> DbResult<()> {
+        let value = OndoKey {
+            values: neighbors.iter().map(id_value).collect(),
+        };
+        self.set(layer_key(layer, id), Some(value))
+    }
+
+    fn max_neighbors(&self, layer: u64) -> usize {
+        if layer == 0 {
+            self.options.m * 2
+        } else {
+            self.options.m
+        }
+    }
+
+    // The distance of a node from the vector, None if the node was removed.
+    fn distance(&mut self, vector: &[f64], id: &OndoKey) -> DbResult<Option<Candidate>> {
+        match self.node(id)? {
+            Some((_, node_vector)) => Ok(Some(Candidate::new(
+                self.metric.distance(vector, &node_vector),
+                id.clone(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    // The n closest of the nodes to the vector.
+    fn closest(&mut self, vector: &[f64], ids: &[OndoKey], n: usize) -> DbResult<Vec<OndoKey>> {
+        let mut nearest = Nearest::new(n);
+        for id in ids {
+            if let Some(candidate) = self.distance(vector, id)? {
+                nearest.push(candidate);
+            }
+        }
+        Ok(nearest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|candidate| candidate.id)
+            .collect())
+    }
+
+    // The ef closest nodes of a layer found from the entry points, closest first.
+    fn search_layer(
+        &mut self,
+        vector: &[f64],
+        entry_points: Vec<Candidate>,
+        ef: usize,
+        layer: u64,
+    ) -> DbResult<Vec<Candidate>> {
+        let mut visited: HashSet<String> = entry_points.iter().map(|c| c.key.clone()).collect();
+        let mut candidates: BinaryHeap<Reverse<Candidate>> =
+            entry_points.iter().cloned().map(Reverse).collect();
+        let mut nearest = Nearest::new(ef);
+        for entry_point in entry_points {
+            nearest.push(entry_point);
+        }
+        while let Some(Reverse(closest)) = candidates.pop() {
+            if nearest.is_full()
+                && nearest
+                    .farthest_distance()
+                    .map_or(false, |farthest| closest.distance > farthest)
+            {
+                break;
+            }
+            for neighbor in self.neighbors(layer, &closest.id)? {
+                if !visited.insert(id_key(&neighbor)?) {
+                    continue;
+                }
+                let candidate = match self.distance(vector, &neighbor)? {
+                    Some(candidate) => candidate,
+                    None => continue,
+                };
+                let is_closer = !nearest.is_full()
+                    || nearest
+                        .farthest_distance()
+                        .map_or(true, |farthest| candidate.distance < farthest);
+                if is_closer {
+                    candidates.push(Reverse(candidate.clone()));
+                    nearest.push(candidate);
+                }
+            }
+        }
+        Ok(nearest.into_sorted_vec())
+    }
+
+    /// Whether the graph has a node with the id.
+    pub fn contains(&mut self, id: &OndoKey) -> DbResult<bool> {
+        Ok(self.node(id)?.is_some())
+    }
+
+    /// The vector of the node with the id.
+    pub fn vector(&mut self, id: &OndoKey) -> DbResult<Option<Vec<f64>>> {
+        Ok(self.node(id)?.map(|(_, vector)| vector))
+    }
+
+    /// A page of the ids of the stored nodes of the graph of the index.
+    pub fn stored_ids_page<'r>(
+        index_reference: &IndexReference,
+        page_request: &PageRequest,
+        requests: &'r dyn IndexIteratorRequests<'r>,
+    ) -> DbResult<Page<OndoKey>> {
+        let node_prefix = OndoKey {
+            values: vec![json!(NODE_TAG)],
+        };
+        let page = requests.entries_page_with_key_prefix(
+            &CfNameMaker::for_index_graph(index_reference),
+            node_prefix,
+            page_request,
+        )?;
+        Ok(Page {
+            values: page
+                .values
+                .into_iter()
+                .map(|(key, _)| OndoKey {
+                    values: key.values[1..].to_vec(),
+                })
+                .collect(),
+            next_key: page.next_key,
+        })
+    }
+
+    /// Adds a node, replacing the node with the same id.
+    pub fn insert(&mut self, id: &OndoKey, vector: &[f64]) -> DbResult<()> {
+        self.remove(id)?;
+        let level = level_of(&id_key(id)?, self.options.m);
+        let mut node_values = vec![json!(level)];
+        node_values.extend(vector.iter().map(|x| json!(x)));
+        self.set(
+            node_key(id),
+            Some(OndoKey {
+                values: node_values,
+            }),
+        )?;
+
+        let entry_point = match self.entry_point()? {
+            Some((entry_level, entry_id)) => self
+                .distance(vector, &entry_id)?
+                .map(|candidate| (entry_level, candidate)),
+            None => None,
+        };
+        let (entry_level, entry_candidate) = match entry_point {
+            Some(entry_point) => entry_point,
+            None => return self.set_entry_point(Some((level, id.clone()))),
+        };
+
+        let mut nearest = vec![entry_candidate];
+        for layer in ((level + 1)..=entry_level).rev() {
+            nearest = self.search_layer(vector, nearest, 1, layer)?;
+        }
+        for layer in (0..=level.min(entry_level)).rev() {
+            nearest = self.search_layer(vector, nearest, self.options.ef_construction, layer)?;
+            let neighbors = nearest
+                .iter()
+                .take(self.options.m)
+                .map(|candidate| candidate.id.clone())
+                .collect::<Vec<_>>();
+            self.set_neighbors(layer, id, &neighbors)?;
+            for neighbor in neighbors.iter() {
+                self.link(layer, neighbor, id)?;
+            }
+        }
+        if level > entry_level {
+            self.set_entry_point(Some((level, id.clone())))?;
+        }
+        Ok(())
+    }
+
+    // Adds the node to the neighbors of another node, dropping the farthest if there are too many.
+    fn link(&mut self, layer: u64, from: &OndoKey, to: &OndoKey) -> DbResult<()> {
+        let mut neighbors = self.neighbors(layer, from)?;
+        if neighbors.contains(to) {
+            return Ok(());
+        }
+        neighbors.push(to.clone());
+        let max_neighbors = self.max_neighbors(layer);
+        if neighbors.len() > max_neighbors {
+            let from_vector = match self.node(from)? {
+                Some((_, vector)) => vector,
+                None => return Ok(()),
+            };
+            neighbors = self.closest(&from_vector, &neighbors, max_neighbors)?;
+        }
+        self.set_neighbors(layer, from, &neighbors)
+    }
+
+    /// Removes a node. Its neighbors are linked to its other neighbors instead.
+    pub fn remove(&mut self, id: &OndoKey) -> DbResult<()> {
+        let level = match self.node(id)? {
+            Some((level, _)) => level,
+            None => return Ok(()),
+        };
+        // The neighbor of the highest level replaces the node as the entry point.
+        let mut replacement: Option<(u64, OndoKey)> = None;
+        for layer in 0..=level {
+            let removed_neighbors = self.neighbors(layer, id)?;
+            for neighbor in removed_neighbors.iter() {
+                let neighbor_level = match self.node(neighbor)? {
+                    Some((neighbor_level, neighbor_vector)) => {
+                        self.unlink(layer, neighbor, &neighbor_vector, id, &removed_neighbors)?;
+                        neighbor_level
+                    }
+                    None => continue,
+                };
+                if replacement.as_ref().map_or(true, |(replacement_level, _)| {
+                    neighbor_level > *replacement_level
+                }) {
+                    replacement = Some((neighbor_level, neighbor.clone()));
+                }
+            }
+            self.set(layer_key(layer, id), None)?;
+        }
+        self.set(node_key(id), None)?;
+
+        if let Some((_, entry_id)) = self.entry_point()? {
+            if &entry_id == id {
+                let replacement = match replacement {
+                    Some(replacement) => Some(replacement),
+                    None => self.highest_node()?,
+                };
+                self.set_entry_point(replacement)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces the removed node in the neighbors of a node by the neighbors of the removed node.
+    fn unlink(
+        &mut self,
+        layer: u64,
+        from: &OndoKey,
+        from_vector: &[f64],
+        removed: &OndoKey,
+        removed_neighbors: &[OndoKey],
+    ) -> DbResult<()> {
+        let mut neighbors = self.neighbors(layer, from)?;
+        if !neighbors.contains(removed) {
+            return Ok(());
+        }
+        neighbors.retain(|neighbor| neighbor != removed);
+        for other in removed_neighbors {
+            if other != from && !neighbors.contains(other) {
+                neighbors.push(other.clone());
+            }
+        }
+        let neighbors = self.closest(from_vector, &neighbors, self.max_neighbors(layer))?;
+        self.set_neighbors(layer, from, &neighbors)
+    }
+
+    // The node of the highest level, found by reading all the nodes.
+    fn highest_node(&mut self) -> DbResult<Option<(u64, OndoKey)>> {
+        let node_prefix = OndoKey {
+            values: vec![json!(NODE_TAG)],
+        };
+        let stored =
+            self.requests
+                .index_values_with_key_prefix(&self.cf_name, &node_prefix, usize::MAX)?;
+        let written = self
+            .written
+            .iter()
+            .filter(|(_, key)| key.values.starts_with(&node_prefix.values))
+            .map(|(_, key)| key.clone())
+            .collect::<Vec<_>>();
+        let mut highest: Option<(u64, OndoKey)> = None;
+        for key in stored.into_iter().map(|(key, _)| key).chain(written) {
+            let id = OndoKey {
+                values: key.values[1..].to_vec(),
+            };
+            if let Some((level, _)) = self.node(&id)? {
+                if highest
+                    .as_ref()
+                    .map_or(true, |(highest_level, _)| level > *highest_level)
+                {
+                    highest = Some((level, id));
+                }
+            }
+        }
+        Ok(highest)
+    }
+
+    /// The k nearest nodes to the vector with their distances, closest first.
+    /// ef is the number of candidates searched on layer 0, at least k.
+    pub fn search(&mut self, vector: &[f64], k: usize, ef: usize) -> DbResult<Vec<(OndoKey, f64)>> {
+        let entry_point = match self.entry_point()? {
+            Some((entry_level, entry_id)) => self
+                .distance(vector, &entry_id)?
+                .map(|candidate| (entry_level, candidate)),
+            None => None,
+        };
+        let (entry_level, entry_candidate) = match entry_point {
+            Some(entry_point) => entry_point,
+            None => return Ok(vec![]),
+        };
+        let mut nearest = vec![entry_candidate];
+        for layer in (1..=entry_level).rev() {
+            nearest = self.search_layer(vector, nearest, 1, layer)?;
+        }
+        let found = self.search_layer(vector, nearest, ef.max(k), 0)?;
+        Ok(found
+            .into_iter()
+            .take(k)
+            .map(|candidate| (candidate.id, candidate.distance))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> HnswGraph<'static> {
+        HnswGraph::new_empty(
+            &IndexReference::build("domain", "table", "index"),
+            VectorMetric::L2,
+            HnswOptions {
+                m: 4,
+                ef_construction: 16,
+            },
+        )
+    }
+
+    fn point(i: u64) -> Vec<f64> {
+        vec![(i % 10) as f64, (i / 10) as f64]
+    }
+
+    fn ids(found: Vec<(OndoKey, f64)>) -> Vec<OndoKey> {
+        found.into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn test_search_finds_nearest() {
+        let mut graph = graph();
+        assert!(graph.search(&[0.0, 0.0], 3, 10).unwrap().is_empty());
+        for i in 0..100u64 {
+            graph.insert(&i.into(), &point(i)).unwrap();
+        }
+        let found = graph.search(&[4.2, 5.1], 3, 32).unwrap();
+        assert_eq!(found[0].0, 54u64.into());
+        assert!((found[0].1 - 0.05f64.sqrt()).abs() < 1e-9);
+        let mut found = ids(found);
+        found.sort_by_key(|id| id.values[0].as_u64());
+        assert_eq!(found, vec![54u64.into(), 55u64.into(), 64u64.into()]);
+    }
+
+    #[test]
+    fn test_recall() {
+        let mut graph = HnswGraph::new_empty(
+            &IndexReference::build("domain", "table", "index"),
+            VectorMetric::Cosine,
+            HnswOptions {
+                m: 8,
+                ef_construction: 32,
+            },
+        );
+        // A linear congruential generator, so the vectors are the same on every run.
+        let mut seed = 42u64;
+        let mut random_vector = || {
+            (0..8)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (seed >> 33) as f64 / (1u64 << 31) as f64 - 0.5
+                })
+                .collect::<Vec<_>>()
+        };
+        let vectors = (0..300).map(|_| random_vector()).collect::<Vec<_>>();
+        for (i, vector) in vectors.iter().enumerate() {
+            graph.insert(&(i as u64).into(), vector).unwrap();
+        }
+        let mut found_count = 0;
+        for _ in 0..20 {
+            let query = random_vector();
+            let mut exact = Nearest::new(10);
+            for (i, vector) in vectors.iter().enumerate() {
+                let distance = VectorMetric::Cosine.distance(&query, vector);
+                exact.push(Candidate::new(distance, (i as u64).into()).unwrap());
+            }
+            let found = ids(graph.search(&query, 10, DEFAULT_EF_SEARCH).unwrap());
+            found_count += exact
+                .into_sorted_vec()
+                .iter()
+                .filter(|candidate| found.contains(&candidate.id))
+                .count();
+        }
+        assert!(found_count >= 190, "recall {} of 200", found_count);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut graph = graph();
+        for i in 0..100u64 {
+            graph.insert(&i.into(), &point(i)).unwrap();
+        }
+        for i in 0..100u64 {
+            if i != 55 {
+                graph.remove(&i.into()).unwrap();
+            }
+        }
+        assert_eq!(
+            ids(graph.search(&[0.0, 0.0], 3, 10).unwrap()),
+            vec![55u64.into()]
+        );
+        graph.remove(&55u64.into()).unwrap();
+        assert!(graph.search(&[0.0, 0.0], 3, 10).unwrap().is_empty());
+        assert_eq!(graph.entry_point().unwrap(), None);
+    }
+
+    #[test]
+    fn test_insert_replaces_node() {
+        let mut graph = graph();
+        for i in 0..20u64 {
+            graph.insert(&i.into(), &point(i)).unwrap();
+        }
+        graph.insert(&3u64.into(), &[100.0, 100.0]).unwrap();
+        assert_eq!(
+            graph.search(&[100.0, 99.0], 1, 10).unwrap(),
+            vec![(3u64.into(), 1.0)]
+        );
+        assert_eq!(
+            ids(graph.search(&[2.9, 0.0], 1, 10).unwrap()),
+            vec![2u64.into()]
+        );
+    }
+
+    #[test]
+    fn test_into_effects() {
+        let mut graph = graph();
+        graph.insert(&1u64.into(), &[1.0, 2.0]).unwrap();
+        graph.insert(&2u64.into(), &[1.0, 3.0]).unwrap();
+        graph.remove(&2u64.into()).unwrap();
+        let cf_name =
+            CfNameMaker::for_index_graph(&IndexReference::build("domain", "table", "index"));
+        let effects = graph.into_effects();
+        assert!(
+            effects.contains(&Effect::IndexValueEffect(IndexValueEffect::Delete(
+                cf_name.clone(),
+                node_key(&2u64.into())
+            )))
+        );
+        assert!(
+            effects.contains(&Effect::IndexValueEffect(IndexValueEffect::Put(
+                cf_name,
+                layer_key(0, &1u64.into()),
+                OndoKey { values: vec![] }
+            )))
+        );
+    }
+}
@@ -1,37 +1,64 @@
 //index_reference.rs
-//TODO: validate index name
-use crate::db::entity::table_value::do_index_table_value;
+use crate::db::entity::table_value::{do_index_table_value, get_key_from_table_value};
 use crate::db::enums::table_stored_iterator_requests_factory::TableStoredIteratorRequestsFactoryEnum;
 use crate::db::enums::index_iterator_requests_factory::IndexIteratorRequestsFactoryEnum;
+use crate::db::reference::table_value_reference::check_unique;
 use crate::db::{
-    entity::{Index, OndoKey, TableValue},
+    entity::{Index, IndexKind, OndoKey, TableValue},
     reference::{
-        requests::{IndexIteratorRequests, TableStoredRequests, TableValueRequests},
+        hnsw::HnswGraph,
+        index_verification::IndexVerifier,
+        requests::{
+            IndexIteratorRequests, IndexValueRequests, TableStoredIteratorRequests,
+            TableStoredRequests, TableValueRequests,
+        },
         table_reference::stored::TableStoredReferenceTrait,
-        CfNameMaker, DomainReference, Effect, Effects, TableReference, TableReferenceTrait,
-        TableValueReference, TableValueReferenceTrait,
+        text_search::{parse_text_query, search_text_index},
+        text_stats::TextStatsUpdate,
+        validate_name,
+        vector_search::{search_vector_index, VectorQuery},
+        CfNameMaker, DomainReference, Effect, Effects, Page, PageRequest, TableReference,
+        TableReferenceTrait, TableValueReference, TableValueReferenceTrait,
     },
     DbError, DbResult,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 pub(crate) trait IndexReferenceTrait {
     fn value_cf_name(&self) -> String;
     fn required_cf_names(&self) -> Vec<String>;
     fn get_index(&self, parent_requests: &dyn TableStoredRequests) -> DbResult<Option<Index>>;
-    fn put_index<'a>(
+    fn put_index(
         &self,
         index: &Index,
         parent_requests: &dyn TableStoredRequests,
-        table_stored_iterator_requests_factory: &TableStoredIteratorRequestsFactoryEnum,
     ) -> DbResult<Effects>;
-    fn post_index<'a>(
+    fn post_index(
         &self,
         index: &Index,
         parent_requests: &dyn TableStoredRequests,
-        table_stored_iterator_requests_factory: &TableStoredIteratorRequestsFactoryEnum,
     ) -> DbResult<Effects>;
     fn delete_index(&self, parent_requests: &dyn TableStoredRequests) -> DbResult<Effects>;
+    fn build_index_chunk(
+        &self,
+        start_key: Option<OndoKey>,
+        chunk_size: usize,
+        parent_requests: &dyn TableStoredRequests,
+        index_value_requests: &dyn IndexValueRequests,
+        table_stored_iterator_requests_factory: &TableStoredIteratorRequestsFactoryEnum,
+    ) -> DbResult<IndexBuildChunk>;
+    fn finish_index_build(&self, parent_requests: &dyn TableStoredRequests) -> DbResult<Effects>;
+    fn check_index_ready(&self, parent_requests: &dyn TableStoredRequests) -> DbResult<()>;
+    fn verify_index_chunk<'a>(
+        &self,
+        verifier: &mut IndexVerifier,
+        parent_requests: &dyn TableStoredRequests,
+        table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+        table_stored_iterator_requests: &'a dyn TableStoredIteratorRequests<'a>,
+        index_iterator_requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Effects>;
 
     fn all_values_with_key_prefix<'a>(
         &self,
@@ -58,16 +85,49 @@ pub(crate) trait IndexReferenceTrait {
         requests: &'a dyn IndexIteratorRequests<'a>,
     ) -> DbResult<Box<dyn Iterator<Item = DbResult<OndoKey>> + 'a>>;
 
+    fn values_page_with_key_prefix<'a>(
+        &self,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+        table_value_requests: &'a dyn TableValueRequests,
+        requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Page<TableValue>>;
+    fn values_page_with_key_range<'a>(
+        &self,
+        start_key_prefix: OndoKey,
+        end_key_prefix: OndoKey,
+        page_request: &PageRequest,
+        table_value_requests: &'a dyn TableValueRequests,
+        requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Page<TableValue>>;
+    fn search_values<'a>(
+        &self,
+        query: &str,
+        limit: Option<usize>,
+        parent_requests: &dyn TableStoredRequests,
+        table_value_requests: &'a dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+        requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Vec<(TableValue, f64)>>;
+    fn nearest_values<'a>(
+        &self,
+        query: &VectorQuery,
+        parent_requests: &dyn TableStoredRequests,
+        table_value_requests: &'a dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+        requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Vec<(TableValue, f64)>>;
+
     fn all_values_with_key_prefix_vec<'a>(
         &self,
         key_prefix: OndoKey,
         table_value_requests: &'a dyn TableValueRequests,
         index_iterator_requests_factory: &'a IndexIteratorRequestsFactoryEnum,
     ) -> DbResult<Vec<DbResult<TableValue>>>;
-    fn all_index_values_with_key_prefix_vec<'a>(
+    fn all_index_values_with_key_prefix_vec(
         &self,
         key_prefix: OndoKey,
-        index_iterator_requests_factory: &'a IndexIteratorRequestsFactoryEnum,
+        index_iterator_requests_factory: &IndexIteratorRequestsFactoryEnum,
     ) -> DbResult<Vec<DbResult<OndoKey>>>;
     fn all_values_with_key_range_vec<'a>(
         &self,
@@ -76,14 +136,23 @@ pub(crate) trait IndexReferenceTrait {
         table_value_requests: &'a dyn TableValueRequests,
         index_iterator_requests_factory: &'a IndexIteratorRequestsFactoryEnum,
     ) -> DbResult<Vec<DbResult<TableValue>>>;
-    fn all_index_values_with_key_range_vec<'a>(
+    fn all_index_values_with_key_range_vec(
         &self,
         start_key_prefix: OndoKey,
         end_key_prefix: OndoKey,
-        index_iterator_requests_factory: &'a IndexIteratorRequestsFactoryEnum,
+        index_iterator_requests_factory: &IndexIteratorRequestsFactoryEnum,
     ) -> DbResult<Vec<DbResult<OndoKey>>>;
 }
 
+/// The effects of indexing a chunk of the values of a table.
+#[derive(Debug)]
+pub(crate) struct IndexBuildChunk {
+    pub effects: Effects,
+    pub values_indexed: usize,
+    /// The key of the first value of the next chunk, None after the last chunk.
+    pub next_key: Option<OndoKey>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct IndexReference {
     pub table_reference: TableReference,
@@ -115,13 +184,27 @@ impl IndexReference {
 
 trait IndexReferencePrivateTrait<'a> {
     fn recreate_index_values_cf(&self) -> Effects;
-    fn index_related_table_values(
-        &self,
-        the_index: &Index,
-        table_stored_iterator_requests_factory: &TableStoredIteratorRequestsFactoryEnum,
-    ) -> DbResult<Effects>;
+    fn graph_cf_names(&self, index: &Index) -> Vec<String>;
     fn create_required_cfs(&self) -> Effects;
     fn delete_required_cfs(&self) -> Effects;
+    fn table_values_page(
+        &self,
+        index_page: Page<OndoKey>,
+        table_value_requests: &dyn TableValueRequests,
+    ) -> DbResult<Page<TableValue>>;
+    fn scored_table_values(
+        &self,
+        found: Vec<(OndoKey, f64)>,
+        table_value_requests: &dyn TableValueRequests,
+    ) -> DbResult<Vec<(TableValue, f64)>>;
+}
+
+// The index as it is stored until it is built.
+fn building(index: &Index) -> Index {
+    Index {
+        building: true,
+        ..index.clone()
+    }
 }
 
 impl<'a> IndexReferencePrivateTrait<'a> for IndexReference {
@@ -131,33 +214,58 @@ impl<'a> IndexReferencePrivateTrait<'a> for IndexReference {
         vec![delete_effect, create_effect]
     }
 
-    fn index_related_table_values(
-        &self,
-        the_index: &Index,
-        table_stored_iterator_requests_factory: &TableStoredIteratorRequestsFactoryEnum,
-    ) -> DbResult<Effects> {
-        let table_stored_iterator_requests_enum =
-            table_stored_iterator_requests_factory.create_read_locked_requests()?;
-        let table_stored_iterator_requests = table_stored_iterator_requests_enum.as_trait();
-        {
-            let table_reference = self.to_table_reference();
-            let all_values = table_reference.all_values(table_stored_iterator_requests);
-            let nested_effects = all_values?.try_fold(vec![], |mut acc, r_value| {
-                let value = r_value?;
-                let r_index_value_effects = do_index_table_value(&value, &the_index);
-                match r_index_value_effects {
-                    Ok(index_value_effect) => {
-                        acc.push(index_value_effect);
-                        Ok(acc)
-                    }
-                    Err(e) => Err(e),
-                }
-            })?;
-            let effects = nested_effects.into_iter().flatten().collect::<Vec<_>>();
-            Ok(effects)
+    // The column family of the HNSW graph, if the index has one.
+    fn graph_cf_names(&self, index: &Index) -> Vec<String> {
+        match index.graph_options() {
+            Some(_) => vec![CfNameMaker::for_index_graph(self)],
+            None => vec![],
         }
     }
 
+    fn table_values_page(
+        &self,
+        index_page: Page<OndoKey>,
+        table_value_requests: &dyn TableValueRequests,
+    ) -> DbResult<Page<TableValue>> {
+        let values = index_page
+            .values
+            .into_iter()
+            .map(|ondo_key| {
+                let table_value_reference = TableValueReference {
+                    table_reference: self.table_reference.clone(),
+                    id: ondo_key,
+                };
+                table_value_reference
+                    .get_table_value(table_value_requests)
+                    .and_then(|opt| opt.ok_or(DbError::NotFound))
+            })
+            .collect::<DbResult<Vec<_>>>()?;
+        Ok(Page {
+            values,
+            next_key: index_page.next_key,
+        })
+    }
+
+    fn scored_table_values(
+        &self,
+        found: Vec<(OndoKey, f64)>,
+        table_value_requests: &dyn TableValueRequests,
+    ) -> DbResult<Vec<(TableValue, f64)>> {
+        found
+            .into_iter()
+            .map(|(id, score)| {
+                let table_value_reference = TableValueReference {
+                    table_reference: self.table_reference.clone(),
+                    id,
+                };
+                table_value_reference
+                    .get_table_value(table_value_requests)
+                    .and_then(|opt| opt.ok_or(DbError::NotFound))
+                    .map(|value| (value, score))
+            })
+            .collect()
+    }
+
     fn create_required_cfs(&self) -> Effects {
         let effects = self
             .required_cf_names()
@@ -179,7 +287,7 @@ impl<'a> IndexReferencePrivateTrait<'a> for IndexReference {
 
 impl IndexReferenceTrait for IndexReference {
     fn value_cf_name(&self) -> String {
-        CfNameMaker::for_index_values(&self)
+        CfNameMaker::for_index_values(self)
     }
 
     fn required_cf_names(&self) -> Vec<String> {
@@ -192,49 +300,51 @@ impl IndexReferenceTrait for IndexReference {
         Ok(table_stored.indexes.get(&self.index_name).cloned())
     }
 
-    fn put_index<'a>(
+    /// Replaces the index by an empty one, that is built later with build_index_chunk.
+    fn put_index(
         &self,
         index: &Index,
         parent_requests: &dyn TableStoredRequests,
-        table_stored_iterator_requests_factory: &TableStoredIteratorRequestsFactoryEnum,
     ) -> DbResult<Effects> {
         let table_stored_opt = self.table_reference.get_table_stored(parent_requests)?;
         let mut table_stored = table_stored_opt.ok_or(DbError::TableNotInitialized)?;
         let result = table_stored
             .indexes
-            .insert(self.index_name.clone(), index.clone());
-        if result == None {
-            Err(DbError::IndexNotInitialized)
-        } else {
+            .insert(self.index_name.clone(), building(index));
+        if let Some(old_index) = result {
             let mut effects: Vec<Effect> = Vec::new();
             effects.extend(self.table_reference.put_table_stored(&table_stored)?);
             effects.extend(self.recreate_index_values_cf());
-            let index_related_table_values_effects =
-                self.index_related_table_values(index, table_stored_iterator_requests_factory)?;
-            effects.extend(index_related_table_values_effects);
+            effects.extend(
+                self.graph_cf_names(&old_index)
+                    .into_iter()
+                    .map(Effect::DeleteCf),
+            );
+            effects.extend(self.graph_cf_names(index).into_iter().map(Effect::CreateCf));
             Ok(effects)
+        } else {
+            Err(DbError::IndexNotInitialized)
         }
     }
 
-    fn post_index<'a>(
+    /// Adds an empty index, that is built later with build_index_chunk.
+    fn post_index(
         &self,
         index: &Index,
         parent_requests: &dyn TableStoredRequests,
-        table_stored_iterator_requests_factory: &TableStoredIteratorRequestsFactoryEnum,
     ) -> DbResult<Effects> {
+        validate_name("index", &self.index_name)?;
         let table_stored_opt = self.table_reference.get_table_stored(parent_requests)?;
         let mut table_stored = table_stored_opt.ok_or(DbError::TableNotInitialized)?;
         let result = table_stored
             .indexes
-            .insert(self.index_name.clone(), index.clone());
-        if result == None {
+            .insert(self.index_name.clone(), building(index));
+        if result.is_none() {
             // new index
             let mut effects = self.create_required_cfs();
+            effects.extend(self.graph_cf_names(index).into_iter().map(Effect::CreateCf));
             let put_effects = self.table_reference.put_table_stored(&table_stored)?;
             effects.extend(put_effects);
-            let index_related_table_values_effects =
-                self.index_related_table_values(index, table_stored_iterator_requests_factory)?;
-            effects.extend(index_related_table_values_effects);
             Ok(effects)
         } else {
             Err(DbError::AlreadyExists)
@@ -244,12 +354,143 @@ impl IndexReferenceTrait for IndexReference {
     fn delete_index(&self, parent_requests: &dyn TableStoredRequests) -> DbResult<Effects> {
         let table_stored_opt = self.table_reference.get_table_stored(parent_requests)?;
         let mut table_stored = table_stored_opt.ok_or(DbError::TableNotInitialized)?;
-        table_stored.indexes.remove(&self.index_name);
+        let removed = table_stored.indexes.remove(&self.index_name);
         let mut effects = self.table_reference.put_table_stored(&table_stored)?;
         effects.extend(self.delete_required_cfs());
+        for removed_index in removed.iter() {
+            effects.extend(
+                self.graph_cf_names(removed_index)
+                    .into_iter()
+                    .map(Effect::DeleteCf),
+            );
+        }
         Ok(effects)
     }
 
+    /// Indexes the values of the table from start_key on, at most chunk_size of them.
+    ///
+    /// The writes index the values while the index is being built, so a value may
+    /// already be indexed. Its entries are then written again with the same content.
+    fn build_index_chunk(
+        &self,
+        start_key: Option<OndoKey>,
+        chunk_size: usize,
+        parent_requests: &dyn TableStoredRequests,
+        index_value_requests: &dyn IndexValueRequests,
+        table_stored_iterator_requests_factory: &TableStoredIteratorRequestsFactoryEnum,
+    ) -> DbResult<IndexBuildChunk> {
+        let the_index = self
+            .get_index(parent_requests)?
+            .ok_or(DbError::IndexNotInitialized)?;
+        let page = {
+            let table_stored_iterator_requests_enum =
+                table_stored_iterator_requests_factory.create_read_locked_requests()?;
+            let page_request = PageRequest {
+                start_key,
+                page_size: Some(chunk_size),
+            };
+            self.table_reference.values_page_with_key_prefix(
+                OndoKey { values: vec![] },
+                &page_request,
+                table_stored_iterator_requests_enum.as_trait(),
+            )?
+        };
+        // The ids of the unique keys of the chunk, keyed by their JSON.
+        let mut unique_ids: HashMap<String, OndoKey> = HashMap::new();
+        let mut graph = the_index
+            .graph_options()
+            .map(|(metric, hnsw)| HnswGraph::new(self, metric, hnsw, index_value_requests));
+        let mut stats = matches!(the_index.kind, IndexKind::Text(_))
+            .then(|| TextStatsUpdate::new(self, index_value_requests));
+        let mut effects = vec![];
+        for value in page.values.iter() {
+            let id = get_key_from_table_value(value)?;
+            let table_value_reference = TableValueReference::new(self.to_table_reference(), id);
+            check_unique(
+                &table_value_reference,
+                value,
+                &the_index,
+                index_value_requests,
+            )?;
+            let id = table_value_reference.id;
+            for unique_key in the_index.unique_keys_of(value)? {
+                let json = serde_json::to_string(&unique_key.values)
+                    .map_err(|e| DbError::SerializationError(e.to_string()))?;
+                if let Some(other_id) = unique_ids.insert(json, id.clone()) {
+                    return Err(DbError::UniqueConstraintViolation(format!(
+                        "index {} has {:?} and {:?} for the key {:?}",
+                        self.index_name, other_id.values, id.values, unique_key.values
+                    )));
+                }
+            }
+            if let (Some(graph), Some(vector)) = (graph.as_mut(), the_index.vector_of(value)?) {
+                if !graph.contains(&id)? {
+                    graph.insert(&id, &vector)?;
+                }
+            }
+            // A value written during the build is already counted.
+            if let Some(stats) = stats.as_mut() {
+                stats.set_length(&id, the_index.text_length_of(value))?;
+            }
+            effects.extend(do_index_table_value(value, &the_index)?);
+        }
+        effects.extend(graph.map(HnswGraph::into_effects).unwrap_or_default());
+        if let Some(stats) = stats {
+            effects.extend(stats.into_effects()?);
+        }
+        Ok(IndexBuildChunk {
+            effects,
+            values_indexed: page.values.len(),
+            next_key: page.next_key,
+        })
+    }
+
+    /// Makes the built index queryable.
+    fn finish_index_build(&self, parent_requests: &dyn TableStoredRequests) -> DbResult<Effects> {
+        let table_stored_opt = self.table_reference.get_table_stored(parent_requests)?;
+        let mut table_stored = table_stored_opt.ok_or(DbError::TableNotInitialized)?;
+        let the_index = table_stored
+            .indexes
+            .get_mut(&self.index_name)
+            .ok_or(DbError::IndexNotInitialized)?;
+        the_index.building = false;
+        self.table_reference.put_table_stored(&table_stored)
+    }
+
+    /// Fails with IndexNotReady while the index is being built.
+    fn check_index_ready(&self, parent_requests: &dyn TableStoredRequests) -> DbResult<()> {
+        match self.get_index(parent_requests)? {
+            Some(the_index) if the_index.building => Err(DbError::IndexNotReady),
+            _ => Ok(()),
+        }
+    }
+
+    /// Compares the next chunk of the index with the values of its table. An index that
+    /// is being built is not complete yet, so it fails with IndexNotReady.
+    fn verify_index_chunk<'a>(
+        &self,
+        verifier: &mut IndexVerifier,
+        parent_requests: &dyn TableStoredRequests,
+        table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+        table_stored_iterator_requests: &'a dyn TableStoredIteratorRequests<'a>,
+        index_iterator_requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Effects> {
+        let index = self
+            .get_index(parent_requests)?
+            .ok_or(DbError::IndexNotInitialized)?;
+        if index.building {
+            return Err(DbError::IndexNotReady);
+        }
+        verifier.verify_chunk(
+            &index,
+            table_value_requests,
+            index_value_requests,
+            table_stored_iterator_requests,
+            index_iterator_requests,
+        )
+    }
+
     fn all_values_with_key_prefix<'a>(
         &self,
         key_prefix: OndoKey,
@@ -326,6 +567,102 @@ impl IndexReferenceTrait for IndexReference {
         Ok(Box::new(index_value_iterator))
     }
 
+    fn values_page_with_key_prefix<'a>(
+        &self,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+        table_value_requests: &'a dyn TableValueRequests,
+        requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Page<TableValue>> {
+        let index_page =
+            requests.values_page_with_key_prefix(&self.value_cf_name(), key_prefix, page_request)?;
+        self.table_values_page(index_page, table_value_requests)
+    }
+
+    fn values_page_with_key_range<'a>(
+        &self,
+        start_key_prefix: OndoKey,
+        end_key_prefix: OndoKey,
+        page_request: &PageRequest,
+        table_value_requests: &'a dyn TableValueRequests,
+        requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Page<TableValue>> {
+        let index_page = requests.values_page_with_key_range(
+            &self.value_cf_name(),
+            start_key_prefix,
+            end_key_prefix,
+            page_request,
+        )?;
+        self.table_values_page(index_page, table_value_requests)
+    }
+
+    /// Runs a query on a text index, see text_search for the syntax.
+    /// Returns the matching values with their scores, best first.
+    fn search_values<'a>(
+        &self,
+        query: &str,
+        limit: Option<usize>,
+        parent_requests: &dyn TableStoredRequests,
+        table_value_requests: &'a dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+        requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Vec<(TableValue, f64)>> {
+        let index = self
+            .get_index(parent_requests)?
+            .ok_or(DbError::IndexNotInitialized)?;
+        if index.building {
+            return Err(DbError::IndexNotReady);
+        }
+        let options = match &index.kind {
+            IndexKind::Text(options) => options,
+            _ => {
+                return Err(DbError::InvalidQuery(format!(
+                    "index {} is not a text index",
+                    self.index_name
+                )))
+            }
+        };
+        let text_query = parse_text_query(query, options)?;
+        let found = search_text_index(self, &text_query, limit, index_value_requests, requests)?;
+        self.scored_table_values(found, table_value_requests)
+    }
+
+    /// Finds the values whose vectors are the nearest to the vector of the query.
+    /// Returns the values with their distances, closest first.
+    fn nearest_values<'a>(
+        &self,
+        query: &VectorQuery,
+        parent_requests: &dyn TableStoredRequests,
+        table_value_requests: &'a dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+        requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Vec<(TableValue, f64)>> {
+        let index = self
+            .get_index(parent_requests)?
+            .ok_or(DbError::IndexNotInitialized)?;
+        if index.building {
+            return Err(DbError::IndexNotReady);
+        }
+        let options = match &index.kind {
+            IndexKind::Vector(options) => options,
+            _ => {
+                return Err(DbError::InvalidQuery(format!(
+                    "index {} is not a vector index",
+                    self.index_name
+                )))
+            }
+        };
+        let found = search_vector_index(
+            &index,
+            options,
+            query,
+            parent_requests,
+            index_value_requests,
+            requests,
+        )?;
+        self.scored_table_values(found, table_value_requests)
+    }
+
     fn all_values_with_key_prefix_vec<'a>(
         &self,
         key_prefix: OndoKey,
@@ -344,10 +681,10 @@ impl IndexReferenceTrait for IndexReference {
         Ok(iterator.collect())
     }
 
-    fn all_index_values_with_key_prefix_vec<'a>(
+    fn all_index_values_with_key_prefix_vec(
         &self,
         key_prefix: OndoKey,
-        index_iterator_requests_factory: &'a IndexIteratorRequestsFactoryEnum,
+        index_iterator_requests_factory: &IndexIteratorRequestsFactoryEnum,
     ) -> DbResult<Vec<DbResult<OndoKey>>> {
         let index_iterator_requests_enum =
             index_iterator_requests_factory.create_read_locked_requests()?;
@@ -378,11 +715,11 @@ impl IndexReferenceTrait for IndexReference {
         Ok(iterator.collect())
     }
 
-    fn all_index_values_with_key_range_vec<'a>(
+    fn all_index_values_with_key_range_vec(
         &self,
         start_key_prefix: OndoKey,
         end_key_prefix: OndoKey,
-        index_iterator_requests_factory: &'a IndexIteratorRequestsFactoryEnum,
+        index_iterator_requests_factory: &IndexIteratorRequestsFactoryEnum,
     ) -> DbResult<Vec<DbResult<OndoKey>>> {
         let index_iterator_requests_enum =
             index_iterator_requests_factory.create_read_locked_requests()?;
@@ -402,6 +739,7 @@ mod tests {
     use super::*;
     use crate::db::entity::{Table, TableStored};
     use crate::db::reference::effect::table_stored_effect::TableStoredEffect;
+    use crate::db::reference::index_value_reference::tests::MockIndexValueTestRequests;
     use crate::db::reference::table_reference::stored::tests::{
         create_table, create_table_stored, MockTableStoredTestRequests,
     };
@@ -414,6 +752,9 @@ mod tests {
         Index {
             reference: create_index_ref(),
             fields: vec!["sample_field".to_owned()],
+            unique: false,
+            kind: IndexKind::Ordered,
+            building: false,
         }
     }
 
@@ -461,7 +802,6 @@ mod tests {
         #[test]
         fn test_put_index() {
             let mut parent_mock = MockTableStoredTestRequests::new();
-            let iterator_mock_factory = TableStoredIteratorRequestsFactoryEnum::new_mock();
             let index_reference =
                 IndexReference::build("sample_domain", "sample_table", "sample_index");
             let index = create_index();
@@ -470,7 +810,7 @@ mod tests {
                 .expect_get_table_stored()
                 .returning(move |_, _| Ok(Some(table_stored.clone())));
 
-            let effects = index_reference.put_index(&index, &parent_mock, &iterator_mock_factory);
+            let effects = index_reference.put_index(&index, &parent_mock);
             let expected_effects = vec![
                 Effect::TableStoredEffect(TableStoredEffect::Put(
                     "/domains/sample_domain/tables".to_owned(),
@@ -481,6 +821,7 @@ mod tests {
                                 domain_reference: DomainReference::build("sample_domain"),
                                 table_name: "sample_table".to_owned(),
                             },
+                            schema: None,
                         },
                         indexes: [(
                             "sample_index".to_owned(),
@@ -493,14 +834,17 @@ mod tests {
                                     },
                                 },
                                 fields: vec!["sample_field".to_owned()],
+                                unique: false,
+                                kind: IndexKind::Ordered,
+                                building: true,
                             },
                         )]
                         .into_iter()
                         .collect(),
                     },
                 )),
-                Effect::DeleteCf("sample_domain::/sample_table/indexes/sample_index".to_owned()),
-                Effect::CreateCf("sample_domain::/sample_table/indexes/sample_index".to_owned()),
+                Effect::DeleteCf("/domains/sample_domain/tables/sample_table/indexes/sample_index".to_owned()),
+                Effect::CreateCf("/domains/sample_domain/tables/sample_table/indexes/sample_index".to_owned()),
             ];
             assert_eq!(effects.unwrap(), expected_effects);
         }
@@ -508,7 +852,6 @@ mod tests {
         #[test]
         fn test_put_index_failure() {
             let mut parent_mock = MockTableStoredTestRequests::new();
-            let iterator_mock_factory = TableStoredIteratorRequestsFactoryEnum::new_mock();
             let index_reference =
                 IndexReference::build("sample_domain", "sample_table", "sample_index");
             let index = create_index();
@@ -517,7 +860,7 @@ mod tests {
                 .expect_get_table_stored()
                 .returning(move |_, _| Ok(Some(table_stored.clone())));
 
-            let effects = index_reference.put_index(&index, &parent_mock, &iterator_mock_factory);
+            let effects = index_reference.put_index(&index, &parent_mock);
             assert!(effects.is_err());
 
             assert_eq!(effects.unwrap_err(), DbError::IndexNotInitialized);
@@ -525,8 +868,6 @@ mod tests {
         #[test]
         fn test_post_index() {
             let mut parent_mock = MockTableStoredTestRequests::new();
-            let iterator_mock_factory = TableStoredIteratorRequestsFactoryEnum::new_mock();
-
             let index_reference =
                 IndexReference::build("sample_domain", "sample_table", "sample_index");
             let index = create_index();
@@ -544,10 +885,10 @@ mod tests {
             //     .times(1) // Second call
             //     .returning(move |_, _| Ok(Some(expected_table_stored.clone())));
 
-            let effects = index_reference.post_index(&index, &parent_mock, &iterator_mock_factory);
+            let effects = index_reference.post_index(&index, &parent_mock);
             // assert!(effects.is_ok());
             let expected_effects = vec![
-                Effect::CreateCf("sample_domain::/sample_table/indexes/sample_index".to_owned()),
+                Effect::CreateCf("/domains/sample_domain/tables/sample_table/indexes/sample_index".to_owned()),
                 Effect::TableStoredEffect(TableStoredEffect::Put(
                     "/domains/sample_domain/tables".to_owned(),
                     "sample_table".to_owned(),
@@ -557,6 +898,7 @@ mod tests {
                                 domain_reference: DomainReference::build("sample_domain"),
                                 table_name: "sample_table".to_owned(),
                             },
+                            schema: None,
                         },
                         indexes: [(
                             "sample_index".to_owned(),
@@ -569,6 +911,9 @@ mod tests {
                                     },
                                 },
                                 fields: vec!["sample_field".to_owned()],
+                                unique: false,
+                                kind: IndexKind::Ordered,
+                                building: true,
                             },
                         )]
                         .into_iter()
@@ -582,7 +927,6 @@ mod tests {
         #[test]
         fn test_post_index_failure() {
             let mut parent_mock = MockTableStoredTestRequests::new();
-            let iterator_mock_factory = TableStoredIteratorRequestsFactoryEnum::new_mock();
             let index_reference =
                 IndexReference::build("sample_domain", "sample_table", "sample_index");
             let index = create_index();
@@ -591,12 +935,75 @@ mod tests {
                 .expect_get_table_stored()
                 .returning(move |_, _| Ok(Some(table_stored.clone())));
 
-            let effects = index_reference.post_index(&index, &parent_mock, &iterator_mock_factory);
+            let effects = index_reference.post_index(&index, &parent_mock);
             assert!(effects.is_err());
 
             assert_eq!(effects.unwrap_err(), DbError::AlreadyExists);
         }
 
+        #[test]
+        fn test_post_index_invalid_name() {
+            let parent_mock = MockTableStoredTestRequests::new();
+            let index_reference = IndexReference::build("sample_domain", "sample_table", "a/b");
+            let index = create_index();
+
+            let effects = index_reference.post_index(&index, &parent_mock);
+            assert!(matches!(effects, Err(DbError::InvalidName(_))));
+        }
+
+        #[test]
+        fn test_build_index_chunk_of_empty_table() {
+            let mut parent_mock = MockTableStoredTestRequests::new();
+            let index_value_mock = MockIndexValueTestRequests::new();
+            let iterator_mock_factory = TableStoredIteratorRequestsFactoryEnum::new_mock();
+            let index_reference =
+                IndexReference::build("sample_domain", "sample_table", "sample_index");
+            let index = building(&create_index());
+            let table_stored = create_table_stored_with_index(&index);
+            parent_mock
+                .expect_get_table_stored()
+                .returning(move |_, _| Ok(Some(table_stored.clone())));
+
+            let chunk = index_reference
+                .build_index_chunk(
+                    None,
+                    10,
+                    &parent_mock,
+                    &index_value_mock,
+                    &iterator_mock_factory,
+                )
+                .unwrap();
+            assert!(chunk.effects.is_empty());
+            assert_eq!(chunk.values_indexed, 0);
+            assert_eq!(chunk.next_key, None);
+            assert_eq!(
+                index_reference.check_index_ready(&parent_mock),
+                Err(DbError::IndexNotReady)
+            );
+        }
+
+        #[test]
+        fn test_finish_index_build() {
+            let mut parent_mock = MockTableStoredTestRequests::new();
+            let index_reference =
+                IndexReference::build("sample_domain", "sample_table", "sample_index");
+            let index = create_index();
+            let table_stored = create_table_stored_with_index(&building(&index));
+            parent_mock
+                .expect_get_table_stored()
+                .returning(move |_, _| Ok(Some(table_stored.clone())));
+
+            let effects = index_reference.finish_index_build(&parent_mock).unwrap();
+            assert_eq!(
+                effects,
+                vec![Effect::TableStoredEffect(TableStoredEffect::Put(
+                    "/domains/sample_domain/tables".to_owned(),
+                    "sample_table".to_owned(),
+                    create_table_stored_with_index(&index),
+                ))]
+            );
+        }
+
         fn test_delete_index() {
             let mut parent_mock = MockTableStoredTestRequests::new();
             let index_reference =
@@ -44,7 +44,7 @@ impl IndexValueReferenceTrait for IndexValueReference {
         CfNameMaker::for_index_values(&self.index_reference)
     }
     fn get_index_value(&self, requests: &dyn IndexValueRequests) -> DbResult<Option<IndexValue>> {
-        requests.get_index_value_stored(&self.container_cf_name(), &self)
+        requests.get_index_value_stored(&self.container_cf_name(), self)
     }
     fn put_index_value(&self, id: &IndexValue) -> DbResult<Effects> {
         let effect = Effect::IndexValueEffect(IndexValueEffect::Put(
@@ -64,20 +64,27 @@ impl IndexValueReferenceTrait for IndexValueReference {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::db::entity::OndoKey;
     use mockall::*;
     use serde_json::Value;
 
     mock! {
-        IndexValueTestRequests {}
+        pub(crate) IndexValueTestRequests {}
         impl IndexValueRequests for IndexValueTestRequests {
             fn get_index_value_stored(
                 &self,
                 cf_name: &str,
                 key: &IndexValueReference,
-            ) -> DbResult<Option<IndexValue>>;        }
+            ) -> DbResult<Option<IndexValue>>;
+            fn index_values_with_key_prefix(
+                &self,
+                cf_name: &str,
+                key_prefix: &IndexKey,
+                limit: usize,
+            ) -> DbResult<Vec<(IndexKey, IndexValue)>>;
+        }
     }
 
     fn create_index_value_ref(
@@ -134,7 +141,7 @@ mod tests {
 
             let effects = index_value_ref.put_index_value(&index_value).unwrap();
             let expected_effect = Effect::IndexValueEffect(IndexValueEffect::Put(
-                "sample_domain::/sample_table/indexes/sample_index".to_owned(),
+                "/domains/sample_domain/tables/sample_table/indexes/sample_index".to_owned(),
                 index_value_ref.key.clone(),
                 index_value,
             ));
@@ -153,7 +160,7 @@ mod tests {
 
             let effects = index_value_ref.delete_index_value().unwrap();
             let expected_effect = Effect::IndexValueEffect(IndexValueEffect::Delete(
-                "sample_domain::/sample_table/indexes/sample_index".to_owned(),
+                "/domains/sample_domain/tables/sample_table/indexes/sample_index".to_owned(),
                 index_value_ref.key.clone(),
             ));
 
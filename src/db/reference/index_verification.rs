@@ -0,0 +1,358 @@
+//index_verification.rs
+//! The verification of an index against the values of its table.
+//!
+//! The values are indexed again with Index::key_values_of and compared with the entries
+//! of the index. An entry that a value should have and the index lacks is missing, an
+//! entry of a value that is not in the table is orphaned, and an entry that does not match
+//! its value anymore is stale. The HNSW graph of a vector index is compared with the
+//! vectors of the values the same way.
+//!
+//! The repair puts the missing entries, writes the stale entries again or deletes them, and
+//! deletes the orphaned ones, so the index does not need to be rebuilt. The aggregate entry
+//! of a text index is changed with the document entries that the repair writes.
+use crate::db::entity::index::text::is_document_key;
+use crate::db::entity::table_value::get_key_from_table_value;
+use crate::db::entity::{Index, IndexKey, IndexKind, IndexValue, OndoKey, TableValue};
+use crate::db::reference::hnsw::HnswGraph;
+use crate::db::reference::requests::{
+    IndexIteratorRequests, IndexValueRequests, TableStoredIteratorRequests, TableValueRequests,
+};
+use crate::db::reference::text_stats::TextStatsUpdate;
+use crate::db::reference::{
+    Effects, IndexReferenceTrait, IndexValueReference, IndexValueReferenceTrait, PageRequest,
+    TableReferenceTrait, TableValueReference, TableValueReferenceTrait,
+};
+use crate::db::DbResult;
+
+/// The number of keys listed for each kind of problem. The rest are only counted.
+pub(crate) const MAX_REPORTED_KEYS: usize = 100;
+
+/// The number of values, entries or graph nodes verified under one hold of the writes lock.
+pub(crate) const VERIFICATION_CHUNK_SIZE: usize = 1000;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct IndexProblems {
+    pub count: u64,
+    /// The first MAX_REPORTED_KEYS keys of the entries, or ids of the graph nodes.
+    pub keys: Vec<IndexKey>,
+}
+
+impl IndexProblems {
+    fn add(&mut self, key: &IndexKey) {
+        self.count += 1;
+        if self.keys.len() < MAX_REPORTED_KEYS {
+            self.keys.push(key.clone());
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct IndexVerification {
+    pub values_checked: u64,
+    pub entries_checked: u64,
+    pub missing: IndexProblems,
+    pub orphaned: IndexProblems,
+    pub stale: IndexProblems,
+    /// The nodes of the HNSW graph of a vector index.
+    pub missing_nodes: IndexProblems,
+    pub orphaned_nodes: IndexProblems,
+    pub stale_nodes: IndexProblems,
+}
+
+impl IndexVerification {
+    pub fn is_consistent(&self) -> bool {
+        [
+            &self.missing,
+            &self.orphaned,
+            &self.stale,
+            &self.missing_nodes,
+            &self.orphaned_nodes,
+            &self.stale_nodes,
+        ]
+        .iter()
+        .all(|problems| problems.count == 0)
+    }
+}
+
+/// Where a verification continues. The values of the table are compared with the index
+/// first, then the entries of the index with the values, then the nodes of the HNSW graph
+/// of a vector index, a chunk at a time from the key where the previous chunk stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum VerificationStep {
+    Values(Option<OndoKey>),
+    Entries(Option<OndoKey>),
+    Nodes(Option<OndoKey>),
+    Done,
+}
+
+/// A verification that runs a chunk at a time, so that the writes wait for at most one chunk.
+#[derive(Debug)]
+pub(crate) struct IndexVerifier {
+    pub repair: bool,
+    pub chunk_size: usize,
+    pub step: VerificationStep,
+    pub verification: IndexVerification,
+}
+
+impl IndexVerifier {
+    pub fn new(repair: bool, chunk_size: usize) -> Self {
+        IndexVerifier {
+            repair,
+            chunk_size,
+            step: VerificationStep::Values(None),
+            verification: IndexVerification::default(),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.step == VerificationStep::Done
+    }
+
+    /// Compares the next chunk of values, entries or nodes, adds their problems to the
+    /// verification and moves to the next step. Returns the effects that repair the
+    /// problems of the chunk if repair is set.
+    pub fn verify_chunk<'a>(
+        &mut self,
+        index: &Index,
+        table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+        table_stored_iterator_requests: &'a dyn TableStoredIteratorRequests<'a>,
+        index_iterator_requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Effects> {
+        let chunk_size = self.chunk_size;
+        let page_request = |start_key| PageRequest {
+            start_key,
+            page_size: Some(chunk_size),
+        };
+        let all_keys = OndoKey { values: vec![] };
+        let verification = &mut self.verification;
+        let (effects, next_step) = match self.step.clone() {
+            VerificationStep::Values(start_key) => {
+                let page = index
+                    .reference
+                    .table_reference
+                    .values_page_with_key_prefix(
+                        all_keys,
+                        &page_request(start_key),
+                        table_stored_iterator_requests,
+                    )?;
+                let effects = verify_values(
+                    index,
+                    &page.values,
+                    self.repair,
+                    verification,
+                    index_value_requests,
+                )?;
+                let next_step = match page.next_key {
+                    Some(next_key) => VerificationStep::Values(Some(next_key)),
+                    None => VerificationStep::Entries(None),
+                };
+                (effects, next_step)
+            }
+            VerificationStep::Entries(start_key) => {
+                let page = index_iterator_requests.entries_page_with_key_prefix(
+                    &index.reference.value_cf_name(),
+                    all_keys,
+                    &page_request(start_key),
+                )?;
+                let effects = verify_entries(
+                    index,
+                    page.values,
+                    self.repair,
+                    verification,
+                    table_value_requests,
+                    index_value_requests,
+                )?;
+                let next_step = match (page.next_key, index.graph_options()) {
+                    (Some(next_key), _) => VerificationStep::Entries(Some(next_key)),
+                    (None, Some(_)) => VerificationStep::Nodes(None),
+                    (None, None) => VerificationStep::Done,
+                };
+                (effects, next_step)
+            }
+            VerificationStep::Nodes(start_key) => {
+                let page = HnswGraph::stored_ids_page(
+                    &index.reference,
+                    &page_request(start_key),
+                    index_iterator_requests,
+                )?;
+                let effects = verify_nodes(
+                    index,
+                    &page.values,
+                    self.repair,
+                    verification,
+                    table_value_requests,
+                    index_value_requests,
+                )?;
+                let next_step = match page.next_key {
+                    Some(next_key) => VerificationStep::Nodes(Some(next_key)),
+                    None => VerificationStep::Done,
+                };
+                (effects, next_step)
+            }
+            VerificationStep::Done => (vec![], VerificationStep::Done),
+        };
+        self.step = next_step;
+        Ok(effects)
+    }
+}
+
+// The entries and the graph nodes that the values should have.
+fn verify_values(
+    index: &Index,
+    values: &[TableValue],
+    repair: bool,
+    verification: &mut IndexVerification,
+    index_value_requests: &dyn IndexValueRequests,
+) -> DbResult<Effects> {
+    let index_reference = &index.reference;
+    let mut graph = index
+        .graph_options()
+        .map(|(metric, hnsw)| HnswGraph::new(index_reference, metric, hnsw, index_value_requests));
+    let mut stats = text_stats_update(index, repair, index_value_requests);
+    let mut effects = vec![];
+    for value in values {
+        verification.values_checked += 1;
+        if let Some(stats) = stats.as_mut() {
+            stats.set_length(
+                &get_key_from_table_value(value)?,
+                index.text_length_of(value),
+            )?;
+        }
+        for key_value in index.key_values_of(value)? {
+            let reference = IndexValueReference::new(index_reference.clone(), key_value.key);
+            let stored = reference.get_index_value(index_value_requests)?;
+            let problems = match stored {
+                None => &mut verification.missing,
+                Some(stored) if stored != key_value.value => &mut verification.stale,
+                Some(_) => continue,
+            };
+            problems.add(&reference.key);
+            if repair {
+                effects.extend(reference.put_index_value(&key_value.value)?);
+            }
+        }
+        if let Some(graph) = graph.as_mut() {
+            let id = get_key_from_table_value(value)?;
+            let vector = index.vector_of(value)?;
+            let problems = match (graph.vector(&id)?, &vector) {
+                (None, Some(_)) => &mut verification.missing_nodes,
+                (Some(node_vector), Some(vector)) if node_vector != *vector => {
+                    &mut verification.stale_nodes
+                }
+                (Some(_), None) => &mut verification.stale_nodes,
+                _ => continue,
+            };
+            problems.add(&id);
+            if repair {
+                match vector {
+                    Some(vector) => graph.insert(&id, &vector)?,
+                    None => graph.remove(&id)?,
+                }
+            }
+        }
+    }
+    if repair {
+        effects.extend(graph.map(HnswGraph::into_effects).unwrap_or_default());
+    }
+    if let Some(stats) = stats {
+        effects.extend(stats.into_effects()?);
+    }
+    Ok(effects)
+}
+
+// The changes of the aggregate entry of a text index, if the index is repaired.
+fn text_stats_update<'a>(
+    index: &Index,
+    repair: bool,
+    index_value_requests: &'a dyn IndexValueRequests,
+) -> Option<TextStatsUpdate<'a>> {
+    match (repair, &index.kind) {
+        (true, IndexKind::Text(_)) => {
+            Some(TextStatsUpdate::new(&index.reference, index_value_requests))
+        }
+        _ => None,
+    }
+}
+
+// The entries of values that are not in the table or do not match their values anymore.
+fn verify_entries(
+    index: &Index,
+    entries: Vec<(IndexKey, IndexValue)>,
+    repair: bool,
+    verification: &mut IndexVerification,
+    table_value_requests: &dyn TableValueRequests,
+    index_value_requests: &dyn IndexValueRequests,
+) -> DbResult<Effects> {
+    let index_reference = &index.reference;
+    let table_reference = &index_reference.table_reference;
+    let mut stats = text_stats_update(index, repair, index_value_requests);
+    let mut effects = vec![];
+    for (key, value) in entries {
+        verification.entries_checked += 1;
+        if index.is_stats_entry(&key) {
+            continue;
+        }
+        let id = index.id_of_entry(&key, &value);
+        let table_value = match &id {
+            Some(id) => TableValueReference::new(table_reference.clone(), id.clone())
+                .get_table_value(table_value_requests)?,
+            None => None,
+        };
+        let problems = match table_value {
+            None => &mut verification.orphaned,
+            Some(table_value) => {
+                let key_values = index.key_values_of(&table_value)?;
+                if key_values.iter().any(|key_value| key_value.key == key) {
+                    continue;
+                }
+                &mut verification.stale
+            }
+        };
+        problems.add(&key);
+        if let (Some(stats), Some(id)) = (stats.as_mut(), &id) {
+            if is_document_key(&key) {
+                stats.set_length(id, None)?;
+            }
+        }
+        if repair {
+            let reference = IndexValueReference::new(index_reference.clone(), key);
+            effects.extend(reference.delete_index_value()?);
+        }
+    }
+    if let Some(stats) = stats {
+        effects.extend(stats.into_effects()?);
+    }
+    Ok(effects)
+}
+
+// The graph nodes of values that are not in the table.
+fn verify_nodes(
+    index: &Index,
+    ids: &[OndoKey],
+    repair: bool,
+    verification: &mut IndexVerification,
+    table_value_requests: &dyn TableValueRequests,
+    index_value_requests: &dyn IndexValueRequests,
+) -> DbResult<Effects> {
+    let index_reference = &index.reference;
+    let mut graph = match index.graph_options() {
+        Some((metric, hnsw)) => HnswGraph::new(index_reference, metric, hnsw, index_value_requests),
+        None => return Ok(vec![]),
+    };
+    for id in ids {
+        let table_value =
+            TableValueReference::new(index_reference.table_reference.clone(), id.clone())
+                .get_table_value(table_value_requests)?;
+        if table_value.is_none() {
+            verification.orphaned_nodes.add(id);
+            if repair {
+                graph.remove(id)?;
+            }
+        }
+    }
+    Ok(match repair {
+        true => graph.into_effects(),
+        false => vec![],
+    })
+}
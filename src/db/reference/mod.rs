@@ -19,9 +19,27 @@ pub(crate) use table_value_reference::*;
 pub(crate) mod column_value_reference;
 pub(crate) use column_value_reference::*;
 
+pub(crate) mod page;
+pub(crate) use page::*;
+
+pub(crate) mod transaction;
+pub(crate) use transaction::*;
+
+pub(crate) mod filter;
+pub(crate) mod hnsw;
+pub(crate) mod index_verification;
+pub(crate) mod query;
+pub(crate) mod table_validation;
+pub(crate) mod text_search;
+pub(crate) mod text_stats;
+pub(crate) mod vector_search;
+
 mod cf_name;
 pub(crate) use cf_name::*;
 
+mod name;
+pub(crate) use name::*;
+
 pub(crate) mod effect;
 pub(crate) use effect::*;
 // TODO: Check get_ Ok(None) vs Err
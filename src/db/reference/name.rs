@@ -0,0 +1,81 @@
+//name.rs
+use crate::db::{DbError, DbResult};
+
+pub(crate) const MAX_NAME_LENGTH: usize = 128;
+
+/// Checks a domain, table or index name before the entity is created.
+///
+/// A name starts with an ASCII letter or `_` and continues with ASCII letters,
+/// digits, `_`, `-` or `.`, up to MAX_NAME_LENGTH characters.
+pub(crate) fn validate_name(kind: &str, name: &str) -> DbResult<()> {
+    let invalid = |reason: String| {
+        Err(DbError::InvalidName(format!(
+            "{} name {:?} {}",
+            kind, name, reason
+        )))
+    };
+    let mut chars = name.chars();
+    match chars.next() {
+        None => return invalid("is empty".to_owned()),
+        Some(c) if !(c.is_ascii_alphabetic() || c == '_') => {
+            return invalid("must start with an ASCII letter or '_'".to_owned())
+        }
+        Some(_) => {}
+    }
+    if let Some(c) = chars.find(|&c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))) {
+        return invalid(format!(
+            "contains {:?}, only ASCII letters, digits, '_', '-' and '.' are allowed",
+            c
+        ));
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return invalid(format!("is longer than {} characters", MAX_NAME_LENGTH));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_names() {
+        for name in ["a", "_a", "table1", "my-table.v2", "A_B"] {
+            assert_eq!(validate_name("table", name), Ok(()), "{}", name);
+        }
+        assert_eq!(validate_name("table", &"a".repeat(MAX_NAME_LENGTH)), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let too_long = "a".repeat(MAX_NAME_LENGTH + 1);
+        for name in [
+            "",
+            "1a",
+            "-a",
+            ".a",
+            "x/indexes/y",
+            "a::b",
+            "a b",
+            "ä",
+            &too_long,
+        ] {
+            assert!(
+                matches!(validate_name("table", name), Err(DbError::InvalidName(_))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_name_message() {
+        assert_eq!(
+            validate_name("domain", "a::b"),
+            Err(DbError::InvalidName(
+                "domain name \"a::b\" contains ':', only ASCII letters, digits, '_', '-' and '.' are allowed"
+                    .to_owned()
+            ))
+        );
+    }
+}
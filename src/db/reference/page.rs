@@ -0,0 +1,16 @@
+//page.rs
+use crate::db::entity::OndoKey;
+
+/// Where a scan starts and how many values it returns.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct PageRequest {
+    pub start_key: Option<OndoKey>, // the next_key of the previous page
+    pub page_size: Option<usize>,   // None returns all the values
+}
+
+/// The values of a scan, and the key where the next page starts.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Page<T> {
+    pub values: Vec<T>,
+    pub next_key: Option<OndoKey>, // None on the last page
+}
@@ -0,0 +1,673 @@
+//query.rs
+//! Queries of the values of a table with a filter, a sort, a limit and a projection.
+//!
+//! The planner picks the ordered index that serves the most leading predicates of the
+//! filter: equalities and $in on its first fields, then a range on the next field.
+//! An index scan can find values that the filter does not match, so every value found
+//! is checked against the whole filter. Without such an index the table is scanned.
+use crate::db::entity::index::DEFAULT_ID_FIELD;
+use crate::db::entity::{Index, IndexKind, OndoKey, TableValue};
+use crate::db::reference::filter::{compare_values, field_values, integer_of, Condition, Filter};
+use crate::db::reference::requests::{
+    IndexIteratorRequests, TableStoredIteratorRequests, TableStoredRequests, TableValueRequests,
+};
+use crate::db::reference::table_reference::stored::TableStoredReferenceTrait;
+use crate::db::reference::{
+    CfNameMaker, TableReference, TableReferenceTrait, TableValueReference, TableValueReferenceTrait,
+};
+use crate::db::{DbError, DbResult};
+use serde_json::{json, Map, Number, Value};
+use std::collections::{HashMap, HashSet};
+
+// An index scan with more key ranges is not planned further than the previous field.
+const MAX_KEY_RANGES: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Query {
+    pub filter: Filter,
+    /// The values are sorted by the first field, then by the second one, and so on.
+    pub sort: Vec<SortField>,
+    pub limit: Option<usize>,
+    /// The dotted fields of the values to return, with the _id. All the fields if empty.
+    pub projection: Vec<String>,
+}
+
+/// Values without the field are sorted as if it was null, and values with an array
+/// by the first element.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SortField {
+    pub field: String,
+    pub descending: bool,
+}
+
+/// The index keys that an index scan reads.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum KeyRange {
+    Prefix(OndoKey),
+    /// From the start key to the end key, and the keys that start with the end key.
+    Range(OndoKey, OndoKey),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum QueryPlan {
+    TableScan,
+    IndexScan {
+        index_name: String,
+        ranges: Vec<KeyRange>,
+    },
+}
+
+/// The predicates of the filter that an index scan serves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Served {
+    /// The number of leading fields of the index with equalities or $in.
+    pub equalities: usize,
+    /// Whether the next field of the index has a range.
+    pub range: bool,
+}
+
+/// An ordered index that the planner considered, and what it can serve of the filter.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct IndexCandidate {
+    pub index_name: String,
+    /// None if the index can not serve the filter.
+    pub served: Option<Served>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct QueryExplanation {
+    pub plan: QueryPlan,
+    /// The ordered indexes of the table that are built, by name.
+    pub candidates: Vec<IndexCandidate>,
+}
+
+/// Picks the ordered index that serves the most leading predicates of the filter,
+/// the first one by name if several serve as many.
+pub(crate) fn plan_query(filter: &Filter, indexes: &HashMap<String, Index>) -> QueryPlan {
+    explain_query(filter, indexes).plan
+}
+
+/// Plans the query like plan_query, with the indexes that were considered.
+pub(crate) fn explain_query(filter: &Filter, indexes: &HashMap<String, Index>) -> QueryExplanation {
+    let conditions = leading_conditions(filter);
+    let mut index_names = indexes.keys().collect::<Vec<_>>();
+    index_names.sort();
+    let mut candidates = vec![];
+    let mut best: Option<(Served, &String, Vec<KeyRange>)> = None;
+    for index_name in index_names {
+        let index = &indexes[index_name];
+        // An index that is being built does not have all the values yet.
+        if index.kind != IndexKind::Ordered || index.building {
+            continue;
+        }
+        let scan = index_scan(index, &conditions);
+        candidates.push(IndexCandidate {
+            index_name: index_name.clone(),
+            served: scan.as_ref().map(|(served, _)| *served),
+        });
+        if let Some((served, ranges)) = scan {
+            if best
+                .as_ref()
+                .map_or(true, |(best_served, _, _)| served > *best_served)
+            {
+                best = Some((served, index_name, ranges));
+            }
+        }
+    }
+    let plan = match best {
+        Some((_, index_name, ranges)) => QueryPlan::IndexScan {
+            index_name: index_name.clone(),
+            ranges,
+        },
+        None => QueryPlan::TableScan,
+    };
+    QueryExplanation { plan, candidates }
+}
+
+// The conditions on fields that every matching value meets.
+fn leading_conditions(filter: &Filter) -> Vec<(&str, &Condition)> {
+    match filter {
+        Filter::And(filters) => filters.iter().flat_map(leading_conditions).collect(),
+        Filter::Field(field, condition) => vec![(field.as_str(), condition)],
+        Filter::Or(_) | Filter::Not(_) => vec![],
+    }
+}
+
+fn index_scan(index: &Index, conditions: &[(&str, &Condition)]) -> Option<(Served, Vec<KeyRange>)> {
+    let mut prefixes = vec![OndoKey { values: vec![] }];
+    let mut equalities = 0;
+    for field in index.fields.iter() {
+        let field_conditions = conditions
+            .iter()
+            .filter(|(condition_field, _)| condition_field == field)
+            .map(|(_, condition)| *condition)
+            .collect::<Vec<_>>();
+        let values = field_conditions
+            .iter()
+            .find_map(|condition| key_values_of(condition));
+        if let Some(values) = values {
+            if prefixes.len() * values.len() > MAX_KEY_RANGES {
+                break;
+            }
+            prefixes = prefixes
+                .iter()
+                .flat_map(|prefix| values.iter().map(move |value| with_value(prefix, value)))
+                .collect();
+            equalities += 1;
+            continue;
+        }
+        if let Some((start, end)) = key_bounds(&field_conditions) {
+            let ranges = prefixes
+                .iter()
+                .map(|prefix| KeyRange::Range(with_value(prefix, &start), with_value(prefix, &end)))
+                .collect();
+            let served = Served {
+                equalities,
+                range: true,
+            };
+            return Some((served, ranges));
+        }
+        break;
+    }
+    if equalities == 0 {
+        return None;
+    }
+    let ranges = prefixes.into_iter().map(KeyRange::Prefix).collect();
+    let served = Served {
+        equalities,
+        range: false,
+    };
+    Some((served, ranges))
+}
+
+fn with_value(prefix: &OndoKey, value: &Value) -> OndoKey {
+    let mut key = prefix.clone();
+    key.values.push(value.clone());
+    key
+}
+
+// Objects and arrays are not in the keys of the ordered indexes.
+fn is_key_value(value: &Value) -> bool {
+    !value.is_array() && !value.is_object()
+}
+
+// The index key values of the values that are equal to the condition.
+fn key_values_of(condition: &Condition) -> Option<Vec<Value>> {
+    let operands = match condition {
+        Condition::Eq(operand) => std::slice::from_ref(operand),
+        Condition::In(operands) if !operands.is_empty() => operands.as_slice(),
+        _ => return None,
+    };
+    if !operands.iter().all(is_key_value) {
+        return None;
+    }
+    let mut values: Vec<Value> = vec![];
+    for value in operands.iter().flat_map(key_forms) {
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+    Some(values)
+}
+
+// An integer has a different key as an integer and as a float, e.g. 1 and 1.0.
+fn key_forms(value: &Value) -> Vec<Value> {
+    let number = match value {
+        Value::Number(number) => number,
+        _ => return vec![value.clone()],
+    };
+    let mut forms = vec![value.clone()];
+    if let Some(integer) = integer_of(number) {
+        let other_form = if number.is_f64() {
+            i64::try_from(integer)
+                .ok()
+                .map(Number::from)
+                .or_else(|| u64::try_from(integer).ok().map(Number::from))
+        } else {
+            Number::from_f64(integer as f64).filter(|float| integer_of(float) == Some(integer))
+        };
+        forms.extend(other_form.map(Value::Number));
+    }
+    forms
+}
+
+// The start and the end keys of the range conditions of a field. The keys can be
+// wider than the conditions, e.g. the end of the strings is the empty array.
+fn key_bounds(conditions: &[&Condition]) -> Option<(Value, Value)> {
+    let bounds = conditions
+        .iter()
+        .filter_map(|condition| match condition {
+            Condition::Gt(operand) | Condition::Gte(operand) => Some((Some(operand), None)),
+            Condition::Lt(operand) | Condition::Lte(operand) => Some((None, Some(operand))),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let (first_lower, first_upper) = bounds.first()?;
+    let rank = type_of(first_lower.or(*first_upper)?);
+    let same_type = |operand: &&Value| type_of(operand) == rank;
+    let lower = bounds
+        .iter()
+        .filter_map(|(lower, _)| *lower)
+        .filter(same_type)
+        .max_by(|a, b| compare_values(a, b));
+    let upper = bounds
+        .iter()
+        .filter_map(|(_, upper)| *upper)
+        .filter(same_type)
+        .min_by(|a, b| compare_values(a, b));
+    let start = match (lower, rank) {
+        (Some(Value::Number(number)), _) => float_key(step_float(number.as_f64()?, false)),
+        (Some(lower), _) => lower.clone(),
+        (None, key_type) => key_type.first_key(),
+    };
+    let end = match (upper, rank) {
+        (Some(Value::Number(number)), _) => float_key(step_float(number.as_f64()?, true)),
+        (Some(upper), _) => upper.clone(),
+        (None, KeyType::Null) => Value::Null,
+        (None, KeyType::Bool) => json!(true),
+        (None, KeyType::Number) => float_key(f64::MAX),
+        (None, KeyType::String) => json!([]),
+    };
+    Some((start, end))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KeyType {
+    Null,
+    Bool,
+    Number,
+    String,
+}
+
+impl KeyType {
+    fn first_key(&self) -> Value {
+        match self {
+            KeyType::Null => Value::Null,
+            KeyType::Bool => json!(false),
+            KeyType::Number => float_key(f64::MIN),
+            KeyType::String => json!(""),
+        }
+    }
+}
+
+// The operands of the range conditions are null, booleans, numbers or strings.
+fn type_of(value: &Value) -> KeyType {
+    match value {
+        Value::Bool(_) => KeyType::Bool,
+        Value::Number(_) => KeyType::Number,
+        Value::String(_) => KeyType::String,
+        _ => KeyType::Null,
+    }
+}
+
+// The keys of the numbers start with their floats, so a float key one step away
+// is a bound of the keys of every number equal to the float.
+fn float_key(float: f64) -> Value {
+    json!(float.clamp(f64::MIN, f64::MAX))
+}
+
+// The next float up or down, like f64::next_up and f64::next_down.
+fn step_float(float: f64, up: bool) -> f64 {
+    if float == 0.0 {
+        let smallest = f64::from_bits(1);
+        return if up { smallest } else { -smallest };
+    }
+    let bits = float.to_bits();
+    if (float > 0.0) == up {
+        f64::from_bits(bits + 1)
+    } else {
+        f64::from_bits(bits - 1)
+    }
+}
+
+/// Runs the query on the values of the table.
+pub(crate) fn query_values<'a>(
+    table_reference: &TableReference,
+    query: &Query,
+    parent_requests: &dyn TableStoredRequests,
+    table_value_requests: &'a dyn TableValueRequests,
+    index_requests: &'a dyn IndexIteratorRequests<'a>,
+    requests: &'a dyn TableStoredIteratorRequests<'a>,
+) -> DbResult<Vec<TableValue>> {
+    let table_stored = table_reference
+        .get_table_stored(parent_requests)?
+        .ok_or(DbError::TableNotInitialized)?;
+    let values = match plan_query(&query.filter, &table_stored.indexes) {
+        QueryPlan::TableScan => table_reference.all_values(requests)?,
+        QueryPlan::IndexScan { index_name, ranges } => {
+            let index = &table_stored.indexes[&index_name];
+            scan_index(index, ranges, table_value_requests, index_requests)?
+        }
+    };
+    let mut found = vec![];
+    for value in values {
+        let value = value?;
+        if query.filter.matches(&value) {
+            found.push(value);
+            if query.sort.is_empty() && Some(found.len()) == query.limit {
+                break;
+            }
+        }
+    }
+    sort_values(&mut found, &query.sort);
+    if let Some(limit) = query.limit {
+        found.truncate(limit);
+    }
+    Ok(found
+        .into_iter()
+        .map(|value| project(value, &query.projection))
+        .collect())
+}
+
+// The values found in the key ranges of the index, each one once.
+fn scan_index<'a>(
+    index: &Index,
+    ranges: Vec<KeyRange>,
+    table_value_requests: &'a dyn TableValueRequests,
+    requests: &'a dyn IndexIteratorRequests<'a>,
+) -> DbResult<Box<dyn Iterator<Item = DbResult<TableValue>> + 'a>> {
+    let cf_name = CfNameMaker::for_index_values(&index.reference);
+    let ids = ranges
+        .into_iter()
+        .map(|range| match range {
+            KeyRange::Prefix(prefix) => requests.all_values_with_key_prefix(&cf_name, prefix),
+            KeyRange::Range(start, end) => requests.all_values_with_key_range(&cf_name, start, end),
+        })
+        .collect::<DbResult<Vec<_>>>()?;
+    let table_reference = index.reference.table_reference.clone();
+    let mut seen = HashSet::new();
+    let values = ids
+        .into_iter()
+        .flatten()
+        .filter(move |id| match id {
+            // A multikey index or several key ranges can find a value more than once.
+            Ok(id) => seen.insert(serde_json::to_string(&id.values).unwrap_or_default()),
+            Err(_) => true,
+        })
+        .map(move |id| {
+            TableValueReference::new(table_reference.clone(), id?)
+                .get_table_value(table_value_requests)?
+                .ok_or(DbError::NotFound)
+        });
+    Ok(Box::new(values))
+}
+
+fn sort_values(values: &mut [TableValue], sort: &[SortField]) {
+    if sort.is_empty() {
+        return;
+    }
+    values.sort_by(|a, b| {
+        sort.iter()
+            .map(|sort_field| {
+                let ordering = compare_values(
+                    sort_value(a, &sort_field.field),
+                    sort_value(b, &sort_field.field),
+                );
+                if sort_field.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+}
+
+fn sort_value<'a>(value: &'a TableValue, field: &str) -> &'a Value {
+    let first = field_values(value, field).into_iter().next().flatten();
+    match first {
+        Some(Value::Array(elements)) => elements.first().unwrap_or(&Value::Null),
+        Some(value) => value,
+        None => &Value::Null,
+    }
+}
+
+// The _id and the fields of the projection, all the fields if it is empty.
+fn project(value: TableValue, projection: &[String]) -> TableValue {
+    if projection.is_empty() {
+        return value;
+    }
+    let mut projected = Map::new();
+    if let Some(id) = value.get(DEFAULT_ID_FIELD) {
+        projected.insert(DEFAULT_ID_FIELD.to_owned(), id.clone());
+    }
+    for field in projection {
+        let parts = field.split('.').collect::<Vec<_>>();
+        let found = parts
+            .iter()
+            .try_fold(&value, |value, part| value.as_object()?.get(*part));
+        if let Some(found) = found {
+            insert_field(&mut projected, &parts, found.clone());
+        }
+    }
+    Value::Object(projected)
+}
+
+fn insert_field(object: &mut Map<String, Value>, parts: &[&str], value: Value) {
+    match parts {
+        [] => {}
+        [last] => {
+            object.insert(last.to_string(), value);
+        }
+        [first, rest @ ..] => {
+            let inner = object
+                .entry(first.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(inner) = inner {
+                insert_field(inner, rest, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::reference::filter::parse_filter;
+    use crate::db::reference::{IndexReference, TableReference};
+
+    fn indexes(indexes: &[(&str, &[&str], IndexKind)]) -> HashMap<String, Index> {
+        let table_reference = TableReference::build("domain", "table");
+        indexes
+            .iter()
+            .map(|(name, fields, kind)| {
+                let index = Index {
+                    reference: IndexReference::new(table_reference.clone(), name),
+                    fields: fields.iter().map(|field| field.to_string()).collect(),
+                    unique: false,
+                    kind: kind.clone(),
+                    building: false,
+                };
+                (name.to_string(), index)
+            })
+            .collect()
+    }
+
+    fn plan(filter: Value) -> QueryPlan {
+        let indexes = indexes(&[
+            ("by_city", &["city"], IndexKind::Ordered),
+            ("by_city_age", &["city", "age"], IndexKind::Ordered),
+            ("by_age", &["age"], IndexKind::Ordered),
+            ("by_text", &["name"], IndexKind::Text(Default::default())),
+        ]);
+        plan_query(&parse_filter(&filter).unwrap(), &indexes)
+    }
+
+    fn key(values: Vec<Value>) -> OndoKey {
+        OndoKey { values }
+    }
+
+    fn index_scan(index_name: &str, ranges: Vec<KeyRange>) -> QueryPlan {
+        QueryPlan::IndexScan {
+            index_name: index_name.to_owned(),
+            ranges,
+        }
+    }
+
+    #[test]
+    fn test_plan_query() {
+        assert_eq!(plan(json!({})), QueryPlan::TableScan);
+        assert_eq!(plan(json!({"name": "John"})), QueryPlan::TableScan);
+        assert_eq!(
+            plan(json!({"$or": [{"city": "Paris"}, {"age": 1}]})),
+            QueryPlan::TableScan
+        );
+        assert_eq!(
+            plan(json!({"city": "Paris", "name": "John"})),
+            index_scan("by_city", vec![KeyRange::Prefix(key(vec![json!("Paris")]))])
+        );
+        assert_eq!(
+            plan(json!({"$and": [{"city": "Paris"}, {"age": {"$gte": 18}}]})),
+            index_scan(
+                "by_city_age",
+                vec![KeyRange::Range(
+                    key(vec![json!("Paris"), json!(17.999999999999996)]),
+                    key(vec![json!("Paris"), json!(f64::MAX)]),
+                )]
+            )
+        );
+        assert_eq!(
+            plan(json!({"city": {"$in": ["Paris", "Rome"]}, "age": 30})),
+            index_scan(
+                "by_city_age",
+                vec![
+                    KeyRange::Prefix(key(vec![json!("Paris"), json!(30)])),
+                    KeyRange::Prefix(key(vec![json!("Paris"), json!(30.0)])),
+                    KeyRange::Prefix(key(vec![json!("Rome"), json!(30)])),
+                    KeyRange::Prefix(key(vec![json!("Rome"), json!(30.0)])),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_explain_query() {
+        let indexes = indexes(&[
+            ("by_city", &["city"], IndexKind::Ordered),
+            ("by_city_age", &["city", "age"], IndexKind::Ordered),
+            ("by_age", &["age"], IndexKind::Ordered),
+            ("by_text", &["name"], IndexKind::Text(Default::default())),
+        ]);
+        let filter = parse_filter(&json!({"city": "Paris", "age": {"$gt": 1}})).unwrap();
+        let explanation = explain_query(&filter, &indexes);
+        assert_eq!(explanation.plan, plan_query(&filter, &indexes));
+        let candidate = |index_name: &str, served: Option<(usize, bool)>| IndexCandidate {
+            index_name: index_name.to_owned(),
+            served: served.map(|(equalities, range)| Served { equalities, range }),
+        };
+        assert_eq!(
+            explanation.candidates,
+            vec![
+                candidate("by_age", Some((0, true))),
+                candidate("by_city", Some((1, false))),
+                candidate("by_city_age", Some((1, true))),
+            ]
+        );
+
+        let filter = parse_filter(&json!({"name": "John"})).unwrap();
+        let explanation = explain_query(&filter, &indexes);
+        assert_eq!(explanation.plan, QueryPlan::TableScan);
+        assert!(explanation
+            .candidates
+            .iter()
+            .all(|candidate| candidate.served.is_none()));
+        assert_eq!(explanation.candidates.len(), 3);
+    }
+
+    #[test]
+    fn test_plan_query_with_ranges() {
+        assert_eq!(
+            plan(json!({"city": {"$gt": "P", "$lte": "R"}})),
+            index_scan(
+                "by_city",
+                vec![KeyRange::Range(
+                    key(vec![json!("P")]),
+                    key(vec![json!("R")])
+                )]
+            )
+        );
+        assert_eq!(
+            plan(json!({"city": {"$gt": "P"}})),
+            index_scan(
+                "by_city",
+                vec![KeyRange::Range(key(vec![json!("P")]), key(vec![json!([])]))]
+            )
+        );
+        assert_eq!(
+            plan(json!({"age": {"$lt": 0.5}})),
+            index_scan(
+                "by_age",
+                vec![KeyRange::Range(
+                    key(vec![json!(f64::MIN)]),
+                    key(vec![json!(0.5000000000000001)])
+                )]
+            )
+        );
+        assert_eq!(
+            plan(json!({"city": {"$gte": false}})),
+            index_scan(
+                "by_city",
+                vec![KeyRange::Range(
+                    key(vec![json!(false)]),
+                    key(vec![json!(true)])
+                )]
+            )
+        );
+        assert_eq!(
+            plan(json!({"city": {"$exists": true}})),
+            QueryPlan::TableScan
+        );
+        assert_eq!(
+            plan(json!({"city": {"$ne": "Paris"}})),
+            QueryPlan::TableScan
+        );
+    }
+
+    #[test]
+    fn test_key_forms() {
+        assert_eq!(key_forms(&json!(1)), vec![json!(1), json!(1.0)]);
+        assert_eq!(key_forms(&json!(-2.0)), vec![json!(-2.0), json!(-2)]);
+        assert_eq!(key_forms(&json!(1.5)), vec![json!(1.5)]);
+        assert_eq!(key_forms(&json!(u64::MAX)), vec![json!(u64::MAX)]);
+        assert_eq!(key_forms(&json!("1")), vec![json!("1")]);
+    }
+
+    #[test]
+    fn test_step_float() {
+        for float in [0.0, 1.0, -1.0, 1e-300, -3.5e10, f64::MAX] {
+            assert!(step_float(float, true) > float);
+            assert!(step_float(float, false) < float);
+        }
+        assert_eq!(step_float(step_float(1.0, true), false), 1.0);
+    }
+
+    #[test]
+    fn test_sort_and_project() {
+        let mut values = vec![
+            json!({"_id": [1], "a": {"b": 2, "c": 3}, "d": 1}),
+            json!({"_id": [2], "a": {"b": 1}, "d": 2}),
+            json!({"_id": [3], "d": 1}),
+        ];
+        sort_values(
+            &mut values,
+            &[
+                SortField {
+                    field: "d".to_owned(),
+                    descending: false,
+                },
+                SortField {
+                    field: "a.b".to_owned(),
+                    descending: true,
+                },
+            ],
+        );
+        let ids = values.iter().map(|value| &value["_id"]).collect::<Vec<_>>();
+        assert_eq!(ids, vec![&json!([1]), &json!([3]), &json!([2])]);
+        assert_eq!(
+            project(values[0].clone(), &["a.c".to_owned(), "x".to_owned()]),
+            json!({"_id": [1], "a": {"c": 3}})
+        );
+        assert_eq!(project(values[1].clone(), &[]), values[1]);
+    }
+}
@@ -1,16 +1,44 @@
-use crate::db::entity::{IndexValue, OndoKey};
+use crate::db::entity::{IndexKey, IndexValue, OndoKey};
+use crate::db::reference::{Page, PageRequest};
 use crate::db::DbResult;
 
+pub(crate) type IndexEntryIterator<'a> =
+    Box<dyn Iterator<Item = DbResult<(IndexKey, IndexValue)>> + 'a>;
+
 pub(crate) trait IndexIteratorRequests<'a> {
     fn all_values_with_key_prefix(
         &'a self,
         value_cf_name: &str,
         key_prefix: OndoKey,
     ) -> DbResult<Box<dyn Iterator<Item = DbResult<IndexValue>> + 'a>>;
+    fn all_entries_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+    ) -> DbResult<IndexEntryIterator<'a>>;
+    fn values_page_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<IndexValue>>;
+    fn entries_page_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<(IndexKey, IndexValue)>>;
     fn all_values_with_key_range(
         &'a self,
         value_cf_name: &str,
         start_key_prefix: OndoKey,
         end_key_prefix: OndoKey,
     ) -> DbResult<Box<dyn Iterator<Item = DbResult<IndexValue>> + 'a>>;
+    fn values_page_with_key_range(
+        &'a self,
+        value_cf_name: &str,
+        start_key_prefix: OndoKey,
+        end_key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<IndexValue>>;
 }
@@ -1,4 +1,4 @@
-use crate::db::entity::IndexValue;
+use crate::db::entity::{IndexKey, IndexValue};
 use crate::db::reference::IndexValueReference;
 use crate::db::DbResult;
 pub(crate) trait IndexValueRequests {
@@ -7,4 +7,11 @@ pub(crate) trait IndexValueRequests {
         cf_name: &str,
         key: &IndexValueReference,
     ) -> DbResult<Option<IndexValue>>;
+    /// Returns at most limit index entries whose keys start with key_prefix.
+    fn index_values_with_key_prefix(
+        &self,
+        cf_name: &str,
+        key_prefix: &IndexKey,
+        limit: usize,
+    ) -> DbResult<Vec<(IndexKey, IndexValue)>>;
 }
@@ -1,10 +1,15 @@
+use crate::db::entity::table_schema::TableSchemas;
 use crate::db::entity::OndoKey;
 use crate::db::entity::{TableStored, TableValue};
-use crate::db::reference::TableName;
+use crate::db::reference::{Page, PageRequest, TableName};
 use crate::db::DbResult;
 
 pub(crate) trait TableStoredRequests {
     fn get_table_stored(&self, cf_name: &str, key: &TableName) -> DbResult<Option<TableStored>>;
+    /// The compiled schemas of the tables, if the database keeps them between requests.
+    fn table_schemas(&self) -> Option<&TableSchemas> {
+        None
+    }
 }
 
 pub(crate) trait TableStoredIteratorRequests<'a> {
@@ -17,6 +22,12 @@ pub(crate) trait TableStoredIteratorRequests<'a> {
         value_cf_name: &str,
         key_prefix: OndoKey,
     ) -> DbResult<Box<dyn Iterator<Item = DbResult<TableValue>> + 'a>>;
+    fn values_page_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<TableValue>>;
     fn all_values_with_key_range(
         &'a self,
         value_cf_name: &str,
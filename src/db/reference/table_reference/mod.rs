@@ -1,10 +1,14 @@
 //table_reference.rs
-use super::{CfNameMaker, DomainReference, Effect, Effects};
+use super::filter::Filter;
+use super::query::{explain_query, query_values, Query, QueryExplanation};
+use super::table_validation::{validate_table_values, TableValidation};
+use super::{validate_name, CfNameMaker, DomainReference, Effect, Effects, Page, PageRequest};
 use crate::db::reference::requests::{
-    DomainStoredRequests, TableStoredIteratorRequests, TableStoredRequests,
+    DomainStoredRequests, IndexIteratorRequests, TableStoredIteratorRequests, TableStoredRequests,
+    TableValueRequests,
 };
 use crate::db::{
-    entity::{table_value::TableValue, OndoKey, Table, TableStored},
+    entity::{table_schema::compile_schema, table_value::TableValue, OndoKey, Table, TableStored},
     DbError, DbResult,
 };
 use serde::{Deserialize, Serialize};
@@ -42,11 +46,35 @@ pub(crate) trait TableReferenceTrait {
         end_key: OndoKey,
         requests: &'a dyn TableStoredIteratorRequests<'a>,
     ) -> DbResult<Box<dyn Iterator<Item = DbResult<TableValue>> + 'a>>;
+    fn values_page_with_key_prefix<'a>(
+        &self,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+        requests: &'a dyn TableStoredIteratorRequests<'a>,
+    ) -> DbResult<Page<TableValue>>;
+    fn query_values<'a>(
+        &self,
+        query: &Query,
+        parent_requests: &dyn TableStoredRequests,
+        table_value_requests: &'a dyn TableValueRequests,
+        index_requests: &'a dyn IndexIteratorRequests<'a>,
+        requests: &'a dyn TableStoredIteratorRequests<'a>,
+    ) -> DbResult<Vec<TableValue>>;
+    fn explain_query(
+        &self,
+        filter: &Filter,
+        parent_requests: &dyn TableStoredRequests,
+    ) -> DbResult<QueryExplanation>;
+    fn validate_values<'a>(
+        &self,
+        schema: &serde_json::Value,
+        requests: &'a dyn TableStoredIteratorRequests<'a>,
+    ) -> DbResult<TableValidation>;
 }
 // FIXME use factory instead of iterator requests.
 
 pub type TableName = String;
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct TableReference {
     pub domain_reference: DomainReference,
     pub table_name: TableName,
@@ -61,7 +89,7 @@ impl TableReference {
     }
     pub fn new(domain_reference: DomainReference, table_name: &str) -> Self {
         TableReference {
-            domain_reference: domain_reference,
+            domain_reference,
             table_name: table_name.to_owned(),
         }
     }
@@ -94,16 +122,66 @@ impl TableReferenceTrait for TableReference {
     ) -> DbResult<Box<dyn Iterator<Item = DbResult<TableValue>> + 'a>> {
         self.all_values_with_key_range_(start_key, end_key, requests)
     }
+    fn values_page_with_key_prefix<'a>(
+        &self,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+        requests: &'a dyn TableStoredIteratorRequests<'a>,
+    ) -> DbResult<Page<TableValue>> {
+        self.values_page_with_key_prefix_(key_prefix, page_request, requests)
+    }
+
+    /// Runs a query with a filter on the values of the table, see query for the plans.
+    fn query_values<'a>(
+        &self,
+        query: &Query,
+        parent_requests: &dyn TableStoredRequests,
+        table_value_requests: &'a dyn TableValueRequests,
+        index_requests: &'a dyn IndexIteratorRequests<'a>,
+        requests: &'a dyn TableStoredIteratorRequests<'a>,
+    ) -> DbResult<Vec<TableValue>> {
+        query_values(
+            self,
+            query,
+            parent_requests,
+            table_value_requests,
+            index_requests,
+            requests,
+        )
+    }
+
+    /// Plans a query with the filter without running it.
+    fn explain_query(
+        &self,
+        filter: &Filter,
+        parent_requests: &dyn TableStoredRequests,
+    ) -> DbResult<QueryExplanation> {
+        let table_stored = self
+            .get_table_stored(parent_requests)?
+            .ok_or(DbError::TableNotInitialized)?;
+        Ok(explain_query(filter, &table_stored.indexes))
+    }
+
+    /// Checks the values of the table against a schema, which need not be the table's.
+    fn validate_values<'a>(
+        &self,
+        schema: &serde_json::Value,
+        requests: &'a dyn TableStoredIteratorRequests<'a>,
+    ) -> DbResult<TableValidation> {
+        validate_table_values(self, schema, requests)
+    }
     fn get_table(&self, requests: &dyn TableStoredRequests) -> DbResult<Option<Table>> {
         self.get_table_stored(requests)
             .map(|opt| opt.map(|table_stored| table_stored.table))
     }
 
     fn put_table(&self, table: &Table, requests: &dyn TableStoredRequests) -> DbResult<Effects> {
+        check_schema(table)?;
         let stored_opt = self.get_table_stored(requests)?;
         let stored = stored_opt.ok_or(DbError::TableNotInitialized)?;
         let mut new_stored = stored.clone();
         new_stored.table = (*table).clone();
+        forget_table_schema(self, requests);
         self.put_table_stored(&new_stored)
     }
 
@@ -113,6 +191,8 @@ impl TableReferenceTrait for TableReference {
         requests: &dyn TableStoredRequests,
         parent_requests: &dyn DomainStoredRequests,
     ) -> DbResult<Effects> {
+        validate_name("table", &self.table_name)?;
+        check_schema(table)?;
         let stored_opt = self.get_table_stored(requests)?;
         match stored_opt {
             Some(_) => {
@@ -134,6 +214,7 @@ impl TableReferenceTrait for TableReference {
         requests: &dyn TableStoredRequests,
         parent_requests: &dyn DomainStoredRequests,
     ) -> DbResult<Effects> {
+        forget_table_schema(self, requests);
         self.delete_table_stored(requests, parent_requests)
     }
 
@@ -142,6 +223,20 @@ impl TableReferenceTrait for TableReference {
     }
 }
 
+// The compiled schema of the table is dropped when its schema changes or it is deleted.
+fn forget_table_schema(table_reference: &TableReference, requests: &dyn TableStoredRequests) {
+    if let Some(table_schemas) = requests.table_schemas() {
+        table_schemas.forget(table_reference);
+    }
+}
+
+fn check_schema(table: &Table) -> DbResult<()> {
+    match &table.schema {
+        Some(schema) => compile_schema(schema).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
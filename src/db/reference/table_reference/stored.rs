@@ -1,5 +1,4 @@
 //table_reference/stored.rs
-//TODO: validate domain name
 //TEST: test cascade_delete
 use super::*;
 use crate::db::{
@@ -9,7 +8,7 @@ use crate::db::{
         effect::TableStoredEffect,
         index_reference::*,
         requests::{DomainStoredRequests, TableStoredIteratorRequests, TableStoredRequests},
-        IndexReference,
+        IndexReference, Page, PageRequest,
     },
     DbResult,
 };
@@ -38,6 +37,18 @@ impl<'a> TableStoredIteratorRequests<'a> for MockTableStoredIteratorTestRequests
         Ok(Box::new(std::iter::empty()))
     }
 
+    fn values_page_with_key_prefix(
+        &'a self,
+        _value_cf_name: &str,
+        _key_prefix: OndoKey,
+        _page_request: &PageRequest,
+    ) -> DbResult<Page<TableValue>> {
+        Ok(Page {
+            values: vec![],
+            next_key: None,
+        })
+    }
+
     fn all_values_with_key_range(
         &'a self,
         _value_cf_name: &str,
@@ -84,6 +95,12 @@ pub(crate) trait TableStoredReferenceTrait {
         end_key: OndoKey,
         requests: &'a dyn TableStoredIteratorRequests<'a>,
     ) -> DbResult<Box<dyn Iterator<Item = DbResult<TableValue>> + 'a>>;
+    fn values_page_with_key_prefix_<'a>(
+        &self,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+        requests: &'a dyn TableStoredIteratorRequests<'a>,
+    ) -> DbResult<Page<TableValue>>;
 }
 
 impl TableStoredReferenceTrait for TableReference {
@@ -92,7 +109,7 @@ impl TableStoredReferenceTrait for TableReference {
     }
 
     fn value_cf_name(&self) -> String {
-        CfNameMaker::for_table_values(&self)
+        CfNameMaker::for_table_values(self)
     }
 
     fn required_cf_names(&self) -> Vec<String> {
@@ -123,6 +140,15 @@ impl TableStoredReferenceTrait for TableReference {
         requests.all_values_with_key_range(&self.value_cf_name(), start_key, end_key)
     }
 
+    fn values_page_with_key_prefix_<'a>(
+        &self,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+        requests: &'a dyn TableStoredIteratorRequests<'a>,
+    ) -> DbResult<Page<TableValue>> {
+        requests.values_page_with_key_prefix(&self.value_cf_name(), key_prefix, page_request)
+    }
+
     fn get_table_stored(
         &self,
         requests: &dyn TableStoredRequests,
@@ -244,6 +270,7 @@ pub mod tests {
     pub(crate) fn create_table() -> Table {
         Table {
             reference: create_table_ref(),
+            schema: None,
         }
     }
 
@@ -299,6 +326,7 @@ pub mod tests {
                             domain_reference: DomainReference::build("sample_domain"),
                             table_name: "sample_table".to_owned(),
                         },
+                        schema: None,
                     },
                     indexes: HashMap::new(),
                 },
@@ -329,7 +357,7 @@ pub mod tests {
                             .collect(),
                     },
                 )),
-                Effect::CreateCf("sample_domain::/sample_table".to_owned()),
+                Effect::CreateCf("/domains/sample_domain/tables/sample_table".to_owned()),
                 Effect::TableStoredEffect(TableStoredEffect::Put(
                     "/domains/sample_domain/tables".to_owned(),
                     "sample_table".to_owned(),
@@ -339,6 +367,7 @@ pub mod tests {
                                 table_name: "sample_table".to_owned(),
                                 domain_reference: DomainReference::build("sample_domain"),
                             },
+                            schema: None,
                         },
                         indexes: HashMap::new(),
                     },
@@ -377,7 +406,7 @@ pub mod tests {
                     "/domains/sample_domain/tables".to_owned(),
                     "sample_table".to_owned(),
                 )),
-                Effect::DeleteCf("sample_domain::/sample_table".to_owned()),
+                Effect::DeleteCf("/domains/sample_domain/tables/sample_table".to_owned()),
             ];
 
             let mut mock = MockTableStoredTestRequests::new();
@@ -0,0 +1,45 @@
+//table_validation.rs
+//! The validation of the values of a table against a JSON Schema, see table_schema.
+use crate::db::entity::table_schema::{compile_schema, schema_violations};
+use crate::db::entity::table_value::get_key_from_table_value;
+use crate::db::entity::OndoKey;
+use crate::db::reference::requests::TableStoredIteratorRequests;
+use crate::db::reference::{TableReference, TableReferenceTrait};
+use crate::db::{DbResult, SchemaViolation};
+use serde_json::Value;
+
+/// The number of invalid values listed. The rest are only counted.
+pub(crate) const MAX_REPORTED_VALUES: usize = 100;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct TableValidation {
+    pub values_checked: u64,
+    pub invalid_values: u64,
+    /// The ids and violations of the first MAX_REPORTED_VALUES invalid values.
+    pub invalid: Vec<(OndoKey, Vec<SchemaViolation>)>,
+}
+
+/// Checks every value of the table against the schema.
+pub(crate) fn validate_table_values<'a>(
+    table_reference: &TableReference,
+    schema: &Value,
+    requests: &'a dyn TableStoredIteratorRequests<'a>,
+) -> DbResult<TableValidation> {
+    let schema = compile_schema(schema)?;
+    let mut validation = TableValidation::default();
+    for value in table_reference.all_values(requests)? {
+        let value = value?;
+        validation.values_checked += 1;
+        let violations = schema_violations(&schema, &value);
+        if violations.is_empty() {
+            continue;
+        }
+        validation.invalid_values += 1;
+        if validation.invalid.len() < MAX_REPORTED_VALUES {
+            validation
+                .invalid
+                .push((get_key_from_table_value(&value)?, violations));
+        }
+    }
+    Ok(validation)
+}
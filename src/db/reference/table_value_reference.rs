@@ -1,16 +1,25 @@
 //table_value_reference.rs
 //TODO!XXX: find by index
-use crate::db::entity::table_value::{do_deindex_table_value, do_index_table_value, insert_key_into_table_value};
+use crate::db::entity::table_schema::{compile_schema, validate_table_value};
+use crate::db::entity::table_value::{
+    do_deindex_table_value, do_index_table_value, get_revision_from_table_value,
+    insert_key_into_table_value, insert_revision_into_table_value,
+};
+use crate::db::entity::value_patch::ValuePatch;
 use crate::db::{
-    entity::{ondo_key::OptionalOndoKey, OndoKey, TableValue},
+    entity::{ondo_key::OptionalOndoKey, Index, IndexKind, OndoKey, TableValue},
     reference::{
         effect::TableValueEffect,
-        requests::{ColumnValueRequests, TableStoredRequests, TableValueRequests},
+        hnsw::HnswGraph,
+        requests::{
+            ColumnValueRequests, IndexValueRequests, TableStoredRequests, TableValueRequests,
+        },
         table_reference::stored::TableStoredReferenceTrait,
+        text_stats::TextStatsUpdate,
         CfNameMaker, ColumnValueReference, ColumnValueReferenceTrait, Effect, Effects,
         TableReference,
     },
-    DbResult,
+    DbError, DbResult,
 };
 use serde::{Deserialize, Serialize};
 
@@ -20,13 +29,32 @@ pub(crate) trait TableValueReferenceTrait {
     fn put_table_value(
         &self,
         value: &TableValue,
+        expected_revision: Option<u64>,
+        table_stored_requests: &dyn TableStoredRequests,
+        table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+    ) -> DbResult<Effects>;
+    fn patch_table_value(
+        &self,
+        patch: &ValuePatch,
+        expected_revision: Option<u64>,
         table_stored_requests: &dyn TableStoredRequests,
         table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
     ) -> DbResult<Effects>;
+    fn upsert_table_value(
+        &self,
+        value: &mut TableValue,
+        table_stored_requests: &dyn TableStoredRequests,
+        table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+    ) -> DbResult<(bool, Effects)>;
     fn delete_table_value(
         &self,
+        expected_revision: Option<u64>,
         table_stored_requests: &dyn TableStoredRequests,
         table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
     ) -> DbResult<Effects>;
 }
 pub(crate) trait CreateTableValueReferenceTrait {
@@ -37,6 +65,7 @@ pub(crate) trait CreateTableValueReferenceTrait {
         column_value_requests: &dyn ColumnValueRequests,
         table_stored_requests: &dyn TableStoredRequests,
         table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
     ) -> DbResult<(OndoKey, Effects)>;
 }
 
@@ -75,12 +104,26 @@ impl CreateTableValueReference {
     }
 }
 
-//TODO:XXX Full text indexing
-//TODO:XXX Serve to  https://github.com/openai/chatgpt-retrieval-plugin?s=31
+// Whether the index has the same entries for the value it replaces, so it need not be rewritten.
+fn is_index_unchanged(
+    the_index: &Index,
+    table_value: &TableValue,
+    replaced_value: Option<&TableValue>,
+) -> DbResult<bool> {
+    match replaced_value {
+        Some(replaced_value) => {
+            Ok(the_index.key_values_of(table_value)? == the_index.key_values_of(replaced_value)?)
+        }
+        None => Ok(false),
+    }
+}
+
 fn do_indexing(
     table_value_reference: &TableValueReference,
     table_value: &TableValue,
+    replaced_value: Option<&TableValue>,
     table_stored_requests: &dyn TableStoredRequests,
+    index_value_requests: &dyn IndexValueRequests,
 ) -> DbResult<Effects> {
     let table_reference = table_value_reference.to_table_reference();
     let table_stored = table_reference
@@ -88,15 +131,52 @@ fn do_indexing(
         .ok_or(crate::db::DbError::TableNotInitialized)?;
     let mut effects: Vec<Effect> = Vec::new();
     for the_index in table_stored.indexes.values() {
+        if is_index_unchanged(the_index, table_value, replaced_value)? {
+            continue;
+        }
+        check_unique(
+            table_value_reference,
+            table_value,
+            the_index,
+            index_value_requests,
+        )?;
         let index_effects = do_index_table_value(table_value, the_index)?;
         effects.extend(index_effects);
     }
     Ok(effects)
 }
 
+/// Fails if another document has the same values in the fields of a unique index.
+pub(crate) fn check_unique(
+    table_value_reference: &TableValueReference,
+    table_value: &TableValue,
+    the_index: &Index,
+    index_value_requests: &dyn IndexValueRequests,
+) -> DbResult<()> {
+    for unique_key in the_index.unique_keys_of(table_value)? {
+        // The document itself may already be indexed, one more entry is enough to find another.
+        let entries = index_value_requests.index_values_with_key_prefix(
+            &CfNameMaker::for_index_values(&the_index.reference),
+            &unique_key,
+            2,
+        )?;
+        if let Some((_, other_id)) = entries
+            .into_iter()
+            .find(|(_, id)| id != &table_value_reference.id)
+        {
+            return Err(DbError::UniqueConstraintViolation(format!(
+                "index {} already has {:?} for the key {:?}",
+                the_index.reference.index_name, other_id.values, unique_key.values
+            )));
+        }
+    }
+    Ok(())
+}
+
 fn do_deindexing(
     table_value_reference: &TableValueReference,
     table_value: &TableValue,
+    new_value: Option<&TableValue>,
     table_stored_requests: &dyn TableStoredRequests,
 ) -> DbResult<Effects> {
     let table_reference = table_value_reference.to_table_reference();
@@ -105,12 +185,106 @@ fn do_deindexing(
         .ok_or(crate::db::DbError::TableNotInitialized)?;
     let mut effects: Vec<Effect> = Vec::new();
     for the_index in table_stored.indexes.values() {
+        if is_index_unchanged(the_index, table_value, new_value)? {
+            continue;
+        }
         let index_effects = do_deindex_table_value(table_value, the_index)?;
         effects.extend(index_effects);
     }
     Ok(effects)
 }
 
+// Moves the document from its old vector to its new one in the HNSW graphs of the vector indexes.
+fn do_graph_indexing(
+    table_value_reference: &TableValueReference,
+    old_value: Option<&TableValue>,
+    new_value: Option<&TableValue>,
+    table_stored_requests: &dyn TableStoredRequests,
+    index_value_requests: &dyn IndexValueRequests,
+) -> DbResult<Effects> {
+    let table_reference = table_value_reference.to_table_reference();
+    let table_stored = table_reference
+        .get_table_stored(table_stored_requests)?
+        .ok_or(crate::db::DbError::TableNotInitialized)?;
+    let mut effects: Vec<Effect> = Vec::new();
+    for the_index in table_stored.indexes.values() {
+        let (metric, hnsw) = match the_index.graph_options() {
+            Some(graph_options) => graph_options,
+            None => continue,
+        };
+        let old_vector = old_value
+            .map(|value| the_index.vector_of(value))
+            .transpose()?;
+        let new_vector = new_value
+            .map(|value| the_index.vector_of(value))
+            .transpose()?;
+        let (old_vector, new_vector) = (old_vector.flatten(), new_vector.flatten());
+        if old_vector == new_vector {
+            continue;
+        }
+        let mut graph = HnswGraph::new(&the_index.reference, metric, hnsw, index_value_requests);
+        match new_vector {
+            Some(vector) => graph.insert(&table_value_reference.id, &vector)?,
+            None => graph.remove(&table_value_reference.id)?,
+        }
+        effects.extend(graph.into_effects());
+    }
+    Ok(effects)
+}
+
+// Counts the new number of terms of the document in the aggregates of the text indexes.
+fn do_text_stats_indexing(
+    table_value_reference: &TableValueReference,
+    new_value: Option<&TableValue>,
+    table_stored_requests: &dyn TableStoredRequests,
+    index_value_requests: &dyn IndexValueRequests,
+) -> DbResult<Effects> {
+    let table_reference = table_value_reference.to_table_reference();
+    let table_stored = table_reference
+        .get_table_stored(table_stored_requests)?
+        .ok_or(crate::db::DbError::TableNotInitialized)?;
+    let mut effects: Vec<Effect> = Vec::new();
+    for the_index in table_stored.indexes.values() {
+        if !matches!(the_index.kind, IndexKind::Text(_)) {
+            continue;
+        }
+        let length = new_value.and_then(|value| the_index.text_length_of(value));
+        let mut stats = TextStatsUpdate::new(&the_index.reference, index_value_requests);
+        stats.set_length(&table_value_reference.id, length)?;
+        effects.extend(stats.into_effects()?);
+    }
+    Ok(effects)
+}
+
+fn check_revision(old_value: &TableValue, expected_revision: Option<u64>) -> DbResult<u64> {
+    let revision = get_revision_from_table_value(old_value);
+    match expected_revision {
+        Some(expected) if expected != revision => Err(DbError::RevisionConflict(expected, revision)),
+        _ => Ok(revision),
+    }
+}
+
+/// Fails with SchemaViolation if the table has a schema and the value does not match it.
+fn check_schema(
+    table_reference: &TableReference,
+    value: &TableValue,
+    table_stored_requests: &dyn TableStoredRequests,
+) -> DbResult<()> {
+    let table_stored = table_reference
+        .get_table_stored(table_stored_requests)?
+        .ok_or(DbError::TableNotInitialized)?;
+    match &table_stored.table.schema {
+        Some(schema) => {
+            let compiled = match table_stored_requests.table_schemas() {
+                Some(table_schemas) => table_schemas.compiled(table_reference, schema)?,
+                None => return validate_table_value(&compile_schema(schema)?, value),
+            };
+            validate_table_value(&compiled, value)
+        }
+        None => Ok(()),
+    }
+}
+
 impl CreateTableValueReferenceTrait for CreateTableValueReference {
     fn container_cf_name(&self) -> String {
         CfNameMaker::for_table_values(&self.table_reference)
@@ -121,7 +295,8 @@ impl CreateTableValueReferenceTrait for CreateTableValueReference {
         value: &mut TableValue,
         column_value_requests: &dyn ColumnValueRequests,
         table_stored_requests: &dyn TableStoredRequests,
-        _table_value_requests: &dyn TableValueRequests,
+        table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
     ) -> DbResult<(OndoKey, Effects)> {
         let mut effects: Vec<Effect> = Vec::new();
 
@@ -132,9 +307,18 @@ impl CreateTableValueReferenceTrait for CreateTableValueReference {
                     column_reference: domain_reference.cf_name_for_table_counters(),
                     id: self.table_reference.table_name.clone().into(),
                 };
-                let (new_id_int, counter_effects) =
+                let (mut new_id_int, _) =
                     table_counter_reference.increment_column_value(column_value_requests)?;
-                effects.extend(counter_effects);
+                // The counter skips the keys that were given to values explicitly.
+                while TableValueReference::new(self.table_reference.clone(), new_id_int.into())
+                    .get_table_value(table_value_requests)?
+                    .is_some()
+                {
+                    new_id_int += 1;
+                }
+                effects.extend(
+                    table_counter_reference.put_column_value(&serde_json::json!(new_id_int))?,
+                );
 
                 let new_ondo_key: OndoKey = new_id_int.into();
                 insert_key_into_table_value(value, &new_ondo_key);
@@ -146,19 +330,107 @@ impl CreateTableValueReferenceTrait for CreateTableValueReference {
             table_reference: self.table_reference.clone(),
             id: id_used.clone(),
         };
-        let put_effect = Effect::TableValueEffect(TableValueEffect::Put(
-            self.container_cf_name(),
-            new_reference.id.clone(),
-            value.clone(),
-        ));
-        effects.push(put_effect);
-        let index_effects = do_indexing(&new_reference, value, table_stored_requests)?;
-        effects.extend(index_effects);
+        if self.id.is_some()
+            && new_reference
+                .get_table_value(table_value_requests)?
+                .is_some()
+        {
+            return Err(DbError::AlreadyExists);
+        }
+        effects.extend(new_reference.insert_table_value(
+            value,
+            table_stored_requests,
+            index_value_requests,
+        )?);
         Ok((id_used, effects))
     }
 }
 
+impl TableValueReference {
+    // Writes a new value at revision 1 and indexes it.
+    fn insert_table_value(
+        &self,
+        value: &mut TableValue,
+        table_stored_requests: &dyn TableStoredRequests,
+        index_value_requests: &dyn IndexValueRequests,
+    ) -> DbResult<Effects> {
+        check_schema(&self.table_reference, value, table_stored_requests)?;
+        insert_revision_into_table_value(value, 1);
+        let mut effects = vec![Effect::TableValueEffect(TableValueEffect::Put(
+            self.container_cf_name(),
+            self.id.clone(),
+            value.clone(),
+        ))];
+        effects.extend(do_indexing(
+            self,
+            value,
+            None,
+            table_stored_requests,
+            index_value_requests,
+        )?);
+        effects.extend(do_graph_indexing(
+            self,
+            None,
+            Some(value),
+            table_stored_requests,
+            index_value_requests,
+        )?);
+        effects.extend(do_text_stats_indexing(
+            self,
+            Some(value),
+            table_stored_requests,
+            index_value_requests,
+        )?);
+        Ok(effects)
+    }
 
+    // Writes the value over the old one at the next revision. Only the indexes whose entries
+    // differ between the two values are rewritten.
+    fn replace_table_value(
+        &self,
+        old_value: &TableValue,
+        value: &TableValue,
+        revision: u64,
+        table_stored_requests: &dyn TableStoredRequests,
+        index_value_requests: &dyn IndexValueRequests,
+    ) -> DbResult<Effects> {
+        check_schema(&self.table_reference, value, table_stored_requests)?;
+        let mut value = value.clone();
+        insert_revision_into_table_value(&mut value, revision + 1);
+        let mut effects = vec![Effect::TableValueEffect(TableValueEffect::Put(
+            self.container_cf_name(),
+            self.id.clone(),
+            value.clone(),
+        ))];
+        effects.extend(do_deindexing(
+            self,
+            old_value,
+            Some(&value),
+            table_stored_requests,
+        )?);
+        effects.extend(do_indexing(
+            self,
+            &value,
+            Some(old_value),
+            table_stored_requests,
+            index_value_requests,
+        )?);
+        effects.extend(do_graph_indexing(
+            self,
+            Some(old_value),
+            Some(&value),
+            table_stored_requests,
+            index_value_requests,
+        )?);
+        effects.extend(do_text_stats_indexing(
+            self,
+            Some(&value),
+            table_stored_requests,
+            index_value_requests,
+        )?);
+        Ok(effects)
+    }
+}
 
 impl TableValueReferenceTrait for TableValueReference {
     fn container_cf_name(&self) -> String {
@@ -166,36 +438,90 @@ impl TableValueReferenceTrait for TableValueReference {
     }
 
     fn get_table_value(&self, request: &dyn TableValueRequests) -> DbResult<Option<TableValue>> {
-        request.get_table_value(&self.container_cf_name(), &self)
+        request.get_table_value(&self.container_cf_name(), self)
     }
 
     fn put_table_value(
         &self,
         value: &TableValue,
+        expected_revision: Option<u64>,
         table_stored_requests: &dyn TableStoredRequests,
         table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
     ) -> DbResult<Effects> {
-        let mut effects = vec![];
         let old_value = self
             .get_table_value(table_value_requests)?
             .ok_or(crate::db::DbError::NotFound)?;
-        let put_effect = Effect::TableValueEffect(TableValueEffect::Put(
-            self.container_cf_name(),
-            self.id.clone(),
-            value.clone(),
-        ));
-        effects.push(put_effect);
-        let deindex_effects = do_deindexing(self, &old_value, table_stored_requests)?;
-        let index_effects = do_indexing(self, value, table_stored_requests)?;
-        effects.extend(deindex_effects);
-        effects.extend(index_effects);
-        Ok(effects)
+        let revision = check_revision(&old_value, expected_revision)?;
+        self.replace_table_value(
+            &old_value,
+            value,
+            revision,
+            table_stored_requests,
+            index_value_requests,
+        )
+    }
+
+    /// Applies the patch to the stored value on the server, see value_patch.
+    fn patch_table_value(
+        &self,
+        patch: &ValuePatch,
+        expected_revision: Option<u64>,
+        table_stored_requests: &dyn TableStoredRequests,
+        table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+    ) -> DbResult<Effects> {
+        let old_value = self
+            .get_table_value(table_value_requests)?
+            .ok_or(crate::db::DbError::NotFound)?;
+        let revision = check_revision(&old_value, expected_revision)?;
+        let value = patch.apply(&old_value)?;
+        self.replace_table_value(
+            &old_value,
+            &value,
+            revision,
+            table_stored_requests,
+            index_value_requests,
+        )
+    }
+
+    /// Creates the value with the key of the reference, or replaces the stored one and
+    /// deindexes it. Sets the _id and _revision fields of the value, and returns whether
+    /// it was created.
+    fn upsert_table_value(
+        &self,
+        value: &mut TableValue,
+        table_stored_requests: &dyn TableStoredRequests,
+        table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+    ) -> DbResult<(bool, Effects)> {
+        insert_key_into_table_value(value, &self.id);
+        let old_value = match self.get_table_value(table_value_requests)? {
+            Some(old_value) => old_value,
+            None => {
+                let effects =
+                    self.insert_table_value(value, table_stored_requests, index_value_requests)?;
+                return Ok((true, effects));
+            }
+        };
+        let revision = get_revision_from_table_value(&old_value);
+        insert_revision_into_table_value(value, revision + 1);
+        let effects = self.replace_table_value(
+            &old_value,
+            value,
+            revision,
+            table_stored_requests,
+            index_value_requests,
+        )?;
+        Ok((false, effects))
     }
 
     fn delete_table_value(
         &self,
+        expected_revision: Option<u64>,
         table_stored_requests: &dyn TableStoredRequests,
         table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
     ) -> DbResult<Effects> {
         let effect = Effect::TableValueEffect(TableValueEffect::Delete(
             self.container_cf_name(),
@@ -205,15 +531,30 @@ impl TableValueReferenceTrait for TableValueReference {
         let old_value = self
             .get_table_value(table_value_requests)?
             .ok_or(crate::db::DbError::NotFound)?;
-        let deindex_effects = do_deindexing(self, &old_value, table_stored_requests)?;
+        check_revision(&old_value, expected_revision)?;
+        let deindex_effects = do_deindexing(self, &old_value, None, table_stored_requests)?;
         effects.extend(deindex_effects);
+        effects.extend(do_graph_indexing(
+            self,
+            Some(&old_value),
+            None,
+            table_stored_requests,
+            index_value_requests,
+        )?);
+        effects.extend(do_text_stats_indexing(
+            self,
+            None,
+            table_stored_requests,
+            index_value_requests,
+        )?);
         Ok(effects)
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::db::reference::index_value_reference::tests::MockIndexValueTestRequests;
     use crate::db::reference::table_reference::stored::tests::{
         create_table_stored, MockTableStoredTestRequests,
     };
@@ -282,23 +623,67 @@ mod tests {
             mock.expect_get_table_value()
                 .returning(move |_, _| Ok(Some(create_table_value())));
 
+            let index_mock = MockIndexValueTestRequests::new();
+
             let table_value_ref =
                 create_table_value_ref("sample_domain", "sample_table", create_table_key());
             let table_value = create_table_value();
 
             let effects = table_value_ref
-                .put_table_value(&table_value, &table_mock, &mock)
+                .put_table_value(&table_value, None, &table_mock, &mock, &index_mock)
                 .unwrap();
+            let mut expected_value = table_value;
+            expected_value["_revision"] = json!(1);
             let expected_effect = Effect::TableValueEffect(TableValueEffect::Put(
-                "sample_domain::/sample_table".to_owned(),
+                "/domains/sample_domain/tables/sample_table".to_owned(),
                 table_value_ref.id.clone(),
-                table_value,
+                expected_value,
             ));
 
             assert_eq!(effects.len(), 1);
             assert_eq!(effects[0], expected_effect);
         }
 
+        #[test]
+        fn test_put_table_value_with_expected_revision() {
+            let mut table_mock = MockTableStoredTestRequests::new();
+            table_mock
+                .expect_get_table_stored()
+                .returning(move |_, _| Ok(Some(create_table_stored())));
+            let mut mock = MockTableValueTestRequests::new();
+            mock.expect_get_table_value().returning(move |_, _| {
+                let mut value = create_table_value();
+                value["_revision"] = json!(3);
+                Ok(Some(value))
+            });
+
+            let index_mock = MockIndexValueTestRequests::new();
+
+            let table_value_ref =
+                create_table_value_ref("sample_domain", "sample_table", create_table_key());
+            let table_value = create_table_value();
+
+            assert_eq!(
+                table_value_ref.put_table_value(
+                    &table_value,
+                    Some(2),
+                    &table_mock,
+                    &mock,
+                    &index_mock
+                ),
+                Err(DbError::RevisionConflict(2, 3))
+            );
+            let effects = table_value_ref
+                .put_table_value(&table_value, Some(3), &table_mock, &mock, &index_mock)
+                .unwrap();
+            match &effects[0] {
+                Effect::TableValueEffect(TableValueEffect::Put(_, _, value)) => {
+                    assert_eq!(value["_revision"], json!(4))
+                }
+                effect => panic!("Unexpected effect {:?}", effect),
+            }
+        }
+
         #[test]
         fn test_delete_table_value() {
             let mut table_mock = MockTableStoredTestRequests::new();
@@ -315,9 +700,85 @@ mod tests {
                 table_value_ref.container_cf_name(),
                 table_value_ref.id.clone(),
             ));
-            let result = table_value_ref.delete_table_value(&table_mock, &mock);
+            let index_mock = MockIndexValueTestRequests::new();
+            let result = table_value_ref.delete_table_value(None, &table_mock, &mock, &index_mock);
             assert_eq!(result, Ok(vec![expected_effect]));
         }
+
+        #[test]
+        fn test_delete_table_value_with_expected_revision() {
+            let mut table_mock = MockTableStoredTestRequests::new();
+            table_mock
+                .expect_get_table_stored()
+                .returning(move |_, _| Ok(Some(create_table_stored())));
+            let mut mock = MockTableValueTestRequests::new();
+            mock.expect_get_table_value()
+                .returning(move |_, _| Ok(Some(create_table_value())));
+
+            let index_mock = MockIndexValueTestRequests::new();
+            let table_value_ref =
+                create_table_value_ref("sample_domain", "sample_table", create_table_key());
+            assert_eq!(
+                table_value_ref.delete_table_value(Some(1), &table_mock, &mock, &index_mock),
+                Err(DbError::RevisionConflict(1, 0))
+            );
+            assert!(table_value_ref
+                .delete_table_value(Some(0), &table_mock, &mock, &index_mock)
+                .is_ok());
+        }
+
+        #[test]
+        fn test_patch_table_value_rewrites_changed_indexes() {
+            use crate::db::entity::index::IndexKind;
+
+            let mut table_mock = MockTableStoredTestRequests::new();
+            table_mock.expect_get_table_stored().returning(move |_, _| {
+                let mut table_stored = create_table_stored();
+                let index = Index {
+                    reference: IndexReference::build("sample_domain", "sample_table", "by_age"),
+                    fields: vec!["age".to_owned()],
+                    unique: false,
+                    kind: IndexKind::Ordered,
+                    building: false,
+                };
+                table_stored.indexes.insert("by_age".to_owned(), index);
+                Ok(Some(table_stored))
+            });
+            let stored_value = json!({"_id": {"values": [1]}, "name": "John", "age": 30});
+            let mut mock = MockTableValueTestRequests::new();
+            let value = stored_value.clone();
+            mock.expect_get_table_value()
+                .returning(move |_, _| Ok(Some(value.clone())));
+            let index_mock = MockIndexValueTestRequests::new();
+            let table_value_ref =
+                create_table_value_ref("sample_domain", "sample_table", create_table_key());
+            let patch_effects = |patch: ValuePatch| {
+                table_value_ref.patch_table_value(&patch, None, &table_mock, &mock, &index_mock)
+            };
+
+            let effects = patch_effects(ValuePatch::Merge(json!({"name": "Jane"}))).unwrap();
+            let mut expected_value = stored_value;
+            expected_value["name"] = json!("Jane");
+            expected_value["_revision"] = json!(1);
+            assert_eq!(
+                effects,
+                vec![Effect::TableValueEffect(TableValueEffect::Put(
+                    table_value_ref.container_cf_name(),
+                    table_value_ref.id.clone(),
+                    expected_value,
+                ))]
+            );
+
+            let patch = ValuePatch::json(json!([{"op": "replace", "path": "/age", "value": 31}]));
+            let effects = patch_effects(patch.unwrap()).unwrap();
+            assert_eq!(effects.len(), 3, "{:?}", effects);
+
+            let patch = ValuePatch::json(json!([{"op": "test", "path": "/age", "value": 40}]));
+            assert!(matches!(
+                patch_effects(patch.unwrap()),
+                Err(DbError::PatchTestFailed(_))
+            ));
+        }
     }
 }
 //TEST:: Missing test for post_table_value
@@ -0,0 +1,545 @@
+//text_search.rs
+//! Queries of the full-text indexes, ranked by BM25.
+//!
+//! A query has words, "quoted phrases", parentheses and the operators AND, OR, NOT and
+//! `-`. Words next to each other must all match, AND binds tighter than OR. The words
+//! of the query are analyzed like the indexed texts, so a word can become a phrase, e.g.
+//! "e-mail", or disappear if it is a stop word.
+use crate::db::entity::index::text::{
+    documents_prefix, parse_document, parse_posting, posting_prefix, Analyzer, TextIndexOptions,
+    TextIndexStats,
+};
+use crate::db::entity::OndoKey;
+use crate::db::reference::requests::{IndexIteratorRequests, IndexValueRequests};
+use crate::db::reference::text_stats::{get_document_length, get_text_stats};
+use crate::db::reference::{IndexReference, IndexReferenceTrait};
+use crate::db::{DbError, DbResult};
+use std::collections::{BTreeMap, BTreeSet};
+
+// The BM25 parameters of term frequency saturation and document length normalization.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+// The deepest nesting of parentheses and negations in a query, which bounds the recursion
+// of the parser.
+const MAX_QUERY_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TextQuery {
+    Term(String),
+    /// The terms with their positions relative to each other.
+    Phrase(Vec<(String, u64)>),
+    And(Vec<TextQuery>),
+    Or(Vec<TextQuery>),
+    Not(Box<TextQuery>),
+    /// A query left without terms by the analyzer. It matches nothing on its own and
+    /// is ignored in AND and OR.
+    Empty,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Phrase(String),
+    Minus,
+    Open,
+    Close,
+}
+
+fn tokenize(query: &str) -> DbResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '-' => {
+                chars.next();
+                tokens.push(Token::Minus);
+            }
+            '"' => {
+                chars.next();
+                let mut phrase = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => phrase.push(c),
+                        None => {
+                            return Err(DbError::InvalidQuery("unterminated phrase".to_owned()))
+                        }
+                    }
+                }
+                tokens.push(Token::Phrase(phrase));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+    analyzer: &'a Analyzer,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w == word)
+    }
+
+    fn parse_or(&mut self) -> DbResult<TextQuery> {
+        let mut queries = vec![self.parse_and()?];
+        while self.is_word("OR") {
+            self.next();
+            queries.push(self.parse_and()?);
+        }
+        Ok(combine(queries, TextQuery::Or))
+    }
+
+    fn parse_and(&mut self) -> DbResult<TextQuery> {
+        let mut queries = vec![self.parse_unary()?];
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) => break,
+                Some(Token::Word(word)) if word == "OR" => break,
+                Some(Token::Word(word)) if word == "AND" => {
+                    self.next();
+                }
+                _ => {}
+            }
+            queries.push(self.parse_unary()?);
+        }
+        Ok(combine(queries, TextQuery::And))
+    }
+
+    fn parse_unary(&mut self) -> DbResult<TextQuery> {
+        if self.depth >= MAX_QUERY_DEPTH {
+            return Err(DbError::InvalidQuery(format!(
+                "more than {} nested parentheses and negations",
+                MAX_QUERY_DEPTH
+            )));
+        }
+        self.depth += 1;
+        let query = self.parse_operand();
+        self.depth -= 1;
+        query
+    }
+
+    fn parse_operand(&mut self) -> DbResult<TextQuery> {
+        match self.next() {
+            Some(Token::Minus) => Ok(negate(self.parse_unary()?)),
+            Some(Token::Word(word)) if word == "NOT" => Ok(negate(self.parse_unary()?)),
+            Some(Token::Word(word)) if word == "AND" || word == "OR" => Err(DbError::InvalidQuery(
+                format!("{} without a term before it", word),
+            )),
+            Some(Token::Word(word)) => Ok(self.analyze(&word)),
+            Some(Token::Phrase(phrase)) => Ok(self.analyze(&phrase)),
+            Some(Token::Open) => {
+                let query = self.parse_or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err(DbError::InvalidQuery("missing ')'".to_owned())),
+                }
+            }
+            Some(Token::Close) => Err(DbError::InvalidQuery("unexpected ')'".to_owned())),
+            None => Err(DbError::InvalidQuery("missing term at the end".to_owned())),
+        }
+    }
+
+    fn analyze(&self, text: &str) -> TextQuery {
+        let mut terms = self.analyzer.analyze(text);
+        match terms.len() {
+            0 => TextQuery::Empty,
+            1 => TextQuery::Term(terms.remove(0).0),
+            _ => TextQuery::Phrase(terms),
+        }
+    }
+}
+
+fn negate(query: TextQuery) -> TextQuery {
+    match query {
+        TextQuery::Empty => TextQuery::Empty,
+        query => TextQuery::Not(Box::new(query)),
+    }
+}
+
+fn combine(queries: Vec<TextQuery>, operator: fn(Vec<TextQuery>) -> TextQuery) -> TextQuery {
+    let mut queries = queries
+        .into_iter()
+        .filter(|query| *query != TextQuery::Empty)
+        .collect::<Vec<_>>();
+    match queries.len() {
+        0 => TextQuery::Empty,
+        1 => queries.remove(0),
+        _ => operator(queries),
+    }
+}
+
+/// Parses the query with the analyzer of the text index.
+pub(crate) fn parse_text_query(query: &str, options: &TextIndexOptions) -> DbResult<TextQuery> {
+    let analyzer = Analyzer::new(options);
+    let mut parser = Parser {
+        tokens: tokenize(query)?,
+        position: 0,
+        depth: 0,
+        analyzer: &analyzer,
+    };
+    if parser.peek().is_none() {
+        return Ok(TextQuery::Empty);
+    }
+    let text_query = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(text_query),
+        Some(_) => Err(DbError::InvalidQuery("unexpected ')'".to_owned())),
+    }
+}
+
+// Documents are identified by the JSON of their id in the sets.
+type DocumentId = String;
+
+fn document_id(id: &OndoKey) -> DbResult<DocumentId> {
+    serde_json::to_string(&id.values).map_err(|e| DbError::SerializationError(e.to_string()))
+}
+
+struct TextIndexSnapshot<'s, 'a> {
+    index_reference: &'s IndexReference,
+    index_value_requests: &'s dyn IndexValueRequests,
+    requests: &'a dyn IndexIteratorRequests<'a>,
+    // The number of documents and their total number of terms, from the aggregate entry.
+    stats: TextIndexStats,
+    // The ids of the documents read so far.
+    ids: BTreeMap<DocumentId, OndoKey>,
+    // The positions of the terms of the query in the documents.
+    postings: BTreeMap<String, BTreeMap<DocumentId, Vec<u64>>>,
+    // All the documents, read only for a negation without terms to match next to it.
+    all_documents: Option<BTreeSet<DocumentId>>,
+}
+
+impl<'s, 'a> TextIndexSnapshot<'s, 'a> {
+    fn read(
+        index_reference: &'s IndexReference,
+        terms: &BTreeSet<String>,
+        index_value_requests: &'s dyn IndexValueRequests,
+        requests: &'a dyn IndexIteratorRequests<'a>,
+    ) -> DbResult<Self> {
+        let cf_name = index_reference.value_cf_name();
+        let mut ids = BTreeMap::new();
+        let mut postings = BTreeMap::new();
+        for term in terms {
+            let mut term_postings = BTreeMap::new();
+            let entries = requests.all_entries_with_key_prefix(&cf_name, posting_prefix(term))?;
+            for entry in entries {
+                let (key, value) = entry?;
+                let (id, positions) = parse_posting(&key, &value);
+                let document_id = document_id(&id)?;
+                term_postings.insert(document_id.clone(), positions);
+                ids.insert(document_id, id);
+            }
+            postings.insert(term.clone(), term_postings);
+        }
+        Ok(TextIndexSnapshot {
+            index_reference,
+            index_value_requests,
+            requests,
+            stats: get_text_stats(index_reference, index_value_requests)?,
+            ids,
+            postings,
+            all_documents: None,
+        })
+    }
+
+    fn postings(&self, term: &str) -> Option<&BTreeMap<DocumentId, Vec<u64>>> {
+        self.postings.get(term)
+    }
+
+    fn all_documents(&mut self) -> DbResult<BTreeSet<DocumentId>> {
+        if let Some(all_documents) = &self.all_documents {
+            return Ok(all_documents.clone());
+        }
+        let mut all_documents = BTreeSet::new();
+        let entries = self.requests.all_entries_with_key_prefix(
+            &self.index_reference.value_cf_name(),
+            documents_prefix(),
+        )?;
+        for entry in entries {
+            let (key, value) = entry?;
+            let (id, _) = parse_document(&key, &value);
+            let document_id = document_id(&id)?;
+            all_documents.insert(document_id.clone());
+            self.ids.insert(document_id, id);
+        }
+        self.all_documents = Some(all_documents.clone());
+        Ok(all_documents)
+    }
+
+    fn matches(&mut self, query: &TextQuery) -> DbResult<BTreeSet<DocumentId>> {
+        Ok(match query {
+            TextQuery::Term(term) => self
+                .postings(term)
+                .map(|postings| postings.keys().cloned().collect())
+                .unwrap_or_default(),
+            TextQuery::Phrase(terms) => self.phrase_matches(terms),
+            // The negations only remove documents from the matches of the other queries.
+            TextQuery::And(queries) => {
+                let (negated, matched): (Vec<_>, Vec<_>) = queries
+                    .iter()
+                    .partition(|query| matches!(query, TextQuery::Not(_)));
+                let mut documents = match matched.split_first() {
+                    Some((first, rest)) => {
+                        let mut documents = self.matches(first)?;
+                        for query in rest {
+                            let set = self.matches(query)?;
+                            documents.retain(|id| set.contains(id));
+                        }
+                        documents
+                    }
+                    None => self.all_documents()?,
+                };
+                for query in negated {
+                    if let TextQuery::Not(query) = query {
+                        let excluded = self.matches(query)?;
+                        documents.retain(|id| !excluded.contains(id));
+                    }
+                }
+                documents
+            }
+            TextQuery::Or(queries) => {
+                let mut documents = BTreeSet::new();
+                for query in queries {
+                    documents.extend(self.matches(query)?);
+                }
+                documents
+            }
+            TextQuery::Not(query) => {
+                let excluded = self.matches(query)?;
+                let mut documents = self.all_documents()?;
+                documents.retain(|id| !excluded.contains(id));
+                documents
+            }
+            TextQuery::Empty => BTreeSet::new(),
+        })
+    }
+
+    // The documents with the terms at the same distances as in the phrase.
+    fn phrase_matches(&self, terms: &[(String, u64)]) -> BTreeSet<DocumentId> {
+        let (first_term, first_position) = match terms.first() {
+            Some(first) => first,
+            None => return BTreeSet::new(),
+        };
+        let first_postings = match self.postings(first_term) {
+            Some(postings) => postings,
+            None => return BTreeSet::new(),
+        };
+        first_postings
+            .iter()
+            .filter(|(id, starts)| {
+                starts.iter().any(|start| {
+                    terms.iter().skip(1).all(|(term, position)| {
+                        let expected = start + position - first_position;
+                        self.postings(term)
+                            .and_then(|postings| postings.get(*id))
+                            .map(|positions| positions.contains(&expected))
+                            .unwrap_or(false)
+                    })
+                })
+            })
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    // Only the matching documents are scored, so only their numbers of terms are read.
+    fn score(
+        &self,
+        id: &OndoKey,
+        document_id: &DocumentId,
+        terms: &BTreeSet<String>,
+    ) -> DbResult<f64> {
+        let document_count = self.stats.document_count as f64;
+        let average_length = self.stats.average_length();
+        let length = get_document_length(self.index_reference, id, self.index_value_requests)?
+            .unwrap_or(0) as f64;
+        Ok(terms
+            .iter()
+            .filter_map(|term| self.postings(term))
+            .map(|postings| {
+                let frequency = postings
+                    .get(document_id)
+                    .map(|p| p.len() as f64)
+                    .unwrap_or(0.0);
+                let with_term = postings.len() as f64;
+                let idf = (1.0 + (document_count - with_term + 0.5) / (with_term + 0.5)).ln();
+                idf * frequency * (K1 + 1.0)
+                    / (frequency + K1 * (1.0 - B + B * length / average_length))
+            })
+            .sum())
+    }
+}
+
+// The terms that are looked up, and the terms that rank the matches, which are not negated.
+fn query_terms(
+    query: &TextQuery,
+    negated: bool,
+    terms: &mut BTreeSet<String>,
+    ranking: &mut BTreeSet<String>,
+) {
+    let mut add = |term: &String| {
+        terms.insert(term.clone());
+        if !negated {
+            ranking.insert(term.clone());
+        }
+    };
+    match query {
+        TextQuery::Term(term) => add(term),
+        TextQuery::Phrase(phrase_terms) => phrase_terms.iter().for_each(|(term, _)| add(term)),
+        TextQuery::And(queries) | TextQuery::Or(queries) => queries
+            .iter()
+            .for_each(|query| query_terms(query, negated, terms, ranking)),
+        TextQuery::Not(query) => query_terms(query, !negated, terms, ranking),
+        TextQuery::Empty => {}
+    }
+}
+
+/// Finds the ids of the documents matching the query in the text index, best first.
+/// Returns at most limit ids if limit is set.
+pub(crate) fn search_text_index<'a>(
+    index_reference: &IndexReference,
+    query: &TextQuery,
+    limit: Option<usize>,
+    index_value_requests: &dyn IndexValueRequests,
+    requests: &'a dyn IndexIteratorRequests<'a>,
+) -> DbResult<Vec<(OndoKey, f64)>> {
+    let mut terms = BTreeSet::new();
+    let mut ranking_terms = BTreeSet::new();
+    query_terms(query, false, &mut terms, &mut ranking_terms);
+    let mut snapshot =
+        TextIndexSnapshot::read(index_reference, &terms, index_value_requests, requests)?;
+
+    let mut scored = Vec::new();
+    for document_id in snapshot.matches(query)? {
+        if let Some(id) = snapshot.ids.get(&document_id) {
+            let score = snapshot.score(id, &document_id, &ranking_terms)?;
+            scored.push((document_id, id.clone(), score));
+        }
+    }
+    // Ties are ordered by id, so the order does not change between calls.
+    scored.sort_by(|(id_a, _, score_a), (id_b, _, score_b)| {
+        score_b.total_cmp(score_a).then_with(|| id_a.cmp(id_b))
+    });
+    let scored = scored
+        .into_iter()
+        .map(|(_, ondo_key, score)| (ondo_key, score));
+    Ok(match limit {
+        Some(limit) => scored.take(limit).collect(),
+        None => scored.collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> DbResult<TextQuery> {
+        parse_text_query(
+            query,
+            &TextIndexOptions {
+                stemming: true,
+                stop_words: true,
+            },
+        )
+    }
+
+    fn term(term: &str) -> TextQuery {
+        TextQuery::Term(term.to_owned())
+    }
+
+    #[test]
+    fn test_parse_text_query() {
+        assert_eq!(parse("Foxes"), Ok(term("fox")));
+        assert_eq!(
+            parse("red fox"),
+            Ok(TextQuery::And(vec![term("red"), term("fox")]))
+        );
+        assert_eq!(
+            parse("red AND fox OR dog"),
+            Ok(TextQuery::Or(vec![
+                TextQuery::And(vec![term("red"), term("fox")]),
+                term("dog")
+            ]))
+        );
+        assert_eq!(
+            parse("fox -(red OR \"big dog\")"),
+            Ok(TextQuery::And(vec![
+                term("fox"),
+                TextQuery::Not(Box::new(TextQuery::Or(vec![
+                    term("red"),
+                    TextQuery::Phrase(vec![("big".to_owned(), 0), ("dog".to_owned(), 1)])
+                ])))
+            ]))
+        );
+        assert_eq!(
+            parse("\"the end of the road\""),
+            Ok(TextQuery::Phrase(vec![
+                ("end".to_owned(), 1),
+                ("road".to_owned(), 4)
+            ]))
+        );
+        assert_eq!(parse("the NOT a"), Ok(TextQuery::Empty));
+        assert_eq!(parse(""), Ok(TextQuery::Empty));
+    }
+
+    #[test]
+    fn test_parse_invalid_text_query() {
+        for query in ["(fox", "fox)", "\"fox", "fox AND", "OR fox", "NOT"] {
+            assert!(
+                matches!(parse(query), Err(DbError::InvalidQuery(_))),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_deeply_nested_text_query() {
+        let nested = |depth: usize| format!("{}fox{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(parse(&nested(MAX_QUERY_DEPTH - 1)), Ok(term("fox")));
+        for query in [
+            nested(MAX_QUERY_DEPTH),
+            nested(100_000),
+            "-".repeat(100_000) + "fox",
+            "NOT ".repeat(100_000) + "fox",
+        ] {
+            assert!(matches!(parse(&query), Err(DbError::InvalidQuery(_))));
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//text_stats.rs
+//! The aggregate entry of the text indexes.
+//!
+//! The aggregate follows the `["d", ...id]` entries of the documents: a document is counted
+//! when its entry is put and uncounted when its entry is deleted, so every writer of those
+//! entries changes the aggregate in the same effects.
+use crate::db::entity::index::text::{
+    document_key, parse_document, parse_stats, stats_key, stats_value, TextIndexStats,
+};
+use crate::db::entity::OndoKey;
+use crate::db::reference::requests::IndexValueRequests;
+use crate::db::reference::{
+    Effects, IndexReference, IndexValueReference, IndexValueReferenceTrait,
+};
+use crate::db::DbResult;
+
+/// Reads the aggregate of the text index, which is empty before the first document.
+pub(crate) fn get_text_stats(
+    index_reference: &IndexReference,
+    requests: &dyn IndexValueRequests,
+) -> DbResult<TextIndexStats> {
+    let stored =
+        IndexValueReference::new(index_reference.clone(), stats_key()).get_index_value(requests)?;
+    Ok(stored.map(|value| parse_stats(&value)).unwrap_or_default())
+}
+
+/// The number of terms of the document in its stored entry.
+pub(crate) fn get_document_length(
+    index_reference: &IndexReference,
+    id: &OndoKey,
+    requests: &dyn IndexValueRequests,
+) -> DbResult<Option<u64>> {
+    let key = document_key(id);
+    let stored =
+        IndexValueReference::new(index_reference.clone(), key.clone()).get_index_value(requests)?;
+    Ok(stored.map(|value| parse_document(&key, &value).1))
+}
+
+/// The changes of the aggregate of a text index, read on the first change.
+/// The changes become effects with into_effects.
+pub(crate) struct TextStatsUpdate<'a> {
+    index_reference: IndexReference,
+    requests: &'a dyn IndexValueRequests,
+    stats: Option<TextIndexStats>,
+}
+
+impl<'a> TextStatsUpdate<'a> {
+    pub fn new(index_reference: &IndexReference, requests: &'a dyn IndexValueRequests) -> Self {
+        TextStatsUpdate {
+            index_reference: index_reference.clone(),
+            requests,
+            stats: None,
+        }
+    }
+
+    /// Counts the document with the number of terms its entry gets, None if its entry is
+    /// deleted, in place of the stored entry. Each document is changed once per update.
+    pub fn set_length(&mut self, id: &OndoKey, length: Option<u64>) -> DbResult<()> {
+        let stored = get_document_length(&self.index_reference, id, self.requests)?;
+        if stored == length {
+            return Ok(());
+        }
+        let mut stats = match self.stats {
+            Some(stats) => stats,
+            None => get_text_stats(&self.index_reference, self.requests)?,
+        };
+        if let Some(stored) = stored {
+            stats.remove(stored);
+        }
+        if let Some(length) = length {
+            stats.add(length);
+        }
+        self.stats = Some(stats);
+        Ok(())
+    }
+
+    /// Puts the aggregate if a document was counted or uncounted.
+    pub fn into_effects(self) -> DbResult<Effects> {
+        match self.stats {
+            Some(stats) => IndexValueReference::new(self.index_reference, stats_key())
+                .put_index_value(&stats_value(&stats)),
+            None => Ok(vec![]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::entity::{IndexKey, IndexValue};
+    use crate::db::reference::effect::IndexValueEffect;
+    use crate::db::reference::{CfNameMaker, Effect};
+    use serde_json::json;
+
+    struct StoredEntries(Vec<(IndexKey, IndexValue)>);
+
+    impl IndexValueRequests for StoredEntries {
+        fn get_index_value_stored(
+            &self,
+            _cf_name: &str,
+            key: &IndexValueReference,
+        ) -> DbResult<Option<IndexValue>> {
+            Ok(self
+                .0
+                .iter()
+                .find(|(stored_key, _)| *stored_key == key.key)
+                .map(|(_, value)| value.clone()))
+        }
+
+        fn index_values_with_key_prefix(
+            &self,
+            _cf_name: &str,
+            _key_prefix: &IndexKey,
+            _limit: usize,
+        ) -> DbResult<Vec<(IndexKey, IndexValue)>> {
+            Ok(vec![])
+        }
+    }
+
+    fn length_value(length: u64) -> IndexValue {
+        OndoKey {
+            values: vec![json!(length)],
+        }
+    }
+
+    #[test]
+    fn test_text_stats_update() {
+        let index_reference = IndexReference::build("domain", "table", "index");
+        let (one, two, three): (OndoKey, OndoKey, OndoKey) =
+            (1u64.into(), 2u64.into(), 3u64.into());
+        let stored_stats = TextIndexStats {
+            document_count: 2,
+            total_length: 7,
+        };
+        let requests = StoredEntries(vec![
+            (stats_key(), stats_value(&stored_stats)),
+            (document_key(&one), length_value(3)),
+            (document_key(&two), length_value(4)),
+        ]);
+
+        let mut update = TextStatsUpdate::new(&index_reference, &requests);
+        update.set_length(&one, Some(3)).unwrap();
+        assert_eq!(update.into_effects().unwrap(), vec![]);
+
+        let mut update = TextStatsUpdate::new(&index_reference, &requests);
+        update.set_length(&one, Some(5)).unwrap();
+        update.set_length(&two, None).unwrap();
+        update.set_length(&three, Some(1)).unwrap();
+        let expected_stats = TextIndexStats {
+            document_count: 2,
+            total_length: 6,
+        };
+        assert_eq!(
+            update.into_effects().unwrap(),
+            vec![Effect::IndexValueEffect(IndexValueEffect::Put(
+                CfNameMaker::for_index_values(&index_reference),
+                stats_key(),
+                stats_value(&expected_stats),
+            ))]
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//transaction.rs
+use crate::db::entity::{IndexKey, IndexValue, OndoKey, TableValue};
+use crate::db::reference::{
+    effect::{ColumnValueEffect, IndexValueEffect, TableValueEffect},
+    requests::{ColumnValueRequests, IndexValueRequests, TableStoredRequests, TableValueRequests},
+    table_value_reference::{CreateTableValueReference, CreateTableValueReferenceTrait},
+    CfName, ColumnKey, ColumnValue, DomainReference, Effect, Effects, IndexValueReference,
+    TableValueReference, TableValueReferenceTrait,
+};
+use crate::db::DbResult;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TransactionOperation {
+    Create(CreateTableValueReference, TableValue),
+    Update(TableValueReference, TableValue, Option<u64>), // expected revision
+    Delete(TableValueReference, Option<u64>),             // expected revision
+}
+
+impl TransactionOperation {
+    pub fn to_domain_reference(&self) -> DomainReference {
+        match self {
+            TransactionOperation::Create(reference, _) => {
+                reference.table_reference.to_domain_reference()
+            }
+            TransactionOperation::Update(reference, _, _)
+            | TransactionOperation::Delete(reference, _) => {
+                reference.table_reference.to_domain_reference()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Transaction {
+    pub operations: Vec<TransactionOperation>,
+}
+
+pub(crate) trait TransactionTrait {
+    fn execute_transaction(
+        &self,
+        column_value_requests: &dyn ColumnValueRequests,
+        table_stored_requests: &dyn TableStoredRequests,
+        table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+    ) -> DbResult<(Vec<OndoKey>, Effects)>;
+}
+
+impl TransactionTrait for Transaction {
+    // Runs the operations in order and merges their effects.
+    // Every operation reads the values written by the operations before it.
+    // Returns the key of the value of each operation.
+    fn execute_transaction(
+        &self,
+        column_value_requests: &dyn ColumnValueRequests,
+        table_stored_requests: &dyn TableStoredRequests,
+        table_value_requests: &dyn TableValueRequests,
+        index_value_requests: &dyn IndexValueRequests,
+    ) -> DbResult<(Vec<OndoKey>, Effects)> {
+        let mut pending = PendingEffectsRequests {
+            effects: vec![],
+            column_value_requests,
+            table_value_requests,
+            index_value_requests,
+        };
+        let mut keys = vec![];
+        for operation in self.operations.iter() {
+            let (key, effects) = match operation {
+                TransactionOperation::Create(reference, value) => {
+                    let mut value = value.clone();
+                    reference.post_table_value(
+                        &mut value,
+                        &pending,
+                        table_stored_requests,
+                        &pending,
+                        &pending,
+                    )?
+                }
+                TransactionOperation::Update(reference, value, expected_revision) => {
+                    let effects = reference.put_table_value(
+                        value,
+                        *expected_revision,
+                        table_stored_requests,
+                        &pending,
+                        &pending,
+                    )?;
+                    (reference.id.clone(), effects)
+                }
+                TransactionOperation::Delete(reference, expected_revision) => {
+                    let effects = reference.delete_table_value(
+                        *expected_revision,
+                        table_stored_requests,
+                        &pending,
+                        &pending,
+                    )?;
+                    (reference.id.clone(), effects)
+                }
+            };
+            keys.push(key);
+            pending.effects.extend(effects);
+        }
+        Ok((keys, pending.effects))
+    }
+}
+
+// Serves reads from the effects of the transaction so far, falling back to the database.
+struct PendingEffectsRequests<'a> {
+    effects: Effects,
+    column_value_requests: &'a dyn ColumnValueRequests,
+    table_value_requests: &'a dyn TableValueRequests,
+    index_value_requests: &'a dyn IndexValueRequests,
+}
+
+impl<'a> ColumnValueRequests for PendingEffectsRequests<'a> {
+    fn get_column_value(&self, cf_name: &str, key: &ColumnKey) -> DbResult<Option<ColumnValue>> {
+        let pending = self.effects.iter().rev().find_map(|effect| match effect {
+            Effect::ColumnValueEffect(ColumnValueEffect::Put(cf, k, value))
+                if is_same(cf, k, cf_name, key) =>
+            {
+                Some(Some(value.clone()))
+            }
+            Effect::ColumnValueEffect(ColumnValueEffect::Delete(cf, k))
+                if is_same(cf, k, cf_name, key) =>
+            {
+                Some(None)
+            }
+            _ => None,
+        });
+        match pending {
+            Some(value) => Ok(value),
+            None => self.column_value_requests.get_column_value(cf_name, key),
+        }
+    }
+}
+
+impl<'a> TableValueRequests for PendingEffectsRequests<'a> {
+    fn get_table_value(
+        &self,
+        cf_name: &str,
+        key: &TableValueReference,
+    ) -> DbResult<Option<TableValue>> {
+        let pending = self.effects.iter().rev().find_map(|effect| match effect {
+            Effect::TableValueEffect(TableValueEffect::Put(cf, k, value))
+                if is_same(cf, k, cf_name, &key.id) =>
+            {
+                Some(Some(value.clone()))
+            }
+            Effect::TableValueEffect(TableValueEffect::Delete(cf, k))
+                if is_same(cf, k, cf_name, &key.id) =>
+            {
+                Some(None)
+            }
+            _ => None,
+        });
+        match pending {
+            Some(value) => Ok(value),
+            None => self.table_value_requests.get_table_value(cf_name, key),
+        }
+    }
+}
+
+impl<'a> IndexValueRequests for PendingEffectsRequests<'a> {
+    fn get_index_value_stored(
+        &self,
+        cf_name: &str,
+        key: &IndexValueReference,
+    ) -> DbResult<Option<IndexValue>> {
+        let pending = self.effects.iter().rev().find_map(|effect| match effect {
+            Effect::IndexValueEffect(IndexValueEffect::Put(cf, k, value))
+                if is_same(cf, k, cf_name, &key.key) =>
+            {
+                Some(Some(value.clone()))
+            }
+            Effect::IndexValueEffect(IndexValueEffect::Delete(cf, k))
+                if is_same(cf, k, cf_name, &key.key) =>
+            {
+                Some(None)
+            }
+            _ => None,
+        });
+        match pending {
+            Some(value) => Ok(value),
+            None => self
+                .index_value_requests
+                .get_index_value_stored(cf_name, key),
+        }
+    }
+
+    // The pending entries are appended after the stored ones, so the entries are not
+    // sorted by key.
+    fn index_values_with_key_prefix(
+        &self,
+        cf_name: &str,
+        key_prefix: &IndexKey,
+        limit: usize,
+    ) -> DbResult<Vec<(IndexKey, IndexValue)>> {
+        let index_effects = self
+            .effects
+            .iter()
+            .filter_map(|effect| match effect {
+                Effect::IndexValueEffect(index_effect) => Some(index_effect),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // Every pending delete may hide one of the stored entries.
+        let deletes = index_effects
+            .iter()
+            .filter(|effect| matches!(effect, IndexValueEffect::Delete(cf, _) if cf == cf_name))
+            .count();
+        let mut entries = self.index_value_requests.index_values_with_key_prefix(
+            cf_name,
+            key_prefix,
+            limit.saturating_add(deletes),
+        )?;
+        for effect in index_effects {
+            match effect {
+                IndexValueEffect::Put(cf, k, value)
+                    if cf == cf_name && k.values.starts_with(&key_prefix.values) =>
+                {
+                    entries.retain(|(key, _)| key != k);
+                    entries.push((k.clone(), value.clone()));
+                }
+                IndexValueEffect::Delete(cf, k) if cf == cf_name => {
+                    entries.retain(|(key, _)| key != k)
+                }
+                _ => {}
+            }
+        }
+        entries.truncate(limit);
+        Ok(entries)
+    }
+}
+
+fn is_same(cf: &CfName, key: &OndoKey, cf_name: &str, other_key: &OndoKey) -> bool {
+    cf == cf_name && key == other_key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::reference::column_value_reference::tests::MockColumnValueTestRequests;
+    use crate::db::reference::index_value_reference::tests::MockIndexValueTestRequests;
+    use crate::db::reference::table_reference::stored::tests::{
+        create_table_stored, MockTableStoredTestRequests,
+    };
+    use crate::db::reference::table_value_reference::tests::MockTableValueTestRequests;
+    use crate::db::reference::TableReference;
+    use serde_json::json;
+
+    fn create_reference() -> CreateTableValueReference {
+        CreateTableValueReference {
+            table_reference: TableReference::build("sample_domain", "sample_table"),
+            id: None,
+        }
+    }
+
+    fn table_mock() -> MockTableStoredTestRequests {
+        let mut table_mock = MockTableStoredTestRequests::new();
+        table_mock
+            .expect_get_table_stored()
+            .returning(|_, _| Ok(Some(create_table_stored())));
+        table_mock
+    }
+
+    #[test]
+    fn test_creates_see_previous_counter() {
+        let mut column_mock = MockColumnValueTestRequests::new();
+        column_mock
+            .expect_get_column_value()
+            .times(1)
+            .returning(|_, _| Ok(Some(json!(5))));
+        let mut value_mock = MockTableValueTestRequests::new();
+        value_mock
+            .expect_get_table_value()
+            .returning(|_, _| Ok(None));
+
+        let transaction = Transaction {
+            operations: vec![
+                TransactionOperation::Create(create_reference(), json!({"name": "a"})),
+                TransactionOperation::Create(create_reference(), json!({"name": "b"})),
+            ],
+        };
+        let (keys, effects) = transaction
+            .execute_transaction(
+                &column_mock,
+                &table_mock(),
+                &value_mock,
+                &MockIndexValueTestRequests::new(),
+            )
+            .unwrap();
+
+        assert_eq!(keys, vec![6u64.into(), 7u64.into()]);
+        assert_eq!(effects.len(), 4);
+    }
+
+    #[test]
+    fn test_update_sees_created_value() {
+        let mut column_mock = MockColumnValueTestRequests::new();
+        column_mock
+            .expect_get_column_value()
+            .returning(|_, _| Ok(None));
+        let mut value_mock = MockTableValueTestRequests::new();
+        value_mock
+            .expect_get_table_value()
+            .returning(|_, _| Ok(None));
+
+        let reference = TableValueReference::build("sample_domain", "sample_table", 1u64.into());
+        let transaction = Transaction {
+            operations: vec![
+                TransactionOperation::Create(create_reference(), json!({"name": "a"})),
+                TransactionOperation::Update(
+                    reference.clone(),
+                    json!({"_id": 1, "name": "b"}),
+                    Some(1),
+                ),
+                TransactionOperation::Delete(reference.clone(), Some(2)),
+            ],
+        };
+        let (keys, effects) = transaction
+            .execute_transaction(
+                &column_mock,
+                &table_mock(),
+                &value_mock,
+                &MockIndexValueTestRequests::new(),
+            )
+            .unwrap();
+
+        assert_eq!(keys, vec![1u64.into(), 1u64.into(), 1u64.into()]);
+        assert_eq!(
+            effects.last(),
+            Some(&Effect::TableValueEffect(TableValueEffect::Delete(
+                reference.container_cf_name(),
+                reference.id.clone(),
+            )))
+        );
+    }
+
+    #[test]
+    fn test_failed_operation_fails_transaction() {
+        let column_mock = MockColumnValueTestRequests::new();
+        let mut value_mock = MockTableValueTestRequests::new();
+        value_mock
+            .expect_get_table_value()
+            .returning(|_, _| Ok(None));
+
+        let reference = TableValueReference::build("sample_domain", "sample_table", 1u64.into());
+        let transaction = Transaction {
+            operations: vec![TransactionOperation::Delete(reference, None)],
+        };
+        let result = transaction.execute_transaction(
+            &column_mock,
+            &table_mock(),
+            &value_mock,
+            &MockIndexValueTestRequests::new(),
+        );
+
+        assert_eq!(result, Err(crate::db::DbError::NotFound));
+    }
+}
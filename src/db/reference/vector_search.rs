@@ -0,0 +1,165 @@
+//vector_search.rs
+//! Nearest neighbor queries of the vector indexes.
+//!
+//! Without a filter the search is approximate on the HNSW graph of the index if it
+//! has one, and exact otherwise. With a filter the candidates are the values found
+//! by a key prefix in an ordered index of the same table, and the search is exact.
+use crate::db::entity::index::vector::{check_vector, parse_vector, VectorIndexOptions};
+use crate::db::entity::{Index, IndexKind, OndoKey};
+use crate::db::reference::hnsw::{Candidate, HnswGraph, Nearest, DEFAULT_EF_SEARCH};
+use crate::db::reference::requests::{
+    IndexIteratorRequests, IndexValueRequests, TableStoredRequests,
+};
+use crate::db::reference::table_reference::stored::TableStoredReferenceTrait;
+use crate::db::reference::{CfNameMaker, IndexReference, IndexValueReference};
+use crate::db::{DbError, DbResult};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VectorQuery {
+    pub vector: Vec<f64>,
+    /// The number of values to find.
+    pub k: usize,
+    /// Compares every vector, even if the index has an HNSW graph.
+    pub exact: bool,
+    /// The number of candidates of the HNSW search, DEFAULT_EF_SEARCH if not set.
+    pub ef_search: Option<usize>,
+    /// Only the values found by the key prefix in an ordered index.
+    pub filter: Option<(IndexReference, OndoKey)>,
+}
+
+/// Finds the ids of the k nearest documents to the vector, closest first.
+pub(crate) fn search_vector_index<'a>(
+    index: &Index,
+    options: &VectorIndexOptions,
+    query: &VectorQuery,
+    parent_requests: &dyn TableStoredRequests,
+    index_value_requests: &dyn IndexValueRequests,
+    requests: &'a dyn IndexIteratorRequests<'a>,
+) -> DbResult<Vec<(OndoKey, f64)>> {
+    check_vector(&query.vector, options)?;
+    if query.k == 0 {
+        return Err(DbError::InvalidQuery("k must be at least 1".to_owned()));
+    }
+    if let Some((filter_reference, key_prefix)) = &query.filter {
+        let ids = filtered_ids(
+            index,
+            filter_reference,
+            key_prefix,
+            parent_requests,
+            requests,
+        )?;
+        return search_ids(index, options, query, &ids, index_value_requests);
+    }
+    match options.hnsw {
+        Some(hnsw) if !query.exact => {
+            let mut graph =
+                HnswGraph::new(&index.reference, options.metric, hnsw, index_value_requests);
+            let ef = query.ef_search.unwrap_or(DEFAULT_EF_SEARCH);
+            graph.search(&query.vector, query.k, ef)
+        }
+        _ => search_all(index, options, query, requests),
+    }
+}
+
+fn into_found(nearest: Nearest) -> Vec<(OndoKey, f64)> {
+    nearest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|candidate| (candidate.id, candidate.distance))
+        .collect()
+}
+
+// Compares the vector with every vector of the index.
+fn search_all<'a>(
+    index: &Index,
+    options: &VectorIndexOptions,
+    query: &VectorQuery,
+    requests: &'a dyn IndexIteratorRequests<'a>,
+) -> DbResult<Vec<(OndoKey, f64)>> {
+    let mut nearest = Nearest::new(query.k);
+    let entries = requests.all_entries_with_key_prefix(
+        &CfNameMaker::for_index_values(&index.reference),
+        OndoKey { values: vec![] },
+    )?;
+    for entry in entries {
+        let (id, value) = entry?;
+        let distance = options
+            .metric
+            .distance(&query.vector, &parse_vector(&value.values));
+        nearest.push(Candidate::new(distance, id)?);
+    }
+    Ok(into_found(nearest))
+}
+
+// The distinct ids found by the key prefix in the filter index.
+fn filtered_ids<'a>(
+    index: &Index,
+    filter_reference: &IndexReference,
+    key_prefix: &OndoKey,
+    parent_requests: &dyn TableStoredRequests,
+    requests: &'a dyn IndexIteratorRequests<'a>,
+) -> DbResult<Vec<OndoKey>> {
+    if filter_reference.table_reference != index.reference.table_reference {
+        return Err(DbError::InvalidQuery(format!(
+            "the filter index {} is not an index of the table {}",
+            filter_reference.index_name, index.reference.table_reference.table_name
+        )));
+    }
+    let filter_index = filter_reference
+        .table_reference
+        .get_table_stored(parent_requests)?
+        .ok_or(DbError::TableNotInitialized)?
+        .indexes
+        .get(&filter_reference.index_name)
+        .cloned()
+        .ok_or(DbError::IndexNotInitialized)?;
+    if filter_index.building {
+        return Err(DbError::IndexNotReady);
+    }
+    if filter_index.kind != IndexKind::Ordered {
+        return Err(DbError::InvalidQuery(format!(
+            "the filter index {} is not an ordered index",
+            filter_reference.index_name
+        )));
+    }
+    let mut seen = HashSet::new();
+    let mut ids = vec![];
+    let found = requests.all_values_with_key_prefix(
+        &CfNameMaker::for_index_values(filter_reference),
+        key_prefix.clone(),
+    )?;
+    for id in found {
+        let id = id?;
+        // A multikey index may find a value more than once.
+        let key = serde_json::to_string(&id.values)
+            .map_err(|e| DbError::SerializationError(e.to_string()))?;
+        if seen.insert(key) {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+// Compares the vector with the vectors of the documents.
+fn search_ids(
+    index: &Index,
+    options: &VectorIndexOptions,
+    query: &VectorQuery,
+    ids: &[OndoKey],
+    index_value_requests: &dyn IndexValueRequests,
+) -> DbResult<Vec<(OndoKey, f64)>> {
+    let cf_name = CfNameMaker::for_index_values(&index.reference);
+    let mut nearest = Nearest::new(query.k);
+    for id in ids {
+        let reference = IndexValueReference::new(index.reference.clone(), id.clone());
+        // Documents without a vector are not in the index.
+        if let Some(value) = index_value_requests.get_index_value_stored(&cf_name, &reference)? {
+            let distance = options
+                .metric
+                .distance(&query.vector, &parse_vector(&value.values));
+            nearest.push(Candidate::new(distance, id.clone())?);
+        }
+    }
+    Ok(into_found(nearest))
+}
@@ -13,20 +13,20 @@ use crate::ondo_remote::{
 };
 use tonic::{Request, Response, Status};
 
-impl<'a> Into<DatabaseServerReference> for &'a DatabaseServerReferenceMessage {
-    fn into(self) -> DatabaseServerReference {
+impl From<&DatabaseServerReferenceMessage> for DatabaseServerReference {
+    fn from(_val: &DatabaseServerReferenceMessage) -> Self {
         DatabaseServerReference
     }
 }
 
-impl<'a> Into<DatabaseServer> for &'a DatabaseServerMessage {
-    fn into(self) -> DatabaseServer {
+impl From<&DatabaseServerMessage> for DatabaseServer {
+    fn from(_val: &DatabaseServerMessage) -> Self {
         DatabaseServer::default()
     }
 }
 
-impl Into<DatabaseServerMessage> for DatabaseServer {
-    fn into(self) -> DatabaseServerMessage {
+impl From<DatabaseServer> for DatabaseServerMessage {
+    fn from(_val: DatabaseServer) -> Self {
         DatabaseServerMessage {}
     }
 }
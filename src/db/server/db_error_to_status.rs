@@ -1,43 +1,294 @@
 use crate::db::db_error::*;
-use tonic::Status;
+use crate::db::reference::{DomainReference, IndexReference, TableReference, TableValueReference};
+use crate::ondo_remote::{ErrorDetailsMessage, SchemaViolationMessage};
+use prost::Message;
+use rocksdb::ErrorKind;
+use tonic::{Code, Status};
 
-fn db_error_to_status(err: DbError) -> Status {
+impl From<&SchemaViolation> for SchemaViolationMessage {
+    fn from(violation: &SchemaViolation) -> Self {
+        SchemaViolationMessage {
+            path: violation.path.clone(),
+            message: violation.message.clone(),
+        }
+    }
+}
+
+/// The domain, table and index an error is about.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct DbErrorContext {
+    pub domain_name: String,
+    pub table_name: String,
+    pub index_name: String,
+}
+
+impl From<&DomainReference> for DbErrorContext {
+    fn from(val: &DomainReference) -> Self {
+        DbErrorContext {
+            domain_name: val.domain_name.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&TableReference> for DbErrorContext {
+    fn from(val: &TableReference) -> Self {
+        DbErrorContext {
+            table_name: val.table_name.clone(),
+            ..(&val.domain_reference).into()
+        }
+    }
+}
+
+impl From<&IndexReference> for DbErrorContext {
+    fn from(val: &IndexReference) -> Self {
+        DbErrorContext {
+            index_name: val.index_name.clone(),
+            ..(&val.table_reference).into()
+        }
+    }
+}
+
+impl DbErrorContext {
+    /// Names the domain, table and index on the span of the request.
+    fn record_in_current_span(&self) {
+        let span = tracing::Span::current();
+        for (field, name) in [
+            ("domain", &self.domain_name),
+            ("table", &self.table_name),
+            ("index", &self.index_name),
+        ] {
+            if !name.is_empty() {
+                span.record(field, name.as_str());
+            }
+        }
+    }
+}
+
+impl From<&TableValueReference> for DbErrorContext {
+    fn from(val: &TableValueReference) -> Self {
+        (&val.table_reference).into()
+    }
+}
+
+fn db_error_code_to_status_code(err: &DbError) -> Code {
+    match err {
+        DbError::NotFound | DbError::CfNotFound => Code::NotFound,
+        DbError::AlreadyExists | DbError::UniqueConstraintViolation(_) => Code::AlreadyExists,
+        DbError::DatabaseNotInitialized
+        | DbError::DomainNotInitialized
+        | DbError::TableNotInitialized
+        | DbError::IndexNotInitialized
+        | DbError::IndexNotReady
+        | DbError::ValuesViolateSchema(_)
+        | DbError::PatchTestFailed(_)
+        | DbError::NotU64 => Code::FailedPrecondition,
+        DbError::SerializationError(_)
+        | DbError::InvalidName(_)
+        | DbError::InvalidQuery(_)
+        | DbError::InvalidVector(_)
+        | DbError::InvalidSchema(_)
+        | DbError::SchemaViolation(_)
+        | DbError::InvalidPatch(_)
+        | DbError::ParallelArrays(_) => Code::InvalidArgument,
+        DbError::RevisionConflict(_, _) => Code::Aborted,
+        DbError::RocksDbError(rocks_db_error) => match rocks_db_error.kind() {
+            ErrorKind::Busy
+            | ErrorKind::TimedOut
+            | ErrorKind::TryAgain
+            | ErrorKind::ShutdownInProgress => Code::Unavailable,
+            _ => Code::Internal,
+        },
+        DbError::Other(_) | DbError::CanNotLockDbMutex => Code::Internal,
+    }
+}
+
+// The details of the status are an encoded ErrorDetailsMessage.
+fn db_error_to_status(err: DbError, context: DbErrorContext) -> Status {
     let db_error_message = err.to_string();
+    let code = db_error_code_to_status_code(&err);
+    let schema_violations = match &err {
+        DbError::SchemaViolation(violations) => violations
+            .iter()
+            .map(SchemaViolationMessage::from)
+            .collect(),
+        _ => vec![],
+    };
     let db_error_code = u32::from(err);
     let status_message = format!("Database error {}: {}", db_error_code, db_error_message);
-    Status::unknown(status_message)
+    if code == Code::Internal || code == Code::Unavailable {
+        tracing::error!(db_error_code, "{}", db_error_message);
+    } else {
+        tracing::debug!(db_error_code, "{}", db_error_message);
+    }
+    let details = ErrorDetailsMessage {
+        db_error_code,
+        domain_name: context.domain_name,
+        table_name: context.table_name,
+        index_name: context.index_name,
+        schema_violations,
+    };
+    Status::with_details(code, status_message, details.encode_to_vec().into())
 }
 
-fn map_db_error_to_status<T>(r: DbResult<T>) -> Result<T, Status> {
+fn map_db_error_to_status<T>(r: DbResult<T>, context: DbErrorContext) -> Result<T, Status> {
     match r {
         Ok(t) => Ok(t),
-        Err(e) => Err(db_error_to_status(e)),
+        Err(e) => Err(db_error_to_status(e, context)),
     }
 }
 
-fn map_db_none_to_status<T>(opt: Option<T>) -> Result<T, Status> {
+fn map_db_none_to_status<T>(opt: Option<T>, context: DbErrorContext) -> Result<T, Status> {
     match opt {
         Some(t) => Ok(t),
-        None => Err(Status::not_found("Not found")),
+        None => Err(db_error_to_status(DbError::NotFound, context)),
     }
 }
 
+/// The `_for` variants also name the domain, table and index of the request on its span.
 pub(crate) trait DbErrorToStatus<T> {
     fn map_db_err_to_status(self) -> Result<T, Status>;
+    fn map_db_err_to_status_for<C: Into<DbErrorContext>>(self, context: C) -> Result<T, Status>;
 }
 
 impl<T> DbErrorToStatus<T> for DbResult<T> {
     fn map_db_err_to_status(self) -> Result<T, Status> {
-        map_db_error_to_status(self)
+        map_db_error_to_status(self, DbErrorContext::default())
+    }
+
+    fn map_db_err_to_status_for<C: Into<DbErrorContext>>(self, context: C) -> Result<T, Status> {
+        let context = context.into();
+        context.record_in_current_span();
+        map_db_error_to_status(self, context)
     }
 }
 
 pub(crate) trait DbErrorOptionToStatus<T> {
     fn map_db_err_option_to_status(self) -> Result<T, Status>;
+    fn map_db_err_option_to_status_for<C: Into<DbErrorContext>>(
+        self,
+        context: C,
+    ) -> Result<T, Status>;
 }
 
 impl<T> DbErrorOptionToStatus<T> for DbResult<Option<T>> {
     fn map_db_err_option_to_status(self) -> Result<T, Status> {
-        map_db_none_to_status(map_db_error_to_status(self)?)
+        self.map_db_err_option_to_status_for(DbErrorContext::default())
+    }
+
+    fn map_db_err_option_to_status_for<C: Into<DbErrorContext>>(
+        self,
+        context: C,
+    ) -> Result<T, Status> {
+        let context = context.into();
+        context.record_in_current_span();
+        map_db_none_to_status(map_db_error_to_status(self, context.clone())?, context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn details(status: &Status) -> ErrorDetailsMessage {
+        ErrorDetailsMessage::decode(status.details()).unwrap()
+    }
+
+    #[test]
+    fn test_status_codes() {
+        let cases = vec![
+            (DbError::NotFound, Code::NotFound),
+            (DbError::AlreadyExists, Code::AlreadyExists),
+            (DbError::TableNotInitialized, Code::FailedPrecondition),
+            (
+                DbError::SerializationError("bad".to_owned()),
+                Code::InvalidArgument,
+            ),
+            (DbError::RevisionConflict(1, 2), Code::Aborted),
+            (
+                DbError::InvalidName("a/b".to_owned()),
+                Code::InvalidArgument,
+            ),
+            (
+                DbError::UniqueConstraintViolation("email".to_owned()),
+                Code::AlreadyExists,
+            ),
+            (DbError::InvalidQuery("(".to_owned()), Code::InvalidArgument),
+            (
+                DbError::InvalidVector("[]".to_owned()),
+                Code::InvalidArgument,
+            ),
+            (DbError::IndexNotReady, Code::FailedPrecondition),
+            (
+                DbError::InvalidSchema("{".to_owned()),
+                Code::InvalidArgument,
+            ),
+            (DbError::ValuesViolateSchema(2), Code::FailedPrecondition),
+            (
+                DbError::InvalidPatch("/a".to_owned()),
+                Code::InvalidArgument,
+            ),
+            (
+                DbError::PatchTestFailed("/a".to_owned()),
+                Code::FailedPrecondition,
+            ),
+            (
+                DbError::ParallelArrays("tags and sizes".to_owned()),
+                Code::InvalidArgument,
+            ),
+            (DbError::CanNotLockDbMutex, Code::Internal),
+        ];
+        for (err, code) in cases {
+            let status = Err::<(), _>(err).map_db_err_to_status().unwrap_err();
+            assert_eq!(status.code(), code);
+        }
+    }
+
+    #[test]
+    fn test_status_details() {
+        let index_reference = IndexReference::build("domain", "table", "index");
+        let status = Err::<(), _>(DbError::IndexNotInitialized)
+            .map_db_err_to_status_for(&index_reference)
+            .unwrap_err();
+        assert_eq!(
+            details(&status),
+            ErrorDetailsMessage {
+                db_error_code: 5,
+                domain_name: "domain".to_owned(),
+                table_name: "table".to_owned(),
+                index_name: "index".to_owned(),
+                schema_violations: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_schema_violation_details() {
+        let violation = SchemaViolation {
+            path: "/age".to_owned(),
+            message: "-1 is less than the minimum of 0".to_owned(),
+        };
+        let status = Err::<(), _>(DbError::SchemaViolation(vec![violation]))
+            .map_db_err_to_status()
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(
+            details(&status).schema_violations,
+            vec![SchemaViolationMessage {
+                path: "/age".to_owned(),
+                message: "-1 is less than the minimum of 0".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_none_is_not_found() {
+        let table_reference = TableReference::build("domain", "table");
+        let status = Ok::<Option<()>, _>(None)
+            .map_db_err_option_to_status_for(&table_reference)
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(details(&status).db_error_code, 1);
+        assert_eq!(details(&status).table_name, "table");
     }
 }
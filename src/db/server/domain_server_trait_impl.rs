@@ -1,6 +1,7 @@
 use super::db_error_to_status::DbErrorOptionToStatus;
 use super::db_error_to_status::DbErrorToStatus;
 use super::domain_server_trait::DomainServerTrait;
+use super::message_field::required_field;
 use super::rocks_db_accessor::RocksDbAccessor;
 use super::source_sink::effects_sink::EffectsSink;
 use crate::{
@@ -12,33 +13,35 @@ use crate::{
 };
 use tonic::{Request, Response, Status};
 
-impl<'a> Into<DomainReference> for &'a DomainReferenceMessage {
-    fn into(self) -> DomainReference {
+impl From<&DomainReferenceMessage> for DomainReference {
+    fn from(val: &DomainReferenceMessage) -> Self {
         DomainReference {
-            domain_name: self.domain_name.clone(),
+            domain_name: val.domain_name.clone(),
         }
     }
 }
 
-impl Into<DomainReferenceMessage> for DomainReference {
-    fn into(self) -> DomainReferenceMessage {
+impl From<DomainReference> for DomainReferenceMessage {
+    fn from(val: DomainReference) -> Self {
         DomainReferenceMessage {
-            domain_name: self.domain_name,
+            domain_name: val.domain_name,
         }
     }
 }
 
-impl<'a> Into<Domain> for &'a DomainMessage {
-    fn into(self) -> Domain {
-        Domain {
-            reference: self.domain_reference.as_ref().unwrap().into(),
-        }
+impl TryFrom<&DomainMessage> for Domain {
+    type Error = Status;
+    fn try_from(val: &DomainMessage) -> Result<Self, Self::Error> {
+        Ok(Domain {
+            reference: required_field(&val.domain_reference, "DomainMessage.domain_reference")?
+                .into(),
+        })
     }
 }
 
-impl Into<DomainMessage> for Domain {
-    fn into(self) -> DomainMessage {
-        let r_msg: DomainReferenceMessage = self.reference.into();
+impl From<Domain> for DomainMessage {
+    fn from(val: Domain) -> Self {
+        let r_msg: DomainReferenceMessage = val.reference.into();
         DomainMessage {
             domain_reference: Some(r_msg),
         }
@@ -47,11 +50,11 @@ impl Into<DomainMessage> for Domain {
 
 impl DomainServerTrait for RocksDbAccessor {
     fn create_domain(&self, r: Request<DomainMessage>) -> Result<Response<EmptyMessage>, Status> {
-        let entity: Domain = r.get_ref().into();
+        let entity: Domain = r.get_ref().try_into()?;
         entity
             .reference
             .post_domain(&entity, self, self)
-            .map_db_err_to_status()?
+            .map_db_err_to_status_for(&entity.reference)?
             .apply_effects(self)
     }
 
@@ -62,7 +65,7 @@ impl DomainServerTrait for RocksDbAccessor {
         let reference: DomainReference = r.get_ref().into();
         reference
             .delete_domain(self, self, self)
-            .map_db_err_to_status()?
+            .map_db_err_to_status_for(&reference)?
             .apply_effects(self)
     }
 
@@ -73,16 +76,16 @@ impl DomainServerTrait for RocksDbAccessor {
         let reference: DomainReference = r.get_ref().into();
         reference
             .get_domain(self)
-            .map_db_err_option_to_status()
+            .map_db_err_option_to_status_for(&reference)
             .map(|entity| Response::new(entity.into()))
     }
 
     fn update_domain(&self, r: Request<DomainMessage>) -> Result<Response<EmptyMessage>, Status> {
-        let entity: Domain = r.get_ref().into();
+        let entity: Domain = r.get_ref().try_into()?;
         entity
             .reference
             .put_domain(&entity, self)
-            .map_db_err_to_status()?
+            .map_db_err_to_status_for(&entity.reference)?
             .apply_effects(self)
     }
 
@@ -91,7 +94,9 @@ impl DomainServerTrait for RocksDbAccessor {
         r: Request<DomainReferenceMessage>,
     ) -> Result<Response<ArrayOfStringResponse>, Status> {
         let reference: DomainReference = r.get_ref().into();
-        let names = reference.list_table_names(self).map_db_err_to_status()?;
+        let names = reference
+            .list_table_names(self)
+            .map_db_err_to_status_for(&reference)?;
         let response = ArrayOfStringResponse { values: names };
         Ok(Response::new(response))
     }
@@ -127,7 +132,7 @@ mod tests {
         let message = DomainMessage {
             domain_reference: Some(reference.into()),
         };
-        let domain: Domain = (&message).into();
+        let domain: Domain = (&message).try_into().unwrap();
         assert_eq!(domain.reference.domain_name, "example.com");
     }
 
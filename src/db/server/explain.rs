@@ -0,0 +1,225 @@
+//explain.rs
+//! The access paths of the queries and the finds of the Explain request.
+//!
+//! A query scans the values of its table, or the ranges of keys in the index chosen by
+//! the planner and then gets the values by their _id. A find scans its index the same way.
+//! Executing the request reads through MeasuredRequests to count and time the reads.
+use super::db_error_to_status::{DbErrorOptionToStatus, DbErrorToStatus};
+use super::index_server_trait_impl::{IndexedValueRangeReference, IndexedValueReference};
+use super::message_field::required_field;
+use super::page::{encode_key, page_request};
+use super::rocks_db_accessor::{DbReadLockGuardWrapper, RocksDbAccessor};
+use super::source_sink::measured_requests::{ExecutionStats, MeasuredRequests};
+use super::table_server_trait_impl::QueryPayload;
+use crate::db::entity::OndoKey;
+use crate::db::reference::query::{IndexCandidate, KeyRange, QueryPlan};
+use crate::db::reference::table_reference::stored::TableStoredReferenceTrait;
+use crate::db::reference::{IndexReference, IndexReferenceTrait, TableReferenceTrait};
+use crate::ondo_remote::{explain_message, *};
+use std::time::{Duration, Instant};
+use tonic::Status;
+
+// What an explained request reads.
+struct AccessPath {
+    index_name: String,
+    cf_name: String,
+    key_ranges: Vec<KeyRange>,
+    fetch_values: bool,
+    candidates: Vec<IndexCandidate>,
+}
+
+pub(super) fn explain(
+    ra: &RocksDbAccessor,
+    message: &ExplainMessage,
+) -> Result<ExplainResponse, Status> {
+    let start = Instant::now();
+    let guarded_db = ra.guarded_db();
+    let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).map_db_err_to_status()?;
+    let measured = MeasuredRequests::new(&db_wrapper);
+    let request = required_field(&message.request, "ExplainMessage.request")?;
+    let (access_path, rows_returned) = match request {
+        explain_message::Request::Query(query) => {
+            explain_query(query.try_into()?, message.execute, &db_wrapper, &measured)?
+        }
+        explain_message::Request::FindValues(find) => {
+            explain_find_values(find, message.execute, &db_wrapper, &measured)?
+        }
+        explain_message::Request::FindValuesByRange(find) => {
+            explain_find_values_by_range(find, message.execute, &db_wrapper, &measured)?
+        }
+    };
+    let execution =
+        rows_returned.map(|rows_returned| stats_message(&measured.stats, rows_returned, start));
+    Ok(ExplainResponse {
+        index_name: access_path.index_name,
+        cf_name: access_path.cf_name,
+        key_ranges: access_path
+            .key_ranges
+            .into_iter()
+            .map(key_range_message)
+            .collect::<Result<_, _>>()?,
+        fetch_values: access_path.fetch_values,
+        candidates: access_path
+            .candidates
+            .into_iter()
+            .map(candidate_message)
+            .collect(),
+        execution,
+    })
+}
+
+// The access path, and the number of values returned if the request was executed.
+type Explained = (AccessPath, Option<usize>);
+
+fn explain_query<'a>(
+    payload: QueryPayload,
+    execute: bool,
+    db_wrapper: &DbReadLockGuardWrapper,
+    measured: &'a MeasuredRequests<'a>,
+) -> Result<Explained, Status> {
+    let reference = payload.table_reference;
+    let explanation = reference
+        .explain_query(&payload.query.filter, db_wrapper)
+        .map_db_err_to_status_for(&reference)?;
+    let access_path = match explanation.plan {
+        QueryPlan::TableScan => AccessPath {
+            index_name: String::new(),
+            cf_name: reference.value_cf_name(),
+            key_ranges: vec![KeyRange::Prefix(OndoKey { values: vec![] })],
+            fetch_values: false,
+            candidates: explanation.candidates,
+        },
+        QueryPlan::IndexScan { index_name, ranges } => AccessPath {
+            cf_name: IndexReference::new(reference.clone(), &index_name).value_cf_name(),
+            index_name,
+            key_ranges: ranges,
+            fetch_values: true,
+            candidates: explanation.candidates,
+        },
+    };
+    if !execute {
+        return Ok((access_path, None));
+    }
+    let values = reference
+        .query_values(&payload.query, db_wrapper, measured, measured, measured)
+        .map_db_err_to_status_for(&reference)?;
+    Ok((access_path, Some(values.len())))
+}
+
+fn index_access_path(
+    reference: &IndexReference,
+    key_range: KeyRange,
+    db_wrapper: &DbReadLockGuardWrapper,
+) -> Result<AccessPath, Status> {
+    reference
+        .get_index(db_wrapper)
+        .map_db_err_option_to_status_for(reference)?;
+    reference
+        .check_index_ready(db_wrapper)
+        .map_db_err_to_status_for(reference)?;
+    Ok(AccessPath {
+        index_name: reference.index_name.clone(),
+        cf_name: reference.value_cf_name(),
+        key_ranges: vec![key_range],
+        fetch_values: true,
+        candidates: vec![],
+    })
+}
+
+fn explain_find_values<'a>(
+    message: &FindValuesMessage,
+    execute: bool,
+    db_wrapper: &DbReadLockGuardWrapper,
+    measured: &'a MeasuredRequests<'a>,
+) -> Result<Explained, Status> {
+    let indexed_value_reference: IndexedValueReference = required_field(
+        &message.indexed_value_reference,
+        "FindValuesMessage.indexed_value_reference",
+    )?
+    .try_into()?;
+    let reference = indexed_value_reference.index_reference;
+    let key_prefix = indexed_value_reference.key;
+    let page_request = page_request(message.limit, &message.continuation_token)?;
+    let key_range = KeyRange::Prefix(key_prefix.clone());
+    let access_path = index_access_path(&reference, key_range, db_wrapper)?;
+    if !execute {
+        return Ok((access_path, None));
+    }
+    let page = reference
+        .values_page_with_key_prefix(key_prefix, &page_request, measured, measured)
+        .map_db_err_to_status_for(&reference)?;
+    Ok((access_path, Some(page.values.len())))
+}
+
+fn explain_find_values_by_range<'a>(
+    message: &FindValuesByRangeMessage,
+    execute: bool,
+    db_wrapper: &DbReadLockGuardWrapper,
+    measured: &'a MeasuredRequests<'a>,
+) -> Result<Explained, Status> {
+    let indexed_value_range_reference: IndexedValueRangeReference = required_field(
+        &message.indexed_value_range_reference,
+        "FindValuesByRangeMessage.indexed_value_range_reference",
+    )?
+    .try_into()?;
+    let reference = indexed_value_range_reference.index_reference;
+    let start_key = indexed_value_range_reference.start_key;
+    let end_key = indexed_value_range_reference.end_key;
+    let page_request = page_request(message.limit, &message.continuation_token)?;
+    let key_range = KeyRange::Range(start_key.clone(), end_key.clone());
+    let access_path = index_access_path(&reference, key_range, db_wrapper)?;
+    if !execute {
+        return Ok((access_path, None));
+    }
+    let page = reference
+        .values_page_with_key_range(start_key, end_key, &page_request, measured, measured)
+        .map_db_err_to_status_for(&reference)?;
+    Ok((access_path, Some(page.values.len())))
+}
+
+fn key_range_message(key_range: KeyRange) -> Result<KeyRangeMessage, Status> {
+    let (start_key, end_key) = match key_range {
+        KeyRange::Prefix(key_prefix) => (key_prefix, None),
+        KeyRange::Range(start_key, end_key) => (start_key, Some(end_key)),
+    };
+    Ok(KeyRangeMessage {
+        encoded_start_key: encode_key(&start_key)?,
+        encoded_end_key: end_key
+            .as_ref()
+            .map(encode_key)
+            .transpose()?
+            .unwrap_or_default(),
+        start_key: Some(start_key.into()),
+        end_key: end_key.map(Into::into),
+    })
+}
+
+fn candidate_message(candidate: IndexCandidate) -> IndexCandidateMessage {
+    IndexCandidateMessage {
+        index_name: candidate.index_name,
+        usable: candidate.served.is_some(),
+        equality_fields: candidate
+            .served
+            .map_or(0, |served| served.equalities as u32),
+        range: candidate.served.map_or(false, |served| served.range),
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos() as u64
+}
+
+fn stats_message(
+    stats: &ExecutionStats,
+    rows_returned: usize,
+    start: Instant,
+) -> ExecutionStatsMessage {
+    ExecutionStatsMessage {
+        rows_scanned: stats.rows_scanned.get(),
+        values_fetched: stats.values_fetched.get(),
+        rows_returned: rows_returned as u64,
+        iteration_nanos: nanos(stats.iteration_time.get()),
+        get_value_nanos: nanos(stats.get_value_time.get()),
+        total_nanos: nanos(start.elapsed()),
+    }
+}
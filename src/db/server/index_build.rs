@@ -0,0 +1,671 @@
+//index_build.rs
+//! The builds of the indexes from the values of their tables.
+//!
+//! A new index is stored as building first, so that the writes keep it up to date,
+//! then the values of the table are indexed chunk by chunk. Each chunk is indexed
+//! under the writes lock, so the writes wait for at most one chunk, and only the
+//! effects of one chunk are in memory. The index is marked as built after the last
+//! chunk, and the reads use it from then on.
+use super::db_error_to_status::{DbErrorOptionToStatus, DbErrorToStatus};
+use super::rocks_db_accessor::RocksDbAccessor;
+use super::source_sink::EffectsSink;
+use crate::db::entity::{Index, OndoKey};
+use crate::db::enums::table_stored_iterator_requests_factory::TableStoredIteratorRequestsFactoryEnum;
+use crate::db::reference::table_reference::stored::TableStoredReferenceTrait;
+use crate::db::reference::{
+    CfNameMaker, DatabaseServerReference, DatabaseServerReferenceTrait, DomainReference,
+    DomainReferenceTrait, IndexReference, IndexReferenceTrait, TableReference,
+};
+use crate::db::{DbError, DbResult};
+use crate::ondo_remote::{self, IndexBuildMessage};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tonic::Status;
+
+/// The number of values indexed under one hold of the writes lock.
+pub(crate) const INDEX_BUILD_CHUNK_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IndexBuildState {
+    Ready,
+    Building,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexBuildProgress {
+    pub state: IndexBuildState,
+    pub values_indexed: u64,
+    /// The estimated number of values of the table when the build started.
+    pub values_estimated: u64,
+    /// Why the build failed.
+    pub error: Option<String>,
+}
+
+impl From<IndexBuildProgress> for IndexBuildMessage {
+    fn from(progress: IndexBuildProgress) -> Self {
+        let state = match progress.state {
+            IndexBuildState::Ready => ondo_remote::IndexBuildState::Ready,
+            IndexBuildState::Building => ondo_remote::IndexBuildState::Building,
+            IndexBuildState::Failed => ondo_remote::IndexBuildState::Failed,
+            IndexBuildState::Cancelled => ondo_remote::IndexBuildState::Cancelled,
+        };
+        IndexBuildMessage {
+            state: state as i32,
+            values_indexed: progress.values_indexed,
+            values_estimated: progress.values_estimated,
+            error: progress.error.unwrap_or_default(),
+        }
+    }
+}
+
+struct IndexBuild {
+    progress: IndexBuildProgress,
+    // A new build of the index replaces the running one, which stops at its next chunk.
+    generation: u64,
+    cancelled: bool,
+}
+
+/// The last build of each index since the server started, keyed by the column family of the index.
+#[derive(Clone, Default)]
+pub(crate) struct IndexBuilds {
+    builds: Arc<Mutex<HashMap<String, IndexBuild>>>,
+    generations: Arc<Mutex<u64>>,
+    // The threads of the builds in the background, joined when the database closes.
+    threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl IndexBuilds {
+    fn with_build<T>(
+        &self,
+        reference: &IndexReference,
+        f: impl FnOnce(Option<&mut IndexBuild>) -> T,
+    ) -> T {
+        let mut builds = self.builds.lock().unwrap_or_else(|err| err.into_inner());
+        f(builds.get_mut(&CfNameMaker::for_index_values(reference)))
+    }
+
+    fn register(&self, reference: &IndexReference, values_estimated: u64) -> u64 {
+        let generation = {
+            let mut generations = self
+                .generations
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            *generations += 1;
+            *generations
+        };
+        let build = IndexBuild {
+            progress: IndexBuildProgress {
+                state: IndexBuildState::Building,
+                values_indexed: 0,
+                values_estimated,
+                error: None,
+            },
+            generation,
+            cancelled: false,
+        };
+        let mut builds = self.builds.lock().unwrap_or_else(|err| err.into_inner());
+        builds.insert(CfNameMaker::for_index_values(reference), build);
+        generation
+    }
+
+    fn is_current(&self, reference: &IndexReference, generation: u64) -> bool {
+        self.with_build(reference, |build| {
+            build.map_or(false, |build| build.generation == generation)
+        })
+    }
+
+    fn is_cancelled(&self, reference: &IndexReference) -> bool {
+        self.with_build(reference, |build| {
+            build.map_or(false, |build| build.cancelled)
+        })
+    }
+
+    fn add_indexed(&self, reference: &IndexReference, values_indexed: usize) {
+        self.with_build(reference, |build| {
+            if let Some(build) = build {
+                build.progress.values_indexed += values_indexed as u64;
+            }
+        })
+    }
+
+    fn finish(
+        &self,
+        reference: &IndexReference,
+        generation: u64,
+        state: IndexBuildState,
+        error: Option<String>,
+    ) {
+        self.with_build(reference, |build| match build {
+            Some(build) if build.generation == generation => {
+                build.progress.state = state;
+                build.progress.error = error;
+            }
+            _ => {}
+        })
+    }
+
+    fn progress(&self, reference: &IndexReference) -> Option<IndexBuildProgress> {
+        self.with_build(reference, |build| build.map(|build| build.progress.clone()))
+    }
+
+    fn add_thread(&self, thread: JoinHandle<()>) {
+        let mut threads = self.threads.lock().unwrap_or_else(|err| err.into_inner());
+        threads.retain(|thread| !thread.is_finished());
+        threads.push(thread);
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Stops the builds before their next chunk and waits for the builds in the background.
+    /// The stopped indexes stay building, and their builds resume on the next start.
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.join_threads();
+    }
+
+    /// Waits for the builds in the background to finish.
+    #[cfg(test)]
+    pub(crate) fn wait(&self) {
+        self.join_threads();
+    }
+
+    fn join_threads(&self) {
+        let threads = {
+            let mut threads = self.threads.lock().unwrap_or_else(|err| err.into_inner());
+            std::mem::take(&mut *threads)
+        };
+        for thread in threads {
+            if thread.join().is_err() {
+                tracing::error!("An index build panicked");
+            }
+        }
+    }
+
+    // Returns false if the index is not being built.
+    fn cancel(&self, reference: &IndexReference) -> bool {
+        self.with_build(reference, |build| match build {
+            Some(build) if build.progress.state == IndexBuildState::Building => {
+                build.cancelled = true;
+                true
+            }
+            _ => false,
+        })
+    }
+}
+
+enum ChunkOutcome {
+    More(OndoKey),
+    Done,
+    Cancelled,
+    // A newer build of the index runs instead.
+    Replaced,
+    // The database is closing.
+    Stopped,
+}
+
+impl RocksDbAccessor {
+    /// Adds the index, or replaces it if replace is set, and registers its build.
+    /// Returns the generation of the build, for run_index_build.
+    pub(crate) fn start_index_build(&self, index: &Index, replace: bool) -> Result<u64, Status> {
+        let reference = &index.reference;
+        let _writes = self.lock_writes().map_db_err_to_status()?;
+        let effects = match replace {
+            true => reference.put_index(index, self),
+            false => reference.post_index(index, self),
+        }
+        .map_db_err_to_status_for(reference)?;
+        effects.apply_effects(self)?;
+        let values_estimated = self
+            .estimate_values(&reference.table_reference)
+            .map_db_err_to_status_for(reference)?;
+        Ok(self.index_builds.register(reference, values_estimated))
+    }
+
+    /// Builds the index, and returns Cancelled if the build was cancelled or replaced,
+    /// or Building if it stopped because the database is closing.
+    /// A failed or cancelled build drops the index.
+    pub(crate) fn run_index_build(
+        &self,
+        reference: &IndexReference,
+        generation: u64,
+    ) -> Result<IndexBuildState, Status> {
+        let mut start_key = None;
+        let state = loop {
+            match self.build_chunk(reference, generation, start_key.take()) {
+                Ok(ChunkOutcome::More(next_key)) => start_key = Some(next_key),
+                Ok(ChunkOutcome::Done) => break IndexBuildState::Ready,
+                Ok(ChunkOutcome::Cancelled) => break IndexBuildState::Cancelled,
+                Ok(ChunkOutcome::Replaced) => return Ok(IndexBuildState::Cancelled),
+                Ok(ChunkOutcome::Stopped) => return Ok(IndexBuildState::Building),
+                Err(status) => {
+                    self.drop_failed_index(reference, generation, &status);
+                    return Err(status);
+                }
+            }
+        };
+        self.index_builds.finish(reference, generation, state, None);
+        Ok(state)
+    }
+
+    /// Runs the build in a thread of its own.
+    pub(crate) fn spawn_index_build(&self, reference: IndexReference, generation: u64) {
+        let ra = self.clone();
+        let thread = std::thread::spawn(move || {
+            if let Err(status) = ra.run_index_build(&reference, generation) {
+                tracing::warn!(
+                    index = %reference.index_name,
+                    "The index build failed: {}",
+                    status.message()
+                );
+            }
+        });
+        self.index_builds.add_thread(thread);
+    }
+
+    /// The state of the last build of the index. An index that was not built since
+    /// the server started is ready, or building if its build is resumed.
+    pub(crate) fn index_build_progress(
+        &self,
+        reference: &IndexReference,
+    ) -> Result<IndexBuildProgress, Status> {
+        if let Some(progress) = self.index_builds.progress(reference) {
+            return Ok(progress);
+        }
+        let index = reference
+            .get_index(self)
+            .map_db_err_option_to_status_for(reference)?;
+        let state = match index.building {
+            true => IndexBuildState::Building,
+            false => IndexBuildState::Ready,
+        };
+        Ok(IndexBuildProgress {
+            state,
+            values_indexed: 0,
+            values_estimated: 0,
+            error: None,
+        })
+    }
+
+    /// Stops the build of the index before its next chunk. The index is dropped then.
+    pub(crate) fn cancel_running_index_build(
+        &self,
+        reference: &IndexReference,
+    ) -> Result<(), Status> {
+        // The last chunk finishes the build under the writes lock,
+        // so a cancel can not land between the finish and the state of the build.
+        let _writes = self.lock_writes().map_db_err_to_status()?;
+        let index = reference
+            .get_index(self)
+            .map_db_err_to_status_for(reference)?;
+        let building = index.map_or(false, |index| index.building);
+        match building && self.index_builds.cancel(reference) {
+            true => Ok(()),
+            false => Err(Status::failed_precondition(format!(
+                "The index {} is not being built",
+                reference.index_name
+            ))),
+        }
+    }
+
+    /// Resumes the builds of the indexes that were being built when the server stopped.
+    /// Returns the number of builds that were resumed.
+    pub fn resume_index_builds(&self) -> DbResult<usize> {
+        let mut resumed = 0;
+        for domain_name in DatabaseServerReference.list_domain_names(self)? {
+            let domain_reference = DomainReference::build(&domain_name);
+            for table_name in domain_reference.list_table_names(self)? {
+                let table_reference = TableReference::new(domain_reference.clone(), &table_name);
+                let table_stored = match table_reference.get_table_stored(self)? {
+                    Some(table_stored) => table_stored,
+                    None => continue,
+                };
+                for index in table_stored.indexes.into_values() {
+                    if !index.building {
+                        continue;
+                    }
+                    let values_estimated = self.estimate_values(&table_reference)?;
+                    let generation = self
+                        .index_builds
+                        .register(&index.reference, values_estimated);
+                    self.spawn_index_build(index.reference, generation);
+                    resumed += 1;
+                }
+            }
+        }
+        Ok(resumed)
+    }
+
+    // The estimated number of values of the table.
+    fn estimate_values(&self, table_reference: &TableReference) -> DbResult<u64> {
+        let guarded_db = self.guarded_db();
+        let db = RocksDbAccessor::db_read_lock(&guarded_db)?;
+        let cf = db
+            .cf_handle(&table_reference.value_cf_name())
+            .ok_or(DbError::CfNotFound)?;
+        let estimate = db
+            .property_int_value_cf(cf, "rocksdb.estimate-num-keys")
+            .map_err(DbError::RocksDbError)?;
+        Ok(estimate.unwrap_or_default())
+    }
+
+    // Indexes the next chunk of values under the writes lock.
+    fn build_chunk(
+        &self,
+        reference: &IndexReference,
+        generation: u64,
+        start_key: Option<OndoKey>,
+    ) -> Result<ChunkOutcome, Status> {
+        let _writes = self.lock_writes().map_db_err_to_status()?;
+        if self.index_builds.is_stopped() {
+            return Ok(ChunkOutcome::Stopped);
+        }
+        if !self.index_builds.is_current(reference, generation) {
+            return Ok(ChunkOutcome::Replaced);
+        }
+        let index = reference
+            .get_index(self)
+            .map_db_err_to_status_for(reference)?;
+        // The index was deleted.
+        if !index.map_or(false, |index| index.building) {
+            return Ok(ChunkOutcome::Cancelled);
+        }
+        if self.index_builds.is_cancelled(reference) {
+            reference
+                .delete_index(self)
+                .map_db_err_to_status_for(reference)?
+                .apply_effects(self)?;
+            return Ok(ChunkOutcome::Cancelled);
+        }
+        let factory_enum_db_arc =
+            TableStoredIteratorRequestsFactoryEnum::new_db_arc(self.guarded_db());
+        let chunk = reference
+            .build_index_chunk(
+                start_key,
+                INDEX_BUILD_CHUNK_SIZE,
+                self,
+                self,
+                &factory_enum_db_arc,
+            )
+            .map_db_err_to_status_for(reference)?;
+        chunk.effects.apply_effects(self)?;
+        self.index_builds
+            .add_indexed(reference, chunk.values_indexed);
+        match chunk.next_key {
+            Some(next_key) => Ok(ChunkOutcome::More(next_key)),
+            None => {
+                reference
+                    .finish_index_build(self)
+                    .map_db_err_to_status_for(reference)?
+                    .apply_effects(self)?;
+                Ok(ChunkOutcome::Done)
+            }
+        }
+    }
+
+    // Drops the index whose build failed, unless a newer build replaced it.
+    fn drop_failed_index(&self, reference: &IndexReference, generation: u64, status: &Status) {
+        let dropped = self
+            .lock_writes()
+            .map_db_err_to_status()
+            .and_then(|_writes| {
+                if !self.index_builds.is_current(reference, generation) {
+                    return Ok(());
+                }
+                let index = reference
+                    .get_index(self)
+                    .map_db_err_to_status_for(reference)?;
+                if index.map_or(false, |index| index.building) {
+                    reference
+                        .delete_index(self)
+                        .map_db_err_to_status_for(reference)?
+                        .apply_effects(self)?;
+                }
+                Ok(())
+            });
+        if let Err(drop_status) = dropped {
+            tracing::error!(
+                index = %reference.index_name,
+                "Can not drop the index of a failed build: {}",
+                drop_status.message()
+            );
+        }
+        let error = Some(status.message().to_owned());
+        self.index_builds
+            .finish(reference, generation, IndexBuildState::Failed, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IndexBuildState;
+    use super::*;
+    use crate::db::server::{index_server_trait::IndexServerTrait, test_fixture};
+    use crate::ondo_remote::*;
+    use serde_json::json;
+    use std::time::Duration;
+    use tonic::{Code, Request};
+
+    fn table_reference() -> TableReferenceMessage {
+        test_fixture::table_reference("domain", "people")
+    }
+
+    fn index_reference() -> IndexReferenceMessage {
+        test_fixture::index_reference(&table_reference(), "by_city")
+    }
+
+    fn index_message(unique: bool, background: bool) -> IndexMessage {
+        IndexMessage {
+            index_reference: Some(index_reference()),
+            fields: vec!["city".to_owned()],
+            unique,
+            kind: None,
+            background,
+        }
+    }
+
+    fn index(unique: bool) -> Index {
+        (&index_message(unique, false)).try_into().unwrap()
+    }
+
+    fn create_table(ra: &RocksDbAccessor) {
+        test_fixture::create_database(ra, &table_reference());
+    }
+
+    fn create_person(ra: &RocksDbAccessor, city: &str) {
+        test_fixture::create_value(ra, &table_reference(), &json!({ "city": city }).to_string());
+    }
+
+    fn find_cities(ra: &RocksDbAccessor, city: &str) -> Result<Vec<String>, Status> {
+        let response = ra
+            .find_values_page(Request::new(FindValuesMessage {
+                indexed_value_reference: Some(IndexedValueReferenceMessage {
+                    index_reference: Some(index_reference()),
+                    key: Some(OndoKeyMessage {
+                        json_keys: vec![json!(city).to_string()],
+                    }),
+                }),
+                limit: 0,
+                continuation_token: String::new(),
+            }))?
+            .into_inner();
+        let values: Vec<serde_json::Value> = serde_json::from_str(&response.json).unwrap();
+        Ok(values
+            .iter()
+            .map(|value| value["city"].as_str().unwrap().to_owned())
+            .collect())
+    }
+
+    fn index_build(ra: &RocksDbAccessor) -> IndexBuildMessage {
+        ra.get_index_build(Request::new(index_reference()))
+            .unwrap()
+            .into_inner()
+    }
+
+    #[test]
+    fn test_build_index_in_background() {
+        let ra = RocksDbAccessor::in_memory();
+        create_table(&ra);
+        for city in ["Paris", "Rome", "Paris"] {
+            create_person(&ra, city);
+        }
+
+        ra.create_index(Request::new(index_message(false, true)))
+            .unwrap();
+        ra.index_builds.wait();
+
+        let build = index_build(&ra);
+        assert_eq!(build.state(), ondo_remote::IndexBuildState::Ready);
+        assert_eq!(build.values_indexed, 3);
+        assert_eq!(find_cities(&ra, "Paris").unwrap(), vec!["Paris", "Paris"]);
+    }
+
+    #[test]
+    fn test_close_stops_index_build_and_reopen_resumes_it() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let db_path = temp_dir.path().to_string_lossy().into_owned();
+        let mut options = rocksdb::Options::default();
+        options.create_if_missing(true);
+        let ra = RocksDbAccessor::open(db_path.clone(), options.clone()).unwrap();
+        create_table(&ra);
+        create_person(&ra, "Paris");
+
+        // The build waits for the writes lock, and stops at its first chunk.
+        let generation = ra.start_index_build(&index(false), false).unwrap();
+        let writer = ra.clone();
+        let writes = writer.lock_writes().unwrap();
+        ra.spawn_index_build(index(false).reference, generation);
+        let index_builds = ra.index_builds.clone();
+        let closing = std::thread::spawn(move || ra.close(Duration::from_secs(10)));
+        while !index_builds.is_stopped() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(writes);
+        drop(writer);
+        assert_eq!(closing.join().unwrap(), Ok(()));
+
+        let ra = RocksDbAccessor::open(db_path, options).unwrap();
+        assert_eq!(
+            index_build(&ra).state(),
+            ondo_remote::IndexBuildState::Building
+        );
+        assert_eq!(ra.resume_index_builds(), Ok(1));
+        ra.index_builds.wait();
+        assert_eq!(
+            index_build(&ra).state(),
+            ondo_remote::IndexBuildState::Ready
+        );
+        assert_eq!(find_cities(&ra, "Paris").unwrap(), vec!["Paris"]);
+    }
+
+    #[test]
+    fn test_building_index_catches_up_with_writes() {
+        let ra = RocksDbAccessor::in_memory();
+        create_table(&ra);
+        create_person(&ra, "Paris");
+        let index = index(false);
+
+        let generation = ra.start_index_build(&index, false).unwrap();
+        create_person(&ra, "Paris");
+        assert_eq!(
+            index_build(&ra).state(),
+            ondo_remote::IndexBuildState::Building
+        );
+        assert_eq!(
+            find_cities(&ra, "Paris").unwrap_err().code(),
+            Code::FailedPrecondition
+        );
+
+        let state = ra.run_index_build(&index.reference, generation).unwrap();
+        assert_eq!(state, IndexBuildState::Ready);
+        assert_eq!(find_cities(&ra, "Paris").unwrap(), vec!["Paris", "Paris"]);
+    }
+
+    #[test]
+    fn test_building_index_rejects_range_stream() {
+        let ra = RocksDbAccessor::in_memory();
+        create_table(&ra);
+        create_person(&ra, "Paris");
+        let index = index(false);
+
+        let generation = ra.start_index_build(&index, false).unwrap();
+        let key = |city: &str| OndoKeyMessage {
+            json_keys: vec![json!(city).to_string()],
+        };
+        let stream =
+            ra.stream_found_values_by_range(Request::new(IndexedValueRangeReferenceMessage {
+                index_reference: Some(index_reference()),
+                start_key: Some(key("Oslo")),
+                end_key: Some(key("Rome")),
+            }));
+        assert_eq!(stream.err().unwrap().code(), Code::FailedPrecondition);
+
+        ra.run_index_build(&index.reference, generation).unwrap();
+        assert_eq!(find_cities(&ra, "Paris").unwrap(), vec!["Paris"]);
+    }
+
+    #[test]
+    fn test_cancel_index_build() {
+        let ra = RocksDbAccessor::in_memory();
+        create_table(&ra);
+        create_person(&ra, "Paris");
+        let index = index(false);
+
+        let generation = ra.start_index_build(&index, false).unwrap();
+        ra.cancel_index_build(Request::new(index_reference()))
+            .unwrap();
+        let state = ra.run_index_build(&index.reference, generation).unwrap();
+
+        assert_eq!(state, IndexBuildState::Cancelled);
+        assert_eq!(
+            index_build(&ra).state(),
+            ondo_remote::IndexBuildState::Cancelled
+        );
+        let get_index = ra.get_index(Request::new(index_reference()));
+        assert_eq!(get_index.unwrap_err().code(), Code::NotFound);
+        let cancel_again = ra.cancel_index_build(Request::new(index_reference()));
+        assert_eq!(cancel_again.unwrap_err().code(), Code::FailedPrecondition);
+    }
+
+    #[test]
+    fn test_cancel_finished_index_build() {
+        let ra = RocksDbAccessor::in_memory();
+        create_table(&ra);
+        create_person(&ra, "Paris");
+        let index = index(false);
+
+        // The last chunk finished the index, but the build is not marked ready yet.
+        let generation = ra.start_index_build(&index, false).unwrap();
+        let outcome = ra.build_chunk(&index.reference, generation, None).unwrap();
+        assert!(matches!(outcome, ChunkOutcome::Done));
+        let cancel = ra.cancel_index_build(Request::new(index_reference()));
+
+        assert_eq!(cancel.unwrap_err().code(), Code::FailedPrecondition);
+        ra.index_builds
+            .finish(&index.reference, generation, IndexBuildState::Ready, None);
+        assert_eq!(find_cities(&ra, "Paris").unwrap(), vec!["Paris"]);
+    }
+
+    #[test]
+    fn test_failed_index_build() {
+        let ra = RocksDbAccessor::in_memory();
+        create_table(&ra);
+        create_person(&ra, "Paris");
+        create_person(&ra, "Paris");
+
+        let create_index = ra.create_index(Request::new(index_message(true, false)));
+
+        assert_eq!(create_index.unwrap_err().code(), Code::AlreadyExists);
+        let build = index_build(&ra);
+        assert_eq!(build.state(), ondo_remote::IndexBuildState::Failed);
+        assert!(!build.error.is_empty());
+        let get_index = ra.get_index(Request::new(index_reference()));
+        assert_eq!(get_index.unwrap_err().code(), Code::NotFound);
+    }
+}
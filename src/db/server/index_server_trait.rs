@@ -1,3 +1,4 @@
+use super::value_stream::JsonMessageStream;
 use crate::ondo_remote;
 use ondo_remote::*;
 use tonic::{Request, Response, Status};
@@ -13,14 +14,56 @@ pub trait IndexServerTrait {
         r: Request<IndexReferenceMessage>,
     ) -> Result<Response<IndexMessage>, Status>;
     fn update_index(&self, _: Request<IndexMessage>) -> Result<Response<EmptyMessage>, Status>;
+    fn get_index_build(
+        &self,
+        r: Request<IndexReferenceMessage>,
+    ) -> Result<Response<IndexBuildMessage>, Status>;
+    fn cancel_index_build(
+        &self,
+        r: Request<IndexReferenceMessage>,
+    ) -> Result<Response<EmptyMessage>, Status>;
+    fn verify_index(
+        &self,
+        r: Request<VerifyIndexMessage>,
+    ) -> Result<Response<IndexVerificationMessage>, Status>;
 
     fn find_values(
         &self,
         r: Request<IndexedValueReferenceMessage>,
     ) -> Result<Response<JsonMessage>, Status>;
 
+    fn find_values_page(
+        &self,
+        r: Request<FindValuesMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status>;
+
+    fn stream_found_values(
+        &self,
+        r: Request<IndexedValueReferenceMessage>,
+    ) -> Result<Response<JsonMessageStream>, Status>;
+
     fn find_values_by_range(
         &self,
         r: Request<IndexedValueRangeReferenceMessage>,
     ) -> Result<Response<JsonMessage>, Status>;
+
+    fn find_values_by_range_page(
+        &self,
+        r: Request<FindValuesByRangeMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status>;
+
+    fn stream_found_values_by_range(
+        &self,
+        r: Request<IndexedValueRangeReferenceMessage>,
+    ) -> Result<Response<JsonMessageStream>, Status>;
+
+    fn search_values(
+        &self,
+        r: Request<SearchValuesMessage>,
+    ) -> Result<Response<SearchValuesResponse>, Status>;
+
+    fn nearest_values(
+        &self,
+        r: Request<NearestValuesMessage>,
+    ) -> Result<Response<NearestValuesResponse>, Status>;
 }
@@ -1,104 +1,278 @@
 use super::{
     db_error_to_status::{DbErrorOptionToStatus, DbErrorToStatus},
+    index_build::IndexBuildState,
     index_server_trait::IndexServerTrait,
+    message_field::required_field,
+    page::{page_request, page_response},
     rocks_db_accessor::{DbReadLockGuardWrapper, RocksDbAccessor},
     source_sink::EffectsSink,
+    value_stream::{stream_values, JsonMessageStream},
 };
-use crate::db::enums::table_stored_iterator_requests_factory::TableStoredIteratorRequestsFactoryEnum;
 use crate::db::{
-    entity::{index::Index, OndoKey, TableValue},
-    reference::{IndexReference, IndexReferenceTrait},
-    DbError,
+    entity::{
+        index::{
+            text::TextIndexOptions,
+            vector::{self, HnswOptions, VectorIndexOptions},
+            Index, IndexKind,
+        },
+        OndoKey,
+    },
+    reference::{
+        index_verification::{
+            IndexProblems, IndexVerification, IndexVerifier, VERIFICATION_CHUNK_SIZE,
+        },
+        vector_search::VectorQuery,
+        IndexReference, IndexReferenceTrait,
+    },
 };
 use crate::ondo_remote;
 use ondo_remote::*;
 use tonic::{Request, Response, Status};
 
-impl<'a> Into<IndexReference> for &'a IndexReferenceMessage {
-    fn into(self) -> IndexReference {
-        IndexReference {
-            table_reference: self.table_reference.as_ref().unwrap().into(),
-            index_name: self.index_name.clone(),
-        }
+impl TryFrom<&IndexReferenceMessage> for IndexReference {
+    type Error = Status;
+    fn try_from(val: &IndexReferenceMessage) -> Result<Self, Self::Error> {
+        Ok(IndexReference {
+            table_reference: required_field(
+                &val.table_reference,
+                "IndexReferenceMessage.table_reference",
+            )?
+            .try_into()?,
+            index_name: val.index_name.clone(),
+        })
     }
 }
-impl Into<IndexReferenceMessage> for IndexReference {
-    fn into(self) -> IndexReferenceMessage {
+impl From<IndexReference> for IndexReferenceMessage {
+    fn from(val: IndexReference) -> Self {
         IndexReferenceMessage {
-            table_reference: Some(self.table_reference.into()),
-            index_name: self.index_name,
+            table_reference: Some(val.table_reference.into()),
+            index_name: val.index_name,
         }
     }
 }
-impl<'a> Into<Index> for &'a IndexMessage {
-    fn into(self) -> Index {
-        let reference: IndexReference = self.index_reference.as_ref().unwrap().into();
-        let fields: Vec<String> = self.fields.clone();
-        Index {
-            fields: fields,
-            reference,
+impl TryFrom<&IndexMessage> for Index {
+    type Error = Status;
+    fn try_from(val: &IndexMessage) -> Result<Self, Self::Error> {
+        let reference: IndexReference =
+            required_field(&val.index_reference, "IndexMessage.index_reference")?.try_into()?;
+        let fields: Vec<String> = val.fields.clone();
+        let kind = match &val.kind {
+            None => IndexKind::Ordered,
+            Some(index_message::Kind::Text(options)) => IndexKind::Text(TextIndexOptions {
+                stemming: options.stemming,
+                stop_words: options.stop_words,
+            }),
+            Some(index_message::Kind::Vector(options)) => {
+                IndexKind::Vector(vector_index_options(options, &fields)?)
+            }
+        };
+        if val.unique && kind != IndexKind::Ordered {
+            return Err(Status::invalid_argument(
+                "Only ordered indexes can be unique",
+            ));
         }
+        Ok(Index {
+            fields,
+            reference,
+            unique: val.unique,
+            kind,
+            building: false,
+        })
     }
 }
-impl Into<IndexMessage> for Index {
-    fn into(self) -> IndexMessage {
-        let reference: IndexReferenceMessage = self.reference.into();
-        let fields: Vec<String> = self.fields.clone();
+impl From<Index> for IndexMessage {
+    fn from(val: Index) -> Self {
+        let reference: IndexReferenceMessage = val.reference.into();
+        let fields: Vec<String> = val.fields.clone();
+        let kind = match val.kind {
+            IndexKind::Ordered => None,
+            IndexKind::Text(options) => Some(index_message::Kind::Text(TextIndexOptionsMessage {
+                stemming: options.stemming,
+                stop_words: options.stop_words,
+            })),
+            IndexKind::Vector(options) => {
+                let metric = match options.metric {
+                    vector::VectorMetric::Cosine => VectorMetric::Cosine,
+                    vector::VectorMetric::DotProduct => VectorMetric::DotProduct,
+                    vector::VectorMetric::L2 => VectorMetric::L2,
+                };
+                Some(index_message::Kind::Vector(VectorIndexOptionsMessage {
+                    dimension: options.dimension as u32,
+                    metric: metric as i32,
+                    hnsw: options.hnsw.map(|hnsw| HnswOptionsMessage {
+                        m: hnsw.m as u32,
+                        ef_construction: hnsw.ef_construction as u32,
+                    }),
+                }))
+            }
+        };
         IndexMessage {
-            fields: fields,
+            fields,
             index_reference: Some(reference),
+            unique: val.unique,
+            kind,
+            background: false,
         }
     }
 }
 
-struct IndexedValueReference {
-    index_reference: IndexReference,
-    key: OndoKey,
-}
-impl<'a> Into<IndexedValueReference> for &'a IndexedValueReferenceMessage {
-    fn into(self) -> IndexedValueReference {
-        IndexedValueReference {
-            index_reference: self.index_reference.as_ref().unwrap().into(),
-            key: self.key.as_ref().unwrap().into(),
+fn vector_index_options(
+    options: &VectorIndexOptionsMessage,
+    fields: &[String],
+) -> Result<VectorIndexOptions, Status> {
+    if fields.len() != 1 {
+        return Err(Status::invalid_argument(
+            "A vector index has exactly one field",
+        ));
+    }
+    if options.dimension == 0 {
+        return Err(Status::invalid_argument(
+            "The dimension of a vector index must be at least 1",
+        ));
+    }
+    let metric = match VectorMetric::from_i32(options.metric) {
+        Some(VectorMetric::Cosine) => vector::VectorMetric::Cosine,
+        Some(VectorMetric::DotProduct) => vector::VectorMetric::DotProduct,
+        Some(VectorMetric::L2) => vector::VectorMetric::L2,
+        None => return Err(Status::invalid_argument("Unknown vector metric")),
+    };
+    let hnsw = match &options.hnsw {
+        Some(hnsw) => {
+            let default = HnswOptions::default();
+            let hnsw = HnswOptions {
+                m: match hnsw.m {
+                    0 => default.m,
+                    m => m as usize,
+                },
+                ef_construction: match hnsw.ef_construction {
+                    0 => default.ef_construction,
+                    ef_construction => ef_construction as usize,
+                },
+            };
+            if hnsw.m < 2 {
+                return Err(Status::invalid_argument("HNSW m must be at least 2"));
+            }
+            Some(hnsw)
         }
+        None => None,
+    };
+    Ok(VectorIndexOptions {
+        dimension: options.dimension as usize,
+        metric,
+        hnsw,
+    })
+}
+
+pub(super) struct IndexedValueReference {
+    pub(super) index_reference: IndexReference,
+    pub(super) key: OndoKey,
+}
+impl TryFrom<&IndexedValueReferenceMessage> for IndexedValueReference {
+    type Error = Status;
+    fn try_from(val: &IndexedValueReferenceMessage) -> Result<Self, Self::Error> {
+        Ok(IndexedValueReference {
+            index_reference: required_field(
+                &val.index_reference,
+                "IndexedValueReferenceMessage.index_reference",
+            )?
+            .try_into()?,
+            key: required_field(&val.key, "IndexedValueReferenceMessage.key")?.try_into()?,
+        })
     }
 }
 
-struct IndexedValueRangeReference {
-    index_reference: IndexReference,
-    start_key: OndoKey,
-    end_key: OndoKey,
+pub(super) struct IndexedValueRangeReference {
+    pub(super) index_reference: IndexReference,
+    pub(super) start_key: OndoKey,
+    pub(super) end_key: OndoKey,
+}
+impl TryFrom<&IndexedValueRangeReferenceMessage> for IndexedValueRangeReference {
+    type Error = Status;
+    fn try_from(val: &IndexedValueRangeReferenceMessage) -> Result<Self, Self::Error> {
+        Ok(IndexedValueRangeReference {
+            index_reference: required_field(
+                &val.index_reference,
+                "IndexedValueRangeReferenceMessage.index_reference",
+            )?
+            .try_into()?,
+            start_key: required_field(
+                &val.start_key,
+                "IndexedValueRangeReferenceMessage.start_key",
+            )?
+            .try_into()?,
+            end_key: required_field(&val.end_key, "IndexedValueRangeReferenceMessage.end_key")?
+                .try_into()?,
+        })
+    }
 }
-impl<'a> Into<IndexedValueRangeReference> for &'a IndexedValueRangeReferenceMessage {
-    fn into(self) -> IndexedValueRangeReference {
-        IndexedValueRangeReference {
-            index_reference: self.index_reference.as_ref().unwrap().into(),
-            start_key: self.start_key.as_ref().unwrap().into(),
-            end_key: self.end_key.as_ref().unwrap().into(),
+
+impl From<IndexProblems> for IndexProblemsMessage {
+    fn from(problems: IndexProblems) -> Self {
+        IndexProblemsMessage {
+            count: problems.count,
+            keys: problems.keys.into_iter().map(Into::into).collect(),
         }
     }
 }
 
+fn verification_message(
+    verification: IndexVerification,
+    repaired: bool,
+) -> IndexVerificationMessage {
+    IndexVerificationMessage {
+        values_checked: verification.values_checked,
+        entries_checked: verification.entries_checked,
+        consistent: verification.is_consistent(),
+        repaired,
+        missing: Some(verification.missing.into()),
+        orphaned: Some(verification.orphaned.into()),
+        stale: Some(verification.stale.into()),
+        missing_nodes: Some(verification.missing_nodes.into()),
+        orphaned_nodes: Some(verification.orphaned_nodes.into()),
+        stale_nodes: Some(verification.stale_nodes.into()),
+    }
+}
+
+// Builds the started index in the background, or before responding.
+fn build_index(
+    ra: &RocksDbAccessor,
+    reference: IndexReference,
+    generation: u64,
+    background: bool,
+) -> Result<Response<EmptyMessage>, Status> {
+    if background {
+        ra.spawn_index_build(reference, generation);
+        return Ok(Response::new(EmptyMessage {}));
+    }
+    match ra.run_index_build(&reference, generation)? {
+        IndexBuildState::Cancelled => Err(Status::cancelled(format!(
+            "The build of the index {} was cancelled",
+            reference.index_name
+        ))),
+        IndexBuildState::Building => Err(Status::unavailable(format!(
+            "The build of the index {} stopped because the database is closing",
+            reference.index_name
+        ))),
+        _ => Ok(Response::new(EmptyMessage {})),
+    }
+}
+
 impl IndexServerTrait for RocksDbAccessor {
     fn create_index(&self, r: Request<IndexMessage>) -> Result<Response<EmptyMessage>, Status> {
-        let guarded_db = self.guarded_db();
-        let factory_enum_db_arc = TableStoredIteratorRequestsFactoryEnum::new_db_arc(guarded_db);
-        let entity: Index = r.get_ref().into();
-        entity
-            .reference
-            .post_index(&entity, self, &factory_enum_db_arc)
-            .map_db_err_to_status()?
-            .apply_effects(self)
+        let entity: Index = r.get_ref().try_into()?;
+        let generation = self.start_index_build(&entity, false)?;
+        build_index(self, entity.reference, generation, r.get_ref().background)
     }
 
     fn delete_index(
         &self,
         r: Request<IndexReferenceMessage>,
     ) -> Result<Response<EmptyMessage>, Status> {
-        let reference: IndexReference = r.get_ref().into();
+        let reference: IndexReference = r.get_ref().try_into()?;
+        let _writes = self.lock_writes().map_db_err_to_status()?;
         reference
             .delete_index(self)
-            .map_db_err_to_status()?
+            .map_db_err_to_status_for(&reference)?
             .apply_effects(self)
     }
 
@@ -106,67 +280,269 @@ impl IndexServerTrait for RocksDbAccessor {
         &self,
         r: Request<IndexReferenceMessage>,
     ) -> Result<Response<IndexMessage>, Status> {
-        let reference: IndexReference = r.get_ref().into();
+        let reference: IndexReference = r.get_ref().try_into()?;
         reference
             .get_index(self)
-            .map_db_err_option_to_status()
+            .map_db_err_option_to_status_for(&reference)
             .map(|entity| Response::new(entity.into()))
     }
 
     fn update_index(&self, r: Request<IndexMessage>) -> Result<Response<EmptyMessage>, Status> {
-        let guarded_db = self.guarded_db();
-        let factory_enum_db_arc = TableStoredIteratorRequestsFactoryEnum::new_db_arc(guarded_db);
-        let entity: Index = r.get_ref().into();
-        entity
-            .reference
-            .put_index(&entity, self, &factory_enum_db_arc)
-            .map_db_err_to_status()?
-            .apply_effects(self)
+        let entity: Index = r.get_ref().try_into()?;
+        let generation = self.start_index_build(&entity, true)?;
+        build_index(self, entity.reference, generation, r.get_ref().background)
+    }
+
+    fn get_index_build(
+        &self,
+        r: Request<IndexReferenceMessage>,
+    ) -> Result<Response<IndexBuildMessage>, Status> {
+        let reference: IndexReference = r.get_ref().try_into()?;
+        let progress = self.index_build_progress(&reference)?;
+        Ok(Response::new(progress.into()))
+    }
+
+    fn cancel_index_build(
+        &self,
+        r: Request<IndexReferenceMessage>,
+    ) -> Result<Response<EmptyMessage>, Status> {
+        let reference: IndexReference = r.get_ref().try_into()?;
+        self.cancel_running_index_build(&reference)?;
+        Ok(Response::new(EmptyMessage {}))
+    }
+
+    fn verify_index(
+        &self,
+        r: Request<VerifyIndexMessage>,
+    ) -> Result<Response<IndexVerificationMessage>, Status> {
+        let message = r.get_ref();
+        let reference: IndexReference =
+            required_field(&message.index_reference, "VerifyIndexMessage.index_reference")?
+                .try_into()?;
+        let mut verifier = IndexVerifier::new(message.repair, VERIFICATION_CHUNK_SIZE);
+        // Each chunk is verified and repaired under the writes lock,
+        // so the writes wait for at most one chunk.
+        while !verifier.is_done() {
+            let _writes = self.lock_writes().map_db_err_to_status()?;
+            let repair_effects = {
+                let guarded_db = self.guarded_db();
+                let db_wrapper =
+                    DbReadLockGuardWrapper::new(&guarded_db).map_db_err_to_status()?;
+                reference
+                    .verify_index_chunk(
+                        &mut verifier,
+                        &db_wrapper,
+                        &db_wrapper,
+                        &db_wrapper,
+                        &db_wrapper,
+                        &db_wrapper,
+                    )
+                    .map_db_err_to_status_for(&reference)?
+            };
+            if !repair_effects.is_empty() {
+                repair_effects.apply_effects(self)?;
+            }
+        }
+        let verification = verifier.verification;
+        let repaired = message.repair && !verification.is_consistent();
+        Ok(Response::new(verification_message(verification, repaired)))
     }
 
     fn find_values(
         &self,
         r: Request<IndexedValueReferenceMessage>,
     ) -> Result<Response<JsonMessage>, Status> {
+        let message = FindValuesMessage {
+            indexed_value_reference: Some(r.into_inner()),
+            limit: 0,
+            continuation_token: String::new(),
+        };
+        let page = self.find_values_page(Request::new(message))?.into_inner();
+        Ok(Response::new(JsonMessage { json: page.json }))
+    }
+
+    fn find_values_page(
+        &self,
+        r: Request<FindValuesMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status> {
         let guarded_db = self.guarded_db();
         let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).map_db_err_to_status()?;
-        let indexed_value_reference: IndexedValueReference = r.get_ref().into();
+        let message = r.get_ref();
+        let indexed_value_reference: IndexedValueReference = required_field(
+            &message.indexed_value_reference,
+            "FindValuesMessage.indexed_value_reference",
+        )?
+        .try_into()?;
         let reference = indexed_value_reference.index_reference;
         let key_prefix = indexed_value_reference.key;
-        let iterator = reference
-            .all_values_with_key_prefix(key_prefix, self, &db_wrapper)
-            .map_db_err_to_status()?;
-        let values_result: Result<Vec<TableValue>, DbError> = iterator.collect();
-        let values = values_result.map_db_err_to_status()?;
-        let json = serde_json::to_string(&values).map_err(|e| Status::internal(e.to_string()))?;
-        let response = Response::new(JsonMessage { json });
-        Ok(response)
+        let page_request = page_request(message.limit, &message.continuation_token)?;
+        reference
+            .check_index_ready(&db_wrapper)
+            .map_db_err_to_status_for(&reference)?;
+        let page = reference
+            .values_page_with_key_prefix(key_prefix, &page_request, &db_wrapper, &db_wrapper)
+            .map_db_err_to_status_for(&reference)?;
+        Ok(Response::new(page_response(page)?))
+    }
+
+    fn stream_found_values(
+        &self,
+        r: Request<IndexedValueReferenceMessage>,
+    ) -> Result<Response<JsonMessageStream>, Status> {
+        let indexed_value_reference: IndexedValueReference = r.get_ref().try_into()?;
+        let reference = indexed_value_reference.index_reference;
+        let key_prefix = indexed_value_reference.key;
+        reference
+            .check_index_ready(self)
+            .map_db_err_to_status_for(&reference)?;
+        let stream = stream_values(self.guarded_db(), move |db_wrapper, page_request| {
+            reference.values_page_with_key_prefix(
+                key_prefix.clone(),
+                page_request,
+                db_wrapper,
+                db_wrapper,
+            )
+        });
+        Ok(Response::new(stream))
     }
 
     fn find_values_by_range(
         &self,
         r: Request<IndexedValueRangeReferenceMessage>,
     ) -> Result<Response<JsonMessage>, Status> {
+        let message = FindValuesByRangeMessage {
+            indexed_value_range_reference: Some(r.into_inner()),
+            limit: 0,
+            continuation_token: String::new(),
+        };
+        let page = self
+            .find_values_by_range_page(Request::new(message))?
+            .into_inner();
+        Ok(Response::new(JsonMessage { json: page.json }))
+    }
+
+    fn find_values_by_range_page(
+        &self,
+        r: Request<FindValuesByRangeMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status> {
         let guarded_db = self.guarded_db();
         let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).map_db_err_to_status()?;
-        let indexed_value_range_reference: IndexedValueRangeReference = r.get_ref().into();
+        let message = r.get_ref();
+        let indexed_value_range_reference: IndexedValueRangeReference = required_field(
+            &message.indexed_value_range_reference,
+            "FindValuesByRangeMessage.indexed_value_range_reference",
+        )?
+        .try_into()?;
         let reference = indexed_value_range_reference.index_reference;
         let start_key_prefix = indexed_value_range_reference.start_key;
         let end_key_prefix = indexed_value_range_reference.end_key;
-        let iterator = reference
-            .all_values_with_key_range(start_key_prefix, end_key_prefix, self, &db_wrapper)
-            .map_db_err_to_status()?;
-        let values_result: Result<Vec<TableValue>, DbError> = iterator.collect();
-        let values = values_result.map_db_err_to_status()?;
+        let page_request = page_request(message.limit, &message.continuation_token)?;
+        reference
+            .check_index_ready(&db_wrapper)
+            .map_db_err_to_status_for(&reference)?;
+        let page = reference
+            .values_page_with_key_range(
+                start_key_prefix,
+                end_key_prefix,
+                &page_request,
+                &db_wrapper,
+                &db_wrapper,
+            )
+            .map_db_err_to_status_for(&reference)?;
+        Ok(Response::new(page_response(page)?))
+    }
+
+    fn search_values(
+        &self,
+        r: Request<SearchValuesMessage>,
+    ) -> Result<Response<SearchValuesResponse>, Status> {
+        let guarded_db = self.guarded_db();
+        let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).map_db_err_to_status()?;
+        let message = r.get_ref();
+        let reference: IndexReference = required_field(
+            &message.index_reference,
+            "SearchValuesMessage.index_reference",
+        )?
+        .try_into()?;
+        let limit = (message.limit > 0).then_some(message.limit as usize);
+        let (values, scores): (Vec<_>, Vec<_>) = reference
+            .search_values(
+                &message.query,
+                limit,
+                &db_wrapper,
+                &db_wrapper,
+                &db_wrapper,
+                &db_wrapper,
+            )
+            .map_db_err_to_status_for(&reference)?
+            .into_iter()
+            .unzip();
+        let json = serde_json::to_string(&values).map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(SearchValuesResponse { json, scores }))
+    }
+
+    fn nearest_values(
+        &self,
+        r: Request<NearestValuesMessage>,
+    ) -> Result<Response<NearestValuesResponse>, Status> {
+        let guarded_db = self.guarded_db();
+        let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).map_db_err_to_status()?;
+        let message = r.get_ref();
+        let reference: IndexReference = required_field(
+            &message.index_reference,
+            "NearestValuesMessage.index_reference",
+        )?
+        .try_into()?;
+        let filter = match &message.filter {
+            Some(filter) => {
+                let filter: IndexedValueReference = filter.try_into()?;
+                Some((filter.index_reference, filter.key))
+            }
+            None => None,
+        };
+        let query = VectorQuery {
+            vector: message.vector.clone(),
+            k: message.k as usize,
+            exact: message.exact,
+            ef_search: (message.ef_search > 0).then_some(message.ef_search as usize),
+            filter,
+        };
+        let (values, distances): (Vec<_>, Vec<_>) = reference
+            .nearest_values(&query, &db_wrapper, &db_wrapper, &db_wrapper, &db_wrapper)
+            .map_db_err_to_status_for(&reference)?
+            .into_iter()
+            .unzip();
         let json = serde_json::to_string(&values).map_err(|e| Status::internal(e.to_string()))?;
-        let response = Response::new(JsonMessage { json });
-        Ok(response)
+        Ok(Response::new(NearestValuesResponse { json, distances }))
+    }
+
+    fn stream_found_values_by_range(
+        &self,
+        r: Request<IndexedValueRangeReferenceMessage>,
+    ) -> Result<Response<JsonMessageStream>, Status> {
+        let indexed_value_range_reference: IndexedValueRangeReference = r.get_ref().try_into()?;
+        let reference = indexed_value_range_reference.index_reference;
+        let start_key_prefix = indexed_value_range_reference.start_key;
+        let end_key_prefix = indexed_value_range_reference.end_key;
+        reference
+            .check_index_ready(self)
+            .map_db_err_to_status_for(&reference)?;
+        let stream = stream_values(self.guarded_db(), move |db_wrapper, page_request| {
+            reference.values_page_with_key_range(
+                start_key_prefix.clone(),
+                end_key_prefix.clone(),
+                page_request,
+                db_wrapper,
+                db_wrapper,
+            )
+        });
+        Ok(Response::new(stream))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::db::entity::{table::Table, table_value::TableValue, DatabaseServer, Domain, Index, ondo_key::OndoKey};
+    use crate::db::entity::{table::Table, table_value::TableValue, DatabaseServer, Domain, Index, index::{IndexKind, text::TextIndexOptions}, ondo_key::OndoKey};
     use crate::db::enums::{table_stored_iterator_requests_factory::TableStoredIteratorRequestsFactoryEnum, index_iterator_requests_factory::IndexIteratorRequestsFactoryEnum};
         use crate::db::reference::Effects;
     use crate::db::reference::{
@@ -174,7 +550,18 @@ mod tests {
         DatabaseServerReferenceTrait, DomainReference, DomainReferenceTrait, IndexReference,
         IndexReferenceTrait, TableReference, TableReferenceTrait, TableValueReference, TableValueReferenceTrait
     };
-    use crate::db::server::{rocks_db_accessor::RocksDbAccessor, source_sink::EffectsSink};
+    use crate::db::entity::table_value::insert_key_into_table_value;
+    use crate::db::server::{rocks_db_accessor::{DbReadLockGuardWrapper, RocksDbAccessor}, source_sink::EffectsSink};
+    use crate::db::{DbError, DbResult};
+    use crate::db::entity::index::vector::{HnswOptions, VectorIndexOptions, VectorMetric};
+    use crate::db::reference::vector_search::VectorQuery;
+    use crate::db::reference::{hnsw::HnswGraph, IndexValueReference, IndexValueReferenceTrait};
+    use crate::db::reference::index_verification::IndexVerifier;
+    use crate::db::entity::index::text::{document_key, TextIndexStats};
+    use crate::db::reference::text_stats::{get_text_stats, TextStatsUpdate};
+    use crate::db::server::index_server_trait::IndexServerTrait;
+    use tonic::Request;
+    use crate::ondo_remote::*;
     use serde::{Deserialize, Serialize};
 
     fn create_database_server_entity() -> DatabaseServer {
@@ -190,6 +577,7 @@ mod tests {
     fn create_table_entity(domain_reference: &DomainReference) -> Table {
         Table {
             reference: TableReference::new(domain_reference.clone(), "test_table"),
+            schema: None,
         }
     }
 
@@ -197,6 +585,9 @@ mod tests {
         Index {
             reference: IndexReference::new(table_reference.clone(), "test_index"),
             fields: vec!["city".to_owned()],
+            unique: false,
+            kind: IndexKind::Ordered,
+            building: false,
         }
     }
 
@@ -284,36 +675,44 @@ mod tests {
         }
     }
 
+    // Adds the index and builds it in chunks of two values.
+    // Returns the effects that added the index.
+    fn post_and_build_index(ra: &RocksDbAccessor, index: &Index) -> DbResult<Effects> {
+        let reference = &index.reference;
+        let index_effects = reference.post_index(index, ra)?;
+        index_effects.apply_effects(ra).unwrap();
+        let factory_enum_db_arc =
+            TableStoredIteratorRequestsFactoryEnum::new_db_arc(ra.guarded_db());
+        let mut start_key = None;
+        loop {
+            let chunk = reference.build_index_chunk(start_key, 2, ra, ra, &factory_enum_db_arc)?;
+            chunk.effects.apply_effects(ra).unwrap();
+            start_key = match chunk.next_key {
+                Some(next_key) => Some(next_key),
+                None => break,
+            };
+        }
+        reference.finish_index_build(ra)?.apply_effects(ra).unwrap();
+        Ok(index_effects)
+    }
+
     fn create_and_apply_index(test_data: &TestData) -> (Index, Effects) {
         let ra = &test_data.rocks_db_accessor;
-        let guarded_db = ra.guarded_db();
-    
         let index = create_index_entity(&test_data.table_reference);
-        let index_reference = &index.reference;
-        let factory_enum_db_arc = TableStoredIteratorRequestsFactoryEnum::new_db_arc(guarded_db);
-    
-        let index_effects = index_reference
-            .post_index(&index, ra, &factory_enum_db_arc)
-            .unwrap();
-        index_effects.apply_effects(ra).unwrap();
-    
+        let index_effects = post_and_build_index(ra, &index).unwrap();
         (index, index_effects)
     }
-    
     fn create_and_apply_record(test_data: &TestData) -> (OndoKey, TableValue, Effects) {
         let ra = &test_data.rocks_db_accessor;
-    
         let create_table_value_reference = create_table_value_reference(&test_data.table_reference);
         let record1 = create_test_record1();
         let mut value1 = serde_json::to_value(record1).unwrap();
         let (value1_key, value1_effects) = create_table_value_reference
-            .post_table_value(&mut value1, ra, ra, ra)
+            .post_table_value(&mut value1, ra, ra, ra, ra)
             .unwrap();
         value1_effects.apply_effects(ra).unwrap();
-    
         (value1_key, value1, value1_effects)
     }
-    
     #[test]
     fn test_get() {
         let test_data = setup();
@@ -332,6 +731,7 @@ mod tests {
             value1_retrieved,
             serde_json::json!({
                 "_id": {"values": [1]},
+                "_revision": 1,
                 "name": "John",
                 "age": 30,
                 "city": "New York"
@@ -342,20 +742,19 @@ mod tests {
     #[test]
     fn test_index_then_populate() {
         let test_data = setup();
-    
         let (_index, _index_effects) = create_and_apply_index(&test_data);
         let (_value1_key, _value1, value1_effects) = create_and_apply_record(&test_data);
-    
         let expected_value1_effects_str =
             "[ColumnValueEffect(Put('/domains/test_domain/counters', \
                 OndoKey { values: [String('test_table')] }, Number(1))), \
-            TableValueEffect(Put('test_domain::/test_table', \
+            TableValueEffect(Put('/domains/test_domain/tables/test_table', \
             OndoKey { values: [Number(1)] }, \
             Object {'_id': Object {'values': Array [Number(1)]}, \
+                    '_revision': Number(1), \
                     'age': Number(30), \
                     'city': String('New York'), \
                     'name': String('John')})), \
-            IndexValueEffect(Put('test_domain::/test_table/indexes/test_index', \
+            IndexValueEffect(Put('/domains/test_domain/tables/test_table/indexes/test_index', \
                     OndoKey { values: [String('New York'), Number(1)] }, \
                     OndoKey { values: [Number(1)] }))]"
                 .to_owned()
@@ -363,67 +762,941 @@ mod tests {
         let value1_effects_str = format!("{:?}", value1_effects);
         assert_eq!(value1_effects_str, expected_value1_effects_str);
     }
-    
     #[test]
     fn test_index_populated_table() {
         let test_data = setup();
-    
         let (_value1_key, _value1, _value1_effects) = create_and_apply_record(&test_data);
         let (_index, index_effects) = create_and_apply_index(&test_data);
-    
         let index_effects_str = format!("{:?}", index_effects);
         let expected_index_effects_str = 
-        "[CreateCf('test_domain::/test_table/indexes/test_index'), \
+        "[CreateCf('/domains/test_domain/tables/test_table/indexes/test_index'), \
           TableStoredEffect(Put('/domains/test_domain/tables', 'test_table', \
           TableStored { table: Table { reference: TableReference { \
                         domain_reference: DomainReference { domain_name: 'test_domain' }, \
-                        table_name: 'test_table' } }, \
+                        table_name: 'test_table' }, schema: None }, \
                         indexes: {'test_index': Index { reference: IndexReference { \
                                   table_reference: TableReference { \
                                   domain_reference: DomainReference { domain_name: 'test_domain' }, \
                                   table_name: 'test_table' }, \
                                   index_name: 'test_index' }, \
-                                  fields: ['city'] }} })), \
-          IndexValueEffect(Put('test_domain::/test_table/indexes/test_index', \
-          OndoKey { values: [String('New York'), Number(1)] }, \
-          OndoKey { values: [Number(1)] }))]"
+                                  fields: ['city'], unique: false, kind: Ordered, \
+                                  building: true }} }))]"
         .to_owned()
         .replace('\'', "\"");
         assert_eq!(index_effects_str, expected_index_effects_str);
+        let ra = &test_data.rocks_db_accessor;
+        assert_eq!(find_names(&test_data, "New York"), vec![serde_json::json!("John")]);
+        let index = create_index_entity(&test_data.table_reference);
+        assert_eq!(index.reference.get_index(ra).unwrap(), Some(index));
     }
-                              
     #[test]
     fn test_all_values_with_key_prefix_vec() {
         let test_data = setup();
-    
         let (_value1_key, _value1, _value1_effects) = create_and_apply_record(&test_data);
         let (index, _index_effects) = create_and_apply_index(&test_data);
-    
         let index_reference = index.reference;
         let ra = &test_data.rocks_db_accessor;
         let index_iterator_factory = IndexIteratorRequestsFactoryEnum::new_db_arc(ra.guarded_db());
-    
         let key_prefix: OndoKey = "New York".into();
         let retrieved_all_values = index_reference
             .all_values_with_key_prefix_vec(key_prefix, ra, &index_iterator_factory)
             .unwrap();
-    
         assert_eq!(
             retrieved_all_values,
             vec![Ok(serde_json::json!({
                 "_id": {"values": [1]},
+                "_revision": 1,
                 "name": "John",
                 "age": 30,
                 "city": "New York"
             }))]
         );
-    
         let key_prefix_fail: OndoKey = "Llanfairpwll".into();
         let retrieved_all_values_fail = index_reference
             .all_values_with_key_prefix_vec(key_prefix_fail, ra, &index_iterator_factory)
             .unwrap();
-    
         assert_eq!(retrieved_all_values_fail, vec![]);
     }
-                              
+
+    fn post_test_index(test_data: &TestData, field: &str, unique: bool) -> DbResult<()> {
+        let ra = &test_data.rocks_db_accessor;
+        let mut index = create_index_entity(&test_data.table_reference);
+        index.fields = vec![field.to_owned()];
+        index.unique = unique;
+        post_and_build_index(ra, &index)?;
+        Ok(())
+    }
+
+    fn post_value(test_data: &TestData, value: serde_json::Value) -> DbResult<OndoKey> {
+        let ra = &test_data.rocks_db_accessor;
+        let mut value = value;
+        let (key, effects) = create_table_value_reference(&test_data.table_reference)
+            .post_table_value(&mut value, ra, ra, ra, ra)?;
+        effects.apply_effects(ra).unwrap();
+        Ok(key)
+    }
+
+    fn put_value(test_data: &TestData, key: &OndoKey, value: serde_json::Value) -> DbResult<()> {
+        let ra = &test_data.rocks_db_accessor;
+        let mut value = value;
+        insert_key_into_table_value(&mut value, key);
+        let reference = TableValueReference::new(test_data.table_reference.clone(), key.clone());
+        let effects = reference.put_table_value(&value, None, ra, ra, ra)?;
+        effects.apply_effects(ra).unwrap();
+        Ok(())
+    }
+
+    fn is_unique_violation<T>(result: DbResult<T>) -> bool {
+        matches!(result, Err(DbError::UniqueConstraintViolation(_)))
+    }
+
+    #[test]
+    fn test_unique_index_rejects_duplicate() {
+        let test_data = setup();
+        post_test_index(&test_data, "city", true).unwrap();
+        post_value(&test_data, serde_json::json!({"city": "York"})).unwrap();
+
+        assert!(is_unique_violation(post_value(
+            &test_data,
+            serde_json::json!({"city": "York"})
+        )));
+        // Only whole values collide, not their prefixes.
+        post_value(&test_data, serde_json::json!({"city": "Yorkshire"})).unwrap();
+        post_value(&test_data, serde_json::json!({"city": "Yor"})).unwrap();
+        // Documents without the field are not constrained.
+        post_value(&test_data, serde_json::json!({"name": "John"})).unwrap();
+        post_value(&test_data, serde_json::json!({"name": "Mary"})).unwrap();
+    }
+
+    #[test]
+    fn test_unique_index_on_update() {
+        let test_data = setup();
+        post_test_index(&test_data, "city", true).unwrap();
+        let york = post_value(&test_data, serde_json::json!({"city": "York"})).unwrap();
+        let paris = post_value(&test_data, serde_json::json!({"city": "Paris"})).unwrap();
+
+        assert!(is_unique_violation(put_value(
+            &test_data,
+            &paris,
+            serde_json::json!({"city": "York"})
+        )));
+        put_value(
+            &test_data,
+            &york,
+            serde_json::json!({"city": "York", "name": "John"}),
+        )
+        .unwrap();
+        put_value(&test_data, &york, serde_json::json!({"city": "Rome"})).unwrap();
+        put_value(&test_data, &paris, serde_json::json!({"city": "York"})).unwrap();
+    }
+
+    #[test]
+    fn test_unique_index_on_duplicate_values() {
+        let test_data = setup();
+        create_and_apply_record(&test_data);
+        create_and_apply_record(&test_data);
+
+        assert!(is_unique_violation(post_test_index(
+            &test_data, "city", true
+        )));
+    }
+
+    fn find_names(test_data: &TestData, key_prefix: &str) -> Vec<serde_json::Value> {
+        let ra = &test_data.rocks_db_accessor;
+        let index_reference = create_index_entity(&test_data.table_reference).reference;
+        let index_iterator_factory = IndexIteratorRequestsFactoryEnum::new_db_arc(ra.guarded_db());
+        index_reference
+            .all_values_with_key_prefix_vec(key_prefix.into(), ra, &index_iterator_factory)
+            .unwrap()
+            .into_iter()
+            .map(|value| value.unwrap()["name"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_multikey_index() {
+        let test_data = setup();
+        post_test_index(&test_data, "tags", false).unwrap();
+        let john = post_value(
+            &test_data,
+            serde_json::json!({"name": "John", "tags": ["a", "b", "a"]}),
+        )
+        .unwrap();
+        post_value(
+            &test_data,
+            serde_json::json!({"name": "Mary", "tags": ["b"]}),
+        )
+        .unwrap();
+
+        assert_eq!(find_names(&test_data, "a"), vec![serde_json::json!("John")]);
+        assert_eq!(
+            find_names(&test_data, "b"),
+            vec![serde_json::json!("John"), serde_json::json!("Mary")]
+        );
+
+        put_value(
+            &test_data,
+            &john,
+            serde_json::json!({"name": "John", "tags": ["c"]}),
+        )
+        .unwrap();
+        assert!(find_names(&test_data, "a").is_empty());
+        assert_eq!(find_names(&test_data, "b"), vec![serde_json::json!("Mary")]);
+        assert_eq!(find_names(&test_data, "c"), vec![serde_json::json!("John")]);
+
+        let ra = &test_data.rocks_db_accessor;
+        TableValueReference::new(test_data.table_reference.clone(), john)
+            .delete_table_value(None, ra, ra, ra)
+            .unwrap()
+            .apply_effects(ra)
+            .unwrap();
+        assert!(find_names(&test_data, "c").is_empty());
+    }
+
+    #[test]
+    fn test_multikey_index_on_nested_array() {
+        let test_data = setup();
+        post_value(
+            &test_data,
+            serde_json::json!({"name": "John", "orders": [{"sku": "x"}, {"sku": "y"}]}),
+        )
+        .unwrap();
+        post_test_index(&test_data, "orders.sku", false).unwrap();
+
+        assert_eq!(find_names(&test_data, "x"), vec![serde_json::json!("John")]);
+        assert_eq!(find_names(&test_data, "y"), vec![serde_json::json!("John")]);
+    }
+
+    #[test]
+    fn test_unique_multikey_index() {
+        let test_data = setup();
+        post_test_index(&test_data, "tags", true).unwrap();
+        post_value(&test_data, serde_json::json!({"tags": ["a", "b", "a"]})).unwrap();
+
+        assert!(is_unique_violation(post_value(
+            &test_data,
+            serde_json::json!({"tags": ["c", "b"]})
+        )));
+        post_value(&test_data, serde_json::json!({"tags": ["c"]})).unwrap();
+    }
+
+    fn post_text_index(test_data: &TestData, fields: &[&str], options: TextIndexOptions) {
+        let ra = &test_data.rocks_db_accessor;
+        let mut index = create_index_entity(&test_data.table_reference);
+        index.fields = fields.iter().map(|field| field.to_string()).collect();
+        index.kind = IndexKind::Text(options);
+        post_and_build_index(ra, &index).unwrap();
+    }
+
+    fn search(test_data: &TestData, query: &str) -> DbResult<Vec<(serde_json::Value, f64)>> {
+        let ra = &test_data.rocks_db_accessor;
+        let guarded_db = ra.guarded_db();
+        let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db)?;
+        let index_reference = create_index_entity(&test_data.table_reference).reference;
+        let found = index_reference.search_values(
+            query,
+            None,
+            &db_wrapper,
+            &db_wrapper,
+            &db_wrapper,
+            &db_wrapper,
+        )?;
+        Ok(found
+            .into_iter()
+            .map(|(value, score)| (value["name"].clone(), score))
+            .collect())
+    }
+
+    fn search_names(test_data: &TestData, query: &str) -> Vec<serde_json::Value> {
+        search(test_data, query)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn test_text_index_queries() {
+        let test_data = setup();
+        post_value(
+            &test_data,
+            serde_json::json!({"name": "fox", "text": "The quick brown fox jumps"}),
+        )
+        .unwrap();
+        post_text_index(&test_data, &["text"], TextIndexOptions::default());
+        post_value(
+            &test_data,
+            serde_json::json!({"name": "dog", "text": "A lazy brown dog sleeps"}),
+        )
+        .unwrap();
+
+        assert_eq!(
+            search_names(&test_data, "Fox"),
+            vec![serde_json::json!("fox")]
+        );
+        assert_eq!(search_names(&test_data, "brown cat").len(), 0);
+        assert_eq!(search_names(&test_data, "fox OR dog").len(), 2);
+        assert_eq!(
+            search_names(&test_data, "brown -fox"),
+            vec![serde_json::json!("dog")]
+        );
+        assert_eq!(
+            search_names(&test_data, "\"brown fox\""),
+            vec![serde_json::json!("fox")]
+        );
+        assert!(search_names(&test_data, "\"fox brown\"").is_empty());
+        assert_eq!(
+            search_names(&test_data, "(quick OR lazy) AND NOT jumps"),
+            vec![serde_json::json!("dog")]
+        );
+    }
+
+    #[test]
+    fn test_text_index_ranking() {
+        let test_data = setup();
+        post_text_index(&test_data, &["text"], TextIndexOptions::default());
+        post_value(
+            &test_data,
+            serde_json::json!({"name": "once", "text": "rust and some other words here"}),
+        )
+        .unwrap();
+        post_value(
+            &test_data,
+            serde_json::json!({"name": "twice", "text": "rust rust"}),
+        )
+        .unwrap();
+        post_value(
+            &test_data,
+            serde_json::json!({"name": "none", "text": "go"}),
+        )
+        .unwrap();
+
+        let found = search(&test_data, "rust").unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0, serde_json::json!("twice"));
+        assert_eq!(found[1].0, serde_json::json!("once"));
+        assert!(found[0].1 > found[1].1);
+    }
+
+    #[test]
+    fn test_text_index_with_stemming_is_kept_in_sync() {
+        let test_data = setup();
+        post_text_index(
+            &test_data,
+            &["title", "tags"],
+            TextIndexOptions {
+                stemming: true,
+                stop_words: true,
+            },
+        );
+        let key = post_value(
+            &test_data,
+            serde_json::json!({"name": "John", "title": "Running dogs", "tags": ["Cats"]}),
+        )
+        .unwrap();
+        assert_eq!(
+            search_names(&test_data, "run cat"),
+            vec![serde_json::json!("John")]
+        );
+        assert!(search_names(&test_data, "the").is_empty());
+
+        put_value(
+            &test_data,
+            &key,
+            serde_json::json!({"name": "John", "title": "Sleeping dogs"}),
+        )
+        .unwrap();
+        assert!(search_names(&test_data, "running").is_empty());
+        assert_eq!(
+            search_names(&test_data, "sleeps"),
+            vec![serde_json::json!("John")]
+        );
+
+        let ra = &test_data.rocks_db_accessor;
+        TableValueReference::new(test_data.table_reference.clone(), key)
+            .delete_table_value(None, ra, ra, ra)
+            .unwrap()
+            .apply_effects(ra)
+            .unwrap();
+        assert!(search_names(&test_data, "dog").is_empty());
+    }
+
+    fn text_stats(test_data: &TestData) -> (u64, u64) {
+        let index_reference = create_index_entity(&test_data.table_reference).reference;
+        let TextIndexStats {
+            document_count,
+            total_length,
+        } = get_text_stats(&index_reference, &test_data.rocks_db_accessor).unwrap();
+        (document_count, total_length)
+    }
+
+    #[test]
+    fn test_text_index_stats_are_kept_in_sync() {
+        let test_data = setup();
+        let ra = &test_data.rocks_db_accessor;
+        let fox = post_value(
+            &test_data,
+            serde_json::json!({"name": "fox", "text": "The quick brown fox"}),
+        )
+        .unwrap();
+        post_text_index(&test_data, &["text"], TextIndexOptions::default());
+        let dog = post_value(
+            &test_data,
+            serde_json::json!({"name": "dog", "text": "A lazy dog"}),
+        )
+        .unwrap();
+        assert_eq!(text_stats(&test_data), (2, 7));
+
+        put_value(
+            &test_data,
+            &fox,
+            serde_json::json!({"name": "fox", "text": "A fox"}),
+        )
+        .unwrap();
+        assert_eq!(text_stats(&test_data), (2, 5));
+        assert_eq!(
+            search_names(&test_data, "NOT fox"),
+            vec![serde_json::json!("dog")]
+        );
+
+        // A value deleted without its entries is counted until the repair deletes them.
+        let index_reference = create_index_entity(&test_data.table_reference).reference;
+        let gone: OndoKey = 99u64.into();
+        let mut stats = TextStatsUpdate::new(&index_reference, ra);
+        stats.set_length(&gone, Some(2)).unwrap();
+        let mut effects = stats.into_effects().unwrap();
+        let length = OndoKey {
+            values: vec![serde_json::json!(2)],
+        };
+        effects.extend(
+            IndexValueReference::new(index_reference.clone(), document_key(&gone))
+                .put_index_value(&length)
+                .unwrap(),
+        );
+        effects.apply_effects(ra).unwrap();
+        assert_eq!(text_stats(&test_data), (3, 7));
+        let verification = verify(&test_data, &index_reference, true);
+        assert_eq!(problem_count(&verification.orphaned), 1);
+        assert_eq!(text_stats(&test_data), (2, 5));
+        assert!(verify(&test_data, &index_reference, false).consistent);
+
+        TableValueReference::new(test_data.table_reference.clone(), dog)
+            .delete_table_value(None, ra, ra, ra)
+            .unwrap()
+            .apply_effects(ra)
+            .unwrap();
+        assert_eq!(text_stats(&test_data), (1, 2));
+        assert!(search_names(&test_data, "NOT fox").is_empty());
+    }
+
+    #[test]
+    fn test_invalid_text_search() {
+        let test_data = setup();
+        post_test_index(&test_data, "city", false).unwrap();
+        assert!(matches!(
+            search(&test_data, "york"),
+            Err(DbError::InvalidQuery(_))
+        ));
+
+        let test_data = setup();
+        post_text_index(&test_data, &["city"], TextIndexOptions::default());
+        assert!(matches!(
+            search(&test_data, "\"york"),
+            Err(DbError::InvalidQuery(_))
+        ));
+    }
+
+    #[test]
+    fn test_text_index_can_not_be_unique() {
+        let message = IndexMessage {
+            index_reference: Some(IndexReferenceMessage {
+                table_reference: Some(TableReferenceMessage {
+                    domain_reference: Some(DomainReferenceMessage {
+                        domain_name: "domain".to_owned(),
+                    }),
+                    table_name: "table".to_owned(),
+                }),
+                index_name: "index".to_owned(),
+            }),
+            fields: vec!["text".to_owned()],
+            unique: true,
+            kind: Some(index_message::Kind::Text(TextIndexOptionsMessage {
+                stemming: false,
+                stop_words: false,
+            })),
+            background: false,
+        };
+        let status = Index::try_from(&message).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    fn vector_index_reference(test_data: &TestData) -> IndexReference {
+        IndexReference::new(test_data.table_reference.clone(), "vector_index")
+    }
+
+    fn post_vector_index(test_data: &TestData, hnsw: Option<HnswOptions>) {
+        let ra = &test_data.rocks_db_accessor;
+        let index = Index {
+            reference: vector_index_reference(test_data),
+            fields: vec!["embedding".to_owned()],
+            unique: false,
+            kind: IndexKind::Vector(VectorIndexOptions {
+                dimension: 2,
+                metric: VectorMetric::L2,
+                hnsw,
+            }),
+            building: false,
+        };
+        post_and_build_index(ra, &index).unwrap();
+    }
+
+    fn vector_query(vector: Vec<f64>, k: usize, exact: bool) -> VectorQuery {
+        VectorQuery {
+            vector,
+            k,
+            exact,
+            ef_search: None,
+            filter: None,
+        }
+    }
+
+    fn nearest(
+        test_data: &TestData,
+        index_reference: &IndexReference,
+        query: &VectorQuery,
+    ) -> DbResult<Vec<(serde_json::Value, f64)>> {
+        let ra = &test_data.rocks_db_accessor;
+        let guarded_db = ra.guarded_db();
+        let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db)?;
+        let found = index_reference.nearest_values(
+            query,
+            &db_wrapper,
+            &db_wrapper,
+            &db_wrapper,
+            &db_wrapper,
+        )?;
+        Ok(found
+            .into_iter()
+            .map(|(value, distance)| (value["name"].clone(), distance))
+            .collect())
+    }
+
+    // The names of the k nearest values, found both exactly and on the HNSW graph.
+    fn nearest_names(test_data: &TestData, vector: Vec<f64>, k: usize) -> Vec<serde_json::Value> {
+        let index_reference = vector_index_reference(test_data);
+        let names = |exact| -> Vec<serde_json::Value> {
+            nearest(
+                test_data,
+                &index_reference,
+                &vector_query(vector.clone(), k, exact),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+        };
+        let exact_names = names(true);
+        assert_eq!(names(false), exact_names);
+        exact_names
+    }
+
+    fn post_points(test_data: &TestData) -> Vec<OndoKey> {
+        ["a", "b", "c", "d", "e"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                post_value(
+                    test_data,
+                    serde_json::json!({"name": name, "embedding": [i as f64, 0.0]}),
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_vector_index_search() {
+        let test_data = setup();
+        post_vector_index(&test_data, Some(HnswOptions::default()));
+        post_points(&test_data);
+        post_value(&test_data, serde_json::json!({"name": "none"})).unwrap();
+
+        let found = nearest(
+            &test_data,
+            &vector_index_reference(&test_data),
+            &vector_query(vec![2.2, 0.0], 3, true),
+        )
+        .unwrap();
+        let names: Vec<_> = found.iter().map(|(name, _)| name.clone()).collect();
+        assert_eq!(
+            names,
+            vec![
+                serde_json::json!("c"),
+                serde_json::json!("d"),
+                serde_json::json!("b")
+            ]
+        );
+        assert!((found[0].1 - 0.2).abs() < 1e-9);
+        assert!((found[2].1 - 1.2).abs() < 1e-9);
+        assert_eq!(nearest_names(&test_data, vec![2.2, 0.0], 3), names);
+        assert_eq!(nearest_names(&test_data, vec![0.0, 0.0], 10).len(), 5);
+    }
+
+    #[test]
+    fn test_vector_index_of_populated_table() {
+        let test_data = setup();
+        post_points(&test_data);
+        post_vector_index(
+            &test_data,
+            Some(HnswOptions {
+                m: 2,
+                ef_construction: 4,
+            }),
+        );
+        assert_eq!(
+            nearest_names(&test_data, vec![4.4, 1.0], 2),
+            vec![serde_json::json!("e"), serde_json::json!("d")]
+        );
+    }
+
+    #[test]
+    fn test_vector_index_is_kept_in_sync() {
+        let test_data = setup();
+        post_vector_index(&test_data, Some(HnswOptions::default()));
+        let keys = post_points(&test_data);
+
+        put_value(
+            &test_data,
+            &keys[0],
+            serde_json::json!({"name": "a", "embedding": [10.0, 0.0]}),
+        )
+        .unwrap();
+        put_value(
+            &test_data,
+            &keys[4],
+            serde_json::json!({"name": "e2", "embedding": [4.0, 0.0]}),
+        )
+        .unwrap();
+        assert_eq!(
+            nearest_names(&test_data, vec![9.0, 0.0], 2),
+            vec![serde_json::json!("a"), serde_json::json!("e2")]
+        );
+
+        let ra = &test_data.rocks_db_accessor;
+        TableValueReference::new(test_data.table_reference.clone(), keys[0].clone())
+            .delete_table_value(None, ra, ra, ra)
+            .unwrap()
+            .apply_effects(ra)
+            .unwrap();
+        put_value(&test_data, &keys[1], serde_json::json!({"name": "b"})).unwrap();
+        assert_eq!(
+            nearest_names(&test_data, vec![0.0, 0.0], 10),
+            vec![
+                serde_json::json!("c"),
+                serde_json::json!("d"),
+                serde_json::json!("e2")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vector_index_with_filter() {
+        let test_data = setup();
+        post_test_index(&test_data, "city", false).unwrap();
+        post_vector_index(&test_data, Some(HnswOptions::default()));
+        for (name, city, x) in [("a", "Paris", 0.0), ("b", "Rome", 1.0), ("c", "Paris", 3.0)] {
+            post_value(
+                &test_data,
+                serde_json::json!({"name": name, "city": city, "embedding": [x, 0.0]}),
+            )
+            .unwrap();
+        }
+        let mut query = vector_query(vec![1.0, 0.0], 2, false);
+        query.filter = Some((
+            create_index_entity(&test_data.table_reference).reference,
+            OndoKey {
+                values: vec![serde_json::json!("Paris")],
+            },
+        ));
+        let found = nearest(&test_data, &vector_index_reference(&test_data), &query).unwrap();
+        assert_eq!(
+            found,
+            vec![(serde_json::json!("a"), 1.0), (serde_json::json!("c"), 2.0)]
+        );
+
+        query.filter = Some((
+            vector_index_reference(&test_data),
+            OndoKey { values: vec![] },
+        ));
+        assert!(matches!(
+            nearest(&test_data, &vector_index_reference(&test_data), &query),
+            Err(DbError::InvalidQuery(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_nearest_values() {
+        let test_data = setup();
+        post_test_index(&test_data, "city", false).unwrap();
+        post_vector_index(&test_data, None);
+        assert!(matches!(
+            post_value(&test_data, serde_json::json!({"embedding": [1.0]})),
+            Err(DbError::InvalidVector(_))
+        ));
+        assert!(matches!(
+            post_value(&test_data, serde_json::json!({"embedding": "x"})),
+            Err(DbError::InvalidVector(_))
+        ));
+
+        let index_reference = vector_index_reference(&test_data);
+        assert!(matches!(
+            nearest(
+                &test_data,
+                &index_reference,
+                &vector_query(vec![1.0], 1, true)
+            ),
+            Err(DbError::InvalidVector(_))
+        ));
+        assert!(matches!(
+            nearest(
+                &test_data,
+                &index_reference,
+                &vector_query(vec![1.0, 0.0], 0, true)
+            ),
+            Err(DbError::InvalidQuery(_))
+        ));
+        let ordered_reference = create_index_entity(&test_data.table_reference).reference;
+        assert!(matches!(
+            nearest(
+                &test_data,
+                &ordered_reference,
+                &vector_query(vec![1.0, 0.0], 1, true)
+            ),
+            Err(DbError::InvalidQuery(_))
+        ));
+    }
+
+    #[test]
+    fn test_vector_index_message() {
+        let vector_message = |fields: Vec<&str>, unique: bool, m: u32| IndexMessage {
+            index_reference: Some(IndexReferenceMessage {
+                table_reference: Some(TableReferenceMessage {
+                    domain_reference: Some(DomainReferenceMessage {
+                        domain_name: "domain".to_owned(),
+                    }),
+                    table_name: "table".to_owned(),
+                }),
+                index_name: "index".to_owned(),
+            }),
+            fields: fields.into_iter().map(|field| field.to_owned()).collect(),
+            unique,
+            kind: Some(index_message::Kind::Vector(VectorIndexOptionsMessage {
+                dimension: 3,
+                metric: crate::ondo_remote::VectorMetric::Cosine as i32,
+                hnsw: Some(HnswOptionsMessage {
+                    m,
+                    ef_construction: 0,
+                }),
+            })),
+            background: false,
+        };
+        let message = vector_message(vec!["embedding"], false, 0);
+        let index = Index::try_from(&message).unwrap();
+        assert_eq!(
+            index.kind,
+            IndexKind::Vector(VectorIndexOptions {
+                dimension: 3,
+                metric: VectorMetric::Cosine,
+                hnsw: Some(HnswOptions::default()),
+            })
+        );
+        let converted: IndexMessage = index.into();
+        assert_eq!(
+            Index::try_from(&converted).unwrap().kind,
+            Index::try_from(&message).unwrap().kind
+        );
+
+        for message in [
+            vector_message(vec!["a", "b"], false, 0),
+            vector_message(vec!["embedding"], true, 0),
+            vector_message(vec!["embedding"], false, 1),
+        ] {
+            let status = Index::try_from(&message).unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    fn verify(
+        test_data: &TestData,
+        index_reference: &IndexReference,
+        repair: bool,
+    ) -> IndexVerificationMessage {
+        let ra = &test_data.rocks_db_accessor;
+        ra.verify_index(Request::new(VerifyIndexMessage {
+            index_reference: Some(index_reference.clone().into()),
+            repair,
+        }))
+        .unwrap()
+        .into_inner()
+    }
+
+    fn problem_count(problems: &Option<IndexProblemsMessage>) -> u64 {
+        problems.as_ref().unwrap().count
+    }
+
+    #[test]
+    fn test_verify_and_repair_index() {
+        let test_data = setup();
+        let ra = &test_data.rocks_db_accessor;
+        post_test_index(&test_data, "city", false).unwrap();
+        let york = post_value(
+            &test_data,
+            serde_json::json!({"name": "John", "city": "York"}),
+        )
+        .unwrap();
+        let paris = post_value(
+            &test_data,
+            serde_json::json!({"name": "Mary", "city": "Paris"}),
+        )
+        .unwrap();
+        let index_reference = create_index_entity(&test_data.table_reference).reference;
+        let verification = verify(&test_data, &index_reference, false);
+        assert!(verification.consistent);
+        assert_eq!(verification.values_checked, 2);
+        assert_eq!(verification.entries_checked, 2);
+
+        let entry_key = |city: &str, id: &OndoKey| {
+            let mut values = vec![serde_json::json!(city)];
+            values.extend(id.values.iter().cloned());
+            OndoKey { values }
+        };
+        let entry = |city: &str, id: &OndoKey| {
+            IndexValueReference::new(index_reference.clone(), entry_key(city, id))
+        };
+        let gone: OndoKey = 99u64.into();
+        // York lost its entry, Paris kept the entry of an older city, and a deleted value kept its entry.
+        entry("York", &york)
+            .delete_index_value()
+            .unwrap()
+            .apply_effects(ra)
+            .unwrap();
+        entry("Rome", &paris)
+            .put_index_value(&paris)
+            .unwrap()
+            .apply_effects(ra)
+            .unwrap();
+        entry("Oslo", &gone)
+            .put_index_value(&gone)
+            .unwrap()
+            .apply_effects(ra)
+            .unwrap();
+
+        let verification = verify(&test_data, &index_reference, false);
+        assert!(!verification.consistent);
+        assert!(!verification.repaired);
+        assert_eq!(problem_count(&verification.missing), 1);
+        assert_eq!(problem_count(&verification.orphaned), 1);
+        assert_eq!(problem_count(&verification.stale), 1);
+        assert_eq!(
+            verification.missing.unwrap().keys,
+            vec![entry_key("York", &york).into()]
+        );
+        assert_eq!(
+            verification.stale.unwrap().keys,
+            vec![entry_key("Rome", &paris).into()]
+        );
+        assert!(find_names(&test_data, "York").is_empty());
+
+        assert!(verify(&test_data, &index_reference, true).repaired);
+        assert!(verify(&test_data, &index_reference, false).consistent);
+        assert_eq!(find_names(&test_data, "York"), vec![serde_json::json!("John")]);
+        assert!(find_names(&test_data, "Rome").is_empty());
+    }
+
+    #[test]
+    fn test_verify_index_in_chunks() {
+        let test_data = setup();
+        let ra = &test_data.rocks_db_accessor;
+        post_test_index(&test_data, "city", false).unwrap();
+        for city in ["York", "Paris", "Rome"] {
+            post_value(&test_data, serde_json::json!({"name": "John", "city": city})).unwrap();
+        }
+        let index_reference = create_index_entity(&test_data.table_reference).reference;
+        let gone: OndoKey = 99u64.into();
+        let orphan_key = OndoKey {
+            values: vec![serde_json::json!("Oslo"), serde_json::json!(99)],
+        };
+        IndexValueReference::new(index_reference.clone(), orphan_key)
+            .put_index_value(&gone)
+            .unwrap()
+            .apply_effects(ra)
+            .unwrap();
+
+        let verify_in_chunks = |repair: bool| {
+            let mut verifier = IndexVerifier::new(repair, 1);
+            let mut chunks = 0;
+            while !verifier.is_done() {
+                let guarded_db = ra.guarded_db();
+                let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).unwrap();
+                let effects = index_reference
+                    .verify_index_chunk(
+                        &mut verifier,
+                        &db_wrapper,
+                        &db_wrapper,
+                        &db_wrapper,
+                        &db_wrapper,
+                        &db_wrapper,
+                    )
+                    .unwrap();
+                drop(db_wrapper);
+                assert!(repair || effects.is_empty());
+                effects.apply_effects(ra).unwrap();
+                chunks += 1;
+            }
+            (verifier.verification, chunks)
+        };
+        // Without repair, the chunks only report the problems.
+        let (verification, chunks) = verify_in_chunks(false);
+        assert_eq!(chunks, 7);
+        assert_eq!(verification.values_checked, 3);
+        assert_eq!(verification.entries_checked, 4);
+        assert_eq!(verification.orphaned.count, 1);
+        assert!(!verification.is_consistent());
+        assert_eq!(verify_in_chunks(false).0.orphaned.count, 1);
+
+        assert_eq!(verify_in_chunks(true).0.orphaned.count, 1);
+        assert!(verify_in_chunks(false).0.is_consistent());
+    }
+
+    #[test]
+    fn test_verify_and_repair_hnsw_graph() {
+        let test_data = setup();
+        let ra = &test_data.rocks_db_accessor;
+        post_vector_index(&test_data, Some(HnswOptions::default()));
+        let keys = post_points(&test_data);
+        let index_reference = vector_index_reference(&test_data);
+        let mut graph = HnswGraph::new(
+            &index_reference,
+            VectorMetric::L2,
+            HnswOptions::default(),
+            ra,
+        );
+        graph.remove(&keys[2]).unwrap();
+        let gone: OndoKey = 99u64.into();
+        graph.insert(&gone, &[2.1, 0.0]).unwrap();
+        graph.into_effects().apply_effects(ra).unwrap();
+
+        let verification = verify(&test_data, &index_reference, true);
+        assert_eq!(verification.entries_checked, 5);
+        assert_eq!(problem_count(&verification.missing), 0);
+        assert_eq!(problem_count(&verification.missing_nodes), 1);
+        assert_eq!(problem_count(&verification.orphaned_nodes), 1);
+        assert_eq!(problem_count(&verification.stale_nodes), 0);
+        assert!(verification.repaired);
+        assert!(verify(&test_data, &index_reference, false).consistent);
+        assert_eq!(
+            nearest_names(&test_data, vec![2.1, 0.0], 1),
+            vec![serde_json::json!("c")]
+        );
+    }
 }
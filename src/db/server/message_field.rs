@@ -0,0 +1,42 @@
+use serde_json::Value;
+use tonic::Status;
+
+// Requests come from clients, so a missing or malformed field is an invalid argument.
+// The field is named as Message.field so the client can tell which one is wrong.
+
+pub(super) fn required_field<'a, T>(
+    field: &'a Option<T>,
+    field_name: &str,
+) -> Result<&'a T, Status> {
+    field
+        .as_ref()
+        .ok_or_else(|| Status::invalid_argument(format!("Missing field {}", field_name)))
+}
+
+pub(super) fn json_field(json: &str, field_name: &str) -> Result<Value, Status> {
+    serde_json::from_str(json).map_err(|err| {
+        Status::invalid_argument(format!("Invalid JSON in field {}: {}", field_name, err))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    #[test]
+    fn test_required_field() {
+        assert_eq!(*required_field(&Some(1), "M.f").unwrap(), 1);
+        let status = required_field::<u32>(&None, "M.f").unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "Missing field M.f");
+    }
+
+    #[test]
+    fn test_json_field() {
+        assert_eq!(json_field(r#"{"a":1}"#, "M.json").unwrap()["a"], 1);
+        let status = json_field("{", "M.json").unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(status.message().starts_with("Invalid JSON in field M.json"));
+    }
+}
@@ -9,8 +9,17 @@ pub mod table_server_trait;
 pub mod table_server_trait_impl;
 pub mod table_value_server_trait;
 pub mod table_value_server_trait_impl;
+pub mod transaction_server_trait;
+pub mod transaction_server_trait_impl;
+pub mod value_stream;
 
 mod db_error_to_status;
+mod explain;
+mod index_build;
+mod message_field;
 mod ondo_key;
+mod page;
 mod source_sink;
+#[cfg(test)]
+mod test_fixture;
 mod value;
@@ -1,21 +1,24 @@
+use super::message_field::json_field;
 use crate::db::entity::ondo_key::*;
 use crate::ondo_remote;
 use ondo_remote::*;
+use tonic::Status;
 
-impl<'a> Into<OndoKey> for &'a OndoKeyMessage {
-    fn into(self) -> OndoKey {
-        let values = self
+impl TryFrom<&OndoKeyMessage> for OndoKey {
+    type Error = Status;
+    fn try_from(val: &OndoKeyMessage) -> Result<Self, Self::Error> {
+        let values = val
             .json_keys
             .iter()
-            .map(|json_key| serde_json::from_str(json_key).unwrap())
-            .collect();
-        OndoKey { values }
+            .map(|json_key| json_field(json_key, "OndoKeyMessage.json_keys"))
+            .collect::<Result<_, _>>()?;
+        Ok(OndoKey { values })
     }
 }
 
-impl Into<OndoKeyMessage> for OndoKey {
-    fn into(self) -> OndoKeyMessage {
-        let json_keys = self
+impl From<OndoKey> for OndoKeyMessage {
+    fn from(val: OndoKey) -> Self {
+        let json_keys = val
             .values
             .iter()
             .map(|value| serde_json::to_string(value).unwrap())
@@ -23,16 +26,17 @@ impl Into<OndoKeyMessage> for OndoKey {
         OndoKeyMessage { json_keys }
     }
 }
-impl<'a> Into<OptionalOndoKey> for &'a OptionalOndoKeyMessage {
-    fn into(self) -> OptionalOndoKey {
-        let r_ondo_key = self.ondo_key.as_ref();
-        r_ondo_key.map(|ondo_key| ondo_key.into())
+impl TryFrom<&OptionalOndoKeyMessage> for OptionalOndoKey {
+    type Error = Status;
+    fn try_from(val: &OptionalOndoKeyMessage) -> Result<Self, Self::Error> {
+        let r_ondo_key = val.ondo_key.as_ref();
+        r_ondo_key.map(|ondo_key| ondo_key.try_into()).transpose()
     }
 }
 
-impl Into<OptionalOndoKeyMessage> for OptionalOndoKey {
-    fn into(self) -> OptionalOndoKeyMessage {
-        let ondo_key = self.map(|ondo_key| ondo_key.into());
+impl From<OptionalOndoKey> for OptionalOndoKeyMessage {
+    fn from(val: OptionalOndoKey) -> Self {
+        let ondo_key = val.map(|ondo_key| ondo_key.into());
         OptionalOndoKeyMessage { ondo_key }
     }
 }
@@ -46,11 +50,21 @@ mod tests {
         let message = OndoKeyMessage {
             json_keys: vec![r#"{"key":"value"}"#.to_string()],
         };
-        let key: OndoKey = (&message).into();
+        let key: OndoKey = (&message).try_into().unwrap();
         assert_eq!(key.values.len(), 1);
         assert_eq!(key.values[0]["key"], "value");
     }
 
+    #[test]
+    fn test_invalid_ondo_key_message_is_invalid_argument() {
+        let message = OndoKeyMessage {
+            json_keys: vec!["{".to_string()],
+        };
+        let status = OndoKey::try_from(&message).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(status.message().contains("OndoKeyMessage.json_keys"));
+    }
+
     #[test]
     fn test_ondo_key_into_ondo_key_message() {
         let key = OndoKey {
@@ -68,7 +82,7 @@ mod tests {
                 json_keys: vec![r#"{"key":"value"}"#.to_string()],
             }),
         };
-        let optional_key: OptionalOndoKey = (&message).into();
+        let optional_key: OptionalOndoKey = (&message).try_into().unwrap();
         assert_eq!(
             optional_key,
             Some(OndoKey {
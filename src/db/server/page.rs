@@ -0,0 +1,231 @@
+use super::db_error_to_status::DbErrorToStatus;
+use super::source_sink::ondo_serializer::OndoSerializer;
+use crate::db::entity::{OndoKey, TableValue};
+use crate::db::reference::{Page, PageRequest};
+use crate::ondo_remote::JsonPageResponse;
+use tonic::Status;
+
+// The continuation token is the hex encoded storage key of the first value of the next page.
+
+pub(super) fn page_request(limit: u64, continuation_token: &str) -> Result<PageRequest, Status> {
+    let page_size = match limit {
+        0 => None,
+        limit => Some(limit as usize),
+    };
+    let start_key = match continuation_token {
+        "" => None,
+        token => Some(decode_continuation_token(token)?),
+    };
+    Ok(PageRequest {
+        start_key,
+        page_size,
+    })
+}
+
+pub(super) fn page_response(page: Page<TableValue>) -> Result<JsonPageResponse, Status> {
+    let json = serde_json::to_string(&page.values).map_err(|e| Status::internal(e.to_string()))?;
+    let continuation_token = page
+        .next_key
+        .map(|next_key| encode_key(&next_key))
+        .transpose()?
+        .unwrap_or_default();
+    Ok(JsonPageResponse {
+        json,
+        continuation_token,
+    })
+}
+
+/// The hex encoded storage key.
+pub(super) fn encode_key(key: &OndoKey) -> Result<String, Status> {
+    let bytes = key.ondo_serialize().map_db_err_to_status()?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn decode_continuation_token(token: &str) -> Result<OndoKey, Status> {
+    let invalid_token = || Status::invalid_argument("Invalid continuation token");
+    if token.len() % 2 != 0 {
+        return Err(invalid_token());
+    }
+    let bytes = (0..token.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(token.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(invalid_token)?;
+    OndoKey::ondo_deserialize(&bytes).map_err(|_| invalid_token())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::server::{
+        index_server_trait::IndexServerTrait, rocks_db_accessor::RocksDbAccessor,
+        table_server_trait::TableServerTrait, test_fixture,
+    };
+    use crate::ondo_remote::*;
+    use serde_json::json;
+    use tonic::Request;
+
+    fn table_reference() -> TableReferenceMessage {
+        test_fixture::table_reference("domain", "people")
+    }
+
+    fn index_reference() -> IndexReferenceMessage {
+        test_fixture::index_reference(&table_reference(), "by_age")
+    }
+
+    fn key(json_key: &str) -> OndoKeyMessage {
+        OndoKeyMessage {
+            json_keys: vec![json_key.to_owned()],
+        }
+    }
+
+    // Creates people with ids 1 to count and ages 10, 20, ...
+    fn create_people(ra: &RocksDbAccessor, count: u64) {
+        test_fixture::create_database(ra, &table_reference());
+        test_fixture::create_index(ra, &index_reference(), &["age"], false);
+        for id in 1..=count {
+            let json = json!({ "age": id * 10 }).to_string();
+            test_fixture::create_value(ra, &table_reference(), &json);
+        }
+    }
+
+    fn ages(json: &str) -> Vec<u64> {
+        let values: Vec<serde_json::Value> = serde_json::from_str(json).unwrap();
+        values
+            .iter()
+            .map(|value| value["age"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_list_values_in_pages() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra, 5);
+
+        let mut pages = vec![];
+        let mut continuation_token = String::new();
+        loop {
+            let response = ra
+                .list_values_page(Request::new(ListValuesMessage {
+                    table_reference: Some(table_reference()),
+                    limit: 2,
+                    continuation_token,
+                }))
+                .unwrap()
+                .into_inner();
+            pages.push(ages(&response.json));
+            if response.continuation_token.is_empty() {
+                break;
+            }
+            continuation_token = response.continuation_token;
+        }
+
+        assert_eq!(pages, vec![vec![10, 20], vec![30, 40], vec![50]]);
+    }
+
+    #[test]
+    fn test_list_values_without_limit() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra, 3);
+
+        let response = ra
+            .list_values_by_key_prefix_page(Request::new(ListValuesByKeyPrefixMessage {
+                table_value_reference: Some(TableValueReferenceMessage {
+                    table_reference: Some(table_reference()),
+                    key: Some(OndoKeyMessage { json_keys: vec![] }),
+                }),
+                limit: 0,
+                continuation_token: String::new(),
+            }))
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(ages(&response.json), vec![10, 20, 30]);
+        assert_eq!(response.continuation_token, "");
+    }
+
+    #[test]
+    fn test_list_and_find_values_without_pages() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra, 3);
+
+        let response = ra
+            .list_values(Request::new(table_reference()))
+            .unwrap()
+            .into_inner();
+        assert_eq!(ages(&response.json), vec![10, 20, 30]);
+
+        let response = ra
+            .list_values_by_key_prefix(Request::new(TableValueReferenceMessage {
+                table_reference: Some(table_reference()),
+                key: Some(OndoKeyMessage { json_keys: vec![] }),
+            }))
+            .unwrap()
+            .into_inner();
+        assert_eq!(ages(&response.json), vec![10, 20, 30]);
+
+        let response = ra
+            .find_values(Request::new(IndexedValueReferenceMessage {
+                index_reference: Some(index_reference()),
+                key: Some(key("20")),
+            }))
+            .unwrap()
+            .into_inner();
+        assert_eq!(ages(&response.json), vec![20]);
+
+        let response = ra
+            .find_values_by_range(Request::new(IndexedValueRangeReferenceMessage {
+                index_reference: Some(index_reference()),
+                start_key: Some(key("20")),
+                end_key: Some(key("30")),
+            }))
+            .unwrap()
+            .into_inner();
+        assert_eq!(ages(&response.json), vec![20, 30]);
+    }
+
+    #[test]
+    fn test_find_values_by_range_in_pages() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra, 6);
+        let find = |continuation_token: String| {
+            ra.find_values_by_range_page(Request::new(FindValuesByRangeMessage {
+                indexed_value_range_reference: Some(IndexedValueRangeReferenceMessage {
+                    index_reference: Some(index_reference()),
+                    start_key: Some(key("20")),
+                    end_key: Some(key("50")),
+                }),
+                limit: 2,
+                continuation_token,
+            }))
+            .unwrap()
+            .into_inner()
+        };
+
+        let first_page = find(String::new());
+        assert_eq!(ages(&first_page.json), vec![20, 30]);
+        let second_page = find(first_page.continuation_token);
+        assert_eq!(ages(&second_page.json), vec![40, 50]);
+        assert_eq!(second_page.continuation_token, "");
+    }
+
+    #[test]
+    fn test_continuation_token_round_trip() {
+        let key = OndoKey {
+            values: vec![json!("Paris"), json!(7)],
+        };
+        let token = encode_key(&key).unwrap();
+        assert_eq!(decode_continuation_token(&token).unwrap(), key);
+    }
+
+    #[test]
+    fn test_invalid_continuation_token() {
+        for token in ["0", "zz", "99", "é1"] {
+            assert_eq!(
+                page_request(10, token).unwrap_err().code(),
+                tonic::Code::InvalidArgument
+            );
+        }
+        assert_eq!(page_request(0, "").unwrap(), PageRequest::default());
+    }
+}
@@ -1,16 +1,27 @@
+use super::index_build::IndexBuilds;
+use crate::config::DEFAULT_DB_PATH;
+use crate::db::entity::table_schema::TableSchemas;
 use crate::db::DbError;
 use crate::db::DbResult;
-use rocksdb::{Options, DB};
-use std::sync::{Arc, RwLock, RwLockReadGuard};
+use crate::metrics;
+use rocksdb::{Options, DB, DEFAULT_COLUMN_FAMILY_NAME};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
 
 pub(crate) type DbArc = Arc<(RwLock<DB>, Option<TempDir>)>;
 
+// How often close checks whether the other clones of the accessor were dropped.
+const CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 #[derive(Clone)]
 pub struct RocksDbAccessor {
     db: DbArc,
     db_path: String,
     options: Options,
+    writes: Arc<Mutex<()>>,
+    pub(super) index_builds: IndexBuilds,
+    pub(super) table_schemas: Arc<TableSchemas>,
 }
 
 pub struct Version {
@@ -24,26 +35,31 @@ pub struct Version {
 
 impl Default for RocksDbAccessor {
     fn default() -> Self {
-        let db_path = std::env::var("ONDO_DB_PATH").unwrap_or("./db/ondo_rocksdb".to_owned());
+        let db_path = std::env::var("ONDO_DB_PATH").unwrap_or(DEFAULT_DB_PATH.to_owned());
 
         let mut options = Options::default();
         options.create_if_missing(true);
 
-        Self::init(db_path, options)
+        Self::open(db_path, options).unwrap()
     }
 }
 
 impl RocksDbAccessor {
-    fn init(db_path: String, options: Options) -> Self {
-        let cf_names = DB::list_cf(&options, &db_path).unwrap_or(Vec::new());
-        let raw_db = DB::open_cf(&options, &db_path, cf_names).unwrap();
+    /// Opens the database at db_path with all of its column families.
+    /// Column families created later use the same options.
+    pub fn open(db_path: String, options: Options) -> DbResult<Self> {
+        let cf_names = DB::list_cf(&options, &db_path).unwrap_or_default();
+        let raw_db = DB::open_cf(&options, &db_path, cf_names).map_err(DbError::RocksDbError)?;
         let db = Arc::new((RwLock::new(raw_db), None));
 
-        RocksDbAccessor {
+        Ok(RocksDbAccessor {
             db,
             db_path,
             options,
-        }
+            writes: Arc::new(Mutex::new(())),
+            index_builds: IndexBuilds::default(),
+            table_schemas: Arc::default(),
+        })
     }
 
     pub fn in_memory() -> Self {
@@ -53,25 +69,105 @@ impl RocksDbAccessor {
         let mut options = Options::default();
         options.create_if_missing(true);
 
-        Self::init(db_path, options).with_temp_dir(temp_dir)
+        Self::open(db_path, options)
+            .unwrap()
+            .with_temp_dir(temp_dir)
     }
 
     pub fn with_temp_dir(mut self, temp_dir: TempDir) -> Self {
-        let mut db = Arc::get_mut(&mut self.db).unwrap();
+        let db = Arc::get_mut(&mut self.db).unwrap();
         db.1 = Some(temp_dir);
         self
     }
 
+    pub(crate) fn options(&self) -> &Options {
+        &self.options
+    }
+
     pub fn guarded_db(&self) -> DbArc {
         Arc::clone(&self.db)
     }
 
-    pub fn db_read_lock(guarded_db: &DbArc) -> DbResult<std::sync::RwLockReadGuard<DB>> {
-        guarded_db.0.read().map_err(|_| DbError::CanNotLockDbMutex)
+    pub fn db_read_lock(guarded_db: &DbArc) -> DbResult<std::sync::RwLockReadGuard<'_, DB>> {
+        let start = Instant::now();
+        let guard = guarded_db.0.read().map_err(|_| DbError::CanNotLockDbMutex);
+        metrics::record_lock_wait("read", start.elapsed());
+        guard
+    }
+
+    pub fn db_write_lock(guarded_db: &DbArc) -> DbResult<std::sync::RwLockWriteGuard<'_, DB>> {
+        let start = Instant::now();
+        let guard = guarded_db.0.write().map_err(|_| DbError::CanNotLockDbMutex);
+        metrics::record_lock_wait("write", start.elapsed());
+        guard
+    }
+
+    /// Serializes the requests that check the stored values before writing, like the
+    /// expected revisions, the unique indexes and the HNSW graphs. The effects are computed under the read lock
+    /// and applied later, so without it two requests could both pass the check.
+    pub(crate) fn lock_writes(&self) -> DbResult<MutexGuard<'_, ()>> {
+        let start = Instant::now();
+        let guard = self.writes.lock().map_err(|_| DbError::CanNotLockDbMutex);
+        metrics::record_lock_wait("writes", start.elapsed());
+        guard
     }
 
-    pub fn db_write_lock(guarded_db: &DbArc) -> DbResult<std::sync::RwLockWriteGuard<DB>> {
-        guarded_db.0.write().map_err(|_| DbError::CanNotLockDbMutex)
+    /// The value of an integer RocksDB property, such as `rocksdb.total-sst-files-size`,
+    /// for every column family.
+    pub fn cf_property_values(&self, property_name: &str) -> DbResult<Vec<(String, u64)>> {
+        let db = Self::db_read_lock(&self.db)?;
+        let cf_names = DB::list_cf(&self.options, &self.db_path).map_err(DbError::RocksDbError)?;
+        let mut values = Vec::new();
+        for cf_name in cf_names {
+            // The default column family has no handle unless it was opened by name
+            let value = match db.cf_handle(&cf_name) {
+                Some(cf) => db.property_int_value_cf(cf, property_name),
+                None if cf_name == DEFAULT_COLUMN_FAMILY_NAME => {
+                    db.property_int_value(property_name)
+                }
+                None => continue,
+            }
+            .map_err(DbError::RocksDbError)?;
+            if let Some(value) = value {
+                values.push((cf_name, value));
+            }
+        }
+        Ok(values)
+    }
+
+    /// Flushes the memtables of every column family and syncs the WAL.
+    pub fn flush(&self) -> DbResult<()> {
+        let db = Self::db_read_lock(&self.db)?;
+        let cf_names = DB::list_cf(&self.options, &self.db_path).map_err(DbError::RocksDbError)?;
+        for cf_name in cf_names {
+            if let Some(cf) = db.cf_handle(&cf_name) {
+                db.flush_cf(cf).map_err(DbError::RocksDbError)?;
+            }
+        }
+        db.flush_wal(true).map_err(DbError::RocksDbError)
+    }
+
+    /// Stops the index builds, waits up to timeout for the other clones of the accessor
+    /// to be dropped, flushes the database and closes it.
+    /// Fails if the database is still used by a clone, e.g. by a request that did not finish.
+    pub fn close(self, timeout: Duration) -> DbResult<()> {
+        self.index_builds.stop();
+        let deadline = Instant::now() + timeout;
+        while Arc::strong_count(&self.db) > 1 && Instant::now() < deadline {
+            std::thread::sleep(CLOSE_POLL_INTERVAL);
+        }
+        self.flush()?;
+        let (db, temp_dir) = Arc::try_unwrap(self.db).map_err(|db| {
+            DbError::Other(format!(
+                "The database is still used by {} clones",
+                Arc::strong_count(&db) - 1
+            ))
+        })?;
+        let db = db.into_inner().map_err(|_| DbError::CanNotLockDbMutex)?;
+        db.cancel_all_background_work(true);
+        drop(db);
+        drop(temp_dir);
+        Ok(())
     }
 
     pub fn get_version(&self) -> Version {
@@ -118,4 +214,41 @@ mod tests {
         assert!(db_accessor.db.0.read().is_ok());
         assert!(db_accessor.db.as_ref().1.is_some());
     }
+
+    #[test]
+    fn test_flush_and_close() {
+        let db_accessor = RocksDbAccessor::in_memory();
+        {
+            let mut db = RocksDbAccessor::db_write_lock(&db_accessor.db).unwrap();
+            db.create_cf("cf", &Options::default()).unwrap();
+            let cf = db.cf_handle("cf").unwrap();
+            db.put_cf(cf, b"key", b"value").unwrap();
+        }
+        assert!(db_accessor.flush().is_ok());
+        assert!(db_accessor.close(Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn test_close_waits_for_clones() {
+        let db_accessor = RocksDbAccessor::in_memory();
+        let clone = db_accessor.clone();
+        let holder = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            drop(clone);
+        });
+
+        assert!(db_accessor.close(Duration::from_secs(10)).is_ok());
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn test_close_fails_while_a_clone_is_held() {
+        let db_accessor = RocksDbAccessor::in_memory();
+        let _clone = db_accessor.clone();
+
+        assert!(matches!(
+            db_accessor.close(Duration::ZERO),
+            Err(DbError::Other(_))
+        ));
+    }
 }
@@ -0,0 +1,286 @@
+use super::key_encoding_migration::get_stored;
+use super::ondo_serializer::OndoSerializer;
+use super::rocks_trait::RocksTrait;
+use crate::db::constants::{CF_NAME_SCHEME_VERSION, MIGRATION_CHUNK_SIZE};
+use crate::db::entity::{DatabaseServerStored, DomainStored, TableStored};
+use crate::db::reference::{CfNameMaker, DomainReference, LegacyCfNameMaker, TableReference};
+use crate::db::server::rocks_db_accessor::RocksDbAccessor;
+use crate::db::{DbError, DbResult};
+use rocksdb::{WriteBatch, DB};
+use std::collections::{BTreeMap, BTreeSet};
+
+impl RocksDbAccessor {
+    /// Moves the column families written by older versions to the escaped names of
+    /// CfNameMaker.
+    ///
+    /// The name scheme version is kept in the database server record. Databases
+    /// that are not initialized or are already up to date are left untouched.
+    /// The new column families are created first, then the records are copied in
+    /// batches of MIGRATION_CHUNK_SIZE, the legacy column families are dropped, and
+    /// the version is updated last. An interrupted migration is completed by the
+    /// next run, which copies the records of the remaining legacy column families again.
+    ///
+    /// Runs before migrate_key_encoding, which reads the new names.
+    /// Returns true if the database was migrated.
+    pub fn migrate_cf_names(&self) -> DbResult<bool> {
+        let guarded_db = self.guarded_db();
+        let mut db = RocksDbAccessor::db_write_lock(&guarded_db)?;
+
+        let server_cf_name = CfNameMaker::for_server_meta();
+        let server_key = ().ondo_serialize()?;
+        let mut server_stored: DatabaseServerStored =
+            match get_stored(&db, &server_cf_name, &server_key) {
+                Ok(Some(stored)) => stored,
+                Ok(None) | Err(DbError::CfNotFound) => return Ok(false),
+                Err(err) => return Err(err),
+            };
+        if server_stored.cf_name_scheme >= CF_NAME_SCHEME_VERSION {
+            return Ok(false);
+        }
+
+        let renames = legacy_cf_renames(&db, &server_stored)?;
+        for cf_name in renames.iter().flat_map(|(_, cf_names)| cf_names) {
+            if db.cf_handle(cf_name).is_none() {
+                db.create_cf(cf_name, self.options())
+                    .map_err(DbError::RocksDbError)?;
+            }
+        }
+
+        for (legacy_cf_name, cf_names) in renames.iter() {
+            copy_records(&db, legacy_cf_name, cf_names)?;
+        }
+        for (legacy_cf_name, _) in renames.iter() {
+            db.drop_cf(legacy_cf_name).map_err(DbError::RocksDbError)?;
+        }
+
+        server_stored.cf_name_scheme = CF_NAME_SCHEME_VERSION;
+        let server_cf = db.cf_handle(&server_cf_name).ok_or(DbError::CfNotFound)?;
+        db.put_cf(&server_cf, server_key, server_stored.ondo_serialize()?)
+            .map_err(DbError::RocksDbError)?;
+        Ok(true)
+    }
+}
+
+// Copies the records of the legacy column family into each of the new ones.
+fn copy_records(db: &DB, legacy_cf_name: &str, cf_names: &[String]) -> DbResult<()> {
+    let cfs = cf_names
+        .iter()
+        .map(|cf_name| db.cf_handle(cf_name).ok_or(DbError::CfNotFound))
+        .collect::<DbResult<Vec<_>>>()?;
+    let mut batch = WriteBatch::default();
+    let mut records_in_batch = 0;
+    for record in db.get_records_in_cf(legacy_cf_name)? {
+        let (key, value) = record?;
+        for cf in cfs.iter() {
+            batch.put_cf(cf, &key, &value);
+        }
+        records_in_batch += 1;
+        if records_in_batch == MIGRATION_CHUNK_SIZE {
+            db.write(std::mem::take(&mut batch))
+                .map_err(DbError::RocksDbError)?;
+            records_in_batch = 0;
+        }
+    }
+    db.write(batch).map_err(DbError::RocksDbError)
+}
+
+// The existing legacy column families that were renamed, with their new names.
+// Legacy names could collide, such entities shared their records, and each of
+// them gets a copy.
+fn legacy_cf_renames(
+    db: &DB,
+    server_stored: &DatabaseServerStored,
+) -> DbResult<Vec<(String, Vec<String>)>> {
+    let mut renames: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut add_rename = |legacy_cf_name: String, cf_name: String| {
+        if legacy_cf_name == cf_name || db.cf_handle(&legacy_cf_name).is_none() {
+            return;
+        }
+        let cf_names = renames.entry(legacy_cf_name.clone()).or_default();
+        cf_names.insert(cf_name.clone());
+        if cf_names.len() > 1 {
+            tracing::warn!(
+                legacy_cf_name,
+                cf_name,
+                "Column family was shared by several entities, each of them gets its records"
+            );
+        }
+    };
+
+    for domain_name in server_stored.domains.keys() {
+        let domain_reference = DomainReference::build(domain_name);
+        add_rename(
+            LegacyCfNameMaker::for_table_counters(&domain_reference),
+            CfNameMaker::for_table_counters(&domain_reference),
+        );
+        let legacy_table_meta_cf_name = LegacyCfNameMaker::for_table_meta(&domain_reference);
+        add_rename(
+            legacy_table_meta_cf_name.clone(),
+            CfNameMaker::for_table_meta(&domain_reference),
+        );
+
+        let domain_stored: Option<DomainStored> = get_stored(
+            db,
+            &CfNameMaker::for_domain_meta(),
+            &domain_name.ondo_serialize()?,
+        )?;
+        let table_names = domain_stored
+            .map(|stored| stored.tables.into_keys().collect::<Vec<_>>())
+            .unwrap_or_default();
+        for table_name in table_names {
+            let table_reference = TableReference::new(domain_reference.clone(), &table_name);
+            add_rename(
+                LegacyCfNameMaker::for_table_values(&table_reference),
+                CfNameMaker::for_table_values(&table_reference),
+            );
+            // The table meta is still in its legacy column family.
+            let table_meta_cf_name = if db.cf_handle(&legacy_table_meta_cf_name).is_some() {
+                legacy_table_meta_cf_name.clone()
+            } else {
+                CfNameMaker::for_table_meta(&domain_reference)
+            };
+            let table_stored: Option<TableStored> =
+                get_stored(db, &table_meta_cf_name, &table_name.ondo_serialize()?)?;
+            for index in table_stored
+                .iter()
+                .flat_map(|stored| stored.indexes.values())
+            {
+                add_rename(
+                    LegacyCfNameMaker::for_index_values(&index.reference),
+                    CfNameMaker::for_index_values(&index.reference),
+                );
+            }
+        }
+    }
+
+    Ok(renames
+        .into_iter()
+        .map(|(legacy_cf_name, cf_names)| (legacy_cf_name, cf_names.into_iter().collect()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::reference::requests::TableValueRequests;
+    use crate::db::reference::{IndexReference, TableValueReference};
+    use crate::db::server::test_fixture;
+
+    fn create_database(ra: &RocksDbAccessor) {
+        let table_reference = test_fixture::table_reference("domain", "table");
+        test_fixture::create_database(ra, &table_reference);
+        let index_reference = test_fixture::index_reference(&table_reference, "index");
+        test_fixture::create_index(ra, &index_reference, &["age"], false);
+        test_fixture::create_value(ra, &table_reference, r#"{"age": 30}"#);
+    }
+
+    fn move_cf(ra: &RocksDbAccessor, from_cf_name: &str, to_cf_name: &str) {
+        let guarded_db = ra.guarded_db();
+        let mut db = RocksDbAccessor::db_write_lock(&guarded_db).unwrap();
+        db.create_cf(to_cf_name, ra.options()).unwrap();
+        let records = db
+            .get_records_in_cf(from_cf_name)
+            .unwrap()
+            .collect::<DbResult<Vec<_>>>()
+            .unwrap();
+        let to_cf = db.cf_handle(to_cf_name).unwrap();
+        for (key, value) in records {
+            db.put_cf(&to_cf, key, value).unwrap();
+        }
+        db.drop_cf(from_cf_name).unwrap();
+    }
+
+    fn index_cf_names() -> (String, String) {
+        let index_reference = IndexReference::build("domain", "table", "index");
+        (
+            CfNameMaker::for_index_values(&index_reference),
+            LegacyCfNameMaker::for_index_values(&index_reference),
+        )
+    }
+
+    // Moves the column families back to the names older versions used.
+    fn rename_to_legacy_cfs(ra: &RocksDbAccessor) {
+        let table_reference = TableReference::build("domain", "table");
+        move_cf(
+            ra,
+            &CfNameMaker::for_table_values(&table_reference),
+            &LegacyCfNameMaker::for_table_values(&table_reference),
+        );
+        let (index_cf_name, legacy_index_cf_name) = index_cf_names();
+        move_cf(ra, &index_cf_name, &legacy_index_cf_name);
+
+        let guarded_db = ra.guarded_db();
+        let db = RocksDbAccessor::db_write_lock(&guarded_db).unwrap();
+
+        let server_key = ().ondo_serialize().unwrap();
+        let mut server_stored: DatabaseServerStored =
+            get_stored(&db, &CfNameMaker::for_server_meta(), &server_key)
+                .unwrap()
+                .unwrap();
+        server_stored.cf_name_scheme = 0;
+        let server_cf = db.cf_handle(&CfNameMaker::for_server_meta()).unwrap();
+        db.put_cf(
+            &server_cf,
+            server_key,
+            server_stored.ondo_serialize().unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_new_database_is_not_migrated() {
+        let ra = RocksDbAccessor::in_memory();
+        assert_eq!(ra.migrate_cf_names(), Ok(false));
+        create_database(&ra);
+        assert_eq!(ra.migrate_cf_names(), Ok(false));
+    }
+
+    fn assert_migrated(ra: &RocksDbAccessor) {
+        assert_eq!(ra.migrate_cf_names(), Ok(false));
+
+        let table_value_reference = TableValueReference {
+            table_reference: TableReference::build("domain", "table"),
+            id: 1u64.into(),
+        };
+        assert_eq!(
+            ra.get_table_value(
+                &CfNameMaker::for_table_values(&table_value_reference.table_reference),
+                &table_value_reference,
+            )
+            .unwrap()
+            .map(|value| value["age"].clone()),
+            Some(serde_json::json!(30))
+        );
+        let guarded_db = ra.guarded_db();
+        let db = RocksDbAccessor::db_read_lock(&guarded_db).unwrap();
+        let legacy_cf_name =
+            LegacyCfNameMaker::for_table_values(&TableReference::build("domain", "table"));
+        assert!(db.cf_handle(&legacy_cf_name).is_none());
+        let index_cf_name =
+            CfNameMaker::for_index_values(&IndexReference::build("domain", "table", "index"));
+        assert_eq!(db.get_records_in_cf(&index_cf_name).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_migrate_legacy_cf_names() {
+        let ra = RocksDbAccessor::in_memory();
+        create_database(&ra);
+        rename_to_legacy_cfs(&ra);
+
+        assert_eq!(ra.migrate_cf_names(), Ok(true));
+        assert_migrated(&ra);
+    }
+
+    #[test]
+    fn test_complete_interrupted_migration() {
+        let ra = RocksDbAccessor::in_memory();
+        create_database(&ra);
+        rename_to_legacy_cfs(&ra);
+        // The index was moved, and the run stopped before the other drops.
+        let (index_cf_name, legacy_index_cf_name) = index_cf_names();
+        move_cf(&ra, &legacy_index_cf_name, &index_cf_name);
+
+        assert_eq!(ra.migrate_cf_names(), Ok(true));
+        assert_migrated(&ra);
+    }
+}
@@ -16,9 +16,7 @@ impl DatabaseServerStoredRequests for RocksDbAccessor {
         let db = RocksDbAccessor::db_read_lock(&guarded_db)?;
         let cf = db.cf_handle(cf_name).ok_or(CfNotFound)?;
         let ondo_key = DatabaseServerName::ondo_serialize(key)?;
-        let answer = db
-            .get_cf(cf, &ondo_key)
-            .map_err(|err| DbError::RocksDbError(err))?;
+        let answer = db.get_cf(cf, &ondo_key).map_err(DbError::RocksDbError)?;
         answer
             .map(|bytes| DatabaseServerStored::ondo_deserialize(&bytes))
             .transpose()
@@ -1,57 +1,287 @@
 use crate::db::reference::effect::Effect;
+use crate::db::reference::CfName;
 use crate::db::server::db_error_to_status::DbErrorToStatus;
-use crate::db::server::rocks_db_accessor::RocksDbAccessor;
-use crate::db::DbError;
+use crate::db::server::rocks_db_accessor::{DbArc, RocksDbAccessor};
+use crate::db::{DbError, DbResult};
+use crate::metrics;
 use crate::ondo_remote::EmptyMessage;
+use rocksdb::{IteratorMode, Options, WriteBatch, DB};
 use tonic::{Response, Status};
 
 pub(in crate::db::server) trait EffectsSink {
     fn apply_effects(&self, ra: &RocksDbAccessor) -> Result<Response<EmptyMessage>, Status>;
 }
 
+// Effects are applied in three phases so that a request never leaves a half-written state:
+//    create_cfs: Creates the missing column families the batch writes into.
+//    write_batch: Commits every value effect in a single atomic WriteBatch.
+//                 A DeleteCf followed by a CreateCf of the same name empties the column
+//                 family in the batch, so its old entries stay until the new ones commit.
+//                 If the batch fails, the column families created for it are dropped again.
+//    drop_cfs: Drops the remaining column families once the batch is committed.
 impl EffectsSink for Vec<Effect> {
     fn apply_effects(&self, ra: &RocksDbAccessor) -> Result<Response<EmptyMessage>, Status> {
-        let cf_opts = rocksdb::Options::default();
-        for effect in self.into_iter() {
-            println!("Effect: {:?}", effect);
-            match effect {
-                Effect::CreateCf(cf_name) => {
-                    let guarded_db = ra.guarded_db();
-                    let mut db =
-                        RocksDbAccessor::db_write_lock(&guarded_db).map_db_err_to_status()?;
-                    db.create_cf(cf_name, &cf_opts)
-                        .map_err(|err| DbError::RocksDbError(err))
-                        .map_db_err_to_status()?;
-                }
-                Effect::DeleteCf(cf_name) => {
-                    let guarded_db = ra.guarded_db();
-                    let mut db =
-                        RocksDbAccessor::db_write_lock(&guarded_db).map_db_err_to_status()?;
-                    db.drop_cf(cf_name)
-                        .map_err(|err| DbError::RocksDbError(err))
-                        .map_db_err_to_status()?;
-                }
-                Effect::DatabaseServerStoredEffect(effect) => {
-                    super::database_server_sink::apply_effect(&ra, effect)
-                        .map_db_err_to_status()?;
-                }
-                Effect::DomainStoredEffect(effect) => {
-                    super::domain_sink::apply_effect(&ra, effect).map_db_err_to_status()?;
-                }
-                Effect::TableStoredEffect(effect) => {
-                    super::table_sink::apply_effect(&ra, effect).map_db_err_to_status()?;
-                }
-                Effect::TableValueEffect(effect) => {
-                    super::table_value_sink::apply_effect(&ra, effect).map_db_err_to_status()?;
-                }
-                Effect::IndexValueEffect(effect) => {
-                    super::index_value_sink::apply_effect(&ra, effect).map_db_err_to_status()?;
-                }
-                Effect::ColumnValueEffect(effect) => {
-                    super::column_value_sink::apply_effect(&ra, effect).map_db_err_to_status()?;
-                }
-            }
+        tracing::Span::current().record("effect_count", self.len());
+        let guarded_db = ra.guarded_db();
+        let created_cfs = create_cfs(&guarded_db, ra.options(), self).map_db_err_to_status()?;
+        if let Err(err) = write_batch(&guarded_db, self) {
+            drop_created_cfs(&guarded_db, &created_cfs);
+            return Err(err).map_db_err_to_status();
+        }
+        drop_cfs(&guarded_db, self).map_db_err_to_status()?;
+        for effect in self.iter() {
+            metrics::record_effect(effect.variant_name());
         }
         Ok(Response::new(EmptyMessage {}))
     }
 }
+
+fn is_recreated(effects: &[Effect], position: usize, cf_name: &CfName) -> bool {
+    effects[position + 1..]
+        .iter()
+        .any(|effect| matches!(effect, Effect::CreateCf(name) if name == cf_name))
+}
+
+// Creates the column families that do not exist yet, and returns their names.
+// An existing column family is left as it is, so a retried request does not fail on it.
+fn create_cfs(guarded_db: &DbArc, cf_opts: &Options, effects: &[Effect]) -> DbResult<Vec<CfName>> {
+    let has_cf_creation = effects
+        .iter()
+        .any(|effect| matches!(effect, Effect::CreateCf(_)));
+    if !has_cf_creation {
+        return Ok(vec![]);
+    }
+    let mut created_cfs = vec![];
+    let mut db = RocksDbAccessor::db_write_lock(guarded_db)?;
+    for effect in effects.iter() {
+        let cf_name = match effect {
+            Effect::CreateCf(cf_name) if db.cf_handle(cf_name).is_none() => cf_name,
+            _ => continue,
+        };
+        if let Err(err) = db.create_cf(cf_name, cf_opts) {
+            drop(db);
+            drop_created_cfs(guarded_db, &created_cfs);
+            return Err(DbError::RocksDbError(err));
+        }
+        created_cfs.push(cf_name.clone());
+    }
+    Ok(created_cfs)
+}
+
+fn drop_created_cfs(guarded_db: &DbArc, created_cfs: &[CfName]) {
+    if created_cfs.is_empty() {
+        return;
+    }
+    let mut db = match RocksDbAccessor::db_write_lock(guarded_db) {
+        Ok(db) => db,
+        Err(err) => {
+            tracing::error!(%err, "can not drop the column families of a failed batch");
+            return;
+        }
+    };
+    for cf_name in created_cfs {
+        if let Err(err) = db.drop_cf(cf_name) {
+            tracing::error!(%err, cf_name, "can not drop the column family of a failed batch");
+        }
+    }
+}
+
+// Deletes every entry of the column family in the batch, as a range from its first
+// key to its last one, so the entries are not read into memory.
+pub(super) fn clear_cf(db: &DB, batch: &mut WriteBatch, cf_name: &CfName) -> DbResult<()> {
+    let cf = match db.cf_handle(cf_name) {
+        Some(cf) => cf,
+        None => return Ok(()),
+    };
+    let mut first_key = None;
+    let mut last_key = None;
+    for (mode, key) in [
+        (IteratorMode::Start, &mut first_key),
+        (IteratorMode::End, &mut last_key),
+    ] {
+        if let Some(entry) = db.iterator_cf(cf, mode).next() {
+            *key = Some(entry.map_err(DbError::RocksDbError)?.0);
+        }
+    }
+    if let (Some(first_key), Some(last_key)) = (first_key, last_key) {
+        // The end of a range is exclusive.
+        batch.delete_range_cf(cf, &first_key, &last_key);
+        batch.delete_cf(cf, &last_key);
+    }
+    Ok(())
+}
+
+fn write_batch(guarded_db: &DbArc, effects: &[Effect]) -> DbResult<()> {
+    let db = RocksDbAccessor::db_read_lock(guarded_db)?;
+    let mut batch = WriteBatch::default();
+    for (position, effect) in effects.iter().enumerate() {
+        tracing::trace!(?effect, "apply effect");
+        match effect {
+            Effect::DeleteCf(cf_name) if is_recreated(effects, position, cf_name) => {
+                clear_cf(&db, &mut batch, cf_name)?;
+            }
+            Effect::CreateCf(_) | Effect::DeleteCf(_) => {}
+            Effect::DatabaseServerStoredEffect(effect) => {
+                super::database_server_sink::apply_effect(&db, &mut batch, effect)?;
+            }
+            Effect::DomainStoredEffect(effect) => {
+                super::domain_sink::apply_effect(&db, &mut batch, effect)?;
+            }
+            Effect::TableStoredEffect(effect) => {
+                super::table_sink::apply_effect(&db, &mut batch, effect)?;
+            }
+            Effect::TableValueEffect(effect) => {
+                super::table_value_sink::apply_effect(&db, &mut batch, effect)?;
+            }
+            Effect::IndexValueEffect(effect) => {
+                super::index_value_sink::apply_effect(&db, &mut batch, effect)?;
+            }
+            Effect::ColumnValueEffect(effect) => {
+                super::column_value_sink::apply_effect(&db, &mut batch, effect)?;
+            }
+        }
+    }
+    if batch.is_empty() {
+        return Ok(());
+    }
+    db.write(batch).map_err(DbError::RocksDbError)
+}
+
+fn drop_cfs(guarded_db: &DbArc, effects: &[Effect]) -> DbResult<()> {
+    let cf_names_to_drop = effects
+        .iter()
+        .enumerate()
+        .filter_map(|(position, effect)| match effect {
+            Effect::DeleteCf(cf_name) if !is_recreated(effects, position, cf_name) => Some(cf_name),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if cf_names_to_drop.is_empty() {
+        return Ok(());
+    }
+    let mut db = RocksDbAccessor::db_write_lock(guarded_db)?;
+    for cf_name in cf_names_to_drop {
+        db.drop_cf(cf_name).map_err(DbError::RocksDbError)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::entity::OndoKey;
+    use crate::db::reference::effect::{ColumnValueEffect, IndexValueEffect};
+    use crate::db::reference::requests::ColumnValueRequests;
+    use serde_json::json;
+
+    fn put_effect(cf_name: &str, key: &str, value: u64) -> Effect {
+        Effect::ColumnValueEffect(ColumnValueEffect::Put(
+            cf_name.to_owned(),
+            key.into(),
+            json!(value),
+        ))
+    }
+
+    #[test]
+    fn test_failed_effect_discards_whole_batch() {
+        let ra = RocksDbAccessor::in_memory();
+        let key: OndoKey = "counter".into();
+        let mut effects = vec![
+            Effect::CreateCf("values".to_owned()),
+            put_effect("values", "counter", 1),
+            Effect::IndexValueEffect(IndexValueEffect::Put(
+                "missing".to_owned(),
+                key.clone(),
+                key.clone(),
+            )),
+        ];
+
+        assert!(effects.apply_effects(&ra).is_err());
+        assert_eq!(
+            ra.get_column_value("values", &key),
+            Err(DbError::CfNotFound)
+        );
+
+        effects.pop();
+        effects.apply_effects(&ra).unwrap();
+        assert_eq!(ra.get_column_value("values", &key), Ok(Some(json!(1))));
+    }
+
+    #[test]
+    fn test_failed_batch_keeps_recreated_cf() {
+        let ra = RocksDbAccessor::in_memory();
+        let key: OndoKey = "counter".into();
+        vec![
+            Effect::CreateCf("values".to_owned()),
+            put_effect("values", "counter", 1),
+        ]
+        .apply_effects(&ra)
+        .unwrap();
+
+        let effects = vec![
+            Effect::DeleteCf("values".to_owned()),
+            Effect::CreateCf("values".to_owned()),
+            put_effect("values", "other", 2),
+            put_effect("missing", "other", 2),
+        ];
+        assert!(effects.apply_effects(&ra).is_err());
+        assert_eq!(ra.get_column_value("values", &key), Ok(Some(json!(1))));
+        assert_eq!(ra.get_column_value("values", &"other".into()), Ok(None));
+    }
+
+    #[test]
+    fn test_recreate_cf_before_batch() {
+        let ra = RocksDbAccessor::in_memory();
+        let key: OndoKey = "counter".into();
+        vec![
+            Effect::CreateCf("values".to_owned()),
+            put_effect("values", "counter", 1),
+            put_effect("values", "limit", 1),
+            put_effect("values", "total", 1),
+        ]
+        .apply_effects(&ra)
+        .unwrap();
+
+        vec![
+            Effect::DeleteCf("values".to_owned()),
+            Effect::CreateCf("values".to_owned()),
+            put_effect("values", "other", 2),
+        ]
+        .apply_effects(&ra)
+        .unwrap();
+
+        assert_eq!(ra.get_column_value("values", &key), Ok(None));
+        assert_eq!(ra.get_column_value("values", &"limit".into()), Ok(None));
+        assert_eq!(ra.get_column_value("values", &"total".into()), Ok(None));
+        assert_eq!(
+            ra.get_column_value("values", &"other".into()),
+            Ok(Some(json!(2)))
+        );
+    }
+
+    #[test]
+    fn test_drop_cf_after_batch() {
+        let ra = RocksDbAccessor::in_memory();
+        vec![
+            Effect::CreateCf("values".to_owned()),
+            Effect::CreateCf("meta".to_owned()),
+        ]
+        .apply_effects(&ra)
+        .unwrap();
+
+        vec![
+            put_effect("meta", "counter", 1),
+            put_effect("values", "counter", 1),
+            Effect::DeleteCf("values".to_owned()),
+        ]
+        .apply_effects(&ra)
+        .unwrap();
+
+        let key: OndoKey = "counter".into();
+        assert_eq!(ra.get_column_value("meta", &key), Ok(Some(json!(1))));
+        assert_eq!(
+            ra.get_column_value("values", &key),
+            Err(DbError::CfNotFound)
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use super::key_encoding_migration::{
+    get_server_stored, get_stored, put_server_stored, rebuild_index, tables_to_migrate,
+};
+use super::ondo_serializer::OndoSerializer;
+use crate::db::constants::INDEX_ENTRY_VERSION;
+use crate::db::entity::index::IndexKind;
+use crate::db::entity::TableStored;
+use crate::db::reference::CfNameMaker;
+use crate::db::server::rocks_db_accessor::RocksDbAccessor;
+use crate::db::{DbError, DbResult};
+use rocksdb::{WriteBatch, DB};
+
+impl RocksDbAccessor {
+    /// Rebuilds the entries of the ordered indexes written by older versions, which
+    /// indexed an array field as a whole and an object field by its contents.
+    ///
+    /// The index entry version is kept in the database server record. Databases
+    /// that are not initialized or are already up to date are left untouched.
+    /// The entries are rebuilt from the values table by table in batches of
+    /// MIGRATION_CHUNK_SIZE, and each finished table is recorded, so an interrupted
+    /// migration resumes after the last finished table. The version is updated last.
+    ///
+    /// Runs after migrate_key_encoding, which reads the keys of the values.
+    /// Returns true if the database was migrated.
+    pub fn migrate_index_entries(&self) -> DbResult<bool> {
+        let guarded_db = self.guarded_db();
+        let db = RocksDbAccessor::db_write_lock(&guarded_db)?;
+
+        let mut server_stored = match get_server_stored(&db)? {
+            Some(stored) => stored,
+            None => return Ok(false),
+        };
+        if server_stored.index_entries >= INDEX_ENTRY_VERSION {
+            return Ok(false);
+        }
+
+        for (domain_reference, table_name) in tables_to_migrate(&db, &server_stored)? {
+            let table_stored: Option<TableStored> = get_stored(
+                &db,
+                &CfNameMaker::for_table_meta(&domain_reference),
+                &table_name.ondo_serialize()?,
+            )?;
+            if let Some(table_stored) = table_stored {
+                rebuild_ordered_indexes(&db, &table_stored)?;
+            }
+            server_stored.migrated_table = Some((domain_reference.domain_name, table_name));
+            put_server_stored(&db, &server_stored)?;
+        }
+
+        server_stored.index_entries = INDEX_ENTRY_VERSION;
+        server_stored.migrated_table = None;
+        put_server_stored(&db, &server_stored)?;
+        Ok(true)
+    }
+}
+
+// The text and vector indexes do not depend on how the ordered keys are made.
+fn rebuild_ordered_indexes(db: &DB, table_stored: &TableStored) -> DbResult<()> {
+    let values_cf_name = CfNameMaker::for_table_values(&table_stored.table.reference);
+    let mut batch = WriteBatch::default();
+    for index in table_stored.indexes.values() {
+        if index.kind == IndexKind::Ordered {
+            rebuild_index(db, &mut batch, index, &values_cf_name)?;
+        }
+    }
+    db.write(batch).map_err(DbError::RocksDbError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::entity::{DatabaseServerStored, OndoKey};
+    use crate::db::reference::IndexReference;
+    use crate::db::server::{index_server_trait::IndexServerTrait, test_fixture};
+    use crate::ondo_remote::*;
+    use serde_json::json;
+    use tonic::Request;
+
+    fn table_reference() -> TableReferenceMessage {
+        test_fixture::table_reference("domain", "table")
+    }
+
+    fn index_reference() -> IndexReferenceMessage {
+        test_fixture::index_reference(&table_reference(), "by_tag")
+    }
+
+    fn create_database(ra: &RocksDbAccessor) {
+        test_fixture::create_database(ra, &table_reference());
+        test_fixture::create_index(ra, &index_reference(), &["tags"], false);
+        test_fixture::create_value(ra, &table_reference(), r#"{"tags": ["a", "b"]}"#);
+    }
+
+    // Writes the whole array entry of older versions.
+    fn put_legacy_entry(ra: &RocksDbAccessor) {
+        let guarded_db = ra.guarded_db();
+        let db = RocksDbAccessor::db_write_lock(&guarded_db).unwrap();
+        let index_cf = db
+            .cf_handle(&CfNameMaker::for_index_values(&IndexReference::build(
+                "domain", "table", "by_tag",
+            )))
+            .unwrap();
+        let id: OndoKey = 1u64.into();
+        let legacy_key = OndoKey {
+            values: vec![json!(["a", "b"]), json!(1)],
+        };
+        db.put_cf(
+            &index_cf,
+            legacy_key.ondo_serialize().unwrap(),
+            id.ondo_serialize().unwrap(),
+        )
+        .unwrap();
+
+        let server_cf = db.cf_handle(&CfNameMaker::for_server_meta()).unwrap();
+        let server_key = ().ondo_serialize().unwrap();
+        let mut server_stored: DatabaseServerStored =
+            get_stored(&db, &CfNameMaker::for_server_meta(), &server_key)
+                .unwrap()
+                .unwrap();
+        server_stored.index_entries = 0;
+        db.put_cf(
+            &server_cf,
+            server_key,
+            server_stored.ondo_serialize().unwrap(),
+        )
+        .unwrap();
+    }
+
+    fn verify_index(ra: &RocksDbAccessor) -> IndexVerificationMessage {
+        ra.verify_index(Request::new(VerifyIndexMessage {
+            index_reference: Some(index_reference()),
+            repair: false,
+        }))
+        .unwrap()
+        .into_inner()
+    }
+
+    #[test]
+    fn test_new_database_is_not_migrated() {
+        let ra = RocksDbAccessor::in_memory();
+        assert_eq!(ra.migrate_index_entries(), Ok(false));
+        create_database(&ra);
+        assert_eq!(ra.migrate_index_entries(), Ok(false));
+    }
+
+    #[test]
+    fn test_migrate_whole_array_entries() {
+        let ra = RocksDbAccessor::in_memory();
+        create_database(&ra);
+        put_legacy_entry(&ra);
+        assert!(!verify_index(&ra).consistent);
+
+        assert_eq!(ra.migrate_index_entries(), Ok(true));
+        assert_eq!(ra.migrate_index_entries(), Ok(false));
+
+        let verification = verify_index(&ra);
+        assert!(verification.consistent, "{:?}", verification);
+        assert_eq!(verification.entries_checked, 2);
+    }
+
+    #[test]
+    fn test_resume_after_migrated_table() {
+        let ra = RocksDbAccessor::in_memory();
+        create_database(&ra);
+        put_legacy_entry(&ra);
+        {
+            let guarded_db = ra.guarded_db();
+            let db = RocksDbAccessor::db_write_lock(&guarded_db).unwrap();
+            let mut server_stored = get_server_stored(&db).unwrap().unwrap();
+            server_stored.migrated_table = Some(("domain".to_owned(), "table".to_owned()));
+            put_server_stored(&db, &server_stored).unwrap();
+        }
+
+        assert_eq!(ra.migrate_index_entries(), Ok(true));
+
+        assert!(!verify_index(&ra).consistent);
+        let guarded_db = ra.guarded_db();
+        let db = RocksDbAccessor::db_read_lock(&guarded_db).unwrap();
+        let server_stored = get_server_stored(&db).unwrap().unwrap();
+        assert_eq!(server_stored.migrated_table, None);
+        assert_eq!(server_stored.index_entries, INDEX_ENTRY_VERSION);
+    }
+}
@@ -1,11 +1,18 @@
 //index_source.rs
-use super::rocks_trait::RocksTrait;
-use crate::db::entity::IndexValue;
+use super::rocks_trait::{collect_page, RocksTrait};
 use crate::db::entity::OndoKey;
-use crate::db::reference::requests::IndexIteratorRequests;
+use crate::db::entity::{IndexKey, IndexValue};
+use crate::db::reference::requests::{IndexEntryIterator, IndexIteratorRequests};
+use crate::db::reference::{Page, PageRequest};
 use crate::db::server::rocks_db_accessor::DbReadLockGuardWrapper;
 use crate::db::server::source_sink::ondo_serializer::OndoSerializer;
 use crate::db::DbResult;
+use crate::metrics;
+
+fn deserialize_scanned_value(bytes: &[u8]) -> DbResult<IndexValue> {
+    metrics::record_document_scanned("index");
+    OndoKey::ondo_deserialize(bytes)
+}
 
 // Implement IndexIteratorRequests for DbReadLockGuardWrapper
 impl<'a> IndexIteratorRequests<'a> for DbReadLockGuardWrapper<'a> {
@@ -15,18 +22,91 @@ impl<'a> IndexIteratorRequests<'a> for DbReadLockGuardWrapper<'a> {
         key_prefix: OndoKey,
     ) -> DbResult<Box<dyn Iterator<Item = DbResult<IndexValue>> + 'a>> {
         let serialized_key_prefix = key_prefix.ondo_serialize()?;
-        let raw_iterator = self
-            .guard
-            .get_records_in_cf_with_key_prefix_old(value_cf_name, serialized_key_prefix)?;
+        let raw_iterator = self.guard.get_records_in_cf_with_key_prefix(
+            value_cf_name,
+            serialized_key_prefix,
+            None,
+            None,
+        )?;
 
         let all_iterator = raw_iterator.map(|result| {
-            result.and_then(|(_, v)| OndoKey::ondo_deserialize(&v)) // Flatten the nested Result
+            result.and_then(|(_, v)| deserialize_scanned_value(&v)) // Flatten the nested Result
         });
 
         let ok_iterator = Box::new(all_iterator);
         Ok(ok_iterator)
     }
 
+    fn all_entries_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+    ) -> DbResult<IndexEntryIterator<'a>> {
+        let raw_iterator = self.guard.get_records_in_cf_with_key_prefix(
+            value_cf_name,
+            key_prefix.ondo_serialize()?,
+            None,
+            None,
+        )?;
+        let all_iterator = raw_iterator.map(|result| {
+            result.and_then(|(k, v)| {
+                Ok((
+                    OndoKey::ondo_deserialize(&k)?,
+                    deserialize_scanned_value(&v)?,
+                ))
+            })
+        });
+        Ok(Box::new(all_iterator))
+    }
+
+    fn values_page_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<IndexValue>> {
+        let serialized_key_prefix = key_prefix.ondo_serialize()?;
+        let serialized_start_key = page_request
+            .start_key
+            .as_ref()
+            .map(|start_key| start_key.ondo_serialize())
+            .transpose()?;
+        let raw_iterator = self.guard.get_records_in_cf_with_key_prefix(
+            value_cf_name,
+            serialized_key_prefix,
+            serialized_start_key,
+            page_request.page_size,
+        )?;
+        collect_page(raw_iterator, page_request.page_size, |_, value| {
+            deserialize_scanned_value(value)
+        })
+    }
+
+    fn entries_page_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<(IndexKey, IndexValue)>> {
+        let serialized_start_key = page_request
+            .start_key
+            .as_ref()
+            .map(|start_key| start_key.ondo_serialize())
+            .transpose()?;
+        let raw_iterator = self.guard.get_records_in_cf_with_key_prefix(
+            value_cf_name,
+            key_prefix.ondo_serialize()?,
+            serialized_start_key,
+            page_request.page_size,
+        )?;
+        collect_page(raw_iterator, page_request.page_size, |key, value| {
+            Ok((
+                OndoKey::ondo_deserialize(key)?,
+                deserialize_scanned_value(value)?,
+            ))
+        })
+    }
+
     fn all_values_with_key_range(
         &'a self,
         value_cf_name: &str,
@@ -35,17 +115,41 @@ impl<'a> IndexIteratorRequests<'a> for DbReadLockGuardWrapper<'a> {
     ) -> DbResult<Box<dyn Iterator<Item = DbResult<IndexValue>> + 'a>> {
         let serialized_start_key_prefix = start_key_prefix.ondo_serialize()?;
         let serialized_end_key_prefix = end_key_prefix.ondo_serialize()?;
-        let raw_iterator = self.guard.get_records_in_cf_with_key_range_old(
+        let raw_iterator = self.guard.get_records_in_cf_with_key_range(
             value_cf_name,
             serialized_start_key_prefix,
             serialized_end_key_prefix,
+            None,
         )?;
 
         let all_iterator = raw_iterator.map(|result| {
-            result.and_then(|(_, v)| OndoKey::ondo_deserialize(&v)) // Flatten the nested Result
+            result.and_then(|(_, v)| deserialize_scanned_value(&v)) // Flatten the nested Result
         });
 
         let ok_iterator = Box::new(all_iterator);
         Ok(ok_iterator)
     }
+
+    fn values_page_with_key_range(
+        &'a self,
+        value_cf_name: &str,
+        start_key_prefix: OndoKey,
+        end_key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<IndexValue>> {
+        // The next page continues the range from where the previous page stopped.
+        let mut serialized_start_key = start_key_prefix.ondo_serialize()?;
+        if let Some(start_key) = page_request.start_key.as_ref() {
+            serialized_start_key = serialized_start_key.max(start_key.ondo_serialize()?);
+        }
+        let raw_iterator = self.guard.get_records_in_cf_with_key_range(
+            value_cf_name,
+            serialized_start_key,
+            end_key_prefix.ondo_serialize()?,
+            page_request.page_size,
+        )?;
+        collect_page(raw_iterator, page_request.page_size, |_, value| {
+            deserialize_scanned_value(value)
+        })
+    }
 }
@@ -1,25 +1,27 @@
 use super::ondo_serializer::OndoSerializer;
 use crate::db::entity::OndoKey;
 use crate::db::reference::IndexValueEffect;
-use crate::db::server::rocks_db_accessor::RocksDbAccessor;
 use crate::db::DbError;
+use rocksdb::{WriteBatch, DB};
 
-pub(super) fn apply_effect(ra: &RocksDbAccessor, effect: &IndexValueEffect) -> Result<(), DbError> {
-    let guarded_db = ra.guarded_db();
-    let db = RocksDbAccessor::db_read_lock(&guarded_db)?;
+pub(super) fn apply_effect(
+    db: &DB,
+    batch: &mut WriteBatch,
+    effect: &IndexValueEffect,
+) -> Result<(), DbError> {
     match effect {
         IndexValueEffect::Put(cf_name, key, index_value) => {
-            let ondo_key = OndoKey::ondo_serialize(&key)?;
-            let ondo_value = OndoKey::ondo_serialize(&index_value)?;
-            let cf = db.cf_handle(&cf_name).ok_or(DbError::CfNotFound)?;
-            db.put_cf(&cf, ondo_key, ondo_value)
-                .map_err(|err| DbError::RocksDbError(err))
+            let ondo_key = OndoKey::ondo_serialize(key)?;
+            let ondo_value = OndoKey::ondo_serialize(index_value)?;
+            let cf = db.cf_handle(cf_name).ok_or(DbError::CfNotFound)?;
+            batch.put_cf(&cf, ondo_key, ondo_value);
+            Ok(())
         }
         IndexValueEffect::Delete(cf_name, key) => {
-            let ondo_key = OndoKey::ondo_serialize(&key)?;
-            let cf = db.cf_handle(&cf_name).ok_or(DbError::CfNotFound)?;
-            db.delete_cf(&cf, ondo_key)
-                .map_err(|err| DbError::RocksDbError(err))
+            let ondo_key = OndoKey::ondo_serialize(key)?;
+            let cf = db.cf_handle(cf_name).ok_or(DbError::CfNotFound)?;
+            batch.delete_cf(&cf, ondo_key);
+            Ok(())
         }
     }
 }
@@ -1 +1,96 @@
+use super::rocks_trait::RocksTrait;
+use crate::db::entity::{IndexKey, IndexValue, OndoKey};
+use crate::db::reference::requests::IndexValueRequests;
+use crate::db::reference::IndexValueReference;
+use crate::db::server::rocks_db_accessor::{DbReadLockGuardWrapper, RocksDbAccessor};
+use crate::db::server::source_sink::ondo_serializer::OndoSerializer;
+use crate::db::DbError::{self, CfNotFound};
+use crate::db::DbResult;
 
+impl IndexValueRequests for RocksDbAccessor {
+    fn get_index_value_stored(
+        &self,
+        cf_name: &str,
+        key: &IndexValueReference,
+    ) -> DbResult<Option<IndexValue>> {
+        let guarded_db = self.guarded_db();
+        let db = RocksDbAccessor::db_read_lock(&guarded_db)?;
+        let cf = db.cf_handle(cf_name).ok_or(CfNotFound)?;
+        let answer = db
+            .get_cf(cf, key.key.ondo_serialize()?)
+            .map_err(DbError::RocksDbError)?;
+        answer
+            .map(|bytes| OndoKey::ondo_deserialize(&bytes))
+            .transpose()
+    }
+
+    fn index_values_with_key_prefix(
+        &self,
+        cf_name: &str,
+        key_prefix: &IndexKey,
+        limit: usize,
+    ) -> DbResult<Vec<(IndexKey, IndexValue)>> {
+        let guarded_db = self.guarded_db();
+        let db = RocksDbAccessor::db_read_lock(&guarded_db)?;
+        let raw_iterator = db.get_records_in_cf_with_key_prefix(
+            cf_name,
+            key_prefix.ondo_serialize()?,
+            None,
+            None,
+        )?;
+        raw_iterator
+            .take(limit)
+            .map(|result| {
+                result.and_then(|(k, v)| {
+                    Ok((
+                        OndoKey::ondo_deserialize(&k)?,
+                        OndoKey::ondo_deserialize(&v)?,
+                    ))
+                })
+            })
+            .collect()
+    }
+}
+
+// Reads through the lock that is already held, like the TableValueRequests of the wrapper.
+impl<'a> IndexValueRequests for DbReadLockGuardWrapper<'a> {
+    fn get_index_value_stored(
+        &self,
+        cf_name: &str,
+        key: &IndexValueReference,
+    ) -> DbResult<Option<IndexValue>> {
+        let cf = self.guard.cf_handle(cf_name).ok_or(CfNotFound)?;
+        let answer = self
+            .guard
+            .get_cf(cf, key.key.ondo_serialize()?)
+            .map_err(DbError::RocksDbError)?;
+        answer
+            .map(|bytes| OndoKey::ondo_deserialize(&bytes))
+            .transpose()
+    }
+
+    fn index_values_with_key_prefix(
+        &self,
+        cf_name: &str,
+        key_prefix: &IndexKey,
+        limit: usize,
+    ) -> DbResult<Vec<(IndexKey, IndexValue)>> {
+        let raw_iterator = self.guard.get_records_in_cf_with_key_prefix(
+            cf_name,
+            key_prefix.ondo_serialize()?,
+            None,
+            None,
+        )?;
+        raw_iterator
+            .take(limit)
+            .map(|result| {
+                result.and_then(|(k, v)| {
+                    Ok((
+                        OndoKey::ondo_deserialize(&k)?,
+                        OndoKey::ondo_deserialize(&v)?,
+                    ))
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,369 @@
+use super::effects_sink::clear_cf;
+use super::ondo_serializer::legacy_ondo_key::legacy_serialize_ondo_key;
+use super::ondo_serializer::OndoSerializer;
+use super::rocks_trait::RocksTrait;
+use crate::db::constants::{KEY_ENCODING_VERSION, MIGRATION_CHUNK_SIZE};
+use crate::db::entity::index::text::{stats_key, stats_value, TextIndexStats};
+use crate::db::entity::table_value::get_key_from_table_value;
+use crate::db::entity::{
+    DatabaseServerStored, DomainStored, Index, IndexKind, OndoKey, TableStored, TableValue,
+};
+use crate::db::reference::{CfNameMaker, DomainReference};
+use crate::db::server::rocks_db_accessor::RocksDbAccessor;
+use crate::db::{DbError, DbResult};
+use rocksdb::{WriteBatch, DB};
+
+impl RocksDbAccessor {
+    /// Rewrites the keys written by older versions with the current key encoding.
+    ///
+    /// The key encoding version is kept in the database server record. Databases
+    /// that are not initialized or are already up to date are left untouched.
+    /// Keys are rebuilt from the table names, the `_id` of the values and the
+    /// index definitions. The tables are migrated one by one in batches of
+    /// MIGRATION_CHUNK_SIZE, and each finished table is recorded, so an interrupted
+    /// migration resumes after the last finished table. The version is updated last.
+    ///
+    /// Returns true if the database was migrated.
+    pub fn migrate_key_encoding(&self) -> DbResult<bool> {
+        let guarded_db = self.guarded_db();
+        let db = RocksDbAccessor::db_write_lock(&guarded_db)?;
+
+        let mut server_stored = match get_server_stored(&db)? {
+            Some(stored) => stored,
+            None => return Ok(false),
+        };
+        if server_stored.key_encoding >= KEY_ENCODING_VERSION {
+            return Ok(false);
+        }
+
+        for (domain_reference, table_name) in tables_to_migrate(&db, &server_stored)? {
+            let mut batch = WriteBatch::default();
+            migrate_table_counter(&db, &mut batch, &domain_reference, &table_name)?;
+            let table_stored: Option<TableStored> = get_stored(
+                &db,
+                &CfNameMaker::for_table_meta(&domain_reference),
+                &table_name.ondo_serialize()?,
+            )?;
+            if let Some(table_stored) = table_stored {
+                migrate_table_values(&db, &mut batch, &table_stored)?;
+            }
+            db.write(batch).map_err(DbError::RocksDbError)?;
+            server_stored.migrated_table = Some((domain_reference.domain_name, table_name));
+            put_server_stored(&db, &server_stored)?;
+        }
+
+        server_stored.key_encoding = KEY_ENCODING_VERSION;
+        server_stored.migrated_table = None;
+        put_server_stored(&db, &server_stored)?;
+        Ok(true)
+    }
+}
+
+pub(super) fn get_stored<T: OndoSerializer<T>>(db: &DB, cf_name: &str, key: &[u8]) -> DbResult<Option<T>> {
+    let cf = db.cf_handle(cf_name).ok_or(DbError::CfNotFound)?;
+    let answer = db.get_cf(&cf, key).map_err(DbError::RocksDbError)?;
+    answer.map(|bytes| T::ondo_deserialize(&bytes)).transpose()
+}
+
+// The database server record, or None if the database is not initialized.
+pub(super) fn get_server_stored(db: &DB) -> DbResult<Option<DatabaseServerStored>> {
+    match get_stored(db, &CfNameMaker::for_server_meta(), &().ondo_serialize()?) {
+        Err(DbError::CfNotFound) => Ok(None),
+        answer => answer,
+    }
+}
+
+pub(super) fn put_server_stored(db: &DB, server_stored: &DatabaseServerStored) -> DbResult<()> {
+    let server_cf = db
+        .cf_handle(&CfNameMaker::for_server_meta())
+        .ok_or(DbError::CfNotFound)?;
+    db.put_cf(
+        &server_cf,
+        ().ondo_serialize()?,
+        server_stored.ondo_serialize()?,
+    )
+    .map_err(DbError::RocksDbError)
+}
+
+// The tables in the order of their domain and table names, after the last table
+// an interrupted migration finished.
+pub(super) fn tables_to_migrate(
+    db: &DB,
+    server_stored: &DatabaseServerStored,
+) -> DbResult<Vec<(DomainReference, String)>> {
+    let mut domain_names = server_stored.domains.keys().collect::<Vec<_>>();
+    domain_names.sort();
+    let mut tables = vec![];
+    for domain_name in domain_names {
+        let domain_stored: Option<DomainStored> = get_stored(
+            db,
+            &CfNameMaker::for_domain_meta(),
+            &domain_name.ondo_serialize()?,
+        )?;
+        let mut table_names = domain_stored
+            .map(|stored| stored.tables.into_keys().collect::<Vec<_>>())
+            .unwrap_or_default();
+        table_names.sort();
+        for table_name in table_names {
+            let migrated = server_stored.migrated_table.as_ref().map_or(
+                false,
+                |(migrated_domain_name, migrated_table_name)| {
+                    (domain_name, &table_name) <= (migrated_domain_name, migrated_table_name)
+                },
+            );
+            if !migrated {
+                tables.push((DomainReference::build(domain_name), table_name));
+            }
+        }
+    }
+    Ok(tables)
+}
+
+// Writes the batch once it is full, so a migration keeps at most one chunk in memory.
+pub(super) fn write_full_batch(db: &DB, batch: &mut WriteBatch) -> DbResult<()> {
+    if batch.len() >= MIGRATION_CHUNK_SIZE {
+        db.write(std::mem::take(batch))
+            .map_err(DbError::RocksDbError)?;
+    }
+    Ok(())
+}
+
+// Replaces the entries of the index with the ones of the values of its table.
+pub(super) fn rebuild_index(
+    db: &DB,
+    batch: &mut WriteBatch,
+    index: &Index,
+    values_cf_name: &str,
+) -> DbResult<()> {
+    let index_cf_name = CfNameMaker::for_index_values(&index.reference);
+    let index_cf = db.cf_handle(&index_cf_name).ok_or(DbError::CfNotFound)?;
+    clear_cf(db, batch, &index_cf_name)?;
+    let mut stats = TextIndexStats::default();
+    for record in db.get_records_in_cf(values_cf_name)? {
+        let value = TableValue::ondo_deserialize(&record?.1)?;
+        for key_value in index.key_values_of(&value)? {
+            batch.put_cf(
+                &index_cf,
+                key_value.key.ondo_serialize()?,
+                key_value.value.ondo_serialize()?,
+            );
+        }
+        if let Some(length) = index.text_length_of(&value) {
+            stats.add(length);
+        }
+        write_full_batch(db, batch)?;
+    }
+    if matches!(index.kind, IndexKind::Text(_)) {
+        batch.put_cf(
+            &index_cf,
+            stats_key().ondo_serialize()?,
+            stats_value(&stats).ondo_serialize()?,
+        );
+    }
+    Ok(())
+}
+
+// Table counters are keyed by the table name.
+fn migrate_table_counter(
+    db: &DB,
+    batch: &mut WriteBatch,
+    domain_reference: &DomainReference,
+    table_name: &str,
+) -> DbResult<()> {
+    let cf = db
+        .cf_handle(&CfNameMaker::for_table_counters(domain_reference))
+        .ok_or(DbError::CfNotFound)?;
+    let counter_key: OndoKey = table_name.into();
+    let legacy_key = legacy_serialize_ondo_key(&counter_key)?;
+    let counter = db.get_cf(&cf, &legacy_key).map_err(DbError::RocksDbError)?;
+    if let Some(counter) = counter {
+        batch.delete_cf(&cf, legacy_key);
+        batch.put_cf(&cf, counter_key.ondo_serialize()?, counter);
+    }
+    Ok(())
+}
+
+// Values are keyed by their `_id`, and the indexes are rebuilt from the values.
+// A value that already has its new key is left as it is.
+fn migrate_table_values(
+    db: &DB,
+    batch: &mut WriteBatch,
+    table_stored: &TableStored,
+) -> DbResult<()> {
+    let values_cf_name = CfNameMaker::for_table_values(&table_stored.table.reference);
+    let values_cf = db.cf_handle(&values_cf_name).ok_or(DbError::CfNotFound)?;
+    // The iterator reads the values as they were when it was created.
+    for record in db.get_records_in_cf(&values_cf_name)? {
+        let (old_key, bytes) = record?;
+        let value = TableValue::ondo_deserialize(&bytes)?;
+        let key = get_key_from_table_value(&value)?.ondo_serialize()?;
+        if key != old_key {
+            batch.delete_cf(&values_cf, old_key);
+            batch.put_cf(&values_cf, key, bytes);
+            write_full_batch(db, batch)?;
+        }
+    }
+
+    for index in table_stored.indexes.values() {
+        rebuild_index(db, batch, index, &values_cf_name)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::reference::requests::{
+        ColumnValueRequests, IndexIteratorRequests, TableValueRequests,
+    };
+    use crate::db::reference::{IndexReference, TableReference, TableValueReference};
+    use crate::db::server::rocks_db_accessor::DbReadLockGuardWrapper;
+    use crate::db::server::test_fixture;
+    use serde_json::json;
+
+    fn create_database(ra: &RocksDbAccessor) {
+        let table_reference = test_fixture::table_reference("domain", "table");
+        test_fixture::create_database(ra, &table_reference);
+        let index_reference = test_fixture::index_reference(&table_reference, "index");
+        test_fixture::create_index(ra, &index_reference, &["age"], false);
+    }
+
+    // Writes the records the way older versions did.
+    fn put_legacy_records(ra: &RocksDbAccessor) {
+        let guarded_db = ra.guarded_db();
+        let db = RocksDbAccessor::db_write_lock(&guarded_db).unwrap();
+        let table_reference = TableReference::build("domain", "table");
+        let index_reference = IndexReference::build("domain", "table", "index");
+        let values_cf = db
+            .cf_handle(&CfNameMaker::for_table_values(&table_reference))
+            .unwrap();
+        let index_cf = db
+            .cf_handle(&CfNameMaker::for_index_values(&index_reference))
+            .unwrap();
+        let id: OndoKey = 10u64.into();
+        let index_key = OndoKey {
+            values: vec![json!(30), json!(10)],
+        };
+        db.put_cf(
+            &values_cf,
+            legacy_serialize_ondo_key(&id).unwrap(),
+            json!({"_id": id, "age": 30}).ondo_serialize().unwrap(),
+        )
+        .unwrap();
+        db.put_cf(
+            &index_cf,
+            legacy_serialize_ondo_key(&index_key).unwrap(),
+            legacy_serialize_ondo_key(&id).unwrap(),
+        )
+        .unwrap();
+        let counters_cf = db
+            .cf_handle(&CfNameMaker::for_table_counters(&DomainReference::build(
+                "domain",
+            )))
+            .unwrap();
+        let counter_key: OndoKey = "table".into();
+        db.put_cf(
+            &counters_cf,
+            legacy_serialize_ondo_key(&counter_key).unwrap(),
+            json!(10).ondo_serialize().unwrap(),
+        )
+        .unwrap();
+
+        let server_cf = db.cf_handle(&CfNameMaker::for_server_meta()).unwrap();
+        let server_key = ().ondo_serialize().unwrap();
+        let mut server_stored: DatabaseServerStored =
+            get_stored(&db, &CfNameMaker::for_server_meta(), &server_key)
+                .unwrap()
+                .unwrap();
+        server_stored.key_encoding = 0;
+        db.put_cf(
+            &server_cf,
+            server_key,
+            server_stored.ondo_serialize().unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_new_database_is_not_migrated() {
+        let ra = RocksDbAccessor::in_memory();
+        assert_eq!(ra.migrate_key_encoding(), Ok(false));
+        create_database(&ra);
+        assert_eq!(ra.migrate_key_encoding(), Ok(false));
+    }
+
+    #[test]
+    fn test_migrate_legacy_keys() {
+        let ra = RocksDbAccessor::in_memory();
+        create_database(&ra);
+        put_legacy_records(&ra);
+
+        assert_eq!(ra.migrate_key_encoding(), Ok(true));
+        assert_eq!(ra.migrate_key_encoding(), Ok(false));
+
+        let table_value_reference = TableValueReference {
+            table_reference: TableReference::build("domain", "table"),
+            id: 10u64.into(),
+        };
+        assert_eq!(
+            ra.get_table_value(
+                &CfNameMaker::for_table_values(&table_value_reference.table_reference),
+                &table_value_reference,
+            ),
+            Ok(Some(json!({"_id": {"values": [10]}, "age": 30})))
+        );
+        let guarded_db = ra.guarded_db();
+        let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).unwrap();
+        let index_reference = IndexReference::build("domain", "table", "index");
+        let index_values = db_wrapper
+            .all_values_with_key_prefix(
+                &CfNameMaker::for_index_values(&index_reference),
+                OndoKey {
+                    values: vec![json!(30)],
+                },
+            )
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(index_values, vec![Ok(10u64.into())]);
+        drop(db_wrapper);
+
+        let counters_cf_name = CfNameMaker::for_table_counters(&DomainReference::build("domain"));
+        assert_eq!(
+            ra.get_column_value(&counters_cf_name, &"table".into()),
+            Ok(Some(json!(10)))
+        );
+    }
+
+    #[test]
+    fn test_resume_after_migrated_table() {
+        let ra = RocksDbAccessor::in_memory();
+        create_database(&ra);
+        put_legacy_records(&ra);
+        {
+            let guarded_db = ra.guarded_db();
+            let db = RocksDbAccessor::db_write_lock(&guarded_db).unwrap();
+            let mut server_stored = get_server_stored(&db).unwrap().unwrap();
+            server_stored.migrated_table = Some(("domain".to_owned(), "table".to_owned()));
+            put_server_stored(&db, &server_stored).unwrap();
+        }
+
+        assert_eq!(ra.migrate_key_encoding(), Ok(true));
+
+        let table_value_reference = TableValueReference {
+            table_reference: TableReference::build("domain", "table"),
+            id: 10u64.into(),
+        };
+        assert_eq!(
+            ra.get_table_value(
+                &CfNameMaker::for_table_values(&table_value_reference.table_reference),
+                &table_value_reference,
+            ),
+            Ok(None)
+        );
+        let guarded_db = ra.guarded_db();
+        let db = RocksDbAccessor::db_read_lock(&guarded_db).unwrap();
+        let server_stored = get_server_stored(&db).unwrap().unwrap();
+        assert_eq!(server_stored.migrated_table, None);
+        assert_eq!(server_stored.key_encoding, KEY_ENCODING_VERSION);
+    }
+}
@@ -0,0 +1,247 @@
+//measured_requests.rs
+use crate::db::entity::{IndexKey, IndexValue, OndoKey, TableValue};
+use crate::db::reference::requests::{
+    IndexEntryIterator, IndexIteratorRequests, TableStoredIteratorRequests, TableValueRequests,
+};
+use crate::db::reference::{Page, PageRequest, TableValueReference};
+use crate::db::server::rocks_db_accessor::DbReadLockGuardWrapper;
+use crate::db::DbResult;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// What the reads of a request cost.
+#[derive(Debug, Default)]
+pub(crate) struct ExecutionStats {
+    /// The records read by the scans of the column families.
+    pub rows_scanned: Cell<u64>,
+    /// The values read by their _id.
+    pub values_fetched: Cell<u64>,
+    pub iteration_time: Cell<Duration>,
+    pub get_value_time: Cell<Duration>,
+}
+
+impl ExecutionStats {
+    fn add(cell: &Cell<Duration>, start: Instant) {
+        cell.set(cell.get() + start.elapsed());
+    }
+
+    fn time_iteration<T>(&self, read: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = read();
+        Self::add(&self.iteration_time, start);
+        result
+    }
+}
+
+// Counts the records of a scan and times the iteration.
+struct MeasuredIterator<'a, T> {
+    iterator: Box<dyn Iterator<Item = DbResult<T>> + 'a>,
+    stats: &'a ExecutionStats,
+}
+
+impl<'a, T> Iterator for MeasuredIterator<'a, T> {
+    type Item = DbResult<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.stats.time_iteration(|| self.iterator.next());
+        if item.is_some() {
+            let rows_scanned = &self.stats.rows_scanned;
+            rows_scanned.set(rows_scanned.get() + 1);
+        }
+        item
+    }
+}
+
+/// Reads through the read lock like DbReadLockGuardWrapper, and measures the reads.
+pub(crate) struct MeasuredRequests<'a> {
+    db_wrapper: &'a DbReadLockGuardWrapper<'a>,
+    pub(crate) stats: ExecutionStats,
+}
+
+impl<'a> MeasuredRequests<'a> {
+    pub(crate) fn new(db_wrapper: &'a DbReadLockGuardWrapper<'a>) -> Self {
+        MeasuredRequests {
+            db_wrapper,
+            stats: ExecutionStats::default(),
+        }
+    }
+
+    fn measure<T: 'a>(
+        &'a self,
+        iterator: DbResult<Box<dyn Iterator<Item = DbResult<T>> + 'a>>,
+    ) -> DbResult<Box<dyn Iterator<Item = DbResult<T>> + 'a>> {
+        let iterator = MeasuredIterator {
+            iterator: iterator?,
+            stats: &self.stats,
+        };
+        Ok(Box::new(iterator))
+    }
+
+    fn measure_page<T>(&self, read: impl FnOnce() -> DbResult<Page<T>>) -> DbResult<Page<T>> {
+        let page = self.stats.time_iteration(read)?;
+        let rows_scanned = &self.stats.rows_scanned;
+        rows_scanned.set(rows_scanned.get() + page.values.len() as u64);
+        Ok(page)
+    }
+}
+
+impl<'a> TableValueRequests for MeasuredRequests<'a> {
+    fn get_table_value(
+        &self,
+        cf_name: &str,
+        key: &TableValueReference,
+    ) -> DbResult<Option<TableValue>> {
+        let start = Instant::now();
+        let value = self.db_wrapper.get_table_value(cf_name, key);
+        ExecutionStats::add(&self.stats.get_value_time, start);
+        let values_fetched = &self.stats.values_fetched;
+        values_fetched.set(values_fetched.get() + 1);
+        value
+    }
+}
+
+impl<'a> IndexIteratorRequests<'a> for MeasuredRequests<'a> {
+    fn all_values_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+    ) -> DbResult<Box<dyn Iterator<Item = DbResult<IndexValue>> + 'a>> {
+        self.measure(self.stats.time_iteration(|| {
+            IndexIteratorRequests::all_values_with_key_prefix(
+                self.db_wrapper,
+                value_cf_name,
+                key_prefix,
+            )
+        }))
+    }
+
+    fn all_entries_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+    ) -> DbResult<IndexEntryIterator<'a>> {
+        self.measure(self.stats.time_iteration(|| {
+            self.db_wrapper
+                .all_entries_with_key_prefix(value_cf_name, key_prefix)
+        }))
+    }
+
+    fn values_page_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<IndexValue>> {
+        self.measure_page(|| {
+            IndexIteratorRequests::values_page_with_key_prefix(
+                self.db_wrapper,
+                value_cf_name,
+                key_prefix,
+                page_request,
+            )
+        })
+    }
+
+    fn entries_page_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<(IndexKey, IndexValue)>> {
+        self.measure_page(|| {
+            self.db_wrapper
+                .entries_page_with_key_prefix(value_cf_name, key_prefix, page_request)
+        })
+    }
+
+    fn all_values_with_key_range(
+        &'a self,
+        value_cf_name: &str,
+        start_key_prefix: OndoKey,
+        end_key_prefix: OndoKey,
+    ) -> DbResult<Box<dyn Iterator<Item = DbResult<IndexValue>> + 'a>> {
+        self.measure(self.stats.time_iteration(|| {
+            IndexIteratorRequests::all_values_with_key_range(
+                self.db_wrapper,
+                value_cf_name,
+                start_key_prefix,
+                end_key_prefix,
+            )
+        }))
+    }
+
+    fn values_page_with_key_range(
+        &'a self,
+        value_cf_name: &str,
+        start_key_prefix: OndoKey,
+        end_key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<IndexValue>> {
+        self.measure_page(|| {
+            self.db_wrapper.values_page_with_key_range(
+                value_cf_name,
+                start_key_prefix,
+                end_key_prefix,
+                page_request,
+            )
+        })
+    }
+}
+
+impl<'a> TableStoredIteratorRequests<'a> for MeasuredRequests<'a> {
+    fn all_values(
+        &'a self,
+        value_cf_name: &str,
+    ) -> DbResult<Box<dyn Iterator<Item = DbResult<TableValue>> + 'a>> {
+        self.measure(
+            self.stats
+                .time_iteration(|| self.db_wrapper.all_values(value_cf_name)),
+        )
+    }
+
+    fn all_values_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+    ) -> DbResult<Box<dyn Iterator<Item = DbResult<TableValue>> + 'a>> {
+        self.measure(self.stats.time_iteration(|| {
+            TableStoredIteratorRequests::all_values_with_key_prefix(
+                self.db_wrapper,
+                value_cf_name,
+                key_prefix,
+            )
+        }))
+    }
+
+    fn values_page_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<TableValue>> {
+        self.measure_page(|| {
+            TableStoredIteratorRequests::values_page_with_key_prefix(
+                self.db_wrapper,
+                value_cf_name,
+                key_prefix,
+                page_request,
+            )
+        })
+    }
+
+    fn all_values_with_key_range(
+        &'a self,
+        value_cf_name: &str,
+        start_key: OndoKey,
+        end_key: OndoKey,
+    ) -> DbResult<Box<dyn Iterator<Item = DbResult<TableValue>> + 'a>> {
+        self.measure(self.stats.time_iteration(|| {
+            TableStoredIteratorRequests::all_values_with_key_range(
+                self.db_wrapper,
+                value_cf_name,
+                start_key,
+                end_key,
+            )
+        }))
+    }
+}
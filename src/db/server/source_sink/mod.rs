@@ -1,3 +1,4 @@
+pub(super) mod cf_name_migration;
 pub(super) mod column_value_sink;
 pub(super) mod column_value_source;
 pub(super) mod database_server_sink;
@@ -6,9 +7,12 @@ pub(super) mod domain_sink;
 pub(super) mod domain_source;
 pub(super) mod effects_sink;
 pub(super) use effects_sink::EffectsSink;
+pub(super) mod index_entry_migration;
 pub(super) mod index_source;
 pub(super) mod index_value_sink;
 pub(super) mod index_value_source;
+pub(super) mod key_encoding_migration;
+pub(super) mod measured_requests;
 pub(super) mod table_sink;
 pub(super) mod table_source;
 pub(super) mod table_value_sink;
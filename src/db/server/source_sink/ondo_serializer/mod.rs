@@ -5,6 +5,7 @@ pub(super) mod database_server;
 pub(super) mod domain;
 pub(super) mod index;
 pub(super) mod index_value;
+pub(super) mod legacy_ondo_key;
 pub(super) mod ondo_key;
 pub(super) mod serde_value;
 pub(super) mod string;
@@ -1,165 +1,359 @@
 use super::OndoSerializer;
-use crate::db::constants::BINARY_KEY_DELIMITER;
 use crate::db::entity::OndoKey;
-use crate::db::DbResult;
-use rmp_serde::{from_slice, to_vec};
+use crate::db::{DbError, DbResult};
+use serde_json::{Map, Number, Value};
 
 impl OndoSerializer<OndoKey> for OndoKey {
     fn ondo_serialize(&self) -> DbResult<Vec<u8>> {
-        let local = self.clone();
-        let mut serialized_fields: Vec<Vec<u8>> = Vec::new();
-        for field in local.values {
-            let answer = to_vec(&field).expect("Failed to serialize field");
-            serialized_fields.push(answer)
+        let mut key = Vec::new();
+        for field in self.values.iter() {
+            encode_value(field, &mut key);
         }
-        Ok(get_binary_key(serialized_fields))
+        Ok(key)
     }
 
     fn ondo_deserialize(bytes: &[u8]) -> DbResult<OndoKey> {
-        let serialized_fields = get_fields_from_key(bytes);
-        let mut fields: Vec<serde_json::Value> = Vec::new();
-        for serialized_field in serialized_fields {
-            let field = from_slice::<serde_json::Value>(&serialized_field)
-                .expect("Failed to deserialize field");
-            fields.push(field);
+        let mut decoder = Decoder { bytes, position: 0 };
+        let mut fields: Vec<Value> = Vec::new();
+        while !decoder.is_at_end() {
+            fields.push(decoder.decode_value()?);
         }
         let key = OndoKey { values: fields };
         Ok(key)
     }
 }
 
-// We use a vector of binary keys on a key-value store, we need a separator for partial key searches.
-// We convert the vector of keys to 7 bit and use the unused bit as a separator.
-//    convert_to_7_bit: Converts a field to 7-bit representation.
-//    get_binary_key: Creates a binary key from an array of byte fields.
-//    convert_from_7_bit: Converts a 7-bit representation back to the original field.
-//    get_fields_from_key: Extracts the original fields from a binary key.
+// Keys are compared byte by byte by RocksDB, so the encoding preserves the order of the values.
+// Every value starts with a type tag, so values of different types sort as
+//    null < false < true < numbers < strings < arrays < objects
+// Every value is self delimiting, so the encoding of a key prefix is a byte prefix of the key.
+//    Numbers: the 8 bytes of an order preserving f64, followed by the exact number.
+//             Integers are rounded down to the f64, so ties are broken by the exact value.
+//    Strings: 0x00 bytes are escaped as 0x00 0xFF and the string is terminated by 0x00.
+//    Arrays: the encoded elements terminated by 0x00.
+//    Objects: the encoded keys and values, sorted by key, terminated by 0x00.
+
+const END: u8 = 0x00;
+const ESCAPE: u8 = 0xFF;
+const NULL_TAG: u8 = 0x10;
+const FALSE_TAG: u8 = 0x20;
+const TRUE_TAG: u8 = 0x21;
+const NUMBER_TAG: u8 = 0x30;
+const STRING_TAG: u8 = 0x40;
+const ARRAY_TAG: u8 = 0x50;
+const OBJECT_TAG: u8 = 0x60;
+
+const FLOAT_KIND: u8 = 0x00;
+const NEGATIVE_INT_KIND: u8 = 0x01;
+const POSITIVE_INT_KIND: u8 = 0x02;
 
-fn get_binary_key(fields: Vec<Vec<u8>>) -> Vec<u8> {
-    let mut key = Vec::new();
+const SIGN_BIT: u64 = 1 << 63;
 
-    for field in fields {
-        let converted_field = convert_to_7_bit(&field);
-        key.extend_from_slice(&converted_field);
-        key.push(BINARY_KEY_DELIMITER);
+fn encode_value(value: &Value, key: &mut Vec<u8>) {
+    match value {
+        Value::Null => key.push(NULL_TAG),
+        Value::Bool(false) => key.push(FALSE_TAG),
+        Value::Bool(true) => key.push(TRUE_TAG),
+        Value::Number(number) => {
+            key.push(NUMBER_TAG);
+            encode_number(number, key);
+        }
+        Value::String(string) => {
+            key.push(STRING_TAG);
+            encode_string(string, key);
+        }
+        Value::Array(array) => {
+            key.push(ARRAY_TAG);
+            for item in array {
+                encode_value(item, key);
+            }
+            key.push(END);
+        }
+        Value::Object(object) => {
+            key.push(OBJECT_TAG);
+            let mut entries = object.iter().collect::<Vec<_>>();
+            entries.sort_by_key(|(name, _)| *name);
+            for (name, item) in entries {
+                key.push(STRING_TAG);
+                encode_string(name, key);
+                encode_value(item, key);
+            }
+            key.push(END);
+        }
     }
-    key
 }
 
-fn get_fields_from_key(key: &[u8]) -> Vec<Vec<u8>> {
-    let mut fields = Vec::new();
-    let mut start = 0;
+fn encode_number(number: &Number, key: &mut Vec<u8>) {
+    if let Some(u) = number.as_u64() {
+        key.extend_from_slice(&ordered_f64_bytes(floor_to_f64(u as i128)));
+        key.push(POSITIVE_INT_KIND);
+        key.extend_from_slice(&u.to_be_bytes());
+    } else if let Some(i) = number.as_i64() {
+        key.extend_from_slice(&ordered_f64_bytes(floor_to_f64(i as i128)));
+        key.push(NEGATIVE_INT_KIND);
+        key.extend_from_slice(&((i as u64) ^ SIGN_BIT).to_be_bytes());
+    } else {
+        let f = number.as_f64().unwrap_or_default();
+        key.extend_from_slice(&ordered_f64_bytes(f));
+        key.push(FLOAT_KIND);
+    }
+}
 
-    for (i, byte) in key.iter().enumerate() {
-        if *byte == BINARY_KEY_DELIMITER {
-            let field = convert_from_7_bit(&key[start..i]);
-            fields.push(field);
-            start = i + 1;
+fn encode_string(string: &str, key: &mut Vec<u8>) {
+    for byte in string.as_bytes() {
+        key.push(*byte);
+        if *byte == END {
+            key.push(ESCAPE);
         }
     }
+    key.push(END);
+}
+
+// The largest f64 that is not greater than the integer.
+fn floor_to_f64(i: i128) -> f64 {
+    let f = i as f64;
+    if f as i128 <= i {
+        f
+    } else if f > 0.0 {
+        f64::from_bits(f.to_bits() - 1)
+    } else {
+        f64::from_bits(f.to_bits() + 1)
+    }
+}
+
+fn ordered_f64_bytes(f: f64) -> [u8; 8] {
+    // -0.0 and 0.0 are the same key
+    let bits = if f == 0.0 { 0 } else { f.to_bits() };
+    let ordered = if bits & SIGN_BIT == 0 {
+        bits ^ SIGN_BIT
+    } else {
+        !bits
+    };
+    ordered.to_be_bytes()
+}
 
-    fields
+fn f64_from_ordered_bytes(bytes: [u8; 8]) -> f64 {
+    let ordered = u64::from_be_bytes(bytes);
+    let bits = if ordered & SIGN_BIT != 0 {
+        ordered ^ SIGN_BIT
+    } else {
+        !ordered
+    };
+    f64::from_bits(bits)
 }
 
-fn convert_to_7_bit(field: &[u8]) -> Vec<u8> {
-    let mut result = field.to_vec();
-    result.push(0);
-    let mut carry = 0;
-    for i in 0..(result.len()) {
-        for j in i..(result.len()) {
-            let byte = result[j];
-            let shifted_byte = (byte << 1) | carry;
-            carry = byte >> 7;
-            result[j] = shifted_byte;
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn is_at_end(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn error(&self, message: &str) -> DbError {
+        DbError::SerializationError(format!(
+            "Invalid key at byte {}: {}",
+            self.position, message
+        ))
+    }
+
+    fn next_byte(&mut self) -> DbResult<u8> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or_else(|| self.error("unexpected end of key"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn peek_byte(&self) -> DbResult<u8> {
+        self.bytes
+            .get(self.position)
+            .copied()
+            .ok_or_else(|| self.error("unexpected end of key"))
+    }
+
+    fn next_8_bytes(&mut self) -> DbResult<[u8; 8]> {
+        let end = self.position + 8;
+        let slice = self
+            .bytes
+            .get(self.position..end)
+            .ok_or_else(|| self.error("unexpected end of number"))?;
+        self.position = end;
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(slice);
+        Ok(bytes)
+    }
+
+    fn decode_value(&mut self) -> DbResult<Value> {
+        let tag = self.next_byte()?;
+        match tag {
+            NULL_TAG => Ok(Value::Null),
+            FALSE_TAG => Ok(Value::Bool(false)),
+            TRUE_TAG => Ok(Value::Bool(true)),
+            NUMBER_TAG => self.decode_number(),
+            STRING_TAG => self.decode_string().map(Value::String),
+            ARRAY_TAG => {
+                let mut array = Vec::new();
+                while self.peek_byte()? != END {
+                    array.push(self.decode_value()?);
+                }
+                self.position += 1;
+                Ok(Value::Array(array))
+            }
+            OBJECT_TAG => {
+                let mut object = Map::new();
+                while self.peek_byte()? != END {
+                    if self.next_byte()? != STRING_TAG {
+                        return Err(self.error("object key is not a string"));
+                    }
+                    let name = self.decode_string()?;
+                    let item = self.decode_value()?;
+                    object.insert(name, item);
+                }
+                self.position += 1;
+                Ok(Value::Object(object))
+            }
+            _ => Err(self.error("unknown type tag")),
         }
     }
-    result
-}
 
-fn convert_from_7_bit(encoded: &[u8]) -> Vec<u8> {
-    if encoded.is_empty() {
-        return Vec::new();
-    }
-    if encoded.len() == 1 {
-        return encoded.to_vec();
-    }
-    let mut result = encoded.to_vec();
-    for i in (0..result.len()).rev() {
-        let mut prev_carry: u8 = 0;
-        for j in (i..result.len()).rev() {
-            let carry = prev_carry;
-            let shifted_byte = result[j];
-            let byte = (shifted_byte >> 1) | carry;
-            let carry = (shifted_byte & 1) << 7;
-            let _ = std::mem::replace(&mut prev_carry, carry);
-            result[j] = byte;
+    fn decode_number(&mut self) -> DbResult<Value> {
+        let ordered_bytes = self.next_8_bytes()?;
+        match self.next_byte()? {
+            FLOAT_KIND => Number::from_f64(f64_from_ordered_bytes(ordered_bytes))
+                .map(Value::Number)
+                .ok_or_else(|| self.error("number is not finite")),
+            NEGATIVE_INT_KIND => {
+                let i = (u64::from_be_bytes(self.next_8_bytes()?) ^ SIGN_BIT) as i64;
+                Ok(Value::Number(i.into()))
+            }
+            POSITIVE_INT_KIND => {
+                let u = u64::from_be_bytes(self.next_8_bytes()?);
+                Ok(Value::Number(u.into()))
+            }
+            _ => Err(self.error("unknown number kind")),
         }
     }
-    result.pop();
-    result
+
+    fn decode_string(&mut self) -> DbResult<String> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = self.next_byte()?;
+            if byte != END {
+                bytes.push(byte);
+            } else if self.bytes.get(self.position) == Some(&ESCAPE) {
+                bytes.push(END);
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        String::from_utf8(bytes).map_err(|e| DbError::SerializationError(e.to_string()))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
 
-    #[test]
-    fn test_convert_to_7_bit_and_convert_from_7_bit_0() {
-        let input: Vec<u8> = vec![0x0];
-        let encoded = convert_to_7_bit(&input);
-        let decoded = convert_from_7_bit(&encoded);
-        assert_eq!(input, decoded);
+    fn encode(values: Vec<Value>) -> Vec<u8> {
+        OndoKey { values }.ondo_serialize().unwrap()
     }
 
-    #[test]
-    fn test_convert_to_7_bit_and_convert_from_7_bit_1() {
-        let input: Vec<u8> = vec![0x1];
-        let encoded = convert_to_7_bit(&input);
-        let decoded = convert_from_7_bit(&encoded);
-        assert_eq!(input, decoded);
+    fn assert_round_trip(values: Vec<Value>) {
+        let key = OndoKey { values };
+        let bytes = key.ondo_serialize().unwrap();
+        assert_eq!(OndoKey::ondo_deserialize(&bytes), Ok(key));
+    }
+
+    fn assert_sorted(values: Vec<Value>) {
+        let encoded = values
+            .iter()
+            .map(|value| encode(vec![value.clone()]))
+            .collect::<Vec<_>>();
+        for (i, pair) in encoded.windows(2).enumerate() {
+            assert!(
+                pair[0] < pair[1],
+                "{} should sort before {}",
+                values[i],
+                values[i + 1]
+            );
+        }
     }
 
     #[test]
-    fn test_convert_to_7_bit_and_convert_from_7_bit_ff() {
-        let input: Vec<u8> = vec![0xFF];
-        let encoded = convert_to_7_bit(&input);
-        let decoded = convert_from_7_bit(&encoded);
-        assert_eq!(input, decoded);
+    fn test_round_trip() {
+        assert_round_trip(vec![]);
+        assert_round_trip(vec![json!(null), json!(true), json!(false)]);
+        assert_round_trip(vec![json!(0), json!(-1), json!(u64::MAX), json!(i64::MIN)]);
+        assert_round_trip(vec![json!(1.5), json!(-2.25), json!(1e300)]);
+        assert_round_trip(vec![json!(""), json!("New York"), json!("a\u{0}b")]);
+        assert_round_trip(vec![json!([1, "a", [null, []]]), json!([])]);
+        assert_round_trip(vec![json!({"": 1, "b": {"c": [true]}, "\u{0}": {}})]);
     }
+
     #[test]
-    fn test_convert_to_7_bit_and_convert_from_7_bit_ffff() {
-        let input: Vec<u8> = vec![0xFF, 0xFF];
-        let encoded = convert_to_7_bit(&input);
-        let decoded = convert_from_7_bit(&encoded);
-        assert_eq!(input, decoded);
+    fn test_numbers_sort_by_value() {
+        assert_sorted(vec![
+            json!(-1e300),
+            json!(i64::MIN),
+            json!(-10),
+            json!(-9.5),
+            json!(-9),
+            json!(-0.5),
+            json!(0),
+            json!(0.5),
+            json!(9),
+            json!(9.5),
+            json!(10),
+            json!(9007199254740993u64),
+            json!(9007199254740996.0),
+            json!(u64::MAX),
+            json!(1e300),
+        ]);
     }
+
     #[test]
-    fn test_convert_to_7_bit_and_convert_from_7_bit_ffffff() {
-        let input: Vec<u8> = vec![0xFF, 0xFF, 0xFF];
-        let encoded = convert_to_7_bit(&input);
-        let decoded = convert_from_7_bit(&encoded);
-        assert_eq!(input, decoded);
+    fn test_types_sort_in_order() {
+        assert_sorted(vec![
+            json!(null),
+            json!(false),
+            json!(true),
+            json!(-1),
+            json!(1),
+            json!(""),
+            json!("a"),
+            json!("a\u{0}"),
+            json!("ab"),
+            json!("b"),
+            json!([]),
+            json!([1]),
+            json!([1, 2]),
+            json!([2]),
+            json!({}),
+            json!({"a": 1}),
+            json!({"a": 2}),
+            json!({"b": 1}),
+        ]);
     }
+
     #[test]
-    fn test_convert_to_7_bit_and_convert_from_7_bit() {
-        let input: Vec<u8> = vec![0x41, 0x6E, 0x79, 0x20, 0x64, 0x61, 0x74, 0x61];
-        let encoded = convert_to_7_bit(&input);
-        let decoded = convert_from_7_bit(&encoded);
-        assert_eq!(input, decoded);
+    fn test_prefix_is_byte_prefix() {
+        let prefix = encode(vec![json!("New York")]);
+        let key = encode(vec![json!("New York"), json!(30), json!(1)]);
+        let other = encode(vec![json!("New York City"), json!(30), json!(1)]);
+        assert!(key.starts_with(&prefix));
+        assert!(!other.starts_with(&prefix));
     }
 
     #[test]
-    fn test_binary_key_round_trip() {
-        let input: Vec<Vec<u8>> = vec![
-            vec![0x41, 0x6E, 0x79],
-            vec![0x20, 0x64, 0x61],
-            vec![0x74, 0x61],
-        ];
-
-        let binary_key = get_binary_key(input.clone());
-        let fields = get_fields_from_key(&binary_key);
-
-        let fields_as_slices: Vec<&[u8]> = fields.iter().map(AsRef::as_ref).collect();
-        assert_eq!(input, fields_as_slices);
+    fn test_invalid_key() {
+        assert!(OndoKey::ondo_deserialize(&[0x99]).is_err());
+        assert!(OndoKey::ondo_deserialize(&[STRING_TAG, b'a']).is_err());
+        assert!(OndoKey::ondo_deserialize(&[NUMBER_TAG, 0, 0]).is_err());
     }
 }
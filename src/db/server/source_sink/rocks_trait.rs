@@ -1,36 +1,29 @@
+use super::ondo_serializer::OndoSerializer;
 use crate::db::db_error::{DbError, DbResult};
+use crate::db::entity::OndoKey;
+use crate::db::reference::Page;
 use rocksdb::{Direction, IteratorMode, ReadOptions, DB};
 
 type ResultBinaryPair = DbResult<(Vec<u8>, Vec<u8>)>;
-type ResultBinaryPairIterator<'a> = DbResult<Box<dyn Iterator<Item = ResultBinaryPair> + 'a>>;
+type BinaryPairIterator<'a> = Box<dyn Iterator<Item = ResultBinaryPair> + 'a>;
+type ResultBinaryPairIterator<'a> = DbResult<BinaryPairIterator<'a>>;
 
 pub(super) trait RocksTrait<'a> {
     fn get_records_in_cf(&'a self, cf_name: &str) -> ResultBinaryPairIterator<'a>;
-    fn get_records_in_cf_with_key_prefix_old(
-        &'a self,
-        value_cf_name: &str,
-        key_prefix: Vec<u8>,
-    ) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>> + 'a>>;
     fn get_records_in_cf_with_key_prefix(
         &'a self,
         value_cf_name: &str,
         key_prefix: Vec<u8>,
         start_key: Option<Vec<u8>>,
         page_size: Option<usize>,
-    ) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>> + 'a>>;
-    fn get_records_in_cf_with_key_range_old(
-        &self,
-        cf_name: &str,
-        start_key: Vec<u8>,
-        end_key: Vec<u8>,
-    ) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>> + '_>>;
+    ) -> ResultBinaryPairIterator<'a>;
     fn get_records_in_cf_with_key_range(
         &self,
         cf_name: &str,
         start_key: Vec<u8>,
         end_key: Vec<u8>,
         page_size: Option<usize>,
-    ) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>> + '_>>;
+    ) -> ResultBinaryPairIterator<'_>;
 }
 
 impl<'a> RocksTrait<'a> for DB {
@@ -42,91 +35,77 @@ impl<'a> RocksTrait<'a> for DB {
         let new_iter = iter.map(|kv_result| {
             kv_result
                 .map(|(k, v)| (k.to_vec(), v.to_vec()))
-                .map_err(|err| DbError::RocksDbError(err))
+                .map_err(DbError::RocksDbError)
         });
 
         let boxed_new_iter = Box::new(new_iter);
         Ok(boxed_new_iter)
     }
-    fn get_records_in_cf_with_key_prefix_old(
-        &'a self,
-        value_cf_name: &str,
-        key_prefix: Vec<u8>,
-    ) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>> + 'a>> {
-        self.get_records_in_cf_with_key_prefix(value_cf_name, key_prefix, None, None)
-    }
     fn get_records_in_cf_with_key_prefix(
         &'a self,
         value_cf_name: &str,
         key_prefix: Vec<u8>,
         start_key: Option<Vec<u8>>,
         page_size: Option<usize>,
-    ) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>> + 'a>> {
+    ) -> ResultBinaryPairIterator<'a> {
         let cf_handle = self.cf_handle(value_cf_name).ok_or(DbError::CfNotFound)?;
         let mut read_options = ReadOptions::default();
         read_options.set_prefix_same_as_start(true);
 
-        let iterator_mode = if let Some(ref start_key) = start_key {
-            IteratorMode::From(start_key, Direction::Forward)
-        } else {
-            IteratorMode::From(&key_prefix, Direction::Forward)
+        // Keys are sorted, so the keys with the prefix are next to each other.
+        let iterator_mode = match start_key {
+            Some(ref start_key) if start_key > &key_prefix => {
+                IteratorMode::From(start_key, Direction::Forward)
+            }
+            _ => IteratorMode::From(&key_prefix, Direction::Forward),
         };
 
         let raw_iterator = self.iterator_cf_opt(cf_handle, read_options, iterator_mode);
-        let prefixed_iterator = raw_iterator.filter(move |result| {
+        let prefixed_iterator = raw_iterator.take_while(move |result| {
             result
                 .as_ref()
                 .map(|(key, _)| key.starts_with(&key_prefix)) // Use the owned key_prefix
-                .unwrap_or(false)
+                .unwrap_or(true)
         });
 
-        let iterator: Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>> + 'a> =
-            if let Some(page_size) = page_size {
-                Box::new(
-                    prefixed_iterator
-                        .map(|result| {
-                            result
-                                .map(|(k, v)| (k.into_vec(), v.into_vec()))
-                                .map_err(|err| DbError::RocksDbError(err))
-                        })
-                        .take(page_size + 1),
-                )
-            } else {
-                Box::new(prefixed_iterator.map(|result| {
-                    result
-                        .map(|(k, v)| (k.into_vec(), v.into_vec()))
-                        .map_err(|err| DbError::RocksDbError(err))
-                }))
-            };
+        let iterator: BinaryPairIterator<'a> = if let Some(page_size) = page_size {
+            Box::new(
+                prefixed_iterator
+                    .map(|result| {
+                        result
+                            .map(|(k, v)| (k.into_vec(), v.into_vec()))
+                            .map_err(DbError::RocksDbError)
+                    })
+                    .take(page_size + 1),
+            )
+        } else {
+            Box::new(prefixed_iterator.map(|result| {
+                result
+                    .map(|(k, v)| (k.into_vec(), v.into_vec()))
+                    .map_err(DbError::RocksDbError)
+            }))
+        };
 
         Ok(iterator)
     }
 
-    fn get_records_in_cf_with_key_range_old(
-        &self,
-        cf_name: &str,
-        start_key: Vec<u8>,
-        end_key: Vec<u8>,
-    ) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>> + '_>> {
-        self.get_records_in_cf_with_key_range(cf_name, start_key, end_key, None)
-    }
-
     fn get_records_in_cf_with_key_range(
         &self,
         cf_name: &str,
         start_key: Vec<u8>,
         end_key: Vec<u8>,
         page_size: Option<usize>,
-    ) -> DbResult<Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>> + '_>> {
+    ) -> ResultBinaryPairIterator<'_> {
         let cf_handle = self.cf_handle(cf_name).ok_or(DbError::CfNotFound)?;
         let iter = self.iterator_cf(
             cf_handle,
             IteratorMode::From(&start_key, Direction::Forward),
         );
 
+        // The end key is inclusive, and so are the keys that start with it.
         let range_iterator = iter
             .take_while(move |res| match res {
-                Ok((k, _)) => k.as_ref() <= end_key.as_slice(),
+                Ok((k, _)) => k.as_ref() <= end_key.as_slice() || k.starts_with(&end_key),
                 Err(_) => true,
             })
             .map(|result| {
@@ -135,13 +114,38 @@ impl<'a> RocksTrait<'a> for DB {
                     .map(|(k, v)| (k.to_vec(), v.to_vec()))
             });
 
-        let iterator: Box<dyn Iterator<Item = DbResult<(Vec<u8>, Vec<u8>)>> + '_> =
-            if let Some(page_size) = page_size {
-                Box::new(range_iterator.take(page_size + 1))
-            } else {
-                Box::new(range_iterator)
-            };
+        let iterator: BinaryPairIterator<'_> = if let Some(page_size) = page_size {
+            Box::new(range_iterator.take(page_size + 1))
+        } else {
+            Box::new(range_iterator)
+        };
 
         Ok(iterator)
     }
 }
+
+/// Collects the records of an iterator that was limited to page_size + 1 records,
+/// deserializing each from its key and value. The key of the extra record is where
+/// the next page starts.
+pub(super) fn collect_page<T>(
+    records: BinaryPairIterator<'_>,
+    page_size: Option<usize>,
+    deserialize: impl Fn(&[u8], &[u8]) -> DbResult<T>,
+) -> DbResult<Page<T>> {
+    let mut values = Vec::new();
+    for record in records {
+        let (key, value) = record?;
+        if Some(values.len()) == page_size {
+            let next_key = OndoKey::ondo_deserialize(&key)?;
+            return Ok(Page {
+                values,
+                next_key: Some(next_key),
+            });
+        }
+        values.push(deserialize(&key, &value)?);
+    }
+    Ok(Page {
+        values,
+        next_key: None,
+    })
+}
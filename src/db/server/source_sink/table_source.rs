@@ -1,26 +1,49 @@
-use super::rocks_trait::RocksTrait;
+use super::rocks_trait::{collect_page, RocksTrait};
 use crate::db::db_error::{DbError, DbResult};
+use crate::db::entity::table_schema::TableSchemas;
 use crate::db::entity::OndoKey;
 use crate::db::entity::TableStored;
 use crate::db::entity::TableValue;
 use crate::db::reference::requests::TableStoredIteratorRequests;
 use crate::db::reference::requests::TableStoredRequests;
-use crate::db::reference::TableName;
+use crate::db::reference::{Page, PageRequest, TableName};
 use crate::db::server::rocks_db_accessor::DbReadLockGuardWrapper;
 use crate::db::server::rocks_db_accessor::RocksDbAccessor;
 use crate::db::server::source_sink::ondo_serializer::OndoSerializer;
 use crate::db::DbError::CfNotFound;
+use crate::metrics;
 use serde_json::Value;
 
+fn deserialize_scanned_value(bytes: &[u8]) -> DbResult<TableValue> {
+    metrics::record_document_scanned("table");
+    Value::ondo_deserialize(bytes)
+}
+
 impl TableStoredRequests for RocksDbAccessor {
     fn get_table_stored(&self, cf_name: &str, key: &TableName) -> DbResult<Option<TableStored>> {
         let guarded_db = self.guarded_db();
         let db = RocksDbAccessor::db_read_lock(&guarded_db)?;
         let cf = db.cf_handle(cf_name).ok_or(CfNotFound)?;
         let ondo_key = TableName::ondo_serialize(key)?;
-        let answer = db
+        let answer = db.get_cf(cf, &ondo_key).map_err(DbError::RocksDbError)?;
+        answer
+            .map(|bytes| TableStored::ondo_deserialize(&bytes))
+            .transpose()
+    }
+
+    fn table_schemas(&self) -> Option<&TableSchemas> {
+        Some(&self.table_schemas)
+    }
+}
+
+impl<'a> TableStoredRequests for DbReadLockGuardWrapper<'a> {
+    fn get_table_stored(&self, cf_name: &str, key: &TableName) -> DbResult<Option<TableStored>> {
+        let cf = self.guard.cf_handle(cf_name).ok_or(CfNotFound)?;
+        let ondo_key = TableName::ondo_serialize(key)?;
+        let answer = self
+            .guard
             .get_cf(cf, &ondo_key)
-            .map_err(|err| DbError::RocksDbError(err))?;
+            .map_err(DbError::RocksDbError)?;
         answer
             .map(|bytes| TableStored::ondo_deserialize(&bytes))
             .transpose()
@@ -35,7 +58,7 @@ impl<'a> TableStoredIteratorRequests<'a> for DbReadLockGuardWrapper<'a> {
         let raw_all_iterator = self.guard.get_records_in_cf(value_cf_name)?;
 
         let all_iterator = raw_all_iterator.map(|result| {
-            result.and_then(|(_, v)| Value::ondo_deserialize(&v)) // Flatten the nested Result
+            result.and_then(|(_, v)| deserialize_scanned_value(&v)) // Flatten the nested Result
         });
 
         let ok_iterator = Box::new(all_iterator);
@@ -48,17 +71,43 @@ impl<'a> TableStoredIteratorRequests<'a> for DbReadLockGuardWrapper<'a> {
         key_prefix: OndoKey,
     ) -> DbResult<Box<dyn Iterator<Item = DbResult<TableValue>> + 'a>> {
         let serialized_key_prefix = key_prefix.ondo_serialize()?;
-        let raw_iterator = self
-            .guard
-            .get_records_in_cf_with_key_prefix_old(value_cf_name, serialized_key_prefix)?;
+        let raw_iterator = self.guard.get_records_in_cf_with_key_prefix(
+            value_cf_name,
+            serialized_key_prefix,
+            None,
+            None,
+        )?;
 
         let all_iterator = raw_iterator.map(|result| {
-            result.and_then(|(_, v)| Value::ondo_deserialize(&v)) // Flatten the nested Result
+            result.and_then(|(_, v)| deserialize_scanned_value(&v)) // Flatten the nested Result
         });
 
         let ok_iterator = Box::new(all_iterator);
         Ok(ok_iterator)
     }
+
+    fn values_page_with_key_prefix(
+        &'a self,
+        value_cf_name: &str,
+        key_prefix: OndoKey,
+        page_request: &PageRequest,
+    ) -> DbResult<Page<TableValue>> {
+        let serialized_key_prefix = key_prefix.ondo_serialize()?;
+        let serialized_start_key = page_request
+            .start_key
+            .as_ref()
+            .map(|start_key| start_key.ondo_serialize())
+            .transpose()?;
+        let raw_iterator = self.guard.get_records_in_cf_with_key_prefix(
+            value_cf_name,
+            serialized_key_prefix,
+            serialized_start_key,
+            page_request.page_size,
+        )?;
+        collect_page(raw_iterator, page_request.page_size, |_, value| {
+            deserialize_scanned_value(value)
+        })
+    }
     fn all_values_with_key_range(
         &'a self,
         value_cf_name: &str,
@@ -67,14 +116,15 @@ impl<'a> TableStoredIteratorRequests<'a> for DbReadLockGuardWrapper<'a> {
     ) -> DbResult<Box<dyn Iterator<Item = DbResult<TableValue>> + 'a>> {
         let serialized_start_key = start_key.ondo_serialize()?;
         let serialized_end_key = end_key.ondo_serialize()?;
-        let raw_iterator = self.guard.get_records_in_cf_with_key_range_old(
+        let raw_iterator = self.guard.get_records_in_cf_with_key_range(
             value_cf_name,
             serialized_start_key,
             serialized_end_key,
+            None,
         )?;
 
         let all_iterator = raw_iterator.map(|result| {
-            result.and_then(|(_, v)| Value::ondo_deserialize(&v)) // Flatten the nested Result
+            result.and_then(|(_, v)| deserialize_scanned_value(&v)) // Flatten the nested Result
         });
 
         let ok_iterator = Box::new(all_iterator);
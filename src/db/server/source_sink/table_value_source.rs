@@ -3,7 +3,7 @@ use crate::db::entity::OndoKey;
 use crate::db::entity::TableValue;
 use crate::db::reference::requests::TableValueRequests;
 use crate::db::reference::TableValueReference;
-use crate::db::server::rocks_db_accessor::RocksDbAccessor;
+use crate::db::server::rocks_db_accessor::{DbReadLockGuardWrapper, RocksDbAccessor};
 use crate::db::server::source_sink::ondo_serializer::OndoSerializer;
 use crate::db::DbError::CfNotFound;
 use serde_json::Value;
@@ -19,9 +19,27 @@ impl TableValueRequests for RocksDbAccessor {
         let cf = db.cf_handle(cf_name).ok_or(CfNotFound)?;
         let ondo_key = OndoKey::ondo_serialize(&key.id)?;
         // println!("DEBUG: Fetching table value with key: {:?}", ondo_key);
-        let answer = db
+        let answer = db.get_cf(cf, &ondo_key).map_err(DbError::RocksDbError)?;
+        answer
+            .map(|bytes| Value::ondo_deserialize(&bytes))
+            .transpose()
+    }
+}
+
+// Reads through the lock that is already held, so that the values found through an index
+// can be fetched without taking the read lock again.
+impl<'a> TableValueRequests for DbReadLockGuardWrapper<'a> {
+    fn get_table_value(
+        &self,
+        cf_name: &str,
+        key: &TableValueReference,
+    ) -> DbResult<Option<TableValue>> {
+        let cf = self.guard.cf_handle(cf_name).ok_or(CfNotFound)?;
+        let ondo_key = OndoKey::ondo_serialize(&key.id)?;
+        let answer = self
+            .guard
             .get_cf(cf, &ondo_key)
-            .map_err(|err| DbError::RocksDbError(err))?;
+            .map_err(DbError::RocksDbError)?;
         answer
             .map(|bytes| Value::ondo_deserialize(&bytes))
             .transpose()
@@ -1,3 +1,4 @@
+use super::value_stream::JsonMessageStream;
 use crate::ondo_remote;
 use ondo_remote::*;
 use tonic::{Request, Response, Status};
@@ -13,6 +14,10 @@ pub trait TableServerTrait {
         r: Request<TableReferenceMessage>,
     ) -> Result<Response<TableMessage>, Status>;
     fn update_table(&self, _: Request<TableMessage>) -> Result<Response<EmptyMessage>, Status>;
+    fn validate_table_values(
+        &self,
+        r: Request<ValidateTableValuesMessage>,
+    ) -> Result<Response<TableValidationMessage>, Status>;
     fn list_indexes(
         &self,
         r: Request<TableReferenceMessage>,
@@ -21,10 +26,26 @@ pub trait TableServerTrait {
         &self,
         r: Request<TableReferenceMessage>,
     ) -> Result<Response<JsonMessage>, Status>;
+    fn list_values_page(
+        &self,
+        r: Request<ListValuesMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status>;
+    fn stream_values(
+        &self,
+        r: Request<TableReferenceMessage>,
+    ) -> Result<Response<JsonMessageStream>, Status>;
     fn list_values_by_key_prefix(
         &self,
         r: Request<TableValueReferenceMessage>,
     ) -> Result<Response<JsonMessage>, Status>;
+    fn list_values_by_key_prefix_page(
+        &self,
+        r: Request<ListValuesByKeyPrefixMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status>;
+    fn stream_values_by_key_prefix(
+        &self,
+        r: Request<TableValueReferenceMessage>,
+    ) -> Result<Response<JsonMessageStream>, Status>;
     fn list_values_by_id_range(
         &self,
         r: Request<TableIdRangeReferenceMessage>,
@@ -33,4 +54,6 @@ pub trait TableServerTrait {
         &self,
         r: Request<TableIdListReferenceMessage>,
     ) -> Result<Response<JsonMessage>, Status>;
+    fn query(&self, r: Request<QueryMessage>) -> Result<Response<JsonMessage>, Status>;
+    fn explain(&self, r: Request<ExplainMessage>) -> Result<Response<ExplainResponse>, Status>;
 }
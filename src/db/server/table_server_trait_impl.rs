@@ -1,58 +1,82 @@
 use super::db_error_to_status::DbErrorOptionToStatus;
 use super::db_error_to_status::DbErrorToStatus;
+use super::explain::explain;
+use super::message_field::{json_field, required_field};
+use super::page::{page_request, page_response};
 use super::rocks_db_accessor::DbReadLockGuardWrapper;
 use super::rocks_db_accessor::RocksDbAccessor;
 use super::source_sink::effects_sink::EffectsSink;
 use super::table_server_trait::TableServerTrait;
-use crate::db::enums::table_stored_iterator_requests_factory::TableStoredIteratorRequestsFactoryEnum;
+use super::value_stream::{stream_values, JsonMessageStream};
 use crate::db::{
     entity::{table::Table, OndoKey, TableValue},
     reference::{
-        table_reference::TableReference, TableReferenceTrait, TableValueReference,
-        TableValueReferenceTrait,
+        filter::parse_filter,
+        query::{Query, SortField},
+        table_reference::TableReference,
+        table_validation::TableValidation,
+        TableReferenceTrait, TableValueReference, TableValueReferenceTrait,
     },
-    DbError,
+    DbError, DbResult,
 };
 use crate::ondo_remote;
 use ondo_remote::*;
 use tonic::{Request, Response, Status};
 
-impl<'a> Into<TableReference> for &'a TableReferenceMessage {
-    fn into(self) -> TableReference {
-        TableReference {
-            domain_reference: self.domain_reference.as_ref().unwrap().into(),
-            table_name: self.table_name.clone(),
-        }
+impl TryFrom<&TableReferenceMessage> for TableReference {
+    type Error = Status;
+    fn try_from(val: &TableReferenceMessage) -> Result<Self, Self::Error> {
+        Ok(TableReference {
+            domain_reference: required_field(
+                &val.domain_reference,
+                "TableReferenceMessage.domain_reference",
+            )?
+            .into(),
+            table_name: val.table_name.clone(),
+        })
     }
 }
-impl Into<TableReference> for TableReferenceMessage {
-    fn into(self) -> TableReference {
-        let reference = &self;
-        reference.into()
+impl TryFrom<TableReferenceMessage> for TableReference {
+    type Error = Status;
+    fn try_from(val: TableReferenceMessage) -> Result<Self, Self::Error> {
+        let reference = &val;
+        reference.try_into()
     }
 }
 
-impl<'a> Into<Table> for &'a TableMessage {
-    fn into(self) -> Table {
-        Table {
-            reference: self.table_reference.as_ref().unwrap().into(),
-        }
+impl TryFrom<&TableMessage> for Table {
+    type Error = Status;
+    fn try_from(val: &TableMessage) -> Result<Self, Self::Error> {
+        let schema = match val.schema.as_str() {
+            "" => None,
+            schema => Some(json_field(schema, "TableMessage.schema")?),
+        };
+        Ok(Table {
+            reference: required_field(&val.table_reference, "TableMessage.table_reference")?
+                .try_into()?,
+            schema,
+        })
     }
 }
 
-impl Into<TableReferenceMessage> for TableReference {
-    fn into(self) -> TableReferenceMessage {
+impl From<TableReference> for TableReferenceMessage {
+    fn from(val: TableReference) -> Self {
         TableReferenceMessage {
-            domain_reference: Some(self.domain_reference.into()),
-            table_name: self.table_name,
+            domain_reference: Some(val.domain_reference.into()),
+            table_name: val.table_name,
         }
     }
 }
 
-impl Into<TableMessage> for Table {
-    fn into(self) -> TableMessage {
+impl From<Table> for TableMessage {
+    fn from(val: Table) -> Self {
         TableMessage {
-            table_reference: Some(self.reference.into()),
+            table_reference: Some(val.reference.into()),
+            schema: val
+                .schema
+                .map(|schema| schema.to_string())
+                .unwrap_or_default(),
+            validate_values: false,
         }
     }
 }
@@ -62,13 +86,20 @@ struct TableIdRangeReference {
     start_key: OndoKey,
     end_key: OndoKey,
 }
-impl<'a> Into<TableIdRangeReference> for &'a TableIdRangeReferenceMessage {
-    fn into(self) -> TableIdRangeReference {
-        TableIdRangeReference {
-            table_reference: self.table_reference.as_ref().unwrap().into(),
-            start_key: self.start_key.as_ref().unwrap().into(),
-            end_key: self.end_key.as_ref().unwrap().into(),
-        }
+impl TryFrom<&TableIdRangeReferenceMessage> for TableIdRangeReference {
+    type Error = Status;
+    fn try_from(val: &TableIdRangeReferenceMessage) -> Result<Self, Self::Error> {
+        Ok(TableIdRangeReference {
+            table_reference: required_field(
+                &val.table_reference,
+                "TableIdRangeReferenceMessage.table_reference",
+            )?
+            .try_into()?,
+            start_key: required_field(&val.start_key, "TableIdRangeReferenceMessage.start_key")?
+                .try_into()?,
+            end_key: required_field(&val.end_key, "TableIdRangeReferenceMessage.end_key")?
+                .try_into()?,
+        })
     }
 }
 
@@ -76,22 +107,114 @@ struct TableIdListReference {
     table_reference: TableReference,
     keys: Vec<OndoKey>,
 }
-impl<'a> Into<TableIdListReference> for &'a TableIdListReferenceMessage {
-    fn into(self) -> TableIdListReference {
-        TableIdListReference {
-            table_reference: self.table_reference.as_ref().unwrap().into(),
-            keys: self.keys.iter().map(|k| k.into()).collect(),
+impl TryFrom<&TableIdListReferenceMessage> for TableIdListReference {
+    type Error = Status;
+    fn try_from(val: &TableIdListReferenceMessage) -> Result<Self, Self::Error> {
+        Ok(TableIdListReference {
+            table_reference: required_field(
+                &val.table_reference,
+                "TableIdListReferenceMessage.table_reference",
+            )?
+            .try_into()?,
+            keys: val
+                .keys
+                .iter()
+                .map(|k| k.try_into())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+pub(super) struct QueryPayload {
+    pub(super) table_reference: TableReference,
+    pub(super) query: Query,
+}
+impl TryFrom<&QueryMessage> for QueryPayload {
+    type Error = Status;
+    fn try_from(val: &QueryMessage) -> Result<Self, Self::Error> {
+        let filter = match val.filter.as_str() {
+            "" => serde_json::Value::Null,
+            filter => json_field(filter, "QueryMessage.filter")?,
+        };
+        let fields = val
+            .sort
+            .iter()
+            .map(|sort_field| &sort_field.field)
+            .chain(val.projection.iter());
+        for field in fields {
+            if field.is_empty() || field.split('.').any(|part| part.is_empty()) {
+                return Err(Status::invalid_argument(format!(
+                    "Invalid field path \"{}\" in QueryMessage",
+                    field
+                )));
+            }
+        }
+        Ok(QueryPayload {
+            table_reference: required_field(&val.table_reference, "QueryMessage.table_reference")?
+                .try_into()?,
+            query: Query {
+                filter: parse_filter(&filter).map_db_err_to_status()?,
+                sort: val
+                    .sort
+                    .iter()
+                    .map(|sort_field| SortField {
+                        field: sort_field.field.clone(),
+                        descending: sort_field.descending,
+                    })
+                    .collect(),
+                limit: match val.limit {
+                    0 => None,
+                    limit => Some(limit as usize),
+                },
+                projection: val.projection.clone(),
+            },
+        })
+    }
+}
+
+impl From<TableValidation> for TableValidationMessage {
+    fn from(val: TableValidation) -> Self {
+        TableValidationMessage {
+            values_checked: val.values_checked,
+            invalid_values: val.invalid_values,
+            invalid: val
+                .invalid
+                .into_iter()
+                .map(|(id, violations)| InvalidValueMessage {
+                    id: Some(id.into()),
+                    violations: violations
+                        .iter()
+                        .map(SchemaViolationMessage::from)
+                        .collect(),
+                })
+                .collect(),
         }
     }
 }
 
+// Fails with ValuesViolateSchema if values of the table do not match its new schema.
+fn check_table_values(ra: &RocksDbAccessor, table: &Table) -> DbResult<()> {
+    let schema = match &table.schema {
+        Some(schema) => schema,
+        None => return Ok(()),
+    };
+    let guarded_db = ra.guarded_db();
+    let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db)?;
+    let validation = table.reference.validate_values(schema, &db_wrapper)?;
+    match validation.invalid_values {
+        0 => Ok(()),
+        count => Err(DbError::ValuesViolateSchema(count)),
+    }
+}
+
 impl TableServerTrait for RocksDbAccessor {
     fn create_table(&self, r: Request<TableMessage>) -> Result<Response<EmptyMessage>, Status> {
-        let entity: Table = r.get_ref().into();
+        let entity: Table = r.get_ref().try_into()?;
+        let _writes = self.lock_writes().map_db_err_to_status()?;
         entity
             .reference
             .post_table(&entity, self, self)
-            .map_db_err_to_status()?
+            .map_db_err_to_status_for(&entity.reference)?
             .apply_effects(self)
     }
 
@@ -99,10 +222,11 @@ impl TableServerTrait for RocksDbAccessor {
         &self,
         r: Request<TableReferenceMessage>,
     ) -> Result<Response<EmptyMessage>, Status> {
-        let reference: TableReference = r.get_ref().into();
+        let reference: TableReference = r.get_ref().try_into()?;
+        let _writes = self.lock_writes().map_db_err_to_status()?;
         reference
             .delete_table(self, self)
-            .map_db_err_to_status()?
+            .map_db_err_to_status_for(&reference)?
             .apply_effects(self)
     }
 
@@ -110,28 +234,65 @@ impl TableServerTrait for RocksDbAccessor {
         &self,
         r: Request<TableReferenceMessage>,
     ) -> Result<Response<TableMessage>, Status> {
-        let reference: TableReference = r.get_ref().into();
+        let reference: TableReference = r.get_ref().try_into()?;
         reference
             .get_table(self)
-            .map_db_err_option_to_status()
+            .map_db_err_option_to_status_for(&reference)
             .map(|entity| Response::new(entity.into()))
     }
 
     fn update_table(&self, r: Request<TableMessage>) -> Result<Response<EmptyMessage>, Status> {
-        let entity: Table = r.get_ref().into();
+        let entity: Table = r.get_ref().try_into()?;
+        // No value is written between the validation and the new schema.
+        let _writes = self.lock_writes().map_db_err_to_status()?;
+        if r.get_ref().validate_values {
+            check_table_values(self, &entity).map_db_err_to_status_for(&entity.reference)?;
+        }
         entity
             .reference
             .put_table(&entity, self)
-            .map_db_err_to_status()?
+            .map_db_err_to_status_for(&entity.reference)?
             .apply_effects(self)
     }
 
+    fn validate_table_values(
+        &self,
+        r: Request<ValidateTableValuesMessage>,
+    ) -> Result<Response<TableValidationMessage>, Status> {
+        let message = r.get_ref();
+        let reference: TableReference = required_field(
+            &message.table_reference,
+            "ValidateTableValuesMessage.table_reference",
+        )?
+        .try_into()?;
+        let schema = match message.schema.as_str() {
+            "" => reference
+                .get_table(self)
+                .map_db_err_option_to_status_for(&reference)?
+                .schema
+                .ok_or_else(|| {
+                    Status::invalid_argument(
+                        "Missing field ValidateTableValuesMessage.schema, the table has no schema",
+                    )
+                })?,
+            schema => json_field(schema, "ValidateTableValuesMessage.schema")?,
+        };
+        let guarded_db = self.guarded_db();
+        let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).map_db_err_to_status()?;
+        let validation = reference
+            .validate_values(&schema, &db_wrapper)
+            .map_db_err_to_status_for(&reference)?;
+        Ok(Response::new(validation.into()))
+    }
+
     fn list_indexes(
         &self,
         r: Request<TableReferenceMessage>,
     ) -> Result<Response<ArrayOfStringResponse>, Status> {
-        let reference: TableReference = r.get_ref().into();
-        let names = reference.list_index_names(self).map_db_err_to_status()?;
+        let reference: TableReference = r.get_ref().try_into()?;
+        let names = reference
+            .list_index_names(self)
+            .map_db_err_to_status_for(&reference)?;
         let response = ArrayOfStringResponse { values: names };
         Ok(Response::new(response))
     }
@@ -140,40 +301,94 @@ impl TableServerTrait for RocksDbAccessor {
         &self,
         r: Request<TableReferenceMessage>,
     ) -> Result<Response<JsonMessage>, Status> {
+        let message = ListValuesMessage {
+            table_reference: Some(r.into_inner()),
+            limit: 0,
+            continuation_token: String::new(),
+        };
+        let page = self.list_values_page(Request::new(message))?.into_inner();
+        Ok(Response::new(JsonMessage { json: page.json }))
+    }
+
+    fn list_values_page(
+        &self,
+        r: Request<ListValuesMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status> {
         let guarded_db = self.guarded_db();
-        let factory_enum_db_arc = TableStoredIteratorRequestsFactoryEnum::new_db_arc(guarded_db);
-        let table_stored_iterator_requests_enum = factory_enum_db_arc
-            .create_read_locked_requests()
-            .map_db_err_to_status()?;
-        let table_stored_iterator_requests = table_stored_iterator_requests_enum.as_trait();
-        let reference: TableReference = r.get_ref().into();
-        let iterator = reference
-            .all_values(table_stored_iterator_requests)
-            .map_db_err_to_status()?;
-        let values_result: Result<Vec<TableValue>, DbError> = iterator.collect();
-        let values = values_result.map_db_err_to_status()?;
-        let json = serde_json::to_string(&values).map_err(|e| Status::internal(e.to_string()))?;
-        let response = Response::new(JsonMessage { json });
-        Ok(response)
+        let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).map_db_err_to_status()?;
+        let message = r.get_ref();
+        let reference: TableReference = required_field(
+            &message.table_reference,
+            "ListValuesMessage.table_reference",
+        )?
+        .try_into()?;
+        let page_request = page_request(message.limit, &message.continuation_token)?;
+        let all_keys = OndoKey { values: vec![] };
+        let page = reference
+            .values_page_with_key_prefix(all_keys, &page_request, &db_wrapper)
+            .map_db_err_to_status_for(&reference)?;
+        Ok(Response::new(page_response(page)?))
+    }
+
+    fn stream_values(
+        &self,
+        r: Request<TableReferenceMessage>,
+    ) -> Result<Response<JsonMessageStream>, Status> {
+        let reference: TableReference = r.get_ref().try_into()?;
+        let stream = stream_values(self.guarded_db(), move |db_wrapper, page_request| {
+            let all_keys = OndoKey { values: vec![] };
+            reference.values_page_with_key_prefix(all_keys, page_request, db_wrapper)
+        });
+        Ok(Response::new(stream))
     }
 
     fn list_values_by_key_prefix(
         &self,
         r: Request<TableValueReferenceMessage>,
     ) -> Result<Response<JsonMessage>, Status> {
+        let message = ListValuesByKeyPrefixMessage {
+            table_value_reference: Some(r.into_inner()),
+            limit: 0,
+            continuation_token: String::new(),
+        };
+        let page = self
+            .list_values_by_key_prefix_page(Request::new(message))?
+            .into_inner();
+        Ok(Response::new(JsonMessage { json: page.json }))
+    }
+
+    fn list_values_by_key_prefix_page(
+        &self,
+        r: Request<ListValuesByKeyPrefixMessage>,
+    ) -> Result<Response<JsonPageResponse>, Status> {
         let guarded_db = self.guarded_db();
         let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).map_db_err_to_status()?;
-        let value_reference: TableValueReference = r.get_ref().into();
+        let message = r.get_ref();
+        let value_reference: TableValueReference = required_field(
+            &message.table_value_reference,
+            "ListValuesByKeyPrefixMessage.table_value_reference",
+        )?
+        .try_into()?;
         let reference = value_reference.table_reference;
         let key_prefix = value_reference.id; // Assuming 'id' is the key_prefix field in TableValueReference
-        let iterator = reference
-            .all_values_with_key_prefix(key_prefix, &db_wrapper)
-            .map_db_err_to_status()?;
-        let values_result: Result<Vec<TableValue>, DbError> = iterator.collect();
-        let values = values_result.map_db_err_to_status()?;
-        let json = serde_json::to_string(&values).map_err(|e| Status::internal(e.to_string()))?;
-        let response = Response::new(JsonMessage { json });
-        Ok(response)
+        let page_request = page_request(message.limit, &message.continuation_token)?;
+        let page = reference
+            .values_page_with_key_prefix(key_prefix, &page_request, &db_wrapper)
+            .map_db_err_to_status_for(&reference)?;
+        Ok(Response::new(page_response(page)?))
+    }
+
+    fn stream_values_by_key_prefix(
+        &self,
+        r: Request<TableValueReferenceMessage>,
+    ) -> Result<Response<JsonMessageStream>, Status> {
+        let value_reference: TableValueReference = r.get_ref().try_into()?;
+        let reference = value_reference.table_reference;
+        let key_prefix = value_reference.id;
+        let stream = stream_values(self.guarded_db(), move |db_wrapper, page_request| {
+            reference.values_page_with_key_prefix(key_prefix.clone(), page_request, db_wrapper)
+        });
+        Ok(Response::new(stream))
     }
 
     fn list_values_by_id_range(
@@ -182,13 +397,13 @@ impl TableServerTrait for RocksDbAccessor {
     ) -> Result<Response<JsonMessage>, Status> {
         let guarded_db = self.guarded_db();
         let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).map_db_err_to_status()?;
-        let range_reference: TableIdRangeReference = r.get_ref().into();
+        let range_reference: TableIdRangeReference = r.get_ref().try_into()?;
         let reference = range_reference.table_reference;
         let start_key = range_reference.start_key;
         let end_key = range_reference.end_key;
         let iterator = reference
             .all_values_with_key_range(start_key, end_key, &db_wrapper)
-            .map_db_err_to_status()?;
+            .map_db_err_to_status_for(&reference)?;
         let values_result: Result<Vec<TableValue>, DbError> = iterator.collect();
         let values = values_result.map_db_err_to_status()?;
         let json = serde_json::to_string(&values).map_err(|e| Status::internal(e.to_string()))?;
@@ -200,7 +415,7 @@ impl TableServerTrait for RocksDbAccessor {
         &self,
         r: Request<TableIdListReferenceMessage>,
     ) -> Result<Response<JsonMessage>, Status> {
-        let table_id_list_reference: TableIdListReference = r.get_ref().into();
+        let table_id_list_reference: TableIdListReference = r.get_ref().try_into()?;
         let table_reference = table_id_list_reference.table_reference;
         let keys = table_id_list_reference.keys;
 
@@ -222,6 +437,28 @@ impl TableServerTrait for RocksDbAccessor {
         let response = Response::new(JsonMessage { json });
         Ok(response)
     }
+
+    fn query(&self, r: Request<QueryMessage>) -> Result<Response<JsonMessage>, Status> {
+        let payload: QueryPayload = r.get_ref().try_into()?;
+        let reference = payload.table_reference;
+        let guarded_db = self.guarded_db();
+        let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db).map_db_err_to_status()?;
+        let values = reference
+            .query_values(
+                &payload.query,
+                &db_wrapper,
+                &db_wrapper,
+                &db_wrapper,
+                &db_wrapper,
+            )
+            .map_db_err_to_status_for(&reference)?;
+        let json = serde_json::to_string(&values).map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(JsonMessage { json }))
+    }
+
+    fn explain(&self, r: Request<ExplainMessage>) -> Result<Response<ExplainResponse>, Status> {
+        explain(self, r.get_ref()).map(Response::new)
+    }
 }
 
 #[cfg(test)]
@@ -229,6 +466,8 @@ mod tests {
     use super::*;
     use crate::db::reference::domain_reference::DomainReference;
     use crate::db::reference::table_reference::TableReference;
+    use crate::db::server::{table_value_server_trait::TableValueServerTrait, test_fixture};
+    use serde_json::{json, Value};
 
     #[test]
     fn test_table_reference_message_into_table_reference() {
@@ -238,7 +477,7 @@ mod tests {
             }),
             table_name: "table1".to_string(),
         };
-        let reference: TableReference = (&message).into();
+        let reference: TableReference = (&message).try_into().unwrap();
         assert_eq!(reference.domain_reference.domain_name, "example.com");
         assert_eq!(reference.table_name, "table1");
     }
@@ -266,8 +505,10 @@ mod tests {
         };
         let message = TableMessage {
             table_reference: Some(reference.into()),
+            schema: String::new(),
+            validate_values: false,
         };
-        let table: Table = (&message).into();
+        let table: Table = (&message).try_into().unwrap();
         assert_eq!(table.reference.domain_reference.domain_name, "example.com");
         assert_eq!(table.reference.table_name, "table1");
     }
@@ -281,6 +522,7 @@ mod tests {
                 },
                 table_name: "table1".to_string(),
             },
+            schema: None,
         };
         let message: TableMessage = table.into();
         assert_eq!(
@@ -295,4 +537,418 @@ mod tests {
         );
         assert_eq!(message.table_reference.unwrap().table_name, "table1");
     }
+
+    fn people_table() -> TableReferenceMessage {
+        test_fixture::table_reference("domain", "people")
+    }
+
+    fn create_people(ra: &RocksDbAccessor) {
+        test_fixture::create_database(ra, &people_table());
+        for person in [
+            json!({"name": "Ann", "age": 30, "city": "Paris", "tags": ["a", "b"]}),
+            json!({"name": "Bob", "age": 25.0, "city": "Rome", "tags": ["b"]}),
+            json!({"name": "Cid", "age": 41, "city": "Paris", "address": {"zip": "75001"}}),
+            json!({"name": "Dan", "city": "Oslo"}),
+            json!({"name": "Eve", "age": 30, "city": "Rome", "tags": []}),
+        ] {
+            test_fixture::create_value(ra, &people_table(), &person.to_string());
+        }
+    }
+
+    fn create_people_index(ra: &RocksDbAccessor, index_name: &str, fields: &[&str]) {
+        let index_reference = test_fixture::index_reference(&people_table(), index_name);
+        test_fixture::create_index(ra, &index_reference, fields, false);
+    }
+
+    fn query_message(filter: Value, sort: &[(&str, bool)], limit: u64) -> QueryMessage {
+        QueryMessage {
+            table_reference: Some(people_table()),
+            filter: filter.to_string(),
+            sort: sort
+                .iter()
+                .map(|(field, descending)| SortFieldMessage {
+                    field: field.to_string(),
+                    descending: *descending,
+                })
+                .collect(),
+            limit,
+            projection: vec![],
+        }
+    }
+
+    fn query(ra: &RocksDbAccessor, message: QueryMessage) -> Result<Vec<Value>, Status> {
+        let response = ra.query(Request::new(message))?.into_inner();
+        Ok(serde_json::from_str(&response.json).unwrap())
+    }
+
+    fn names(values: &[Value]) -> Vec<&str> {
+        values
+            .iter()
+            .map(|value| value["name"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_query_with_and_without_indexes() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra);
+        let cases = [
+            (json!({}), vec!["Ann", "Bob", "Cid", "Dan", "Eve"]),
+            (json!({"city": "Paris"}), vec!["Ann", "Cid"]),
+            (json!({"age": 30}), vec!["Ann", "Eve"]),
+            (json!({"age": 25}), vec!["Bob"]),
+            (json!({"age": {"$gte": 30}}), vec!["Ann", "Cid", "Eve"]),
+            (json!({"age": {"$gt": 25, "$lt": 41}}), vec!["Ann", "Eve"]),
+            (
+                json!({"city": {"$in": ["Rome", "Oslo"]}, "age": {"$lte": 30.0}}),
+                vec!["Bob", "Eve"],
+            ),
+            (json!({"tags": "b"}), vec!["Ann", "Bob"]),
+            (json!({"age": null}), vec!["Dan"]),
+            (json!({"age": {"$exists": false}}), vec!["Dan"]),
+            (json!({"address.zip": "75001"}), vec!["Cid"]),
+            (
+                json!({"$or": [{"city": "Oslo"}, {"age": 41}]}),
+                vec!["Cid", "Dan"],
+            ),
+            (
+                json!({"city": {"$gt": "Oslo", "$lt": "Rome"}}),
+                vec!["Ann", "Cid"],
+            ),
+            (
+                json!({"$not": {"city": "Paris"}}),
+                vec!["Bob", "Dan", "Eve"],
+            ),
+            (
+                json!({"name": {"$ne": "Ann"}, "city": "Paris"}),
+                vec!["Cid"],
+            ),
+        ];
+        let run_cases = |ra: &RocksDbAccessor| {
+            for (filter, expected) in cases.iter() {
+                let message = query_message(filter.clone(), &[("name", false)], 0);
+                let values = query(ra, message).unwrap();
+                assert_eq!(names(&values), *expected, "{}", filter);
+            }
+        };
+        run_cases(&ra);
+        create_people_index(&ra, "by_age", &["age"]);
+        create_people_index(&ra, "by_city_age", &["city", "age"]);
+        create_people_index(&ra, "by_tags", &["tags"]);
+        run_cases(&ra);
+    }
+
+    #[test]
+    fn test_query_sort_limit_and_projection() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra);
+        create_people_index(&ra, "by_city", &["city"]);
+
+        let mut message = query_message(json!({}), &[("age", true), ("name", false)], 3);
+        message.projection = vec!["name".to_owned(), "address.zip".to_owned()];
+        let values = query(&ra, message).unwrap();
+        assert_eq!(names(&values), vec!["Cid", "Ann", "Eve"]);
+        assert_eq!(values[0]["address"], json!({"zip": "75001"}));
+        for value in values.iter() {
+            assert!(value.get("_id").is_some());
+            assert!(value.get("city").is_none());
+        }
+
+        let values = query(&ra, query_message(json!({}), &[("age", false)], 1)).unwrap();
+        assert_eq!(names(&values), vec!["Dan"]);
+        let values = query(&ra, query_message(json!({"city": "Rome"}), &[], 1)).unwrap();
+        assert_eq!(values.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_query() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra);
+        let mut messages = vec![
+            query_message(json!({"$nor": []}), &[], 0),
+            query_message(json!({"age": {"$gt": [1]}}), &[], 0),
+            query_message(json!({}), &[("", false)], 0),
+        ];
+        let mut message = query_message(json!({}), &[], 0);
+        message.filter = "{".to_owned();
+        messages.push(message);
+        let mut message = query_message(json!({}), &[], 0);
+        message.projection = vec!["a..b".to_owned()];
+        messages.push(message);
+        for message in messages {
+            let status = query(&ra, message).unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+
+        let mut message = query_message(json!({}), &[], 0);
+        message.filter = String::new();
+        assert_eq!(query(&ra, message).unwrap().len(), 5);
+    }
+
+    fn explain(
+        ra: &RocksDbAccessor,
+        request: explain_message::Request,
+        execute: bool,
+    ) -> ExplainResponse {
+        let message = ExplainMessage {
+            request: Some(request),
+            execute,
+        };
+        ra.explain(Request::new(message)).unwrap().into_inner()
+    }
+
+    fn people_index(index_name: &str) -> IndexReferenceMessage {
+        IndexReferenceMessage {
+            table_reference: Some(people_table()),
+            index_name: index_name.to_owned(),
+        }
+    }
+
+    fn json_key(values: &[Value]) -> OndoKeyMessage {
+        OndoKeyMessage {
+            json_keys: values.iter().map(|value| value.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_explain_query() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra);
+        let paris =
+            || explain_message::Request::Query(query_message(json!({"city": "Paris"}), &[], 0));
+
+        let response = explain(&ra, paris(), false);
+        assert_eq!(response.index_name, "");
+        assert_eq!(response.cf_name, "/domains/domain/tables/people");
+        assert_eq!(response.key_ranges.len(), 1);
+        assert_eq!(response.key_ranges[0].start_key, Some(json_key(&[])));
+        assert!(!response.fetch_values);
+        assert!(response.candidates.is_empty());
+        assert!(response.execution.is_none());
+
+        let execution = explain(&ra, paris(), true).execution.unwrap();
+        assert_eq!(execution.rows_scanned, 5);
+        assert_eq!(execution.values_fetched, 0);
+        assert_eq!(execution.rows_returned, 2);
+        assert!(execution.total_nanos >= execution.iteration_nanos);
+
+        create_people_index(&ra, "by_city", &["city"]);
+        create_people_index(&ra, "by_age", &["age"]);
+        let response = explain(&ra, paris(), true);
+        assert_eq!(response.index_name, "by_city");
+        assert_ne!(response.cf_name, "/domains/domain/tables/people");
+        assert_eq!(
+            response.key_ranges,
+            vec![KeyRangeMessage {
+                start_key: Some(json_key(&[json!("Paris")])),
+                end_key: None,
+                encoded_start_key: response.key_ranges[0].encoded_start_key.clone(),
+                encoded_end_key: String::new(),
+            }]
+        );
+        assert!(!response.key_ranges[0].encoded_start_key.is_empty());
+        assert!(response.fetch_values);
+        assert_eq!(
+            response.candidates,
+            vec![
+                IndexCandidateMessage {
+                    index_name: "by_age".to_owned(),
+                    usable: false,
+                    equality_fields: 0,
+                    range: false,
+                },
+                IndexCandidateMessage {
+                    index_name: "by_city".to_owned(),
+                    usable: true,
+                    equality_fields: 1,
+                    range: false,
+                },
+            ]
+        );
+        let execution = response.execution.unwrap();
+        assert_eq!(execution.rows_scanned, 2);
+        assert_eq!(execution.values_fetched, 2);
+        assert_eq!(execution.rows_returned, 2);
+
+        let request =
+            explain_message::Request::Query(query_message(json!({"age": {"$gte": 30}}), &[], 0));
+        let response = explain(&ra, request, false);
+        assert_eq!(response.index_name, "by_age");
+        assert!(response.key_ranges[0].end_key.is_some());
+        assert!(!response.key_ranges[0].encoded_end_key.is_empty());
+    }
+
+    #[test]
+    fn test_explain_find_values() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra);
+        create_people_index(&ra, "by_city", &["city"]);
+        let find_values = |index_name: &str| {
+            explain_message::Request::FindValues(FindValuesMessage {
+                indexed_value_reference: Some(IndexedValueReferenceMessage {
+                    index_reference: Some(people_index(index_name)),
+                    key: Some(json_key(&[json!("Rome")])),
+                }),
+                limit: 1,
+                continuation_token: String::new(),
+            })
+        };
+
+        let response = explain(&ra, find_values("by_city"), true);
+        assert_eq!(response.index_name, "by_city");
+        assert_eq!(
+            response.key_ranges[0].start_key,
+            Some(json_key(&[json!("Rome")]))
+        );
+        assert_eq!(response.key_ranges[0].end_key, None);
+        assert!(response.fetch_values);
+        assert!(response.candidates.is_empty());
+        let execution = response.execution.unwrap();
+        assert_eq!(execution.rows_returned, 1);
+        assert_eq!(execution.values_fetched, 1);
+
+        let request = explain_message::Request::FindValuesByRange(FindValuesByRangeMessage {
+            indexed_value_range_reference: Some(IndexedValueRangeReferenceMessage {
+                index_reference: Some(people_index("by_city")),
+                start_key: Some(json_key(&[json!("Oslo")])),
+                end_key: Some(json_key(&[json!("Paris")])),
+            }),
+            limit: 0,
+            continuation_token: String::new(),
+        });
+        let response = explain(&ra, request, true);
+        assert_eq!(
+            response.key_ranges[0].end_key,
+            Some(json_key(&[json!("Paris")]))
+        );
+        assert_eq!(response.execution.unwrap().rows_returned, 3);
+
+        let status = ra
+            .explain(Request::new(ExplainMessage {
+                request: Some(find_values("missing")),
+                execute: false,
+            }))
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        let status = ra
+            .explain(Request::new(ExplainMessage {
+                request: None,
+                execute: true,
+            }))
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    fn people_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "age": {"type": "integer", "minimum": 0}
+            },
+            "required": ["name", "age"]
+        })
+    }
+
+    fn update_people_table(
+        ra: &RocksDbAccessor,
+        schema: &Value,
+        validate_values: bool,
+    ) -> Result<Response<EmptyMessage>, Status> {
+        ra.update_table(Request::new(TableMessage {
+            table_reference: Some(people_table()),
+            schema: schema.to_string(),
+            validate_values,
+        }))
+    }
+
+    fn violation_paths(status: &Status) -> Vec<String> {
+        use prost::Message;
+        let details = ErrorDetailsMessage::decode(status.details()).unwrap();
+        details
+            .schema_violations
+            .into_iter()
+            .map(|violation| violation.path)
+            .collect()
+    }
+
+    #[test]
+    fn test_table_schema() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra);
+
+        let status = update_people_table(&ra, &json!({"type": "no-such-type"}), false).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let status = update_people_table(&ra, &people_schema(), true).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        update_people_table(&ra, &people_schema(), false).unwrap();
+        let table = ra
+            .get_table(Request::new(people_table()))
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            json_field(&table.schema, "schema").unwrap(),
+            people_schema()
+        );
+
+        let create = |value: Value| {
+            ra.create_value(Request::new(CreateTableValueMessage {
+                create_table_value_reference: Some(CreateTableValueReferenceMessage {
+                    table_reference: Some(people_table()),
+                    key: Some(OptionalOndoKeyMessage { ondo_key: None }),
+                }),
+                json: value.to_string(),
+            }))
+        };
+        let status = create(json!({"name": "Fay", "age": -1})).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(violation_paths(&status), vec!["/age".to_owned()]);
+        let json_keys = create(json!({"name": "Fay", "age": 20}))
+            .unwrap()
+            .into_inner()
+            .json_keys;
+        let key = Some(OndoKeyMessage { json_keys });
+
+        let status = ra
+            .update_value(Request::new(TableValueMessage {
+                table_value_reference: Some(TableValueReferenceMessage {
+                    table_reference: Some(people_table()),
+                    key,
+                }),
+                json: json!({"name": 3, "age": 20}).to_string(),
+                expected_revision: None,
+            }))
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(violation_paths(&status), vec!["/name".to_owned()]);
+    }
+
+    #[test]
+    fn test_validate_table_values() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra);
+        let validate = |schema: &str| {
+            ra.validate_table_values(Request::new(ValidateTableValuesMessage {
+                table_reference: Some(people_table()),
+                schema: schema.to_owned(),
+            }))
+        };
+
+        let status = validate("").unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let validation = validate(&people_schema().to_string()).unwrap().into_inner();
+        assert_eq!(validation.values_checked, 5);
+        assert_eq!(validation.invalid_values, 1);
+        let invalid = &validation.invalid[0];
+        let id: OndoKey = invalid.id.as_ref().unwrap().try_into().unwrap();
+        assert_eq!(id, 4u64.into());
+        assert_eq!(invalid.violations.len(), 1);
+        assert_eq!(invalid.violations[0].path, "");
+
+        update_people_table(&ra, &json!({"required": ["name"]}), true).unwrap();
+        let validation = validate("").unwrap().into_inner();
+        assert_eq!(validation.values_checked, 5);
+        assert_eq!(validation.invalid_values, 0);
+    }
 }
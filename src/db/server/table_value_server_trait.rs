@@ -6,10 +6,10 @@ pub trait TableValueServerTrait {
     fn create_value(
         &self,
         r: Request<CreateTableValueMessage>,
-    ) -> Result<Response<OndoKeyMessage>, Status>;
+    ) -> Result<Response<CreateValueResponse>, Status>;
     fn delete_value(
         &self,
-        r: Request<TableValueReferenceMessage>,
+        r: Request<DeleteTableValueMessage>,
     ) -> Result<Response<EmptyMessage>, Status>;
     fn get_value(
         &self,
@@ -17,4 +17,12 @@ pub trait TableValueServerTrait {
     ) -> Result<Response<JsonMessage>, Status>;
     fn update_value(&self, r: Request<TableValueMessage>)
         -> Result<Response<EmptyMessage>, Status>;
+    fn patch_value(
+        &self,
+        r: Request<PatchTableValueMessage>,
+    ) -> Result<Response<EmptyMessage>, Status>;
+    fn upsert_value(
+        &self,
+        r: Request<UpsertTableValueMessage>,
+    ) -> Result<Response<UpsertValueResponse>, Status>;
 }
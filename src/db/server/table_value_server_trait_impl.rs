@@ -1,8 +1,11 @@
 use super::db_error_to_status::DbErrorOptionToStatus;
 use super::db_error_to_status::DbErrorToStatus;
+use super::message_field::{json_field, required_field};
 use super::rocks_db_accessor::RocksDbAccessor;
 use super::source_sink::effects_sink::EffectsSink;
 use super::table_value_server_trait::TableValueServerTrait;
+use crate::db::entity::table_value::get_revision_from_table_value;
+use crate::db::entity::value_patch::ValuePatch;
 use crate::db::reference::{
     table_value_reference::{CreateTableValueReference, CreateTableValueReferenceTrait},
     TableValueReference, TableValueReferenceTrait,
@@ -12,48 +15,142 @@ use ondo_remote::*;
 use serde_json::Value;
 use tonic::{Request, Response, Status};
 
-impl<'a> Into<TableValueReference> for &'a TableValueReferenceMessage {
-    fn into(self) -> TableValueReference {
-        TableValueReference {
-            table_reference: self.table_reference.as_ref().unwrap().into(),
-            id: self.key.as_ref().unwrap().into(),
-        }
+impl TryFrom<&TableValueReferenceMessage> for TableValueReference {
+    type Error = Status;
+    fn try_from(val: &TableValueReferenceMessage) -> Result<Self, Self::Error> {
+        Ok(TableValueReference {
+            table_reference: required_field(
+                &val.table_reference,
+                "TableValueReferenceMessage.table_reference",
+            )?
+            .try_into()?,
+            id: required_field(&val.key, "TableValueReferenceMessage.key")?.try_into()?,
+        })
     }
 }
-impl<'a> Into<CreateTableValueReference> for &'a CreateTableValueReferenceMessage {
-    fn into(self) -> CreateTableValueReference {
-        CreateTableValueReference {
-            table_reference: self.table_reference.as_ref().unwrap().into(),
-            id: self.key.as_ref().unwrap().into(),
-        }
+impl TryFrom<&CreateTableValueReferenceMessage> for CreateTableValueReference {
+    type Error = Status;
+    fn try_from(val: &CreateTableValueReferenceMessage) -> Result<Self, Self::Error> {
+        Ok(CreateTableValueReference {
+            table_reference: required_field(
+                &val.table_reference,
+                "CreateTableValueReferenceMessage.table_reference",
+            )?
+            .try_into()?,
+            id: required_field(&val.key, "CreateTableValueReferenceMessage.key")?.try_into()?,
+        })
     }
 }
 
 #[derive(Clone)]
-struct TableValuePayload {
-    table_reference: TableValueReference,
-    value: Value,
+pub(super) struct TableValuePayload {
+    pub(super) table_reference: TableValueReference,
+    pub(super) value: Value,
+    pub(super) expected_revision: Option<u64>,
 }
-impl<'a> Into<TableValuePayload> for &'a TableValueMessage {
-    fn into(self) -> TableValuePayload {
-        TableValuePayload {
-            table_reference: self.table_value_reference.as_ref().unwrap().into(),
-            value: serde_json::from_str(&self.json).unwrap(),
-        }
+impl TryFrom<&TableValueMessage> for TableValuePayload {
+    type Error = Status;
+    fn try_from(val: &TableValueMessage) -> Result<Self, Self::Error> {
+        Ok(TableValuePayload {
+            table_reference: required_field(
+                &val.table_value_reference,
+                "TableValueMessage.table_value_reference",
+            )?
+            .try_into()?,
+            value: json_field(&val.json, "TableValueMessage.json")?,
+            expected_revision: val.expected_revision,
+        })
     }
 }
 
 #[derive(Clone)]
-struct CreateTableValuePayload {
-    create_table_reference: CreateTableValueReference,
-    value: Value,
+pub(super) struct PatchTableValuePayload {
+    pub(super) table_reference: TableValueReference,
+    pub(super) patch: ValuePatch,
+    pub(super) expected_revision: Option<u64>,
 }
-impl<'a> Into<CreateTableValuePayload> for &'a CreateTableValueMessage {
-    fn into(self) -> CreateTableValuePayload {
-        CreateTableValuePayload {
-            create_table_reference: self.create_table_value_reference.as_ref().unwrap().into(),
-            value: serde_json::from_str(&self.json).unwrap(),
-        }
+impl TryFrom<&PatchTableValueMessage> for PatchTableValuePayload {
+    type Error = Status;
+    fn try_from(val: &PatchTableValueMessage) -> Result<Self, Self::Error> {
+        use patch_table_value_message::Patch;
+        let patch = match required_field(&val.patch, "PatchTableValueMessage.patch")? {
+            Patch::MergePatch(json) => {
+                ValuePatch::Merge(json_field(json, "PatchTableValueMessage.merge_patch")?)
+            }
+            Patch::JsonPatch(json) => {
+                ValuePatch::json(json_field(json, "PatchTableValueMessage.json_patch")?)
+                    .map_db_err_to_status()?
+            }
+        };
+        Ok(PatchTableValuePayload {
+            table_reference: required_field(
+                &val.table_value_reference,
+                "PatchTableValueMessage.table_value_reference",
+            )?
+            .try_into()?,
+            patch,
+            expected_revision: val.expected_revision,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct UpsertTableValuePayload {
+    pub(super) table_reference: TableValueReference,
+    pub(super) value: Value,
+}
+impl TryFrom<&UpsertTableValueMessage> for UpsertTableValuePayload {
+    type Error = Status;
+    fn try_from(val: &UpsertTableValueMessage) -> Result<Self, Self::Error> {
+        Ok(UpsertTableValuePayload {
+            table_reference: required_field(
+                &val.table_value_reference,
+                "UpsertTableValueMessage.table_value_reference",
+            )?
+            .try_into()?,
+            value: json_field(&val.json, "UpsertTableValueMessage.json")?,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct DeleteTableValuePayload {
+    pub(super) table_reference: TableValueReference,
+    pub(super) expected_revision: Option<u64>,
+}
+impl TryFrom<&DeleteTableValueMessage> for DeleteTableValuePayload {
+    type Error = Status;
+    fn try_from(val: &DeleteTableValueMessage) -> Result<Self, Self::Error> {
+        Ok(DeleteTableValuePayload {
+            table_reference: TableValueReference {
+                table_reference: required_field(
+                    &val.table_reference,
+                    "DeleteTableValueMessage.table_reference",
+                )?
+                .try_into()?,
+                id: required_field(&val.key, "DeleteTableValueMessage.key")?.try_into()?,
+            },
+            expected_revision: val.expected_revision,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct CreateTableValuePayload {
+    pub(super) create_table_reference: CreateTableValueReference,
+    pub(super) value: Value,
+}
+impl TryFrom<&CreateTableValueMessage> for CreateTableValuePayload {
+    type Error = Status;
+    fn try_from(val: &CreateTableValueMessage) -> Result<Self, Self::Error> {
+        Ok(CreateTableValuePayload {
+            create_table_reference: required_field(
+                &val.create_table_value_reference,
+                "CreateTableValueMessage.create_table_value_reference",
+            )?
+            .try_into()?,
+            value: json_field(&val.json, "CreateTableValueMessage.json")?,
+        })
     }
 }
 
@@ -61,25 +158,32 @@ impl TableValueServerTrait for RocksDbAccessor {
     fn create_value(
         &self,
         r: Request<CreateTableValueMessage>,
-    ) -> Result<Response<OndoKeyMessage>, Status> {
-        let payload: CreateTableValuePayload = r.get_ref().into();
+    ) -> Result<Response<CreateValueResponse>, Status> {
+        let payload: CreateTableValuePayload = r.get_ref().try_into()?;
         let reference = payload.create_table_reference;
         let mut entity = payload.value;
+        let _writes = self.lock_writes().map_db_err_to_status()?;
         let (new_id, effects) = reference
-            .post_table_value(&mut entity, self, self, self)
-            .map_db_err_to_status()?;
+            .post_table_value(&mut entity, self, self, self, self)
+            .map_db_err_to_status_for(&reference.table_reference)?;
         effects.apply_effects(self)?;
-        Ok(Response::new(new_id.into()))
+        let key: OndoKeyMessage = new_id.into();
+        Ok(Response::new(CreateValueResponse {
+            json_keys: key.json_keys,
+            revision: get_revision_from_table_value(&entity),
+        }))
     }
 
     fn delete_value(
         &self,
-        r: Request<TableValueReferenceMessage>,
+        r: Request<DeleteTableValueMessage>,
     ) -> Result<Response<EmptyMessage>, Status> {
-        let reference: TableValueReference = r.get_ref().into();
-        reference
-            .delete_table_value(self, self)
-            .map_db_err_to_status()?
+        let payload: DeleteTableValuePayload = r.get_ref().try_into()?;
+        let _writes = self.lock_writes().map_db_err_to_status()?;
+        payload
+            .table_reference
+            .delete_table_value(payload.expected_revision, self, self, self)
+            .map_db_err_to_status_for(&payload.table_reference)?
             .apply_effects(self)
     }
 
@@ -87,10 +191,10 @@ impl TableValueServerTrait for RocksDbAccessor {
         &self,
         r: Request<TableValueReferenceMessage>,
     ) -> Result<Response<JsonMessage>, Status> {
-        let reference: TableValueReference = r.get_ref().into();
+        let reference: TableValueReference = r.get_ref().try_into()?;
         reference
             .get_table_value(self)
-            .map_db_err_option_to_status()
+            .map_db_err_option_to_status_for(&reference)
             .map(|entity| Response::new(entity.into()))
     }
 
@@ -98,19 +202,52 @@ impl TableValueServerTrait for RocksDbAccessor {
         &self,
         r: Request<TableValueMessage>,
     ) -> Result<Response<EmptyMessage>, Status> {
-        let payload: TableValuePayload = r.get_ref().into();
+        let payload: TableValuePayload = r.get_ref().try_into()?;
         let entity = payload.value;
         let reference = payload.table_reference;
+        let _writes = self.lock_writes().map_db_err_to_status()?;
+        reference
+            .put_table_value(&entity, payload.expected_revision, self, self, self)
+            .map_db_err_to_status_for(&reference)?
+            .apply_effects(self)
+    }
+
+    fn patch_value(
+        &self,
+        r: Request<PatchTableValueMessage>,
+    ) -> Result<Response<EmptyMessage>, Status> {
+        let payload: PatchTableValuePayload = r.get_ref().try_into()?;
+        let reference = payload.table_reference;
+        let _writes = self.lock_writes().map_db_err_to_status()?;
         reference
-            .put_table_value(&entity, self, self)
-            .map_db_err_to_status()?
+            .patch_table_value(&payload.patch, payload.expected_revision, self, self, self)
+            .map_db_err_to_status_for(&reference)?
             .apply_effects(self)
     }
+
+    fn upsert_value(
+        &self,
+        r: Request<UpsertTableValueMessage>,
+    ) -> Result<Response<UpsertValueResponse>, Status> {
+        let payload: UpsertTableValuePayload = r.get_ref().try_into()?;
+        let reference = payload.table_reference;
+        let mut entity = payload.value;
+        let _writes = self.lock_writes().map_db_err_to_status()?;
+        let (created, effects) = reference
+            .upsert_table_value(&mut entity, self, self, self)
+            .map_db_err_to_status_for(&reference)?;
+        effects.apply_effects(self)?;
+        Ok(Response::new(UpsertValueResponse {
+            revision: get_revision_from_table_value(&entity),
+            created,
+        }))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::server::{index_server_trait::IndexServerTrait, test_fixture};
 
     #[test]
     fn test_table_value_reference_message_into_table_value_reference() {
@@ -125,7 +262,7 @@ mod tests {
                 json_keys: vec![r#"{"key":"value"}"#.to_string()],
             }),
         };
-        let reference: TableValueReference = (&message).into();
+        let reference: TableValueReference = (&message).try_into().unwrap();
         assert_eq!(
             reference.table_reference.domain_reference.domain_name,
             "example.com"
@@ -150,8 +287,9 @@ mod tests {
                 }),
             }),
             json: r#"{"key":"value"}"#.to_string(),
+            expected_revision: Some(3),
         };
-        let payload: TableValuePayload = (&message).into();
+        let payload: TableValuePayload = (&message).try_into().unwrap();
         assert_eq!(
             payload
                 .table_reference
@@ -164,6 +302,7 @@ mod tests {
         assert_eq!(payload.table_reference.id.values.len(), 1);
         assert_eq!(payload.table_reference.id.values[0]["key"], "value");
         assert_eq!(payload.value["key"], "value");
+        assert_eq!(payload.expected_revision, Some(3));
     }
 
     #[test]
@@ -181,7 +320,7 @@ mod tests {
                 }),
             }),
         };
-        let reference: CreateTableValueReference = (&message).into();
+        let reference: CreateTableValueReference = (&message).try_into().unwrap();
         assert_eq!(
             reference.table_reference.domain_reference.domain_name,
             "example.com"
@@ -209,7 +348,7 @@ mod tests {
             }),
             json: r#"{"key":"value"}"#.to_string(),
         };
-        let payload: CreateTableValuePayload = (&message).into();
+        let payload: CreateTableValuePayload = (&message).try_into().unwrap();
         assert_eq!(
             payload
                 .create_table_reference
@@ -238,4 +377,320 @@ mod tests {
         );
         assert_eq!(payload.value["key"], "value");
     }
+
+    #[test]
+    fn test_malformed_messages_are_invalid_arguments() {
+        let ra = RocksDbAccessor::in_memory();
+        let missing_reference = ra
+            .create_value(Request::new(CreateTableValueMessage {
+                create_table_value_reference: Some(CreateTableValueReferenceMessage {
+                    table_reference: None,
+                    key: Some(OptionalOndoKeyMessage { ondo_key: None }),
+                }),
+                json: r#"{"name": "a"}"#.to_string(),
+            }))
+            .unwrap_err();
+        assert_eq!(missing_reference.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            missing_reference.message(),
+            "Missing field CreateTableValueReferenceMessage.table_reference"
+        );
+
+        let invalid_json = ra
+            .update_value(Request::new(TableValueMessage {
+                table_value_reference: Some(TableValueReferenceMessage {
+                    table_reference: Some(TableReferenceMessage {
+                        domain_reference: Some(DomainReferenceMessage {
+                            domain_name: "domain".to_string(),
+                        }),
+                        table_name: "table".to_string(),
+                    }),
+                    key: Some(OndoKeyMessage {
+                        json_keys: vec!["1".to_string()],
+                    }),
+                }),
+                json: "{".to_string(),
+                expected_revision: None,
+            }))
+            .unwrap_err();
+        assert_eq!(invalid_json.code(), tonic::Code::InvalidArgument);
+        assert!(invalid_json
+            .message()
+            .starts_with("Invalid JSON in field TableValueMessage.json"));
+    }
+
+    #[test]
+    fn test_update_value_with_expected_revision() {
+        let ra = RocksDbAccessor::in_memory();
+        let table_reference = test_fixture::table_reference("domain", "table");
+        test_fixture::create_database(&ra, &table_reference);
+        let created = test_fixture::create_value(&ra, &table_reference, r#"{"name": "a"}"#);
+        assert_eq!(created.revision, 1);
+
+        let table_value_reference = TableValueReferenceMessage {
+            table_reference: Some(table_reference),
+            key: Some(OndoKeyMessage {
+                json_keys: created.json_keys,
+            }),
+        };
+        let update = |expected_revision| {
+            ra.update_value(Request::new(TableValueMessage {
+                table_value_reference: Some(table_value_reference.clone()),
+                json: r#"{"_id": {"values": [1]}, "name": "b"}"#.to_string(),
+                expected_revision,
+            }))
+        };
+        assert!(update(Some(1)).is_ok());
+        assert_eq!(update(Some(1)).unwrap_err().code(), tonic::Code::Aborted);
+        assert!(update(None).is_ok());
+
+        let value = ra
+            .get_value(Request::new(table_value_reference.clone()))
+            .unwrap()
+            .into_inner()
+            .json;
+        assert!(value.contains(r#""_revision":3"#));
+
+        let delete = |expected_revision| {
+            ra.delete_value(Request::new(DeleteTableValueMessage {
+                table_reference: table_value_reference.table_reference.clone(),
+                key: table_value_reference.key.clone(),
+                expected_revision,
+            }))
+        };
+        assert_eq!(delete(Some(2)).unwrap_err().code(), tonic::Code::Aborted);
+        assert!(delete(Some(3)).is_ok());
+    }
+
+    #[test]
+    fn test_messages_of_older_clients() {
+        use prost::Message;
+
+        let table_value_reference = TableValueReferenceMessage {
+            table_reference: Some(test_fixture::table_reference("domain", "table")),
+            key: Some(OndoKeyMessage {
+                json_keys: vec!["1".to_owned()],
+            }),
+        };
+        let delete =
+            DeleteTableValueMessage::decode(table_value_reference.encode_to_vec().as_slice())
+                .unwrap();
+        assert_eq!(
+            delete.table_reference,
+            table_value_reference.table_reference
+        );
+        assert_eq!(delete.key, table_value_reference.key);
+        assert_eq!(delete.expected_revision, None);
+
+        let created = CreateValueResponse {
+            json_keys: vec!["1".to_owned()],
+            revision: 1,
+        };
+        let key = OndoKeyMessage::decode(created.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(key.json_keys, created.json_keys);
+    }
+
+    #[test]
+    fn test_concurrent_updates_with_same_expected_revision() {
+        use std::sync::Barrier;
+
+        let ra = RocksDbAccessor::in_memory();
+        let table_reference = test_fixture::table_reference("domain", "table");
+        test_fixture::create_database(&ra, &table_reference);
+        let created = test_fixture::create_value(&ra, &table_reference, r#"{"name": "a"}"#);
+        let table_value_reference = TableValueReferenceMessage {
+            table_reference: Some(table_reference),
+            key: Some(OndoKeyMessage {
+                json_keys: created.json_keys,
+            }),
+        };
+
+        for revision in 1..=50 {
+            let barrier = Barrier::new(4);
+            let codes = std::thread::scope(|scope| {
+                let threads = ["b", "c", "d", "e"].map(|name| {
+                    let (ra, barrier) = (ra.clone(), &barrier);
+                    let table_value_reference = table_value_reference.clone();
+                    scope.spawn(move || {
+                        barrier.wait();
+                        ra.update_value(Request::new(TableValueMessage {
+                            table_value_reference: Some(table_value_reference),
+                            json: format!(r#"{{"_id": {{"values": [1]}}, "name": "{}"}}"#, name),
+                            expected_revision: Some(revision),
+                        }))
+                        .map_err(|status| status.code())
+                        .err()
+                    })
+                });
+                threads.map(|thread| thread.join().unwrap())
+            });
+            let mut codes = codes.to_vec();
+            codes.sort_by_key(|code| code.is_some());
+            let aborted = Some(tonic::Code::Aborted);
+            assert_eq!(codes, vec![None, aborted, aborted, aborted]);
+        }
+    }
+    #[test]
+    fn test_patch_value() {
+        use patch_table_value_message::Patch;
+
+        let ra = RocksDbAccessor::in_memory();
+        let table_reference = test_fixture::table_reference("domain", "table");
+        test_fixture::create_database(&ra, &table_reference);
+        let created = test_fixture::create_value(
+            &ra,
+            &table_reference,
+            r#"{"name": "a", "tags": ["x"], "bio": "long"}"#,
+        );
+
+        let table_value_reference = TableValueReferenceMessage {
+            table_reference: Some(table_reference),
+            key: Some(OndoKeyMessage {
+                json_keys: created.json_keys,
+            }),
+        };
+        let patch_value = |patch: Option<Patch>, expected_revision| {
+            ra.patch_value(Request::new(PatchTableValueMessage {
+                table_value_reference: Some(table_value_reference.clone()),
+                patch,
+                expected_revision,
+            }))
+        };
+        let merge_patch = Patch::MergePatch(r#"{"name": "b", "bio": null}"#.to_string());
+        patch_value(Some(merge_patch), Some(1)).unwrap();
+        let json_patch = Patch::JsonPatch(
+            r#"[{"op": "test", "path": "/name", "value": "b"},
+                {"op": "add", "path": "/tags/-", "value": "y"}]"#
+                .to_string(),
+        );
+        patch_value(Some(json_patch.clone()), None).unwrap();
+
+        let value = ra
+            .get_value(Request::new(table_value_reference.clone()))
+            .unwrap()
+            .into_inner()
+            .json;
+        let value: Value = serde_json::from_str(&value).unwrap();
+        assert_eq!(value["name"], "b");
+        assert_eq!(value["tags"], serde_json::json!(["x", "y"]));
+        assert!(value.get("bio").is_none());
+        assert_eq!(value["_revision"], 3);
+
+        let failed_test =
+            Patch::JsonPatch(r#"[{"op": "test", "path": "/name", "value": "a"}]"#.to_string());
+        let codes = [
+            (Some(failed_test), None, tonic::Code::FailedPrecondition),
+            (Some(json_patch), Some(1), tonic::Code::Aborted),
+            (
+                Some(Patch::JsonPatch(r#"{"op": "add"}"#.to_string())),
+                None,
+                tonic::Code::InvalidArgument,
+            ),
+            (None, None, tonic::Code::InvalidArgument),
+        ];
+        for (patch, expected_revision, code) in codes {
+            let status = patch_value(patch, expected_revision).unwrap_err();
+            assert_eq!(status.code(), code, "{}", status.message());
+        }
+    }
+
+    #[test]
+    fn test_upsert_value_and_create_value_with_taken_key() {
+        let ra = RocksDbAccessor::in_memory();
+        let table_reference = test_fixture::table_reference("domain", "table");
+        test_fixture::create_database(&ra, &table_reference);
+        let index_reference = test_fixture::index_reference(&table_reference, "by_city");
+        test_fixture::create_index(&ra, &index_reference, &["city"], false);
+
+        let key = OndoKeyMessage {
+            json_keys: vec!["7".to_string()],
+        };
+        let create = || {
+            ra.create_value(Request::new(CreateTableValueMessage {
+                create_table_value_reference: Some(CreateTableValueReferenceMessage {
+                    table_reference: Some(table_reference.clone()),
+                    key: Some(OptionalOndoKeyMessage {
+                        ondo_key: Some(key.clone()),
+                    }),
+                }),
+                json: r#"{"_id": {"values": [7]}, "city": "Paris"}"#.to_string(),
+            }))
+        };
+        assert_eq!(create().unwrap().into_inner().revision, 1);
+        assert_eq!(create().unwrap_err().code(), tonic::Code::AlreadyExists);
+
+        let upsert = |key: &str, city: &str| {
+            ra.upsert_value(Request::new(UpsertTableValueMessage {
+                table_value_reference: Some(TableValueReferenceMessage {
+                    table_reference: Some(table_reference.clone()),
+                    key: Some(OndoKeyMessage {
+                        json_keys: vec![key.to_string()],
+                    }),
+                }),
+                json: serde_json::json!({ "city": city }).to_string(),
+            }))
+            .unwrap()
+            .into_inner()
+        };
+        let response = upsert("7", "Rome");
+        assert_eq!((response.revision, response.created), (2, false));
+        let response = upsert("8", "Oslo");
+        assert_eq!((response.revision, response.created), (1, true));
+        let response = upsert("8", "Rome");
+        assert_eq!((response.revision, response.created), (2, false));
+
+        let value = ra
+            .get_value(Request::new(TableValueReferenceMessage {
+                table_reference: Some(table_reference.clone()),
+                key: Some(key),
+            }))
+            .unwrap()
+            .into_inner()
+            .json;
+        let value: Value = serde_json::from_str(&value).unwrap();
+        assert_eq!(value["_id"], serde_json::json!({"values": [7]}));
+        assert_eq!(value["city"], "Rome");
+
+        let verification = ra
+            .verify_index(Request::new(VerifyIndexMessage {
+                index_reference: Some(index_reference),
+                repair: false,
+            }))
+            .unwrap()
+            .into_inner();
+        assert!(verification.consistent, "{:?}", verification);
+        assert_eq!(verification.entries_checked, 2);
+    }
+
+    #[test]
+    fn test_create_values_with_explicit_and_generated_keys() {
+        let ra = RocksDbAccessor::in_memory();
+        let table_reference = test_fixture::table_reference("domain", "table");
+        test_fixture::create_database(&ra, &table_reference);
+
+        let create = |key: Option<&str>| {
+            ra.create_value(Request::new(CreateTableValueMessage {
+                create_table_value_reference: Some(CreateTableValueReferenceMessage {
+                    table_reference: Some(table_reference.clone()),
+                    key: Some(OptionalOndoKeyMessage {
+                        ondo_key: key.map(|key| OndoKeyMessage {
+                            json_keys: vec![key.to_string()],
+                        }),
+                    }),
+                }),
+                json: r#"{"name": "John"}"#.to_string(),
+            }))
+            .map(|response| response.into_inner().json_keys)
+        };
+        assert_eq!(create(Some("1")).unwrap(), vec!["1"]);
+        assert_eq!(create(Some("2")).unwrap(), vec!["2"]);
+        assert_eq!(create(None).unwrap(), vec!["3"]);
+        assert_eq!(
+            create(Some("3")).unwrap_err().code(),
+            tonic::Code::AlreadyExists
+        );
+        assert_eq!(create(Some("5")).unwrap(), vec!["5"]);
+        assert_eq!(create(None).unwrap(), vec!["4"]);
+        assert_eq!(create(None).unwrap(), vec!["6"]);
+    }
 }
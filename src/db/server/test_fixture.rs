@@ -0,0 +1,84 @@
+//test_fixture.rs
+//! The databases the tests of the server start from, created through the handlers the
+//! way a client would create them.
+use crate::db::server::{
+    database_server_trait::DatabaseServerTrait, domain_server_trait::DomainServerTrait,
+    index_server_trait::IndexServerTrait, rocks_db_accessor::RocksDbAccessor,
+    table_server_trait::TableServerTrait, table_value_server_trait::TableValueServerTrait,
+};
+use crate::ondo_remote::*;
+use tonic::Request;
+
+pub(crate) fn table_reference(domain_name: &str, table_name: &str) -> TableReferenceMessage {
+    TableReferenceMessage {
+        domain_reference: Some(DomainReferenceMessage {
+            domain_name: domain_name.to_owned(),
+        }),
+        table_name: table_name.to_owned(),
+    }
+}
+
+pub(crate) fn index_reference(
+    table_reference: &TableReferenceMessage,
+    index_name: &str,
+) -> IndexReferenceMessage {
+    IndexReferenceMessage {
+        table_reference: Some(table_reference.clone()),
+        index_name: index_name.to_owned(),
+    }
+}
+
+/// Creates the database server, the domain of the table and the table.
+pub(crate) fn create_database(ra: &RocksDbAccessor, table_reference: &TableReferenceMessage) {
+    ra.create_database_server(Request::new(DatabaseServerMessage {}))
+        .unwrap();
+    ra.create_domain(Request::new(DomainMessage {
+        domain_reference: table_reference.domain_reference.clone(),
+    }))
+    .unwrap();
+    create_table(ra, table_reference);
+}
+
+/// Creates a table without a schema in an existing domain.
+pub(crate) fn create_table(ra: &RocksDbAccessor, table_reference: &TableReferenceMessage) {
+    ra.create_table(Request::new(TableMessage {
+        table_reference: Some(table_reference.clone()),
+        schema: String::new(),
+        validate_values: false,
+    }))
+    .unwrap();
+}
+
+/// Creates an ordered index of the fields and waits until it is built.
+pub(crate) fn create_index(
+    ra: &RocksDbAccessor,
+    index_reference: &IndexReferenceMessage,
+    fields: &[&str],
+    unique: bool,
+) {
+    ra.create_index(Request::new(IndexMessage {
+        index_reference: Some(index_reference.clone()),
+        fields: fields.iter().map(|field| field.to_string()).collect(),
+        unique,
+        kind: None,
+        background: false,
+    }))
+    .unwrap();
+}
+
+/// Creates a value with a generated key.
+pub(crate) fn create_value(
+    ra: &RocksDbAccessor,
+    table_reference: &TableReferenceMessage,
+    json: &str,
+) -> CreateValueResponse {
+    ra.create_value(Request::new(CreateTableValueMessage {
+        create_table_value_reference: Some(CreateTableValueReferenceMessage {
+            table_reference: Some(table_reference.clone()),
+            key: Some(OptionalOndoKeyMessage { ondo_key: None }),
+        }),
+        json: json.to_owned(),
+    }))
+    .unwrap()
+    .into_inner()
+}
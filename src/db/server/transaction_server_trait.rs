@@ -0,0 +1,10 @@
+use crate::ondo_remote;
+use ondo_remote::*;
+use tonic::{Request, Response, Status};
+
+pub trait TransactionServerTrait {
+    fn execute_transaction(
+        &self,
+        r: Request<TransactionMessage>,
+    ) -> Result<Response<TransactionResponse>, Status>;
+}
@@ -0,0 +1,251 @@
+use super::db_error_to_status::{DbErrorContext, DbErrorToStatus};
+use super::message_field::required_field;
+use super::rocks_db_accessor::RocksDbAccessor;
+use super::source_sink::effects_sink::EffectsSink;
+use super::table_value_server_trait_impl::{
+    CreateTableValuePayload, DeleteTableValuePayload, TableValuePayload,
+};
+use super::transaction_server_trait::TransactionServerTrait;
+use crate::db::reference::{Transaction, TransactionOperation, TransactionTrait};
+use crate::ondo_remote;
+use ondo_remote::transaction_operation_message::Operation;
+use ondo_remote::*;
+use tonic::{Request, Response, Status};
+
+impl TryFrom<&TransactionOperationMessage> for TransactionOperation {
+    type Error = Status;
+    fn try_from(val: &TransactionOperationMessage) -> Result<Self, Self::Error> {
+        let operation =
+            match required_field(&val.operation, "TransactionOperationMessage.operation")? {
+                Operation::Create(message) => {
+                    let payload: CreateTableValuePayload = message.try_into()?;
+                    TransactionOperation::Create(payload.create_table_reference, payload.value)
+                }
+                Operation::Update(message) => {
+                    let payload: TableValuePayload = message.try_into()?;
+                    TransactionOperation::Update(
+                        payload.table_reference,
+                        payload.value,
+                        payload.expected_revision,
+                    )
+                }
+                Operation::Delete(message) => {
+                    let payload: DeleteTableValuePayload = message.try_into()?;
+                    TransactionOperation::Delete(payload.table_reference, payload.expected_revision)
+                }
+            };
+        Ok(operation)
+    }
+}
+
+impl TryFrom<&TransactionMessage> for Transaction {
+    type Error = Status;
+    fn try_from(val: &TransactionMessage) -> Result<Self, Self::Error> {
+        Ok(Transaction {
+            operations: val
+                .operations
+                .iter()
+                .map(|operation| operation.try_into())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TransactionServerTrait for RocksDbAccessor {
+    fn execute_transaction(
+        &self,
+        r: Request<TransactionMessage>,
+    ) -> Result<Response<TransactionResponse>, Status> {
+        let transaction: Transaction = r.get_ref().try_into()?;
+        let mut domain_references = transaction
+            .operations
+            .iter()
+            .map(|operation| operation.to_domain_reference());
+        let mut context = DbErrorContext::default();
+        if let Some(domain_reference) = domain_references.next() {
+            if domain_references.any(|other| other != domain_reference) {
+                return Err(Status::invalid_argument(
+                    "Transaction operations must be in the same domain",
+                ));
+            }
+            context = (&domain_reference).into();
+        }
+        let _writes = self.lock_writes().map_db_err_to_status()?;
+        let (keys, effects) = transaction
+            .execute_transaction(self, self, self, self)
+            .map_db_err_to_status_for(context)?;
+        effects.apply_effects(self)?;
+        Ok(Response::new(TransactionResponse {
+            keys: keys.into_iter().map(|key| key.into()).collect(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::server::{table_value_server_trait::TableValueServerTrait, test_fixture};
+
+    fn table_reference(table_name: &str) -> TableReferenceMessage {
+        test_fixture::table_reference("shop", table_name)
+    }
+
+    fn key(id: u64) -> OndoKeyMessage {
+        OndoKeyMessage {
+            json_keys: vec![id.to_string()],
+        }
+    }
+
+    fn create_shop(ra: &RocksDbAccessor) {
+        test_fixture::create_database(ra, &table_reference("orders"));
+        test_fixture::create_table(ra, &table_reference("stock"));
+        test_fixture::create_value(ra, &table_reference("stock"), r#"{"count": 10}"#);
+    }
+
+    fn create_value(table_name: &str, json: &str) -> TransactionOperationMessage {
+        TransactionOperationMessage {
+            operation: Some(Operation::Create(CreateTableValueMessage {
+                create_table_value_reference: Some(CreateTableValueReferenceMessage {
+                    table_reference: Some(table_reference(table_name)),
+                    key: Some(OptionalOndoKeyMessage { ondo_key: None }),
+                }),
+                json: json.to_owned(),
+            })),
+        }
+    }
+
+    fn create_order() -> TransactionOperationMessage {
+        create_value("orders", r#"{"item": 1}"#)
+    }
+
+    fn create_unique_index(ra: &RocksDbAccessor, table_name: &str, field: &str) {
+        let index_reference = test_fixture::index_reference(&table_reference(table_name), field);
+        test_fixture::create_index(ra, &index_reference, &[field], true);
+    }
+
+    fn update_stock(id: u64, expected_revision: Option<u64>) -> TransactionOperationMessage {
+        TransactionOperationMessage {
+            operation: Some(Operation::Update(TableValueMessage {
+                table_value_reference: Some(TableValueReferenceMessage {
+                    table_reference: Some(table_reference("stock")),
+                    key: Some(key(id)),
+                }),
+                json: format!(r#"{{"_id": {{"values": [{}]}}, "count": 9}}"#, id),
+                expected_revision,
+            })),
+        }
+    }
+
+    fn get_value(ra: &RocksDbAccessor, table_name: &str, id: u64) -> Result<String, Status> {
+        ra.get_value(Request::new(TableValueReferenceMessage {
+            table_reference: Some(table_reference(table_name)),
+            key: Some(key(id)),
+        }))
+        .map(|response| response.into_inner().json)
+    }
+
+    #[test]
+    fn test_execute_transaction() {
+        let ra = RocksDbAccessor::in_memory();
+        create_shop(&ra);
+
+        let response = ra
+            .execute_transaction(Request::new(TransactionMessage {
+                operations: vec![create_order(), create_order(), update_stock(1, Some(1))],
+            }))
+            .unwrap();
+
+        assert_eq!(response.get_ref().keys, vec![key(1), key(2), key(1)]);
+        assert!(get_value(&ra, "orders", 2).is_ok());
+        assert!(get_value(&ra, "stock", 1).unwrap().contains(r#""count":9"#));
+        assert!(get_value(&ra, "stock", 1)
+            .unwrap()
+            .contains(r#""_revision":2"#));
+    }
+
+    #[test]
+    fn test_failed_transaction_writes_nothing() {
+        let ra = RocksDbAccessor::in_memory();
+        create_shop(&ra);
+
+        let result = ra.execute_transaction(Request::new(TransactionMessage {
+            operations: vec![create_order(), update_stock(99, None)],
+        }));
+
+        assert!(result.is_err());
+        assert!(get_value(&ra, "orders", 1).is_err());
+    }
+
+    #[test]
+    fn test_revision_conflict_aborts_transaction() {
+        let ra = RocksDbAccessor::in_memory();
+        create_shop(&ra);
+
+        let result = ra.execute_transaction(Request::new(TransactionMessage {
+            operations: vec![create_order(), update_stock(1, Some(2))],
+        }));
+
+        assert_eq!(result.unwrap_err().code(), tonic::Code::Aborted);
+        assert!(get_value(&ra, "orders", 1).is_err());
+    }
+
+    #[test]
+    fn test_transaction_across_domains() {
+        let ra = RocksDbAccessor::in_memory();
+        create_shop(&ra);
+        let mut other_domain_operation = create_order();
+        if let Some(Operation::Create(message)) = other_domain_operation.operation.as_mut() {
+            message.create_table_value_reference = Some(CreateTableValueReferenceMessage {
+                table_reference: Some(TableReferenceMessage {
+                    domain_reference: Some(DomainReferenceMessage {
+                        domain_name: "other".to_owned(),
+                    }),
+                    table_name: "orders".to_owned(),
+                }),
+                key: Some(OptionalOndoKeyMessage { ondo_key: None }),
+            });
+        }
+
+        let result = ra.execute_transaction(Request::new(TransactionMessage {
+            operations: vec![create_order(), other_domain_operation],
+        }));
+
+        assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_unique_index_in_transaction() {
+        let ra = RocksDbAccessor::in_memory();
+        create_shop(&ra);
+        create_unique_index(&ra, "orders", "item");
+
+        let result = ra.execute_transaction(Request::new(TransactionMessage {
+            operations: vec![create_order(), create_order()],
+        }));
+
+        assert_eq!(result.unwrap_err().code(), tonic::Code::AlreadyExists);
+        assert!(get_value(&ra, "orders", 1).is_err());
+    }
+
+    #[test]
+    fn test_unique_key_freed_in_transaction() {
+        let ra = RocksDbAccessor::in_memory();
+        create_shop(&ra);
+        create_unique_index(&ra, "stock", "count");
+
+        let result = ra.execute_transaction(Request::new(TransactionMessage {
+            operations: vec![create_value("stock", r#"{"count": 10}"#)],
+        }));
+        assert_eq!(result.unwrap_err().code(), tonic::Code::AlreadyExists);
+
+        let response = ra
+            .execute_transaction(Request::new(TransactionMessage {
+                operations: vec![
+                    update_stock(1, None),
+                    create_value("stock", r#"{"count": 10}"#),
+                ],
+            }))
+            .unwrap();
+        assert_eq!(response.get_ref().keys, vec![key(1), key(2)]);
+    }
+}
@@ -1,16 +1,19 @@
+use super::message_field::json_field;
 use crate::ondo_remote;
 use ondo_remote::*;
+use tonic::Status;
 
-impl Into<JsonMessage> for serde_json::Value {
-    fn into(self) -> JsonMessage {
+impl From<serde_json::Value> for JsonMessage {
+    fn from(val: serde_json::Value) -> Self {
         JsonMessage {
-            json: serde_json::to_string(&self).unwrap(),
+            json: serde_json::to_string(&val).unwrap(),
         }
     }
 }
 
-impl Into<serde_json::Value> for JsonMessage {
-    fn into(self) -> serde_json::Value {
-        serde_json::from_str(&self.json).unwrap()
+impl TryFrom<JsonMessage> for serde_json::Value {
+    type Error = Status;
+    fn try_from(val: JsonMessage) -> Result<Self, Self::Error> {
+        json_field(&val.json, "JsonMessage.json")
     }
 }
@@ -0,0 +1,178 @@
+use super::db_error_to_status::DbErrorToStatus;
+use super::rocks_db_accessor::{DbArc, DbReadLockGuardWrapper};
+use crate::db::reference::{Page, PageRequest};
+use crate::db::{entity::TableValue, DbResult};
+use crate::ondo_remote::JsonMessage;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Status;
+
+/// Streams one value per message.
+pub type JsonMessageStream = ReceiverStream<Result<JsonMessage, Status>>;
+
+// Number of messages that are read ahead of the client, and the number of values
+// that are read under one read lock.
+const STREAM_BUFFER_SIZE: usize = 16;
+
+// Reads the values page by page on a blocking thread and sends them to the client.
+// The read lock is held only while a page is read, so a slow client does not block
+// the writers; each page continues from the key after the last value sent.
+// Reading pauses while the buffer is full and stops when the client goes away,
+// so at most two pages of values are held in memory.
+// Must be called within a Tokio runtime.
+pub(crate) fn stream_values<F>(guarded_db: DbArc, mut values_page: F) -> JsonMessageStream
+where
+    F: for<'a> FnMut(&'a DbReadLockGuardWrapper<'a>, &PageRequest) -> DbResult<Page<TableValue>>
+        + Send
+        + 'static,
+{
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER_SIZE);
+    tokio::task::spawn_blocking(move || {
+        let mut send_values = || -> DbResult<()> {
+            let mut page_request = PageRequest {
+                start_key: None,
+                page_size: Some(STREAM_BUFFER_SIZE),
+            };
+            loop {
+                let page = {
+                    let db_wrapper = DbReadLockGuardWrapper::new(&guarded_db)?;
+                    values_page(&db_wrapper, &page_request)?
+                };
+                for value in page.values {
+                    if sender.blocking_send(Ok(JsonMessage::from(value))).is_err() {
+                        return Ok(());
+                    }
+                }
+                match page.next_key {
+                    Some(next_key) => page_request.start_key = Some(next_key),
+                    None => return Ok(()),
+                }
+            }
+        };
+        if let Err(err) = send_values() {
+            let _ = sender.blocking_send(Err(err).map_db_err_to_status());
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::server::{
+        index_server_trait::IndexServerTrait, rocks_db_accessor::RocksDbAccessor,
+        table_server_trait::TableServerTrait, test_fixture,
+    };
+    use crate::ondo_remote::*;
+    use tokio_stream::StreamExt;
+    use tonic::{Request, Status};
+
+    fn table_reference() -> TableReferenceMessage {
+        test_fixture::table_reference("domain", "people")
+    }
+
+    fn create_people(ra: &RocksDbAccessor, cities: &[&str]) {
+        test_fixture::create_database(ra, &table_reference());
+        let index_reference = test_fixture::index_reference(&table_reference(), "by_city");
+        test_fixture::create_index(ra, &index_reference, &["city"], false);
+        for city in cities {
+            let json = format!(r#"{{"city": "{}"}}"#, city);
+            test_fixture::create_value(ra, &table_reference(), &json);
+        }
+    }
+
+    async fn collect_cities(stream: super::JsonMessageStream) -> Result<Vec<String>, Status> {
+        let messages: Vec<Result<JsonMessage, Status>> = stream.collect().await;
+        messages
+            .into_iter()
+            .map(|message| {
+                let value: serde_json::Value = message?.try_into()?;
+                Ok(value["city"].as_str().unwrap().to_owned())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_stream_values() {
+        let ra = RocksDbAccessor::in_memory();
+        let cities = (0..40).map(|i| format!("city {}", i)).collect::<Vec<_>>();
+        create_people(&ra, &cities.iter().map(|c| c.as_str()).collect::<Vec<_>>());
+
+        let stream = ra
+            .stream_values(Request::new(table_reference()))
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(collect_cities(stream).await.unwrap(), cities);
+    }
+
+    #[tokio::test]
+    async fn test_paused_stream_does_not_block_writes() {
+        let ra = RocksDbAccessor::in_memory();
+        let cities = (0..40).map(|i| format!("city {}", i)).collect::<Vec<_>>();
+        create_people(&ra, &cities.iter().map(|c| c.as_str()).collect::<Vec<_>>());
+
+        let mut stream = ra
+            .stream_values(Request::new(table_reference()))
+            .unwrap()
+            .into_inner();
+        assert!(stream.next().await.unwrap().is_ok());
+
+        // The stream waits for the client with a full buffer, while a new table
+        // takes the write lock to create its column families.
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let writer = ra.clone();
+        std::thread::spawn(move || {
+            let created = writer.create_table(Request::new(TableMessage {
+                table_reference: Some(TableReferenceMessage {
+                    table_name: "cities".to_owned(),
+                    ..table_reference()
+                }),
+                schema: String::new(),
+                validate_values: false,
+            }));
+            let _ = sender.send(created.is_ok());
+        });
+        let created = receiver.recv_timeout(std::time::Duration::from_secs(10));
+        assert_eq!(created, Ok(true));
+
+        let rest = collect_cities(stream).await.unwrap();
+        assert_eq!(rest, cities[1..]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_found_values() {
+        let ra = RocksDbAccessor::in_memory();
+        create_people(&ra, &["Paris", "Rome", "Paris"]);
+
+        let stream = ra
+            .stream_found_values(Request::new(IndexedValueReferenceMessage {
+                index_reference: Some(IndexReferenceMessage {
+                    table_reference: Some(table_reference()),
+                    index_name: "by_city".to_owned(),
+                }),
+                key: Some(OndoKeyMessage {
+                    json_keys: vec![r#""Paris""#.to_owned()],
+                }),
+            }))
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(
+            collect_cities(stream).await.unwrap(),
+            vec!["Paris".to_owned(), "Paris".to_owned()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_values_of_missing_table() {
+        let ra = RocksDbAccessor::in_memory();
+        let stream = ra
+            .stream_values(Request::new(table_reference()))
+            .unwrap()
+            .into_inner();
+
+        let messages: Vec<Result<JsonMessage, Status>> = stream.collect().await;
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//grpc_services.rs
+//! Standard gRPC services of ondo-server.
+//!
+//! `grpc.health.v1.Health` reports the overall server ("") and the served services as
+//! NOT_SERVING until the database is open, and again once a graceful shutdown begins.
+//! Server reflection exposes the descriptors of `ondo_remote.proto`, so the API can be
+//! explored with tools like grpcurl.
+use crate::ondo_remote;
+use tonic::transport::NamedService;
+use tonic_health::pb::health_server::{Health, HealthServer};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tonic_reflection::server::{ServerReflection, ServerReflectionServer};
+
+/// The service name of the overall server health.
+pub const SERVER_HEALTH: &str = "";
+
+/// Creates the health service with the server and `S` reported as NOT_SERVING.
+pub async fn health_service<S: NamedService>() -> (HealthReporter, HealthServer<impl Health>) {
+    let (mut reporter, service) = tonic_health::server::health_reporter();
+    set_serving::<S>(&mut reporter, false).await;
+    (reporter, service)
+}
+
+/// Reports the server and `S` as SERVING or NOT_SERVING.
+pub async fn set_serving<S: NamedService>(reporter: &mut HealthReporter, serving: bool) {
+    let status = if serving {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    };
+    reporter.set_service_status(SERVER_HEALTH, status).await;
+    reporter.set_service_status(S::NAME, status).await;
+}
+
+/// Creates the reflection service for the OndoRemote and health services.
+pub fn reflection_service(
+) -> Result<ServerReflectionServer<impl ServerReflection>, tonic_reflection::server::Error> {
+    tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(ondo_remote::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::{Channel, Server};
+    use tonic_health::pb::health_check_response::ServingStatus as ResponseStatus;
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+    use tonic_reflection::pb::server_reflection_client::ServerReflectionClient;
+    use tonic_reflection::pb::server_reflection_request::MessageRequest;
+    use tonic_reflection::pb::server_reflection_response::MessageResponse;
+    use tonic_reflection::pb::ServerReflectionRequest;
+
+    struct OndoRemoteName;
+
+    impl NamedService for OndoRemoteName {
+        const NAME: &'static str = "OndoRemote.OndoRemote";
+    }
+
+    async fn serve(
+        health: HealthServer<impl Health>,
+        reflection: ServerReflectionServer<impl ServerReflection>,
+    ) -> Channel {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(health)
+                .add_service(reflection)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        Channel::from_shared(format!("http://{}", address))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap()
+    }
+
+    async fn check(client: &mut HealthClient<Channel>, service: &str) -> i32 {
+        client
+            .check(HealthCheckRequest {
+                service: service.to_owned(),
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .status
+    }
+
+    #[tokio::test]
+    async fn test_health_service() {
+        let (mut reporter, health) = health_service::<OndoRemoteName>().await;
+        let channel = serve(health, reflection_service().unwrap()).await;
+        let mut client = HealthClient::new(channel);
+
+        let not_serving = ResponseStatus::NotServing as i32;
+        assert_eq!(check(&mut client, SERVER_HEALTH).await, not_serving);
+        assert_eq!(check(&mut client, OndoRemoteName::NAME).await, not_serving);
+
+        set_serving::<OndoRemoteName>(&mut reporter, true).await;
+        let serving = ResponseStatus::Serving as i32;
+        assert_eq!(check(&mut client, SERVER_HEALTH).await, serving);
+        assert_eq!(check(&mut client, OndoRemoteName::NAME).await, serving);
+
+        set_serving::<OndoRemoteName>(&mut reporter, false).await;
+        assert_eq!(check(&mut client, OndoRemoteName::NAME).await, not_serving);
+    }
+
+    #[tokio::test]
+    async fn test_reflection_service() {
+        let (_reporter, health) = health_service::<OndoRemoteName>().await;
+        let channel = serve(health, reflection_service().unwrap()).await;
+        let mut client = ServerReflectionClient::new(channel);
+
+        let request = ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(MessageRequest::ListServices(String::new())),
+        };
+        let mut responses = client
+            .server_reflection_info(tokio_stream::iter(vec![request]))
+            .await
+            .unwrap()
+            .into_inner();
+        let response = responses.message().await.unwrap().unwrap();
+        let service_names = match response.message_response {
+            Some(MessageResponse::ListServicesResponse(list)) => list
+                .service
+                .into_iter()
+                .map(|service| service.name)
+                .collect::<Vec<_>>(),
+            other => panic!("Unexpected response {:?}", other),
+        };
+        assert!(service_names.contains(&"OndoRemote.OndoRemote".to_owned()));
+        assert!(service_names.contains(&"grpc.health.v1.Health".to_owned()));
+    }
+}
@@ -1,4 +1,11 @@
+// tonic::Status is the error type of every remote call.
+#![allow(clippy::result_large_err)]
+
+pub mod config;
 pub mod db;
+pub mod grpc_services;
+pub mod logging;
+pub mod metrics;
 
 pub mod hello {
     tonic::include_proto!("hello");
@@ -6,4 +13,8 @@ pub mod hello {
 
 pub mod ondo_remote {
     tonic::include_proto!("ondo_remote");
+
+    /// Encoded descriptors of ondo_remote.proto and its imports.
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("ondo_remote_descriptor");
 }
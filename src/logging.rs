@@ -0,0 +1,115 @@
+//logging.rs
+//! Structured logging of ondo-server.
+//!
+//! Every gRPC request runs in a `grpc_request` span that carries its request id and method.
+//! The handlers record the domain, table and index of the request and the number of effects
+//! it applied. The span is logged when it closes, with its latency in `time.busy`.
+use crate::config::{LogConfig, LogFormat};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tonic::codegen::http;
+use tracing::field::Empty;
+use tracing::Span;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Installs the global subscriber. The level is an `EnvFilter` directive such as
+/// `info` or `info,ondo=debug`. Individual effects are logged at the `trace` level.
+pub fn init(config: &LogConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let filter = EnvFilter::try_new(&config.level)?;
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE);
+    match config.format {
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .try_init(),
+        LogFormat::Text => builder.try_init(),
+    }
+}
+
+/// The span of a gRPC request. The request id is taken from the `x-request-id` header
+/// when the client sends one.
+pub fn request_span(request: &http::Request<()>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_owned())
+        .unwrap_or_else(|| NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed).to_string());
+    tracing::info_span!(
+        "grpc_request",
+        request_id = %request_id,
+        method = %request.uri().path(),
+        domain = Empty,
+        table = Empty,
+        index = Empty,
+        effect_count = Empty,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log_in_request_span(request: http::Request<()>) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_current_span(true)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = request_span(&request);
+            let _entered = span.enter();
+            Span::current().record("table", "users");
+            tracing::info!("handled");
+        });
+        let bytes = buffer.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_request_span_fields() {
+        let request = http::Request::builder()
+            .uri("/ondo_remote.OndoRemote/GetValue")
+            .header(REQUEST_ID_HEADER, "abc")
+            .body(())
+            .unwrap();
+        let log = log_in_request_span(request);
+        assert!(log.contains(r#""request_id":"abc""#));
+        assert!(log.contains(r#""method":"/ondo_remote.OndoRemote/GetValue""#));
+        assert!(log.contains(r#""table":"users""#));
+    }
+
+    #[test]
+    fn test_request_ids_are_generated() {
+        let request_id = || {
+            let log = log_in_request_span(http::Request::builder().body(()).unwrap());
+            let event: serde_json::Value = serde_json::from_str(log.trim()).unwrap();
+            event["span"]["request_id"].as_str().unwrap().to_owned()
+        };
+        assert_ne!(request_id(), request_id());
+    }
+}
@@ -0,0 +1,343 @@
+//metrics.rs
+//! Prometheus metrics of ondo-server.
+//!
+//! The metrics are registered in the default registry and served in the text format on
+//! `/metrics` by the optional metrics listener. The RocksDB properties of the column
+//! families are read when the metrics are scraped.
+use crate::db::server::rocks_db_accessor::RocksDbAccessor;
+use crate::ondo_remote;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Response, StatusCode};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use prost::Message;
+use prost_types::FileDescriptorSet;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tonic::codegen::http;
+use tower::{Layer, Service};
+
+static GRPC_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ondo_grpc_requests_total",
+        "gRPC requests by method and status code",
+        &["method", "code"]
+    )
+    .unwrap()
+});
+
+static GRPC_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "ondo_grpc_request_duration_seconds",
+        "Latency of gRPC requests until the response headers",
+        &["method"]
+    )
+    .unwrap()
+});
+
+static EFFECTS_APPLIED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ondo_effects_applied_total",
+        "Effects applied to the database by variant",
+        &["effect"]
+    )
+    .unwrap()
+});
+
+static DOCUMENTS_SCANNED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ondo_documents_scanned_total",
+        "Documents read by the table and index iterators",
+        &["source"]
+    )
+    .unwrap()
+});
+
+static DB_LOCK_WAIT: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "ondo_db_lock_wait_seconds",
+        "Time spent waiting for the database lock",
+        &["lock"],
+        vec![0.000_01, 0.000_1, 0.001, 0.01, 0.1, 1.0, 10.0]
+    )
+    .unwrap()
+});
+
+static ROCKSDB_PROPERTIES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "ondo_rocksdb_property",
+        "RocksDB properties of the column families",
+        &["cf", "property"]
+    )
+    .unwrap()
+});
+
+/// The method label of the requests to paths that are not gRPC methods of the server.
+pub const UNKNOWN_METHOD: &str = "unknown";
+
+// The paths of the methods of the served services, so that a client can not add a label
+// for every path it sends.
+static GRPC_METHODS: Lazy<HashSet<String>> = Lazy::new(|| {
+    let mut methods = HashSet::new();
+    for encoded in [
+        ondo_remote::FILE_DESCRIPTOR_SET,
+        tonic_health::pb::FILE_DESCRIPTOR_SET,
+        tonic_reflection::pb::FILE_DESCRIPTOR_SET,
+    ] {
+        for file in FileDescriptorSet::decode(encoded).unwrap().file {
+            for service in file.service.iter() {
+                for method in service.method.iter() {
+                    methods.insert(format!(
+                        "/{}.{}/{}",
+                        file.package(),
+                        service.name(),
+                        method.name()
+                    ));
+                }
+            }
+        }
+    }
+    methods
+});
+
+fn method_label(path: &str) -> String {
+    match GRPC_METHODS.contains(path) {
+        true => path.to_owned(),
+        false => UNKNOWN_METHOD.to_owned(),
+    }
+}
+
+// Concurrent scrapes would see the properties that another scrape has just reset.
+static GATHER_LOCK: Mutex<()> = Mutex::new(());
+
+/// The RocksDB integer properties exported for each column family.
+pub const ROCKSDB_PROPERTY_NAMES: [&str; 3] = [
+    "rocksdb.cur-size-all-mem-tables",
+    "rocksdb.total-sst-files-size",
+    "rocksdb.estimate-pending-compaction-bytes",
+];
+
+pub(crate) fn record_effect(effect_name: &str) {
+    EFFECTS_APPLIED.with_label_values(&[effect_name]).inc();
+}
+
+pub(crate) fn record_document_scanned(source: &str) {
+    DOCUMENTS_SCANNED.with_label_values(&[source]).inc();
+}
+
+pub(crate) fn record_lock_wait(lock: &str, wait: Duration) {
+    DB_LOCK_WAIT
+        .with_label_values(&[lock])
+        .observe(wait.as_secs_f64());
+}
+
+fn record_grpc_request(method: &str, code: &str, duration: Duration) {
+    GRPC_REQUESTS.with_label_values(&[method, code]).inc();
+    GRPC_REQUEST_DURATION
+        .with_label_values(&[method])
+        .observe(duration.as_secs_f64());
+}
+
+// The gauges of dropped column families are removed with the reset.
+fn update_rocksdb_properties(rocks_db_accessor: &RocksDbAccessor) {
+    ROCKSDB_PROPERTIES.reset();
+    for property_name in ROCKSDB_PROPERTY_NAMES {
+        match rocks_db_accessor.cf_property_values(property_name) {
+            Ok(values) => {
+                for (cf_name, value) in values {
+                    ROCKSDB_PROPERTIES
+                        .with_label_values(&[&cf_name, property_name])
+                        .set(value as i64);
+                }
+            }
+            Err(err) => tracing::warn!(property_name, "Can not read property: {}", err),
+        }
+    }
+}
+
+/// Renders every registered metric in the Prometheus text format.
+pub fn gather(rocks_db_accessor: &RocksDbAccessor) -> String {
+    Lazy::force(&GRPC_REQUESTS);
+    Lazy::force(&GRPC_REQUEST_DURATION);
+    Lazy::force(&EFFECTS_APPLIED);
+    Lazy::force(&DOCUMENTS_SCANNED);
+    Lazy::force(&DB_LOCK_WAIT);
+    let _gathering = GATHER_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    update_rocksdb_properties(rocks_db_accessor);
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+/// Serves the metrics on `GET /metrics` until the future is dropped.
+pub async fn serve(address: SocketAddr, rocks_db_accessor: RocksDbAccessor) -> hyper::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let rocks_db_accessor = rocks_db_accessor.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: http::Request<Body>| {
+                let rocks_db_accessor = rocks_db_accessor.clone();
+                async move {
+                    let response = match (request.method(), request.uri().path()) {
+                        // Gathering locks the database, so it runs off the async workers.
+                        (&Method::GET, "/metrics") => {
+                            match tokio::task::spawn_blocking(move || gather(&rocks_db_accessor))
+                                .await
+                            {
+                                Ok(metrics) => Response::builder()
+                                    .header(
+                                        http::header::CONTENT_TYPE,
+                                        TextEncoder::new().format_type(),
+                                    )
+                                    .body(Body::from(metrics)),
+                                Err(err) => {
+                                    tracing::error!(%err, "Can not gather the metrics");
+                                    Response::builder()
+                                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                                        .body(Body::empty())
+                                }
+                            }
+                        }
+                        _ => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty()),
+                    };
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+    hyper::Server::bind(&address).serve(make_service).await
+}
+
+/// Counts the gRPC requests and measures their latency by method and status code.
+#[derive(Debug, Clone, Default)]
+pub struct GrpcMetricsLayer;
+
+impl<S> Layer<S> for GrpcMetricsLayer {
+    type Service = GrpcMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcMetricsService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GrpcMetricsService<S> {
+    inner: S,
+}
+
+// A failed call answers with the grpc-status in its headers. A successful call sends it
+// in the trailers, so a response without grpc-status header is counted as OK (0).
+fn grpc_status_code<B>(response: &http::Response<B>) -> String {
+    response
+        .headers()
+        .get("grpc-status")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("0")
+        .to_owned()
+}
+
+impl<S, RequestBody, ResponseBody> Service<http::Request<RequestBody>> for GrpcMetricsService<S>
+where
+    S: Service<http::Request<RequestBody>, Response = http::Response<ResponseBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<RequestBody>) -> Self::Future {
+        let method = method_label(request.uri().path());
+        let start = Instant::now();
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let result = future.await;
+            let code = match &result {
+                Ok(response) => grpc_status_code(response),
+                Err(_) => "transport".to_owned(),
+            };
+            record_grpc_request(&method, &code, start.elapsed());
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gather() {
+        let rocks_db_accessor = RocksDbAccessor::in_memory();
+        record_effect("CreateCf");
+        record_document_scanned("table");
+        record_grpc_request(
+            "/OndoRemote.OndoRemote/Version",
+            "0",
+            Duration::from_millis(1),
+        );
+        let metrics = gather(&rocks_db_accessor);
+        assert!(metrics.contains(r#"ondo_effects_applied_total{effect="CreateCf"}"#));
+        assert!(metrics.contains(r#"ondo_documents_scanned_total{source="table"}"#));
+        assert!(metrics.contains(
+            r#"ondo_grpc_requests_total{code="0",method="/OndoRemote.OndoRemote/Version"}"#
+        ));
+        assert!(metrics.contains("ondo_db_lock_wait_seconds"));
+        assert!(metrics.contains(
+            r#"ondo_rocksdb_property{cf="default",property="rocksdb.total-sst-files-size"}"#
+        ));
+    }
+
+    #[test]
+    fn test_method_label() {
+        assert_eq!(
+            method_label("/OndoRemote.OndoRemote/Version"),
+            "/OndoRemote.OndoRemote/Version"
+        );
+        assert_eq!(
+            method_label("/grpc.health.v1.Health/Check"),
+            "/grpc.health.v1.Health/Check"
+        );
+        assert_eq!(
+            method_label("/OndoRemote.OndoRemote/Nothing"),
+            UNKNOWN_METHOD
+        );
+        assert_eq!(method_label("/random/path"), UNKNOWN_METHOD);
+    }
+
+    #[test]
+    fn test_dropped_cf_properties_are_removed() {
+        let rocks_db_accessor = RocksDbAccessor::in_memory();
+        ROCKSDB_PROPERTIES
+            .with_label_values(&["dropped", "rocksdb.total-sst-files-size"])
+            .set(1);
+        let metrics = gather(&rocks_db_accessor);
+        assert!(!metrics.contains(r#"cf="dropped""#));
+    }
+
+    #[test]
+    fn test_grpc_status_code() {
+        let ok = http::Response::builder().body(()).unwrap();
+        assert_eq!(grpc_status_code(&ok), "0");
+        let not_found = http::Response::builder()
+            .header("grpc-status", "5")
+            .body(())
+            .unwrap();
+        assert_eq!(grpc_status_code(&not_found), "5");
+    }
+}